@@ -0,0 +1,108 @@
+//! The account's own streams: orders, fills and positions.
+//!
+//! Recorded by the process that records the public tape, with the same clock,
+//! so a fill's receive time can be set against the trades and book updates
+//! around it. Everything an authenticated stream delivers goes to one file per
+//! venue, named [`SYMBOL`], next to the symbols' files and in the same layout.
+//!
+//! The key and secret are read from the environment, never from the config
+//! file: the config only names the variables, so it can be copied, committed
+//! and logged without carrying anything that can trade.
+
+use std::fmt;
+
+use anyhow::Context;
+use hmac::{Hmac, KeyInit, Mac};
+use jiff::Timestamp;
+use sha2::Sha256;
+
+use collector::record::Stream;
+
+use crate::{config::Account, file::WriteRecord, symbol::Symbol};
+
+/// The "symbol" the account's records are filed under.
+pub const SYMBOL: &str = "account";
+
+#[derive(Clone)]
+pub struct Credentials {
+    pub key: String,
+    secret: String,
+}
+
+/// The key is an identifier an exchange shows in its own UI; the secret is
+/// never printed.
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("key", &self.key)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Credentials {
+    pub fn new(key: String, secret: String) -> Self {
+        Self { key, secret }
+    }
+
+    /// The credentials in the variables `account` names.
+    pub fn from_env(account: &Account) -> Result<Self, anyhow::Error> {
+        let read = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .filter(|value| !value.is_empty())
+                .with_context(|| format!("the environment variable {name} is not set"))
+        };
+        Ok(Self::new(
+            read(&account.key_env)?,
+            read(&account.secret_env)?,
+        ))
+    }
+
+    /// The hex HMAC-SHA256 of `payload` under the secret, which is how Binance
+    /// and Bybit both sign.
+    pub fn sign(&self, payload: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())
+            .unwrap_or_else(|_| unreachable!("HMAC accepts keys of every length"));
+        mac.update(payload.as_bytes());
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+/// A frame from an authenticated stream, filed under [`SYMBOL`]. `connection`
+/// is the index the account's connection delivers on, past the market's.
+pub fn record(recv_time: Timestamp, data: bytes::Bytes, connection: usize) -> WriteRecord {
+    WriteRecord::received(
+        recv_time,
+        Symbol::from(SYMBOL),
+        data,
+        connection,
+        Stream::Account,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures_are_hex_hmac_sha256() {
+        // The example in Binance's API documentation.
+        let credentials = Credentials::new(
+            "vmPUZE6mv9SD5VNHk4HlWFsOr6aKE2zvsw0MuIgwCIPy6utIco14y7Ju91duEh8A".to_owned(),
+            "NhqPtmdSJYdKjVHjA7PZj4Mge3R5YNiP1e3UZjInClVN65XAbvqqM6A7H5fATj0j".to_owned(),
+        );
+
+        assert_eq!(
+            credentials.sign(
+                "symbol=LTCBTC&side=BUY&type=LIMIT&timeInForce=GTC&quantity=1&price=0.1\
+                 &recvWindow=5000&timestamp=1499827319559"
+            ),
+            "c8db56825ae71d6d79447849e617115f4a920fa2acdcab2b053c4b2838bd6b71"
+        );
+        assert!(!format!("{credentials:?}").contains("NhqPt"));
+    }
+}
@@ -0,0 +1,333 @@
+//! Order book reconstruction from the collector's raw recording files.
+//!
+//! The collector records depth diffs beside the snapshots that anchor them —
+//! Binance REST snapshots from the periodic and gap-triggered fetches,
+//! Bybit's `orderbook.N` subscribe snapshots, Hyperliquid's whole-book
+//! `l2Book` messages — so a symbol's series holds everything needed to rebuild
+//! its book. This tool replays one series through `collector::book` and
+//! prints, as JSON lines on stdout, the top levels of the book at every
+//! multiple of the sampling interval:
+//!
+//! ```text
+//! {"recv_ns":1718000001000000000,"bids":[["67000.1","0.5"]],"asks":[["67000.2","1.2"]]}
+//! ```
+//!
+//! A sample shows the book as it stood at that instant, so it is only printed
+//! once the next recorded update proves the book was still whole then. Every
+//! point where that proof fails is printed instead, in order with the samples:
+//!
+//! ```text
+//! {"detail":"update id 1043 does not follow 1040","flag":"gap","recv_ns":1718000002000000000}
+//! ```
+//!
+//! Flags are `gap`, `unanchored` (a snapshot that does not reach the diffs
+//! held for it), `crossed`, `malformed` and `unreadable` (a file that could
+//! not be decoded). No samples are printed from a flag until a snapshot
+//! anchors the book again. Times are the collector's receive times.
+
+use std::{
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+};
+
+use anyhow::{Context as _, Result, bail};
+use clap::Parser;
+use collector::{
+    book::{Book, BookBuilder, DepthContinuity, Market, Outcome},
+    replay::{self, Dictionaries, ReadError, Record},
+};
+use serde_json::json;
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Rebuild order books from the collector's raw recording files"
+)]
+struct Args {
+    /// Directory holding the symbol's <symbol>_<YYYYMMDD>[T<HHMM>[SS[_NNN]]].zst files.
+    dir: PathBuf,
+
+    /// Symbol to rebuild, as the collector names its files' symbols.
+    #[arg(long)]
+    symbol: String,
+
+    /// Exchange the files were recorded from, as named in the collector's config.
+    #[arg(long)]
+    exchange: String,
+
+    /// Levels per side in each sample.
+    #[arg(long, default_value_t = 10)]
+    levels: usize,
+
+    /// Sampling interval, in milliseconds.
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u64).range(1..))]
+    interval_ms: u64,
+
+    /// Bybit only: the `orderbook.N` depth to rebuild when several were
+    /// recorded. Defaults to the first one in the files.
+    #[arg(long)]
+    bybit_depth: Option<u32>,
+
+    /// A trained zstd dictionary the collector was run with. May be repeated.
+    #[arg(long)]
+    dictionary: Vec<PathBuf>,
+}
+
+fn market(exchange: &str, bybit_depth: Option<u32>) -> Result<Market> {
+    Ok(match exchange {
+        "binance" | "binancespot" => Market::Binance(DepthContinuity::FirstUpdateId),
+        "binancefutures" | "binancefuturesum" | "binancefuturescm" => {
+            Market::Binance(DepthContinuity::PrevUpdateId)
+        }
+        "bybit" | "bybitspot" | "bybitlinear" | "bybitinverse" => {
+            Market::Bybit { depth: bybit_depth }
+        }
+        "hyperliquid" => Market::Hyperliquid,
+        other => bail!("no order book rules for exchange {other:?}"),
+    })
+}
+
+/// What a replay printed.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Summary {
+    records: u64,
+    samples: u64,
+    flags: u64,
+}
+
+/// Replay `records` through `builder`, writing samples every `interval_ns`
+/// and flags to `out`.
+///
+/// A sample due at time `t` is the book after the last record received
+/// before `t`. It is captured when the first record at or after `t` arrives
+/// and printed only if that record extends the book: until then nothing shows
+/// the book had not already lost an update by `t`. A break discards the
+/// samples it leaves unproven, and a book that is not proven has no samples.
+fn replay(
+    records: impl Iterator<Item = Result<Record, ReadError>>,
+    builder: &mut BookBuilder,
+    levels: usize,
+    interval_ns: i64,
+    out: &mut impl Write,
+) -> io::Result<Summary> {
+    let mut summary = Summary::default();
+    // The next sample time not yet captured.
+    let mut next: Option<i64> = None;
+    let mut pending: Vec<(i64, String)> = Vec::new();
+
+    for record in records {
+        let record = match record {
+            Ok(record) => record,
+            Err(error) => {
+                // The rest of the file is lost, so nothing chains across it.
+                pending.clear();
+                next = None;
+                *builder = BookBuilder::new(builder.market());
+                summary.flags += 1;
+                flag(out, None, "unreadable", &error.to_string())?;
+                continue;
+            }
+        };
+        summary.records += 1;
+        let at = record.recv_time.as_nanosecond() as i64;
+
+        if let (Some(book), Some(mut due)) = (builder.book(), next)
+            && due < at
+        {
+            let top = top(book, levels);
+            while due < at {
+                pending.push((due, top.clone()));
+                due += interval_ns;
+            }
+            next = Some(due);
+        }
+
+        match builder.apply(&record.payload) {
+            Outcome::Applied => {
+                for (due, top) in pending.drain(..) {
+                    summary.samples += 1;
+                    writeln!(out, r#"{{"recv_ns":{due},{top}}}"#)?;
+                }
+            }
+            Outcome::Broken(broken) => {
+                pending.clear();
+                summary.flags += 1;
+                flag(out, Some(at), broken.label(), &broken.to_string())?;
+            }
+            Outcome::Ignored | Outcome::Buffered | Outcome::Synced => {}
+        }
+        next = match (builder.book(), next) {
+            (None, _) => None,
+            // Proven from this record on: the first sample is the next
+            // multiple of the interval after it.
+            (Some(_), None) => Some(at.div_euclid(interval_ns) * interval_ns + interval_ns),
+            (Some(_), next) => next,
+        };
+    }
+    out.flush()?;
+    Ok(summary)
+}
+
+/// `"bids":[...],"asks":[...]` for the top `levels` of each side.
+fn top(book: &Book, levels: usize) -> String {
+    let side = |levels: &mut dyn Iterator<Item = &collector::book::Level>| {
+        serde_json::Value::from_iter(levels.map(|level| json!([level.price, level.quantity])))
+    };
+    format!(
+        r#""bids":{},"asks":{}"#,
+        side(&mut book.bids().take(levels)),
+        side(&mut book.asks().take(levels))
+    )
+}
+
+fn flag(out: &mut impl Write, at: Option<i64>, kind: &str, detail: &str) -> io::Result<()> {
+    let line = json!({ "recv_ns": at, "flag": kind, "detail": detail });
+    writeln!(out, "{line}")
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let mut builder = BookBuilder::new(market(&args.exchange, args.bybit_depth)?);
+
+    let mut dictionaries = Dictionaries::new();
+    for path in &args.dictionary {
+        let dictionary =
+            std::fs::read(path).with_context(|| format!("cannot read {}", path.display()))?;
+        dictionaries
+            .insert(dictionary)
+            .with_context(|| format!("{} is not a trained zstd dictionary", path.display()))?;
+    }
+
+    let series = replay::series(&args.dir)
+        .with_context(|| format!("cannot list {}", args.dir.display()))?
+        .into_iter()
+        .find(|series| series.symbol == args.symbol)
+        .with_context(|| format!("no recordings of {} in {}", args.symbol, args.dir.display()))?;
+    eprintln!(
+        "rebuilding {} from {} file(s)",
+        series.symbol,
+        series.files.len()
+    );
+
+    let interval_ns = i64::try_from(args.interval_ms)? * 1_000_000;
+    let mut out = BufWriter::new(io::stdout().lock());
+    let summary = replay(
+        series.records(&dictionaries),
+        &mut builder,
+        args.levels,
+        interval_ns,
+        &mut out,
+    )?;
+    eprintln!(
+        "{} records, {} samples, {} flags",
+        summary.records, summary.samples, summary.flags
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use collector::record::Stream;
+    use jiff::Timestamp;
+
+    use super::*;
+
+    fn record(at_ms: i64, payload: &str) -> Result<Record, ReadError> {
+        Ok(Record {
+            recv_time: Timestamp::from_millisecond(at_ms).unwrap(),
+            symbol: Arc::from("BTC"),
+            connection: None,
+            stream: Stream::Other,
+            payload: payload.as_bytes().to_vec(),
+        })
+    }
+
+    fn l2(time: i64, bid: &str) -> String {
+        format!(
+            r#"{{"channel":"l2Book","data":{{"coin":"BTC","time":{time},"levels":[[{{"px":"{bid}","sz":"1","n":1}}],[{{"px":"101","sz":"2","n":1}}]]}}}}"#
+        )
+    }
+
+    fn futures_diff(first: i64, last: i64, prev: i64) -> String {
+        format!(
+            r#"{{"stream":"btcusdt@depth@100ms","data":{{"U":{first},"u":{last},"pu":{prev},"b":[["100","{last}"]],"a":[]}}}}"#
+        )
+    }
+
+    fn run(records: Vec<Result<Record, ReadError>>, market: Market) -> (Vec<String>, Summary) {
+        let mut builder = BookBuilder::new(market);
+        let mut out = Vec::new();
+        let summary = replay(
+            records.into_iter(),
+            &mut builder,
+            1,
+            1_000_000_000,
+            &mut out,
+        )
+        .expect("write to a Vec");
+        let lines = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(str::to_owned)
+            .collect();
+        (lines, summary)
+    }
+
+    #[test]
+    fn samples_wait_for_the_next_update_to_prove_them() {
+        let futures = Market::Binance(DepthContinuity::PrevUpdateId);
+        let snapshot = r#"{"lastUpdateId":10,"bids":[["100","1"]],"asks":[["101","1"]]}"#;
+        let (lines, summary) = run(
+            vec![
+                record(500, snapshot),
+                record(900, &futures_diff(9, 11, 8)),
+                // Proves the book at 1s and 2s.
+                record(2_100, &futures_diff(12, 12, 11)),
+                // A hole: the 3s sample is never printed.
+                record(3_200, &futures_diff(15, 15, 14)),
+                record(3_400, &futures_diff(16, 16, 15)),
+            ],
+            futures,
+        );
+        assert_eq!(
+            lines,
+            [
+                r#"{"recv_ns":1000000000,"bids":[["100","11"]],"asks":[["101","1"]]}"#,
+                r#"{"recv_ns":2000000000,"bids":[["100","11"]],"asks":[["101","1"]]}"#,
+                r#"{"detail":"update id 14 does not follow 12","flag":"gap","recv_ns":3200000000}"#,
+            ]
+        );
+        assert_eq!(
+            summary,
+            Summary {
+                records: 5,
+                samples: 2,
+                flags: 1
+            }
+        );
+    }
+
+    #[test]
+    fn whole_books_prove_the_samples_before_them() {
+        let (lines, summary) = run(
+            vec![
+                record(0, &l2(1, "99")),
+                record(1_500, &l2(2, "100")),
+                // A lagging connection's copy proves nothing.
+                record(2_200, &l2(1, "99")),
+                record(2_500, &l2(3, "100.5")),
+            ],
+            Market::Hyperliquid,
+        );
+        assert_eq!(
+            lines,
+            [
+                r#"{"recv_ns":1000000000,"bids":[["99","1"]],"asks":[["101","2"]]}"#,
+                r#"{"recv_ns":2000000000,"bids":[["100","1"]],"asks":[["101","2"]]}"#,
+            ]
+        );
+        assert_eq!(summary.samples, 2);
+    }
+}
@@ -1,11 +1,14 @@
 //! Gap detector for the collector's raw recording files.
 //!
-//! Raw files are zstd-compressed `<recv_ns> <json>` lines, one file per
-//! symbol per UTC day (`<symbol>_<YYYYMMDD>.zst`). This tool scans a data
+//! Raw files are zstd-compressed `<recv_ns> <json>` lines or the binary
+//! records of `--format binary` (both may share a file), one file per symbol
+//! per rotation period: `<symbol>_<YYYYMMDD>.zst` per UTC day, or
+//! `<symbol>_<YYYYMMDD>T<HHMM>.zst` / `<symbol>_<YYYYMMDD>T<HHMMSS>[_NNN].zst`
+//! under the hourly, N-minute and size-based policies. This tool scans a data
 //! directory, groups the files into per-symbol series, and reports:
 //!
 //! * receive-time gaps above a threshold and receive-time regressions,
-//!   including across the midnight file rotation;
+//!   including across file rotations;
 //! * venue sequence breaks where the payload carries ids. Redundant
 //!   connections deliver events in order but can interleave across
 //!   connections, so the chain tracks `max(last, id)` and only forward
@@ -26,13 +29,12 @@
 //!   sister sbe-collector's are detected and skipped).
 //!
 //! Sequence state is kept per stream *across* files of a series, so breaks
-//! straddling the rotation boundary are attributed correctly.
+//! straddling the rotation boundary are attributed correctly. Files are found
+//! and decoded through `collector::replay`, the same reader research code uses.
 
 use std::{
     collections::{BTreeMap, BTreeSet, HashSet},
-    fmt,
-    fs::{self, File},
-    io::{BufRead, BufReader},
+    fmt, fs,
     path::{Path, PathBuf},
     sync::{
         Mutex,
@@ -43,6 +45,10 @@ use std::{
 
 use anyhow::{Context as _, Result};
 use clap::Parser;
+use collector::{
+    record,
+    replay::{self, Dictionaries, RecordingFile},
+};
 use jiff::{Span, Timestamp, civil, tz::TimeZone};
 use serde::Deserialize;
 use serde::de::{self, IgnoredAny, SeqAccess, Visitor};
@@ -59,7 +65,7 @@ const MAX_BREAK_TIMES: usize = 16;
 #[derive(Parser)]
 #[command(version, about = "Detect gaps in the collector's raw recording files")]
 struct Args {
-    /// Directories to scan recursively for <symbol>_<YYYYMMDD>.zst files.
+    /// Directories to scan recursively for <symbol>_<YYYYMMDD>[T<HHMM>[SS[_NNN]]].zst files.
     #[arg(default_values = ["."])]
     paths: Vec<PathBuf>,
 
@@ -91,6 +97,11 @@ struct Args {
     #[arg(long)]
     exact: bool,
 
+    /// A trained zstd dictionary the collector was run with. May be repeated;
+    /// each file is decoded with the one its frames name.
+    #[arg(long)]
+    dictionary: Vec<PathBuf>,
+
     /// Exit with status 1 if any gap, sequence break, or decode error is found.
     #[arg(long)]
     fail_on_gaps: bool,
@@ -158,21 +169,16 @@ impl Family {
     }
 }
 
-struct DatedFile {
-    path: PathBuf,
-    date: civil::Date,
-}
-
 struct Series {
     key: String,
     family: Family,
-    files: Vec<DatedFile>,
+    files: Vec<RecordingFile>,
 }
 
-/// Discover `<symbol>_<YYYYMMDD>.zst` files under the roots and group them
-/// into per-(directory, symbol) series sorted by date.
+/// Discover the collector's files under the roots and group them into
+/// per-(directory, symbol) series sorted by start time.
 fn discover(roots: &[PathBuf]) -> Result<Vec<Series>> {
-    let mut map: BTreeMap<(PathBuf, String), Vec<DatedFile>> = BTreeMap::new();
+    let mut map: BTreeMap<(PathBuf, String), Vec<RecordingFile>> = BTreeMap::new();
     let mut stack: Vec<PathBuf> = Vec::new();
     for root in roots {
         let meta =
@@ -185,14 +191,11 @@ fn discover(roots: &[PathBuf]) -> Result<Vec<Series>> {
     while let Some(dir) = stack.pop() {
         let meta = fs::metadata(&dir)?;
         if meta.is_file() {
-            if let Some(df) = dated_file(&dir) {
+            if let Some(file) = RecordingFile::from_path(&dir) {
                 let parent = dir.parent().unwrap_or(Path::new(".")).to_path_buf();
-                let stem = df.path.file_stem().unwrap().to_string_lossy().into_owned();
-                let symbol = stem
-                    .rsplit_once('_')
-                    .map(|(sym, _)| sym.to_string())
-                    .unwrap_or(stem);
-                map.entry((parent, symbol)).or_default().push(df);
+                map.entry((parent, file.symbol.clone()))
+                    .or_default()
+                    .push(file);
             } else {
                 skipped += 1;
             }
@@ -206,13 +209,15 @@ fn discover(roots: &[PathBuf]) -> Result<Vec<Series>> {
         }
     }
     if skipped > 0 {
-        eprintln!("note: skipped {skipped} .zst file(s) not named <symbol>_<YYYYMMDD>.zst");
+        eprintln!(
+            "note: skipped {skipped} .zst file(s) not named <symbol>_<YYYYMMDD>[T<HHMM>[SS[_NNN]]].zst"
+        );
     }
 
     let mut series: Vec<Series> = map
         .into_iter()
         .map(|((dir, symbol), mut files)| {
-            files.sort_by_key(|f| f.date);
+            files.sort();
             Series {
                 key: format!("{}/{symbol}", dir.display()),
                 family: Family::guess(&dir),
@@ -224,19 +229,6 @@ fn discover(roots: &[PathBuf]) -> Result<Vec<Series>> {
     Ok(series)
 }
 
-fn dated_file(path: &Path) -> Option<DatedFile> {
-    if path.extension().is_none_or(|ext| ext != "zst") {
-        return None;
-    }
-    let stem = path.file_stem()?.to_str()?;
-    let (_, date_str) = stem.rsplit_once('_')?;
-    let date = civil::Date::strptime("%Y%m%d", date_str).ok()?;
-    Some(DatedFile {
-        path: path.to_path_buf(),
-        date,
-    })
-}
-
 // ---------------------------------------------------------------------------
 // Payload parsing
 // ---------------------------------------------------------------------------
@@ -475,14 +467,13 @@ impl SeriesScan {
         self.last_recv = Some(recv);
     }
 
-    fn process_line(&mut self, family: Family, line: &[u8], min_gap_ns: i64) -> LineOutcome {
-        let Some(space) = line.iter().position(|&b| b == b' ') else {
-            return LineOutcome::Bad;
-        };
-        let Some(recv) = parse_int(&line[..space]) else {
-            return LineOutcome::Bad;
-        };
-        let payload = &line[space + 1..];
+    fn process_record(
+        &mut self,
+        family: Family,
+        recv: i64,
+        payload: &[u8],
+        min_gap_ns: i64,
+    ) -> LineOutcome {
         if payload.first() != Some(&b'{') {
             return LineOutcome::Bad;
         }
@@ -736,24 +727,12 @@ fn observe_point_id(st: &mut StreamState, id: Option<u64>) {
     }
 }
 
-fn parse_int(bytes: &[u8]) -> Option<i64> {
-    if bytes.is_empty() {
-        return None;
-    }
-    let mut value: i64 = 0;
-    for &b in bytes {
-        let digit = (b as char).to_digit(10)?;
-        value = value.checked_mul(10)?.checked_add(digit as i64)?;
-    }
-    Some(value)
-}
-
 // ---------------------------------------------------------------------------
 // Reports
 // ---------------------------------------------------------------------------
 
 struct FileReport {
-    date: civil::Date,
+    stamp: String,
     rows: u64,
     foreign: bool,
     decode_error: Option<String>,
@@ -789,48 +768,73 @@ impl SeriesReport {
     }
 }
 
-fn scan_file(scan: &mut SeriesScan, family: Family, df: &DatedFile, min_gap_ns: i64) -> FileReport {
+fn load_dictionaries(paths: &[PathBuf]) -> Result<Dictionaries> {
+    let mut dictionaries = Dictionaries::new();
+    for path in paths {
+        let dictionary =
+            fs::read(path).with_context(|| format!("cannot read {}", path.display()))?;
+        dictionaries
+            .insert(dictionary)
+            .with_context(|| format!("{} is not a trained zstd dictionary", path.display()))?;
+    }
+    Ok(dictionaries)
+}
+
+fn scan_file(
+    scan: &mut SeriesScan,
+    family: Family,
+    file: &RecordingFile,
+    min_gap_ns: i64,
+    dictionaries: &Dictionaries,
+) -> FileReport {
     let mut report = FileReport {
-        date: df.date,
+        stamp: file.stamp.clone(),
         rows: 0,
         foreign: false,
         decode_error: None,
         live: false,
     };
-    let file = match File::open(&df.path) {
-        Ok(file) => file,
+    let modified = fs::metadata(&file.path).and_then(|m| m.modified()).ok();
+    let mut reader = match replay::open(&file.path, dictionaries) {
+        Ok(reader) => reader,
+        Err(replay::Error::MissingDictionary(id)) => {
+            report.decode_error = Some(format!(
+                "needs zstd dictionary {id}; pass it with --dictionary"
+            ));
+            return report;
+        }
         Err(error) => {
-            report.decode_error = Some(format!("open failed: {error}"));
+            report.decode_error = Some(error.to_string());
             return report;
         }
     };
-    let modified = fs::metadata(&df.path).and_then(|m| m.modified()).ok();
-    let Ok(decoder) = zstd::stream::read::Decoder::new(file) else {
-        report.decode_error = Some("not a zstd stream".into());
-        return report;
-    };
-    let mut reader = BufReader::with_capacity(1 << 20, decoder);
-    let mut line: Vec<u8> = Vec::with_capacity(1 << 16);
+    let mut payload: Vec<u8> = Vec::with_capacity(1 << 16);
     loop {
-        line.clear();
-        match reader.read_until(b'\n', &mut line) {
-            Ok(0) => break,
-            Ok(_) => {
-                if line.last() == Some(&b'\n') {
-                    line.pop();
-                }
-                if line.is_empty() {
-                    continue;
-                }
-                match scan.process_line(family, &line, min_gap_ns) {
+        match reader.read(&mut payload) {
+            Ok(None) => break,
+            Ok(Some(header)) => {
+                match scan.process_record(family, header.recv_ns, &payload, min_gap_ns) {
                     LineOutcome::Ok => report.rows += 1,
-                    LineOutcome::Bad if report.rows == 0 && is_foreign(&line) => {
+                    LineOutcome::Bad if report.rows == 0 && is_foreign(&payload) => {
                         report.foreign = true;
                         break;
                     }
                     LineOutcome::Bad => scan.bad_lines += 1,
                 }
             }
+            // A text line without a receive time; the reader has moved past it.
+            Err(error) if record::is_malformed(&error) && !payload.is_empty() => {
+                if report.rows == 0 && is_foreign(&payload) {
+                    report.foreign = true;
+                    break;
+                }
+                scan.bad_lines += 1;
+            }
+            // A first byte that looked like a binary record but was not one.
+            Err(error) if record::is_malformed(&error) && report.rows == 0 => {
+                report.foreign = true;
+                break;
+            }
             Err(error) => {
                 report.live = modified.is_some_and(|m| {
                     SystemTime::now()
@@ -860,21 +864,32 @@ fn is_foreign(line: &[u8]) -> bool {
     printable * 2 < window.len()
 }
 
-fn scan_series(series: &Series, min_gap_ns: i64, exact: bool) -> SeriesReport {
+fn scan_series(
+    series: &Series,
+    min_gap_ns: i64,
+    exact: bool,
+    dictionaries: &Dictionaries,
+) -> SeriesReport {
     let mut scan = SeriesScan {
         exact,
         ..Default::default()
     };
     let mut files = Vec::with_capacity(series.files.len());
-    for df in &series.files {
-        files.push(scan_file(&mut scan, series.family, df, min_gap_ns));
+    for file in &series.files {
+        files.push(scan_file(
+            &mut scan,
+            series.family,
+            file,
+            min_gap_ns,
+            dictionaries,
+        ));
     }
 
     let mut missing_dates = Vec::new();
     if let (Some(first), Some(last)) = (series.files.first(), series.files.last()) {
-        let present: BTreeSet<civil::Date> = series.files.iter().map(|f| f.date).collect();
-        let mut date = first.date;
-        while date < last.date {
+        let present: BTreeSet<civil::Date> = series.files.iter().map(|f| f.date()).collect();
+        let mut date = first.date();
+        while date < last.date() {
             date = date
                 .checked_add(Span::new().days(1))
                 .expect("date range is tiny");
@@ -1027,26 +1042,26 @@ fn stream_notable(s: &StreamStats) -> bool {
 
 fn print_report(report: &SeriesReport, min_gap_ns: i64, max_reported: usize, exact: bool) {
     println!("{} ({})", report.key, report.family.label());
-    let dates: Vec<String> = report.files.iter().map(|f| f.date.to_string()).collect();
+    let dates: Vec<String> = report.files.iter().map(|f| f.stamp.clone()).collect();
     println!("  files: {}", dates.join(", "));
     for file in &report.files {
         if file.foreign {
             println!(
                 "    {}: foreign format (not `<recv_ns> <json>` lines), skipped",
-                file.date
+                file.stamp
             );
         }
         if let Some(error) = &file.decode_error {
             if file.live {
                 println!(
                     "    {}: unterminated zstd stream after {} rows (file modified recently — still being written?): {error}",
-                    file.date,
+                    file.stamp,
                     grouped(file.rows)
                 );
             } else {
                 println!(
                     "    {}: decode error after {} rows: {error}",
-                    file.date,
+                    file.stamp,
                     grouped(file.rows)
                 );
             }
@@ -1131,6 +1146,7 @@ fn print_report(report: &SeriesReport, min_gap_ns: i64, max_reported: usize, exa
 fn main() -> Result<()> {
     let args = Args::parse();
     let min_gap_ns = (args.min_gap.max(0.0) * 1e9) as i64;
+    let dictionaries = load_dictionaries(&args.dictionary)?;
 
     let mut series = discover(&args.paths)?;
     if let Some(filter) = &args.filter {
@@ -1138,7 +1154,7 @@ fn main() -> Result<()> {
     }
     if series.is_empty() {
         println!(
-            "no <symbol>_<YYYYMMDD>.zst files found under: {}",
+            "no <symbol>_<YYYYMMDD>[T<HHMM>[SS[_NNN]]].zst files found under: {}",
             args.paths
                 .iter()
                 .map(|p| p.display().to_string())
@@ -1184,7 +1200,7 @@ fn main() -> Result<()> {
                         break;
                     }
                     let started = std::time::Instant::now();
-                    let report = scan_series(&series[idx], min_gap_ns, args.exact);
+                    let report = scan_series(&series[idx], min_gap_ns, args.exact, &dictionaries);
                     let finished = done.fetch_add(1, Ordering::Relaxed) + 1;
                     eprintln!(
                         "[{finished}/{total}] {} ({} rows, {:.1}s)",
@@ -1300,13 +1316,138 @@ mod tests {
     use super::*;
 
     fn scan_line(scan: &mut SeriesScan, family: Family, recv: i64, json: &str) {
-        let line = format!("{recv} {json}");
         assert!(matches!(
-            scan.process_line(family, line.as_bytes(), 5_000_000_000),
+            scan.process_record(family, recv, json.as_bytes(), 5_000_000_000),
             LineOutcome::Ok
         ));
     }
 
+    #[test]
+    fn hourly_files_form_one_series_in_time_order() {
+        let dir =
+            std::env::temp_dir().join(format!("gap-detector-discover-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "btcusdt_20240101T1000.zst",
+            "btcusdt_20240101.zst",
+            "btcusdt_20240101T0900.zst",
+            "btcusdt_20240102T000000.zst",
+        ] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let series = discover(std::slice::from_ref(&dir)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(series.len(), 1);
+        let stamps: Vec<&str> = series[0].files.iter().map(|f| f.stamp.as_str()).collect();
+        assert_eq!(
+            stamps,
+            [
+                "20240101",
+                "20240101T0900",
+                "20240101T1000",
+                "20240102T000000"
+            ]
+        );
+    }
+
+    #[test]
+    fn files_written_with_a_dictionary_need_it() {
+        let samples: Vec<String> = (0..2000)
+            .map(|i| {
+                format!(
+                    r#"{{"stream":"btcusdt@aggTrade","data":{{"a":{i},"p":"{}.5"}}}}"#,
+                    60_000 + i % 89
+                )
+            })
+            .collect();
+        let dictionary = zstd::dict::from_samples(&samples, 4096).unwrap();
+        let mut encoder =
+            zstd::stream::write::Encoder::with_dictionary(Vec::new(), 3, &dictionary).unwrap();
+        std::io::Write::write_all(&mut encoder, b"1 {}\n").unwrap();
+        let dir = std::env::temp_dir().join(format!("gap-detector-dict-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("btcusdt_20240101.zst");
+        fs::write(&path, encoder.finish().unwrap()).unwrap();
+        let dictionary_path = dir.join("trades.dict");
+        fs::write(&dictionary_path, &dictionary).unwrap();
+        let file = RecordingFile::from_path(&path).unwrap();
+
+        let scan = |dictionaries: &Dictionaries| {
+            scan_file(
+                &mut SeriesScan::default(),
+                Family::Generic,
+                &file,
+                5_000_000_000,
+                dictionaries,
+            )
+        };
+        let missing = scan(&Dictionaries::new());
+        let found = scan(&load_dictionaries(std::slice::from_ref(&dictionary_path)).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(
+            missing
+                .decode_error
+                .is_some_and(|error| error.contains("--dictionary"))
+        );
+        assert_eq!((found.rows, found.decode_error), (1, None));
+    }
+
+    #[test]
+    fn binary_and_text_records_scan_alike() {
+        let mut raw = Vec::new();
+        for (format, recv, stream, json) in [
+            (
+                record::Format::Text,
+                1_000_000_000,
+                record::Stream::Other,
+                "{}",
+            ),
+            (
+                record::Format::Binary,
+                2_000_000_000,
+                record::Stream::Trade,
+                "{\"a\":\n1}",
+            ),
+            (
+                record::Format::Binary,
+                9_000_000_000,
+                record::Stream::Trade,
+                "{}",
+            ),
+        ] {
+            let header = record::Header {
+                recv_ns: recv,
+                connection: Some(0),
+                stream,
+            };
+            record::encode(&mut raw, format, &header, json.as_bytes());
+        }
+        let path = std::env::temp_dir()
+            .join(format!("gap-detector-binary-{}", std::process::id()))
+            .join("btcusdt_20240101.zst");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, zstd::encode_all(raw.as_slice(), 1).unwrap()).unwrap();
+
+        let mut scan = SeriesScan::default();
+        let file = RecordingFile::from_path(&path).unwrap();
+        let report = scan_file(
+            &mut scan,
+            Family::Generic,
+            &file,
+            5_000_000_000,
+            &Dictionaries::new(),
+        );
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        assert_eq!(report.rows, 3);
+        assert!(!report.foreign && report.decode_error.is_none());
+        assert_eq!(scan.bad_lines, 0);
+        assert_eq!(scan.gaps.len(), 1, "the 7 s hole between the binary rows");
+    }
+
     #[test]
     fn recv_gap_and_regression() {
         let mut scan = SeriesScan::default();
@@ -1436,13 +1577,13 @@ mod tests {
 
     #[test]
     fn foreign_first_line_is_detected() {
-        let mut scan = SeriesScan::default();
         let binary: Vec<u8> = vec![0u8, 1, 2, 3, 255, 254, 253, 252];
-        assert!(matches!(
-            scan.process_line(Family::Generic, &binary, 5_000_000_000),
-            LineOutcome::Bad
-        ));
-        assert!(is_foreign(&binary));
+        let mut payload = Vec::new();
+        let error = record::Reader::new(binary.as_slice())
+            .read(&mut payload)
+            .unwrap_err();
+        assert!(record::is_malformed(&error));
+        assert!(is_foreign(&payload));
         assert!(!is_foreign(b"123 {\"a\":1}"));
     }
 
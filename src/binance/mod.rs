@@ -1,10 +1,11 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
-use tokio::sync::{mpsc::Sender, watch};
+use tokio::sync::watch;
 
 use crate::{
-    binance_market::{self, DepthContinuity, Endpoint},
-    file::WriteRecord,
+    account::Credentials,
+    binance_market::{self, DepthContinuity, Endpoint, UserDataUrls},
+    file::RecordSender,
 };
 
 static ENDPOINT: Endpoint = Endpoint {
@@ -15,22 +16,37 @@ static ENDPOINT: Endpoint = Endpoint {
     // total silence is dead with margin to spare.
     idle_timeout: Duration::from_secs(75),
     depth_continuity: DepthContinuity::FirstUpdateId,
+    funding_urls: None,
+    user_data: Some(UserDataUrls {
+        listen_key: "https://api.binance.com/api/v3/userDataStream",
+        ws_url: "wss://stream.binance.com:9443/ws/",
+    }),
 };
 
+#[allow(clippy::too_many_arguments)]
 pub async fn run_collection(
+    venue: Arc<str>,
     streams: Vec<String>,
-    symbols: Vec<String>,
-    writer_tx: Sender<WriteRecord>,
+    symbols: watch::Receiver<Vec<String>>,
+    writer_tx: RecordSender,
     shutdown: watch::Receiver<bool>,
     connections: usize,
+    snapshot_interval: Duration,
+    account: Option<Credentials>,
+    subscribe: bool,
 ) -> Result<(), anyhow::Error> {
     binance_market::run_collection(
+        venue,
         &ENDPOINT,
         streams,
         symbols,
         writer_tx,
         shutdown,
         connections,
+        snapshot_interval,
+        None,
+        account,
+        subscribe,
     )
     .await
 }
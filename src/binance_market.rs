@@ -10,12 +10,15 @@ use std::{
     collections::HashMap,
     io,
     io::ErrorKind,
+    sync::Arc,
     time::{Duration, Instant},
 };
 
-use anyhow::Error;
+use anyhow::{Context, Error, anyhow, bail};
 use fastwebsockets::OpCode;
 use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
 use tokio::{
     sync::{
         mpsc::{Sender, channel},
@@ -24,17 +27,24 @@ use tokio::{
     task::JoinSet,
     time::timeout,
 };
-use tracing::{error, warn};
+use tracing::{error, info, warn};
+
+pub use collector::book::DepthContinuity;
+use collector::record::Stream;
 
 use crate::{
+    account::{self, Credentials},
     dedup::Dedup,
     error::ConnectorError,
     feed::Feed,
-    file::WriteRecord,
-    routing::BinanceMessage,
+    file::{RecordSender, WriteRecord},
+    metrics,
+    reload::SymbolDiff,
+    routing::{BinanceArrayMessage, BinanceMessage, BinanceSymbol},
+    spool::Spool,
     symbol::{Symbol, SymbolCache},
     throttler::Throttler,
-    ws::{self, Delivery, Overflow},
+    ws::{self, Delivery, FrameSender, Overflow, Priority},
 };
 
 /// Minimum spacing between snapshot requests.
@@ -52,6 +62,22 @@ const SNAPSHOT_PACE: Duration = Duration::from_secs(2);
 /// burning a round every time it comes up.
 const SNAPSHOT_START_DELAY: Duration = Duration::from_secs(5);
 
+/// Default spacing of the periodic depth snapshot rounds.
+pub const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Minimum spacing between open interest and premium index requests.
+const FUNDING_PACE: Duration = Duration::from_secs(1);
+
+/// Requests of the throttler's 100 a minute that funding polls leave free.
+///
+/// Funding shares the budget with the depth snapshots, and at its pace alone
+/// would take 60 of it; with a periodic round under way, little would be left
+/// for the snapshots that repair a gap. Those cannot wait and funding can, so
+/// a funding request waits while the window has no more than this left. The
+/// periodic rounds take 30 a minute at most, which leaves the gap snapshots
+/// this many whatever the polls are doing.
+const GAP_SNAPSHOT_RESERVE: usize = 40;
+
 /// How far a depth update id may fall below the last one seen before the
 /// stream is treated as restarted rather than merely reordered.
 ///
@@ -182,6 +208,9 @@ enum Event {
     /// Open the replacement now. The session keeps delivering until that
     /// replacement is live or the venue closes the socket, whichever is first.
     Relieve(SessionId, Reason),
+    /// The venue answered one of this session's requests; see
+    /// [`Subscriptions`].
+    Response(SessionId, Response),
 }
 
 /// How a session ended.
@@ -249,15 +278,6 @@ impl Handover {
     }
 }
 
-/// How a market's depth stream signals that an update was missed.
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
-pub enum DepthContinuity {
-    /// Spot: each update's `U` must be the previous update's `u` plus one.
-    FirstUpdateId,
-    /// Futures: each update carries the previous update id in `pu`.
-    PrevUpdateId,
-}
-
 /// Static description of one Binance market.
 pub struct Endpoint {
     pub label: &'static str,
@@ -270,6 +290,82 @@ pub struct Endpoint {
     /// than this means the connection is dead.
     pub idle_timeout: Duration,
     pub depth_continuity: DepthContinuity,
+    /// Open interest and premium index REST URL prefixes; `None` on spot,
+    /// which has neither.
+    pub funding_urls: Option<FundingUrls>,
+    /// Where the account's user data stream is opened; `None` on an endpoint
+    /// that only carries market data.
+    pub user_data: Option<UserDataUrls>,
+}
+
+impl Endpoint {
+    /// The combined-stream endpoint without a stream list, for sessions that
+    /// subscribe by request.
+    fn bare_url(&self) -> &'static str {
+        self.ws_stream_url
+            .split_once('?')
+            .map_or(self.ws_stream_url, |(bare, _)| bare)
+    }
+}
+
+/// REST URL prefixes of a futures market's funding data; the uppercased
+/// symbol is appended.
+pub struct FundingUrls {
+    pub open_interest: &'static str,
+    /// Mark price, index price and the funding rate.
+    pub premium_index: &'static str,
+}
+
+/// Where a market's user data stream comes from.
+pub struct UserDataUrls {
+    /// REST URL that creates a listen key (`POST`) and extends it (`PUT`).
+    pub listen_key: &'static str,
+    /// Websocket URL prefix; the listen key is appended.
+    pub ws_url: &'static str,
+}
+
+/// A listen key lapses an hour after it was created or last extended; half
+/// that leaves a failed extension time to be retried.
+const LISTEN_KEY_KEEPALIVE: Duration = Duration::from_secs(30 * 60);
+/// How long to wait before retrying an extension the throttler refused.
+const LISTEN_KEY_RETRY: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListenKey {
+    listen_key: String,
+}
+
+/// Count a depth snapshot request by how it ended: `ok`, `error`, or
+/// `rate_limited` when the throttler refused to send it at all. Per venue as
+/// well as per endpoint, since two venues on one exchange share the endpoint.
+fn count_snapshot(venue: &str, endpoint: &Endpoint, outcome: &str) {
+    metrics::counter(
+        "collector_depth_snapshots_total",
+        "REST depth snapshot requests by outcome.",
+        &[
+            ("venue", venue),
+            ("endpoint", endpoint.label),
+            ("outcome", outcome),
+        ],
+    )
+    .inc();
+}
+
+/// Count an open interest or premium index request by how it ended, like
+/// [`count_snapshot`].
+fn count_funding(venue: &str, endpoint: &Endpoint, request: &str, outcome: &str) {
+    metrics::counter(
+        "collector_funding_requests_total",
+        "REST funding and open interest requests by outcome.",
+        &[
+            ("venue", venue),
+            ("endpoint", endpoint.label),
+            ("request", request),
+            ("outcome", outcome),
+        ],
+    )
+    .inc();
 }
 
 pub async fn fetch_depth_snapshot(
@@ -278,15 +374,30 @@ pub async fn fetch_depth_snapshot(
     throttler: &Throttler,
     symbol: &str,
 ) -> Result<bytes::Bytes, anyhow::Error> {
-    let response = client
-        .get(format!(
-            "{}{}&limit=1000",
-            endpoint.depth_url,
-            symbol.to_uppercase()
-        ))
-        .header("Accept", "application/json")
-        .send()
-        .await?;
+    let url = format!("{}{}&limit=1000", endpoint.depth_url, symbol.to_uppercase());
+    fetch(endpoint, client, throttler, &url, "depth snapshot").await
+}
+
+/// GET `url`, noting a ban in `throttler` if the reply is one.
+async fn fetch(
+    endpoint: &Endpoint,
+    client: &reqwest::Client,
+    throttler: &Throttler,
+    url: &str,
+    what: &str,
+) -> Result<bytes::Bytes, anyhow::Error> {
+    let request = client.get(url).header("Accept", "application/json");
+    send(endpoint, throttler, request, what).await
+}
+
+/// Send `request`, noting a ban in `throttler` if the reply is one.
+async fn send(
+    endpoint: &Endpoint,
+    throttler: &Throttler,
+    request: reqwest::RequestBuilder,
+    what: &str,
+) -> Result<bytes::Bytes, anyhow::Error> {
+    let response = request.send().await?;
     let status = response.status();
     // Headers must be read before the body consumes the response.
     let retry_after = response
@@ -308,7 +419,7 @@ pub async fn fetch_depth_snapshot(
         // an invalid symbol from an IP ban.
         let preview = &body[..body.len().min(1024)];
         anyhow::bail!(
-            "{} depth snapshot returned {status}: {}",
+            "{} {what} returned {status}: {}",
             endpoint.label,
             String::from_utf8_lossy(preview)
         );
@@ -327,20 +438,31 @@ struct Drained {
     shed: usize,
 }
 
+/// A frame with the connection it arrived on, so the recording can say which
+/// redundant connection delivered the copy that was kept.
+type Frame = (usize, Timestamp, bytes::Bytes);
+
 /// Hand one market-data frame to the queue.
 ///
 /// Shared by the steady-state read loop and the retire drain so the two cannot
 /// drift on how frames are stamped or classified.
 async fn deliver_frame(
-    ws_tx: &Sender<(Timestamp, bytes::Bytes)>,
+    ws_tx: &Sender<Frame>,
     overflow: &mut Overflow,
+    connection: usize,
     payload: bytes::Bytes,
 ) -> Delivery {
     let recv_time = Timestamp::now();
-    // Combined streams carry no subscription responses — the stream list is in
-    // the URL — so every frame here is market data and may be shed if the
-    // writer falls behind.
-    ws::deliver(ws_tx, overflow, (recv_time, payload), |_| true).await
+    // A live session sieves out the answers to its requests, and `handle`
+    // ignores any a retiring one drains, so every frame is market data and may
+    // be shed if the writer falls behind, by the class its stream name gives.
+    ws::deliver(
+        ws_tx,
+        overflow,
+        (connection, recv_time, payload),
+        |(_, _, payload)| Priority::of(stream_of(ws::string_field(payload, "stream"))),
+    )
+    .await
 }
 
 /// Deliver what has already arrived on a socket that is about to be closed.
@@ -368,8 +490,9 @@ async fn deliver_frame(
 /// cancelled drain would otherwise report nothing at all.
 async fn drain_buffered<S>(
     conn: &mut ws::Connection<S>,
-    ws_tx: &Sender<(Timestamp, bytes::Bytes)>,
+    ws_tx: &Sender<Frame>,
     overflow: &mut Overflow,
+    connection: usize,
     deadline: Instant,
 ) -> Drained
 where
@@ -383,8 +506,8 @@ where
         if message.opcode != OpCode::Text {
             continue;
         }
-        match deliver_frame(ws_tx, overflow, message.payload).await {
-            Delivery::Sent => drained.delivered += 1,
+        match deliver_frame(ws_tx, overflow, connection, message.payload).await {
+            Delivery::Sent | Delivery::Spooled => drained.delivered += 1,
             Delivery::Dropped => drained.shed += 1,
             Delivery::Closed | Delivery::Undeliverable => break,
         }
@@ -406,16 +529,17 @@ async fn run_session<S>(
     id: SessionId,
     endpoint: &'static Endpoint,
     connection: usize,
-    ws_tx: Sender<(Timestamp, bytes::Bytes)>,
+    ws_tx: Sender<Frame>,
     events: tokio::sync::mpsc::Sender<Event>,
     mut retire: tokio::sync::oneshot::Receiver<()>,
     max_age: Duration,
+    spool: Option<Arc<Spool>>,
 ) -> SessionEnd
 where
     S: tokio::io::AsyncRead + Unpin,
 {
     let sender = conn.sender();
-    let mut overflow = Overflow::new(endpoint.label);
+    let mut overflow = Overflow::new(endpoint.label).with_spool(spool);
     let opened = Instant::now();
     let mut live = false;
     let mut relieved = false;
@@ -436,7 +560,7 @@ where
                 // hand back, under exactly the conditions where slots are
                 // scarce.
                 let drained =
-                    drain_buffered(&mut conn, &ws_tx, &mut overflow, Instant::now() + DRAIN_GRACE)
+                    drain_buffered(&mut conn, &ws_tx, &mut overflow, connection, Instant::now() + DRAIN_GRACE)
                         .await;
                 // Hand the slot back rather than dropping the socket: this IP
                 // has a limited number of them, and a handover deliberately
@@ -510,9 +634,16 @@ where
                     }
                     continue;
                 }
+                // Answers go to the supervisor that sent the requests, and are
+                // never shed: a lost rejection would leave its streams
+                // unsubscribed with nothing to retry them.
+                if let Some(response) = response(&message.payload) {
+                    let _ = events.send(Event::Response(id, response)).await;
+                    continue;
+                }
 
-                match deliver_frame(&ws_tx, &mut overflow, message.payload).await {
-                    Delivery::Sent | Delivery::Dropped => {
+                match deliver_frame(&ws_tx, &mut overflow, connection, message.payload).await {
+                    Delivery::Sent | Delivery::Spooled | Delivery::Dropped => {
                         // Liveness is a property of the *socket* — this session
                         // is connected and reading — not of the consumer. A shed
                         // frame proves the socket just as well, and under
@@ -625,20 +756,106 @@ fn cut_event_time(payload: &[u8]) -> Option<(&[u8], &[u8])> {
     Some((&payload[..pos], &payload[end..]))
 }
 
+/// The record stream tag for a combined-stream name such as `btcusdt@depth@100ms`.
+///
+/// Partial book streams (`depth5`, `depth20`, ...) push the top of the book
+/// whole on every update, so they are snapshots rather than diffs. An
+/// all-market stream such as `!markPrice@arr` has no symbol and names its kind
+/// first.
+fn stream_of(stream: Option<&str>) -> Stream {
+    let kind = match stream {
+        Some(stream) if stream.starts_with('!') => stream[1..].split('@').next(),
+        Some(stream) => stream.split('@').nth(1),
+        None => None,
+    };
+    let Some(kind) = kind else {
+        return Stream::Other;
+    };
+    match kind {
+        "depth" => Stream::Depth,
+        kind if kind.starts_with("depth") => Stream::DepthSnapshot,
+        "trade" => Stream::Trade,
+        "aggTrade" => Stream::AggTrade,
+        "bookTicker" => Stream::Bbo,
+        "forceOrder" => Stream::Liquidation,
+        "markPrice" => Stream::Funding,
+        kind if kind.starts_with("kline_") => Stream::Kline,
+        _ => Stream::Other,
+    }
+}
+
+/// One symbol's event from an all-market frame, in the frame's envelope.
+#[derive(Serialize)]
+struct AllMarketEvent<'a> {
+    stream: &'a str,
+    data: &'a RawValue,
+}
+
+/// Record each configured symbol's event from an all-market frame under that
+/// symbol.
+///
+/// The frame carries every symbol on the market, hundreds of them, once a
+/// second, while files are only wanted for the symbols the venue lists; the
+/// rest are dropped here. Each kept event is rewrapped in the frame's envelope
+/// so its record reads like any other combined-stream frame.
+async fn split_all_market(
+    writer_tx: &RecordSender,
+    symbols: &SymbolCache,
+    connection: usize,
+    recv_time: Timestamp,
+    data: &[u8],
+) -> Result<(), ConnectorError> {
+    let message: BinanceArrayMessage<'_> = serde_json::from_slice(data)?;
+    let stream = stream_of(Some(message.stream));
+    for event in message.data {
+        let BinanceSymbol { symbol } = serde_json::from_str(event.get())?;
+        let Some(symbol) = symbols.known(symbol) else {
+            continue;
+        };
+        let payload = serde_json::to_vec(&AllMarketEvent {
+            stream: message.stream,
+            data: event,
+        })?;
+        writer_tx
+            .send(WriteRecord::received(
+                recv_time,
+                symbol,
+                payload.into(),
+                connection,
+                stream,
+            ))
+            .await
+            .map_err(|_| ConnectorError::WriterClosed)?;
+    }
+    Ok(())
+}
+
 #[allow(clippy::too_many_arguments)]
 async fn handle(
+    venue: &Arc<str>,
     // `'static` because a depth gap spawns a snapshot fetch that outlives the call.
     endpoint: &'static Endpoint,
     prev_u_map: &mut HashMap<Symbol, i64>,
-    writer_tx: &Sender<WriteRecord>,
+    writer_tx: &RecordSender,
     symbols: &mut SymbolCache,
     dedup: &mut Dedup,
+    account_connection: usize,
+    connection: usize,
     recv_time: Timestamp,
     data: bytes::Bytes,
     client: &reqwest::Client,
     throttler: &Throttler,
     tasks: &mut JoinSet<()>,
 ) -> Result<(), ConnectorError> {
+    // The user data stream's events are the account's, whatever symbol they
+    // name: an `executionReport` carries an `"s"` like any trade, and must
+    // neither be filed under it nor take a slot in the market's dedup window.
+    if connection == account_connection {
+        return writer_tx
+            .send(account::record(recv_time, data, connection))
+            .await
+            .map_err(|_| ConnectorError::WriterClosed);
+    }
     // Before anything reads sequence numbers. A second copy of a depth update
     // carries the `pu` of the update *before* it, which no longer matches the
     // `prev_u` the first copy just advanced — every duplicate would be reported
@@ -647,9 +864,15 @@ async fn handle(
         return Ok(());
     }
 
+    if BinanceArrayMessage::matches(&data) {
+        return split_all_market(writer_tx, symbols, connection, recv_time, &data).await;
+    }
+
     let message: BinanceMessage<'_> = serde_json::from_slice(&data)?;
     // Control frames from the combined endpoint (`{"result":null,"id":1}`) have
-    // no `data` and are not an error.
+    // no `data` and are not an error. The session that sent the request has
+    // already acted on the answer; this only sees those a retiring session
+    // drained.
     let Some(ref event) = message.data else {
         return Ok(());
     };
@@ -658,6 +881,7 @@ async fn handle(
     };
 
     let symbol = symbols.resolve(symbol_raw);
+    let stream = stream_of(message.stream);
 
     // Spot's bookTicker frames carry no `e`, so absence just means "not a depth
     // update" rather than a malformed frame.
@@ -678,7 +902,9 @@ async fn handle(
                     );
                     *prev_u = u;
                     return writer_tx
-                        .send((recv_time, symbol, data))
+                        .send(WriteRecord::received(
+                            recv_time, symbol, data, connection, stream,
+                        ))
                         .await
                         .map_err(|_| ConnectorError::WriterClosed);
                 }
@@ -700,6 +926,13 @@ async fn handle(
                 };
                 if gap {
                     warn!(symbol = %symbol, "missing depth feed has been detected.");
+                    metrics::counter(
+                        "collector_depth_gaps_total",
+                        "Depth update sequence gaps detected while collecting.",
+                        &[("venue", venue), ("endpoint", endpoint.label)],
+                    )
+                    .inc();
+                    let venue_ = Arc::clone(venue);
                     let symbol_ = Symbol::clone(&symbol);
                     let writer_tx_ = writer_tx.clone();
                     let client_ = client.clone();
@@ -715,9 +948,17 @@ async fn handle(
                             .await
                         {
                             Some(Ok(data)) => {
-                                let _ = writer_tx_.send((Timestamp::now(), symbol_, data)).await;
+                                count_snapshot(&venue_, endpoint, "ok");
+                                let record = WriteRecord::fetched(
+                                    Timestamp::now(),
+                                    symbol_,
+                                    data,
+                                    Stream::DepthSnapshot,
+                                );
+                                let _ = writer_tx_.send(record).await;
                             }
                             Some(Err(error)) => {
+                                count_snapshot(&venue_, endpoint, "error");
                                 error!(
                                     symbol = %symbol_,
                                     ?error,
@@ -725,6 +966,7 @@ async fn handle(
                                 );
                             }
                             None => {
+                                count_snapshot(&venue_, endpoint, "rate_limited");
                                 warn!(
                                     symbol = %symbol_,
                                     "Fetching the depth snapshot is rate-limited."
@@ -751,39 +993,64 @@ async fn handle(
     }
 
     writer_tx
-        .send((recv_time, symbol, data))
+        .send(WriteRecord::received(
+            recv_time, symbol, data, connection, stream,
+        ))
         .await
         .map_err(|_| ConnectorError::WriterClosed)?;
     Ok(())
 }
 
+/// Record a depth snapshot of every symbol once per `interval`, and of each
+/// symbol a reload adds as soon as it is added: its depth stream starts without
+/// one, and the next round may be an hour away.
 async fn snapshot_loop(
+    venue: Arc<str>,
     endpoint: &'static Endpoint,
-    symbols: Vec<Symbol>,
-    writer_tx: Sender<WriteRecord>,
+    mut symbols: watch::Receiver<Vec<String>>,
+    writer_tx: RecordSender,
     client: reqwest::Client,
     throttler: Throttler,
-    interval_secs: u64,
+    interval: Duration,
 ) {
     tokio::time::sleep(SNAPSHOT_START_DELAY).await;
 
-    let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+    let mut ticker = tokio::time::interval(interval);
     let mut pacer = tokio::time::interval(SNAPSHOT_PACE);
     // A round that overruns its spacing must not then fire the backlog at once.
     pacer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut current = symbols.borrow_and_update().clone();
 
     loop {
-        ticker.tick().await;
+        let round = tokio::select! {
+            _ = ticker.tick() => {
+                current = symbols.borrow_and_update().clone();
+                current.clone()
+            }
+            Ok(()) = symbols.changed() => {
+                let next = symbols.borrow_and_update().clone();
+                let added = SymbolDiff::between(&current, &next).added;
+                current = next;
+                added
+            }
+        };
 
-        for symbol in &symbols {
+        for symbol in &round {
+            let symbol = Symbol::from(symbol.to_ascii_lowercase());
             pacer.tick().await;
             match throttler
-                .execute(fetch_depth_snapshot(endpoint, &client, &throttler, symbol))
+                .execute(fetch_depth_snapshot(endpoint, &client, &throttler, &symbol))
                 .await
             {
                 Some(Ok(data)) => {
+                    count_snapshot(&venue, endpoint, "ok");
                     if writer_tx
-                        .send((Timestamp::now(), Symbol::clone(symbol), data))
+                        .send(WriteRecord::fetched(
+                            Timestamp::now(),
+                            Symbol::clone(&symbol),
+                            data,
+                            Stream::DepthSnapshot,
+                        ))
                         .await
                         .is_err()
                     {
@@ -791,9 +1058,11 @@ async fn snapshot_loop(
                     }
                 }
                 Some(Err(error)) => {
+                    count_snapshot(&venue, endpoint, "error");
                     error!(symbol = %symbol, %error, "failed to fetch depth snapshot");
                 }
                 None => {
+                    count_snapshot(&venue, endpoint, "rate_limited");
                     warn!(symbol = %symbol, "snapshot fetch rate-limited, skipping");
                 }
             }
@@ -801,44 +1070,307 @@ async fn snapshot_loop(
     }
 }
 
+/// Poll open interest and the premium index for every symbol, once per
+/// `interval`, recording both as fetched.
+///
+/// Neither has a websocket stream: `markPrice` pushes the premium index's
+/// numbers but not the interest rate it is built from, and open interest is
+/// REST-only. Each round polls the symbols the venue has at its start.
+#[allow(clippy::too_many_arguments)]
+async fn funding_loop(
+    venue: Arc<str>,
+    endpoint: &'static Endpoint,
+    urls: &'static FundingUrls,
+    symbols: watch::Receiver<Vec<String>>,
+    writer_tx: RecordSender,
+    client: reqwest::Client,
+    throttler: Throttler,
+    interval: Duration,
+) {
+    tokio::time::sleep(SNAPSHOT_START_DELAY).await;
+
+    let mut ticker = tokio::time::interval(interval);
+    // A round that overruns the interval must not then fire the backlog at once.
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut pacer = tokio::time::interval(FUNDING_PACE);
+    pacer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+
+        let round = symbols.borrow().clone();
+        for symbol in &round {
+            let symbol = Symbol::from(symbol.to_ascii_lowercase());
+            for (request, prefix) in [
+                ("openInterest", urls.open_interest),
+                ("premiumIndex", urls.premium_index),
+            ] {
+                pacer.tick().await;
+                while throttler.spare().await <= GAP_SNAPSHOT_RESERVE {
+                    pacer.tick().await;
+                }
+                let url = format!("{prefix}{}", symbol.to_uppercase());
+                match throttler
+                    .execute(fetch(endpoint, &client, &throttler, &url, request))
+                    .await
+                {
+                    Some(Ok(data)) => {
+                        count_funding(&venue, endpoint, request, "ok");
+                        if writer_tx
+                            .send(WriteRecord::fetched(
+                                Timestamp::now(),
+                                Symbol::clone(&symbol),
+                                data,
+                                Stream::Funding,
+                            ))
+                            .await
+                            .is_err()
+                        {
+                            return;
+                        }
+                    }
+                    Some(Err(error)) => {
+                        count_funding(&venue, endpoint, request, "error");
+                        error!(symbol = %symbol, request, %error, "failed to fetch funding data");
+                    }
+                    None => {
+                        count_funding(&venue, endpoint, request, "rate_limited");
+                        warn!(symbol = %symbol, request, "funding fetch rate-limited, skipping");
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A listen key for the account of `credentials`: a new one, or, given `key`,
+/// the same one extended for another hour.
+///
+/// Both go through the throttler: they spend the same per-IP request budget as
+/// the snapshots, and a ban stops them like anything else. `None` is a request
+/// the throttler refused to send.
+async fn listen_key(
+    endpoint: &Endpoint,
+    urls: &UserDataUrls,
+    client: &reqwest::Client,
+    throttler: &Throttler,
+    credentials: &Credentials,
+    key: Option<&str>,
+) -> Option<Result<String, anyhow::Error>> {
+    let request = match key {
+        Some(key) => client.put(format!("{}?listenKey={key}", urls.listen_key)),
+        None => client.post(urls.listen_key),
+    }
+    .header("X-MBX-APIKEY", &credentials.key);
+    let body = throttler
+        .execute(send(endpoint, throttler, request, "listen key"))
+        .await?;
+    Some(body.and_then(|body| {
+        match key {
+            Some(key) => Ok(key.to_owned()),
+            None => Ok(serde_json::from_slice::<ListenKey>(&body)
+                .context("unexpected listen key response")?
+                .listen_key),
+        }
+    }))
+}
+
+/// Extend `key` every [`LISTEN_KEY_KEEPALIVE`]; returns only when it cannot be.
+async fn keep_listen_key(
+    endpoint: &Endpoint,
+    urls: &UserDataUrls,
+    client: &reqwest::Client,
+    throttler: &Throttler,
+    credentials: &Credentials,
+    key: &str,
+) -> Result<(), anyhow::Error> {
+    loop {
+        let mut wait = LISTEN_KEY_KEEPALIVE;
+        loop {
+            tokio::time::sleep(wait).await;
+            match listen_key(endpoint, urls, client, throttler, credentials, Some(key)).await {
+                Some(result) => {
+                    result?;
+                    break;
+                }
+                None => {
+                    warn!(
+                        endpoint = endpoint.label,
+                        "listen key extension rate-limited; retrying"
+                    );
+                    wait = LISTEN_KEY_RETRY;
+                }
+            }
+        }
+    }
+}
+
+/// One user data session: a fresh listen key and the socket it opens, read
+/// into `ws_tx` as `connection` until either gives out.
+///
+/// `Ok` only when the feed has closed. The session ends with an error when the
+/// key cannot be extended, when Binance says it has expired (after the
+/// `listenKeyExpired` event itself is delivered), or when the socket closes or
+/// falls silent; the next session asks for a key again.
+#[allow(clippy::too_many_arguments)]
+async fn user_data_session(
+    endpoint: &'static Endpoint,
+    urls: &UserDataUrls,
+    client: &reqwest::Client,
+    throttler: &Throttler,
+    credentials: &Credentials,
+    connection: usize,
+    ws_tx: &Sender<Frame>,
+) -> Result<(), anyhow::Error> {
+    let key = listen_key(endpoint, urls, client, throttler, credentials, None)
+        .await
+        .ok_or_else(|| anyhow!("the listen key request was rate-limited"))??;
+    let conn = ws::connect(&format!("{}{key}", urls.ws_url)).await?;
+    info!(endpoint = endpoint.label, "user data stream connected");
+
+    let keepalive = keep_listen_key(endpoint, urls, client, throttler, credentials, &key);
+    read_user_data(endpoint, conn, keepalive, connection, ws_tx).await
+}
+
+/// The socket half of [`user_data_session`]: `conn` read into `ws_tx` as
+/// `connection` until `keepalive` gives out, the socket does, or Binance says
+/// the key has expired. Split out so tests can stand an in-memory peer in for
+/// Binance.
+async fn read_user_data<S>(
+    endpoint: &Endpoint,
+    mut conn: ws::Connection<S>,
+    keepalive: impl std::future::Future<Output = Result<(), anyhow::Error>>,
+    connection: usize,
+    ws_tx: &Sender<Frame>,
+) -> Result<(), anyhow::Error>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    let sender = conn.sender();
+    tokio::pin!(keepalive);
+    loop {
+        // `read` is not cancel-safe, so every arm racing it must be terminal.
+        let message = tokio::select! {
+            biased;
+            result = &mut keepalive => {
+                return result.context("couldn't extend the listen key");
+            }
+            result = timeout(endpoint.idle_timeout, conn.read()) => match result {
+                Ok(message) => message?,
+                Err(_) => bail!("no websocket frame within {:?}", endpoint.idle_timeout),
+            },
+        };
+        match message.opcode {
+            OpCode::Text => {
+                let recv_time = Timestamp::now();
+                let expired = ws::payload_contains(&message.payload, b"listenKeyExpired");
+                // Never shed: an order or a fill exists nowhere else, and they
+                // are few enough that waiting for room costs nothing.
+                if ws_tx
+                    .send((connection, recv_time, message.payload))
+                    .await
+                    .is_err()
+                {
+                    return Ok(());
+                }
+                if expired {
+                    bail!("the listen key expired");
+                }
+            }
+            OpCode::Ping => sender.pong(message.payload.to_vec()).await?,
+            OpCode::Close => bail!("connection closed by server"),
+            _ => {}
+        }
+    }
+}
+
+/// Keep the account's user data stream open, delivering its events into
+/// `ws_tx` as `connection`, until the feed closes.
+#[allow(clippy::too_many_arguments)]
+async fn user_data_loop(
+    endpoint: &'static Endpoint,
+    urls: &'static UserDataUrls,
+    credentials: Credentials,
+    connection: usize,
+    ws_tx: Sender<Frame>,
+    client: reqwest::Client,
+    throttler: Throttler,
+) {
+    let mut error_count = 0;
+    loop {
+        let opened = Instant::now();
+        let Err(error) = user_data_session(
+            endpoint,
+            urls,
+            &client,
+            &throttler,
+            &credentials,
+            connection,
+            &ws_tx,
+        )
+        .await
+        else {
+            return;
+        };
+        let lifetime = opened.elapsed();
+        error!(
+            endpoint = endpoint.label,
+            error = format!("{error:#}"),
+            ?lifetime,
+            "user data stream error"
+        );
+        back_off(&mut error_count, Some(lifetime)).await;
+    }
+}
+
+/// The market data arrives over as many connection groups as the symbols need,
+/// `connections` redundant connections each; see [`shard_loop`]. One `Dedup`
+/// and one depth continuity map serve them all. `subscribe` has the sessions
+/// request their streams rather than name them in the URL; see
+/// [`keep_connection`].
+///
+/// `funding_interval` turns on [`funding_loop`] on a futures endpoint, and
+/// `account` the user data stream on one that has it; its frames share the
+/// feed as the connection after the last market-data one, so shutdown drains
+/// them too.
+#[allow(clippy::too_many_arguments)]
 pub async fn run_collection(
+    venue: Arc<str>,
     endpoint: &'static Endpoint,
     streams: Vec<String>,
-    symbols: Vec<String>,
-    writer_tx: Sender<WriteRecord>,
+    mut symbols: watch::Receiver<Vec<String>>,
+    writer_tx: RecordSender,
     shutdown: watch::Receiver<bool>,
     connections: usize,
+    snapshot_interval: Duration,
+    funding_interval: Option<Duration>,
+    account: Option<Credentials>,
+    subscribe: bool,
 ) -> Result<(), anyhow::Error> {
     let connections = connections.max(1);
     let mut prev_u_map = HashMap::new();
-    let mut dedup = Dedup::for_connections(connections);
+    let mut dedup = Dedup::for_connections(connections).with_metrics(endpoint.label);
     // All connections share the queue, so it is sized per connection to keep
     // the burst each one can absorb independent of how many there are.
-    let (ws_tx, ws_rx) =
-        channel::<(Timestamp, bytes::Bytes)>(crate::WS_QUEUE_CAPACITY.saturating_mul(connections));
+    let (ws_tx, ws_rx) = channel::<Frame>(crate::WS_QUEUE_CAPACITY.saturating_mul(connections));
     let mut feed = Feed::new(ws_rx, shutdown);
     let mut tasks = JoinSet::new();
-    let mut symbol_cache = SymbolCache::new(&symbols);
-    let snapshot_symbols: Vec<Symbol> = symbols
-        .iter()
-        .map(|symbol| symbol_cache.resolve(symbol))
-        .collect();
-    for connection in 0..connections {
+    let mut symbol_cache = SymbolCache::new(&symbols.borrow_and_update());
+    {
         let streams = streams.clone();
         let symbols = symbols.clone();
         let ws_tx = ws_tx.clone();
-        tasks.spawn(async move {
-            tokio::time::sleep(crate::CONNECT_STAGGER * connection as u32).await;
-            keep_connection(endpoint, streams, symbols, connection, ws_tx).await;
-            error!(
-                endpoint = endpoint.label,
-                connection, "the websocket connection task exited"
-            );
-        });
+        tasks.spawn(shard_loop(
+            Arc::clone(&venue),
+            endpoint,
+            streams,
+            symbols,
+            connections,
+            ws_tx,
+            Spool::for_feed(endpoint.label, connections),
+            subscribe,
+        ));
     }
-    // The clones above are the only senders that should keep the feed open;
-    // holding this one would stop `Feed` from ever seeing the queue close.
-    drop(ws_tx);
     // https://www.binance.com/en/support/faq/rate-limits-on-binance-futures-281596e222414cdd9051664ea621cdc3
     // The default rate limit per IP is 2,400/min and the weight is 20 at a depth of 1000.
     // The maximum request rate for fetching snapshots is 120 per minute.
@@ -848,24 +1380,69 @@ pub async fn run_collection(
         .connect_timeout(Duration::from_secs(10))
         .timeout(Duration::from_secs(30))
         .build()?;
+    let account_connection = connections;
+    if let (Some(credentials), Some(urls)) = (account, endpoint.user_data.as_ref()) {
+        let ws_tx = ws_tx.clone();
+        let client = client.clone();
+        let throttler = throttler.clone();
+        tasks.spawn(async move {
+            user_data_loop(
+                endpoint,
+                urls,
+                credentials,
+                account_connection,
+                ws_tx,
+                client,
+                throttler,
+            )
+            .await;
+            error!(
+                endpoint = endpoint.label,
+                "the user data stream task exited"
+            );
+        });
+    }
+    // The clones above are the only senders that should keep the feed open;
+    // holding this one would stop `Feed` from ever seeing the queue close.
+    drop(ws_tx);
     // Depth snapshots exist to seed/repair the DEPTH stream; a worker
     // whose stream set carries no depth (e.g. the market-path
     // forceOrder split) must not duplicate the REST snapshot loop —
     // the legacy worker already snapshots the same symbols, and a
     // second loop only burns request budget (review finding).
     let collects_depth = streams.iter().any(|s| s.contains("@depth"));
+    if let (Some(interval), Some(urls)) = (funding_interval, endpoint.funding_urls.as_ref()) {
+        let symbols = symbols.clone();
+        let writer_tx = writer_tx.clone();
+        let client = client.clone();
+        let throttler = throttler.clone();
+        let venue = Arc::clone(&venue);
+        tasks.spawn(async move {
+            funding_loop(
+                venue, endpoint, urls, symbols, writer_tx, client, throttler, interval,
+            )
+            .await;
+            error!(
+                endpoint = endpoint.label,
+                "the periodic funding poller exited"
+            );
+        });
+    }
     if collects_depth {
+        let symbols = symbols.clone();
         let writer_tx = writer_tx.clone();
         let client = client.clone();
         let throttler = throttler.clone();
+        let venue = Arc::clone(&venue);
         tasks.spawn(async move {
             snapshot_loop(
+                venue,
                 endpoint,
-                snapshot_symbols,
+                symbols,
                 writer_tx,
                 client,
                 throttler,
-                3600,
+                snapshot_interval,
             )
             .await;
             error!(
@@ -875,7 +1452,7 @@ pub async fn run_collection(
         });
     }
     let mut messages_before_reap = 1_024;
-    while let Some((recv_time, data)) = feed.recv(&mut tasks).await {
+    while let Some((connection, recv_time, data)) = feed.recv(&mut tasks).await {
         messages_before_reap -= 1;
         if messages_before_reap == 0 {
             while let Some(result) = tasks.try_join_next() {
@@ -889,12 +1466,20 @@ pub async fn run_collection(
             }
             messages_before_reap = 1_024;
         }
+        // All-market frames are split by the symbols the cache was seeded
+        // with, so it follows the venue's list.
+        if symbols.has_changed().unwrap_or(false) {
+            symbol_cache.reseed(&symbols.borrow_and_update());
+        }
         if let Err(error) = handle(
+            &venue,
             endpoint,
             &mut prev_u_map,
             &writer_tx,
             &mut symbol_cache,
             &mut dedup,
+            account_connection,
+            connection,
             recv_time,
             data,
             &client,
@@ -912,59 +1497,509 @@ pub async fn run_collection(
     Ok(())
 }
 
-pub async fn keep_connection(
-    endpoint: &'static Endpoint,
-    streams: Vec<String>,
-    symbol_list: Vec<String>,
-    connection: usize,
-    ws_tx: Sender<(Timestamp, bytes::Bytes)>,
-) {
-    let streams_str = symbol_list
+/// The combined-stream names for `symbols`. A stream without `$symbol`, such
+/// as `!markPrice@arr`, covers the whole market and is listed once.
+fn stream_names(streams: &[String], symbols: &[String]) -> Vec<String> {
+    let (per_symbol, market_wide): (Vec<&String>, Vec<&String>) = streams
+        .iter()
+        .partition(|stream| stream.contains("$symbol"));
+    symbols
         .iter()
         .flat_map(|pair| {
             let pair = pair.to_lowercase();
-            streams
+            per_symbol
                 .iter()
                 .map(move |stream| stream.replace("$symbol", &pair))
         })
-        .collect::<Vec<_>>()
-        .join("/");
-    let url = format!("{}{streams_str}", endpoint.ws_stream_url);
+        .chain(market_wide.into_iter().cloned())
+        .collect()
+}
 
-    // Sessions that have been relieved but are still delivering while their
-    // replacement is brought up. Held in a `JoinSet` because dropping one
-    // aborts what it holds: shutdown aborts `keep_connection`, and a detached
-    // `tokio::spawn` would keep its `ws_tx` clone — and with it the whole feed
-    // — open until the drain timed out.
-    let mut lingering: JoinSet<()> = JoinSet::new();
-    let mut handover = Handover::default();
-    let mut error_count = 0;
-    let max_age = max_session_age(connection);
+/// Most streams Binance serves on one connection.
+const MAX_STREAMS_PER_CONNECTION: usize = 1024;
+/// Longest combined-stream URL a connection is opened with. Binance publishes
+/// no limit, but refuses the handshake well before the stream cap on long
+/// stream names; 8 KiB is where common servers stop accepting a request line.
+const MAX_URL_LEN: usize = 8 * 1024;
+/// Spacing between the first connections of successive groups, so a venue
+/// with many groups does not open them all in one burst of handshakes.
+const GROUP_STAGGER: Duration = Duration::from_millis(500);
+
+/// How the venue's symbols are spread over connection groups, so that no
+/// connection is asked for more streams, or a longer URL, than Binance accepts.
+///
+/// The market-wide streams belong to the first group alone, where they are
+/// received once. A group keeps its symbols for as long as they are listed: a
+/// reload takes the removed ones out of whichever group holds them and puts
+/// each added one in the first group with room, opening a new group only when
+/// none has any. Nothing moves between groups, so a reload never costs an
+/// unchanged symbol its subscription.
+#[derive(Debug)]
+struct Sharding {
+    /// `None` when the streams are requested rather than listed in the URL.
+    url_prefix_len: Option<usize>,
+    per_symbol: Vec<String>,
+    market_wide: Vec<String>,
+    groups: Vec<Vec<String>>,
+}
 
-    // One channel for every session this slot will ever open, held here so it
-    // outlives them. A per-session channel would be dropped the moment its
-    // session was relieved, and a session relieved before it went live could
-    // then never report that it had started carrying the feed — leaving the
-    // session it replaced running until some later one happened to prove
-    // itself. Holding the sender here also means `recv` never yields `None`.
-    let (event_tx, mut event_rx) = tokio::sync::mpsc::channel(EVENT_QUEUE_CAPACITY);
-    let mut next_session: SessionId = 0;
+impl Sharding {
+    fn new(url_prefix: Option<&str>, streams: &[String], symbols: &[String]) -> Self {
+        let (per_symbol, market_wide) = streams
+            .iter()
+            .cloned()
+            .partition(|stream| stream.contains("$symbol"));
+        let mut sharding = Self {
+            url_prefix_len: url_prefix.map(str::len),
+            per_symbol,
+            market_wide,
+            groups: vec![Vec::new()],
+        };
+        sharding.add(symbols);
+        sharding
+    }
 
-    loop {
-        while lingering.try_join_next().is_some() {}
+    /// The stream templates group `group` subscribes to.
+    fn streams(&self, group: usize) -> Vec<String> {
+        let mut streams = self.per_symbol.clone();
+        if group == 0 {
+            streams.extend(self.market_wide.iter().cloned());
+        }
+        streams
+    }
+
+    /// Whether group `group` could also take `symbol`.
+    fn has_room(&self, group: usize, symbol: &str) -> bool {
+        let mut symbols = self.groups[group].clone();
+        symbols.push(symbol.to_owned());
+        let names = stream_names(&self.streams(group), &symbols);
+        let url_len = self
+            .url_prefix_len
+            .map(|prefix| prefix + names.iter().map(|name| name.len() + 1).sum::<usize>());
+        names.len() <= MAX_STREAMS_PER_CONNECTION && url_len.is_none_or(|len| len <= MAX_URL_LEN)
+    }
+
+    fn add(&mut self, symbols: &[String]) {
+        for symbol in symbols {
+            match (0..self.groups.len()).find(|&group| self.has_room(group, symbol)) {
+                Some(group) => self.groups[group].push(symbol.clone()),
+                // A symbol too big for an empty group still gets one to itself:
+                // refusing it there is Binance's to report.
+                None => self.groups.push(vec![symbol.clone()]),
+            }
+        }
+    }
+
+    fn apply(&mut self, diff: &SymbolDiff) {
+        for group in &mut self.groups {
+            group.retain(|symbol| !diff.removed.contains(symbol));
+        }
+        self.add(&diff.added);
+    }
+}
+
+/// Run the connection groups the venue's symbols need — `connections`
+/// redundant connections each, with their own session supervision — and keep
+/// the groups' lists in step with the venue's; see [`Sharding`].
+///
+/// The groups' connections are held here, so stopping this task stops them.
+/// They all deliver into `ws_tx`, and share `spool` with it.
+#[allow(clippy::too_many_arguments)]
+async fn shard_loop(
+    venue: Arc<str>,
+    endpoint: &'static Endpoint,
+    streams: Vec<String>,
+    mut symbols: watch::Receiver<Vec<String>>,
+    connections: usize,
+    ws_tx: Sender<Frame>,
+    spool: Option<Arc<Spool>>,
+    subscribe: bool,
+) {
+    let mut current = symbols.borrow_and_update().clone();
+    let url_prefix = (!subscribe).then_some(endpoint.ws_stream_url);
+    let mut sharding = Sharding::new(url_prefix, &streams, &current);
+    let mut groups: Vec<watch::Sender<Vec<String>>> = Vec::new();
+    let mut tasks = JoinSet::new();
+    loop {
+        let opened = groups.len();
+        for group in opened..sharding.groups.len() {
+            let (group_tx, group_rx) = watch::channel(sharding.groups[group].clone());
+            groups.push(group_tx);
+            for connection in 0..connections {
+                let streams = sharding.streams(group);
+                let symbols = group_rx.clone();
+                let ws_tx = ws_tx.clone();
+                let spool = spool.clone();
+                let venue = Arc::clone(&venue);
+                tasks.spawn(async move {
+                    let stagger = crate::CONNECT_STAGGER * connection as u32
+                        + GROUP_STAGGER * (group - opened) as u32;
+                    tokio::time::sleep(stagger).await;
+                    keep_connection(
+                        &venue, endpoint, streams, symbols, group, connection, ws_tx, spool,
+                        subscribe,
+                    )
+                    .await;
+                    error!(
+                        endpoint = endpoint.label,
+                        group, connection, "the websocket connection task exited"
+                    );
+                });
+            }
+        }
+        if groups.len() > opened && groups.len() > 1 {
+            info!(
+                endpoint = endpoint.label,
+                groups = groups.len(),
+                "the streams are spread over several connection groups"
+            );
+        }
 
+        if symbols.changed().await.is_err() {
+            break;
+        }
+        let next = symbols.borrow_and_update().clone();
+        sharding.apply(&SymbolDiff::between(&current, &next));
+        current = next;
+        for (group_tx, group) in groups.iter().zip(&sharding.groups) {
+            group_tx.send_if_modified(|symbols| {
+                let modified = symbols != group;
+                if modified {
+                    symbols.clone_from(group);
+                }
+                modified
+            });
+        }
+    }
+    // The venue's list is only dropped at shutdown; the groups drain as usual.
+    while tasks.join_next().await.is_some() {}
+}
+
+/// Spacing between a session's requests. Binance allows a connection five
+/// incoming messages a second, pongs included, so this leaves room for one.
+const SUBSCRIBE_PACE: Duration = Duration::from_millis(250);
+/// Streams named in one `SUBSCRIBE` or `UNSUBSCRIBE`.
+const SUBSCRIBE_BATCH: usize = 200;
+/// Times a rejected request is sent again before it is given up until the
+/// next session.
+const MAX_SUBSCRIBE_ATTEMPTS: u32 = 5;
+/// How often requests waiting out a retry delay, or for an answer, are
+/// checked.
+const RETRY_SWEEP_INTERVAL: Duration = Duration::from_millis(250);
+/// How long a sent request waits for its answer before it counts as rejected.
+/// Binance answers within milliseconds when it answers at all; one it never
+/// answers would otherwise hold the handover back for the whole session.
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A `SUBSCRIBE` or `UNSUBSCRIBE` a session has yet to see answered.
+#[derive(Debug, PartialEq)]
+struct Request {
+    method: &'static str,
+    params: Vec<String>,
+    /// Rejections so far, timeouts included.
+    attempts: u32,
+    /// When the answer to the last send is overdue; `None` while the request
+    /// is queued or waiting out a retry delay.
+    due: Option<Instant>,
+}
+
+/// The combined endpoint's answer to a request: `{"result":null,"id":1}`, or
+/// the same id with an `error`.
+#[derive(Deserialize, Debug, PartialEq)]
+struct Response {
+    id: u64,
+    error: Option<ResponseError>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct ResponseError {
+    code: i64,
+    msg: String,
+}
+
+/// The response `payload` carries, if it is one rather than market data.
+///
+/// Market data always comes in the `{"stream":…}` envelope, so only the rare
+/// frame without it is parsed.
+fn response(payload: &[u8]) -> Option<Response> {
+    if payload.starts_with(br#"{"stream""#) {
+        return None;
+    }
+    serde_json::from_slice(payload).ok()
+}
+
+/// What a response did to the request it answers.
+#[derive(Debug, PartialEq)]
+enum Answer {
+    Accepted,
+    /// Sent again once the delay has passed.
+    Retrying(Duration),
+    GaveUp,
+    /// Not a request this session has outstanding.
+    Unknown,
+}
+
+/// A session's requests: queued behind [`SUBSCRIBE_PACE`], sent and awaiting
+/// their answer, or rejected and waiting out a retry delay.
+///
+/// A session that subscribes by request opens with every stream queued; one
+/// whose streams came from its URL opens with nothing, and only a reload adds
+/// to it. Either way the handover waits for the queue to be answered, so a
+/// replacement is not taken to carry the feed while some of its streams are
+/// still unsubscribed.
+#[derive(Default)]
+struct Subscriptions {
+    next_id: u64,
+    queued: std::collections::VecDeque<u64>,
+    outstanding: HashMap<u64, Request>,
+    retries: Vec<(u64, Instant)>,
+}
+
+impl Subscriptions {
+    /// Every stream in `names` queued for a session opened on the bare endpoint.
+    fn open(names: &[String]) -> Self {
+        let mut subscriptions = Self::default();
+        subscriptions.queue("SUBSCRIBE", names);
+        subscriptions
+    }
+
+    fn queue(&mut self, method: &'static str, names: &[String]) {
+        for params in names.chunks(SUBSCRIBE_BATCH) {
+            self.next_id += 1;
+            self.queued.push_back(self.next_id);
+            self.outstanding.insert(
+                self.next_id,
+                Request {
+                    method,
+                    params: params.to_vec(),
+                    attempts: 0,
+                    due: None,
+                },
+            );
+        }
+    }
+
+    /// Bring the session's streams in line with a changed symbol list.
+    /// Market-wide streams do not depend on the symbols and are left alone.
+    ///
+    /// A request still waiting to be sent, answered or retried stops naming a
+    /// stream the change reverses, so a late retry cannot undo a reload.
+    fn change(&mut self, streams: &[String], diff: &SymbolDiff) {
+        let per_symbol: Vec<String> = streams
+            .iter()
+            .filter(|stream| stream.contains("$symbol"))
+            .cloned()
+            .collect();
+        let removed = stream_names(&per_symbol, &diff.removed);
+        let added = stream_names(&per_symbol, &diff.added);
+        for request in self.outstanding.values_mut() {
+            let reversed = match request.method {
+                "SUBSCRIBE" => &removed,
+                _ => &added,
+            };
+            request.params.retain(|name| !reversed.contains(name));
+        }
+        self.outstanding
+            .retain(|_, request| !request.params.is_empty());
+        self.queued.retain(|id| self.outstanding.contains_key(id));
+        self.retries
+            .retain(|(id, _)| self.outstanding.contains_key(id));
+        self.queue("UNSUBSCRIBE", &removed);
+        self.queue("SUBSCRIBE", &added);
+    }
+
+    /// The next request to send, with its id. Its answer is due
+    /// [`ANSWER_TIMEOUT`] after `now`.
+    fn next(&mut self, now: Instant) -> Option<(u64, &Request)> {
+        let id = self.queued.pop_front()?;
+        let request = self.outstanding.get_mut(&id)?;
+        request.due = Some(now + ANSWER_TIMEOUT);
+        Some((id, request))
+    }
+
+    fn on_response(&mut self, response: &Response, now: Instant) -> Answer {
+        if !self.outstanding.contains_key(&response.id) {
+            return Answer::Unknown;
+        }
+        if response.error.is_none() {
+            self.outstanding.remove(&response.id);
+            return Answer::Accepted;
+        }
+        self.reject(response.id, now)
+    }
+
+    /// Count every request whose answer is overdue as rejected, with the
+    /// answer each got.
+    fn time_out(&mut self, now: Instant) -> Vec<(u64, Answer)> {
+        let overdue: Vec<u64> = self
+            .outstanding
+            .iter()
+            .filter(|(_, request)| request.due.is_some_and(|due| due <= now))
+            .map(|(&id, _)| id)
+            .collect();
+        overdue
+            .into_iter()
+            .map(|id| (id, self.reject(id, now)))
+            .collect()
+    }
+
+    fn reject(&mut self, id: u64, now: Instant) -> Answer {
+        let Some(request) = self.outstanding.get_mut(&id) else {
+            return Answer::Unknown;
+        };
+        request.due = None;
+        request.attempts += 1;
+        if request.attempts > MAX_SUBSCRIBE_ATTEMPTS {
+            self.outstanding.remove(&id);
+            return Answer::GaveUp;
+        }
+        // 1s, 2s, 4s, 8s, 16s.
+        let delay = Duration::from_secs(1 << (request.attempts - 1));
+        self.retries.push((id, now + delay));
+        Answer::Retrying(delay)
+    }
+
+    /// Queue the rejected requests whose delay has passed.
+    fn requeue_due(&mut self, now: Instant) {
+        let queued = &mut self.queued;
+        self.retries.retain(|&(id, due)| {
+            let waiting = due > now;
+            if !waiting {
+                queued.push_back(id);
+            }
+            waiting
+        });
+    }
+
+    fn has_queued(&self) -> bool {
+        !self.queued.is_empty()
+    }
+
+    fn is_retrying(&self) -> bool {
+        !self.retries.is_empty()
+    }
+
+    /// Whether some sent request has yet to be answered.
+    fn is_awaiting(&self) -> bool {
+        self.outstanding
+            .values()
+            .any(|request| request.due.is_some())
+    }
+
+    /// Whether every request has been answered, or given up.
+    fn settled(&self) -> bool {
+        self.outstanding.is_empty()
+    }
+}
+
+/// Send one request on a session.
+async fn send_request(sender: &FrameSender, id: u64, request: &Request) -> Result<(), Error> {
+    let request = serde_json::to_vec(&serde_json::json!({
+        "method": request.method,
+        "params": request.params,
+        "id": id,
+    }))?;
+    sender.text(request).await
+}
+
+/// One redundant connection of connection group `group`.
+///
+/// `symbols` is the group's list. It is read at every connect, so each session
+/// opens with the current list, and watched while the session runs; see
+/// [`Subscriptions`]. With `subscribe`, sessions open on the bare endpoint and
+/// request their streams once connected, instead of naming them in the URL.
+#[allow(clippy::too_many_arguments)]
+pub async fn keep_connection(
+    venue: &str,
+    endpoint: &'static Endpoint,
+    streams: Vec<String>,
+    symbols: watch::Receiver<Vec<String>>,
+    group: usize,
+    connection: usize,
+    ws_tx: Sender<Frame>,
+    spool: Option<Arc<Spool>>,
+    subscribe: bool,
+) {
+    let connect = |url: String| async move { ws::connect(&url).await };
+    keep_connection_with(
+        venue, endpoint, streams, symbols, group, connection, ws_tx, spool, subscribe, connect,
+    )
+    .await;
+}
+
+/// [`keep_connection`], opening each session's socket with `connect`, so tests
+/// can stand an in-memory peer in for Binance.
+#[allow(clippy::too_many_arguments)]
+async fn keep_connection_with<S, C, F>(
+    venue: &str,
+    endpoint: &'static Endpoint,
+    streams: Vec<String>,
+    mut symbols: watch::Receiver<Vec<String>>,
+    group: usize,
+    connection: usize,
+    ws_tx: Sender<Frame>,
+    spool: Option<Arc<Spool>>,
+    subscribe: bool,
+    mut connect: C,
+) where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+    C: FnMut(String) -> F,
+    F: std::future::Future<Output = Result<ws::Connection<S>, Error>>,
+{
+    // Sessions that have been relieved but are still delivering while their
+    // replacement is brought up. Held in a `JoinSet` because dropping one
+    // aborts what it holds: shutdown aborts `keep_connection`, and a detached
+    // `tokio::spawn` would keep its `ws_tx` clone — and with it the whole feed
+    // — open until the drain timed out.
+    let mut lingering: JoinSet<()> = JoinSet::new();
+    let mut handover = Handover::default();
+    let mut error_count = 0;
+    let max_age = max_session_age(connection);
+
+    // One channel for every session this slot will ever open, held here so it
+    // outlives them. A per-session channel would be dropped the moment its
+    // session was relieved, and a session relieved before it went live could
+    // then never report that it had started carrying the feed — leaving the
+    // session it replaced running until some later one happened to prove
+    // itself. Holding the sender here also means `recv` never yields `None`.
+    let (event_tx, mut event_rx) = tokio::sync::mpsc::channel(EVENT_QUEUE_CAPACITY);
+    let mut next_session: SessionId = 0;
+
+    loop {
+        while lingering.try_join_next().is_some() {}
+
+        let mut subscribed = symbols.borrow_and_update().clone();
+        let names = stream_names(&streams, &subscribed);
+        if names.is_empty() {
+            // A group whose symbols have all been removed has nothing to open
+            // a session for until some are added.
+            if symbols.changed().await.is_err() {
+                return;
+            }
+            continue;
+        }
+        let (url, mut subscriptions) = if subscribe {
+            (endpoint.bare_url().to_owned(), Subscriptions::open(&names))
+        } else {
+            (
+                format!("{}{}", endpoint.ws_stream_url, names.join("/")),
+                Subscriptions::default(),
+            )
+        };
         let opened = Instant::now();
-        let conn = match ws::connect(&url).await {
+        let conn = match connect(url).await {
             Ok(conn) => conn,
             Err(error) => {
                 error!(
                     endpoint = endpoint.label,
+                    group,
                     connection,
                     ?error,
                     attempt = error_count + 1,
                     still_delivering = handover.waiting(),
                     "websocket handshake failed"
                 );
+                metrics::handshake_failed(venue, endpoint.label, group, connection);
                 back_off(&mut error_count, None).await;
                 continue;
             }
@@ -972,6 +2007,7 @@ pub async fn keep_connection(
 
         let id = next_session;
         next_session += 1;
+        let sender = conn.sender();
         let (retire_tx, retire_rx) = tokio::sync::oneshot::channel();
         let mut session = Box::pin(run_session(
             conn,
@@ -982,17 +2018,63 @@ pub async fn keep_connection(
             event_tx.clone(),
             retire_rx,
             max_age,
+            spool.clone(),
         ));
+        let mut pacer = tokio::time::interval(SUBSCRIBE_PACE);
+        pacer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut sweep = tokio::time::interval(RETRY_SWEEP_INTERVAL);
+        sweep.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // This session's market data has arrived, but some of its requests
+        // have not been answered yet.
+        let mut live_pending = false;
 
         let step = loop {
+            // Whatever settled the requests last — an answer, a timeout, or a
+            // reload that withdrew them — the session now carries every stream
+            // it was asked to.
+            if live_pending && subscriptions.settled() {
+                live_pending = false;
+                handover.on_live(id);
+            }
             tokio::select! {
                 biased;
                 event = event_rx.recv() => match event {
+                    // Until its requests are answered, a session carries only
+                    // part of its streams, and the one it replaces must go on
+                    // delivering the rest.
+                    Some(Event::Live(live)) if live == id && !subscriptions.settled() => {
+                        live_pending = true;
+                    }
                     // Proof that a session carries the feed, and the only thing
                     // that may retire the ones opened before it. It can come
                     // from a relieved session that is still delivering, which is
                     // why the channel is shared and the id is carried.
                     Some(Event::Live(live)) => handover.on_live(live),
+                    Some(Event::Response(from, response)) if from == id => {
+                        let answer = subscriptions.on_response(&response, Instant::now());
+                        match answer {
+                            Answer::Accepted | Answer::Unknown => {}
+                            Answer::Retrying(delay) => warn!(
+                                endpoint = endpoint.label,
+                                group,
+                                connection,
+                                ?response,
+                                ?delay,
+                                "Binance refused a subscription request; retrying"
+                            ),
+                            Answer::GaveUp => error!(
+                                endpoint = endpoint.label,
+                                group,
+                                connection,
+                                ?response,
+                                attempts = MAX_SUBSCRIBE_ATTEMPTS,
+                                "subscription request rejected repeatedly; giving up until the next reconnect"
+                            ),
+                        }
+                    }
+                    // Answers to a relieved session's requests: its streams are
+                    // the replacement's concern now.
+                    Some(Event::Response(..)) => {}
                     Some(Event::Relieve(from, reason)) if from == id => {
                         break Step::Relieved(reason);
                     }
@@ -1001,6 +2083,7 @@ pub async fn keep_connection(
                     Some(Event::Relieve(from, _)) => {
                         tracing::debug!(
                             endpoint = endpoint.label,
+                            group,
                             connection,
                             session = from,
                             "ignoring a stale relieve"
@@ -1010,6 +2093,53 @@ pub async fn keep_connection(
                     // close while the loop runs.
                     None => break Step::Ended(SessionEnd::Finished),
                 },
+                Ok(()) = symbols.changed() => {
+                    let next = symbols.borrow_and_update().clone();
+                    let diff = SymbolDiff::between(&subscribed, &next);
+                    subscribed = next;
+                    subscriptions.change(&streams, &diff);
+                }
+                _ = pacer.tick(), if subscriptions.has_queued() => {
+                    let Some((request_id, request)) = subscriptions.next(Instant::now()) else {
+                        continue;
+                    };
+                    // A session that cannot be written to is about to end, and
+                    // its replacement opens with the current list.
+                    if let Err(error) = send_request(&sender, request_id, request).await {
+                        warn!(
+                            endpoint = endpoint.label,
+                            group,
+                            connection,
+                            ?error,
+                            "couldn't send a subscription request"
+                        );
+                    }
+                }
+                _ = sweep.tick(), if subscriptions.is_retrying() || subscriptions.is_awaiting() => {
+                    let now = Instant::now();
+                    for (request_id, answer) in subscriptions.time_out(now) {
+                        match answer {
+                            Answer::Retrying(delay) => warn!(
+                                endpoint = endpoint.label,
+                                group,
+                                connection,
+                                request_id,
+                                ?delay,
+                                "Binance did not answer a subscription request; retrying"
+                            ),
+                            Answer::GaveUp => error!(
+                                endpoint = endpoint.label,
+                                group,
+                                connection,
+                                request_id,
+                                attempts = MAX_SUBSCRIBE_ATTEMPTS,
+                                "subscription request unanswered or rejected repeatedly; giving up until the next reconnect"
+                            ),
+                            Answer::Accepted | Answer::Unknown => {}
+                        }
+                    }
+                    subscriptions.requeue_due(now);
+                }
                 end = &mut session => break Step::Ended(end),
             }
         };
@@ -1017,12 +2147,14 @@ pub async fn keep_connection(
         match step {
             Step::Relieved(reason) => {
                 let lifetime = opened.elapsed();
+                metrics::session_ended(venue, endpoint.label, group, connection, lifetime);
                 // Deliberately *not* retiring anything here. This session has
                 // asked to be replaced, which says nothing about whether the
                 // replacement will work — only `Event::Live` does.
                 handover.on_relieved(id, retire_tx);
                 warn!(
                     endpoint = endpoint.label,
+                    group,
                     connection,
                     reason = reason.as_str(),
                     ?lifetime,
@@ -1034,6 +2166,7 @@ pub async fn keep_connection(
                     let end = session.await;
                     tracing::info!(
                         endpoint = label,
+                        group,
                         connection,
                         ?end,
                         "the relieved session ended"
@@ -1059,6 +2192,7 @@ pub async fn keep_connection(
                 // keeps a state that cannot happen from becoming a spin.
                 warn!(
                     endpoint = endpoint.label,
+                    group,
                     connection,
                     session = id,
                     "the active session was retired; this should not happen"
@@ -1067,11 +2201,13 @@ pub async fn keep_connection(
             }
             Step::Ended(SessionEnd::Lost(error)) => {
                 let lifetime = opened.elapsed();
+                metrics::session_ended(venue, endpoint.label, group, connection, lifetime);
                 // The lifetime is what separates a venue recycling a healthy
                 // connection from this side failing: an `Unexpected EOF` after
                 // an hour is the former, one after a few seconds is the latter.
                 error!(
                     endpoint = endpoint.label,
+                    group,
                     connection,
                     ?error,
                     ?lifetime,
@@ -1108,6 +2244,7 @@ async fn back_off(error_count: &mut u32, lifetime: Option<Duration>) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::file::record_channel;
 
     static SPOT: Endpoint = Endpoint {
         label: "test-spot",
@@ -1115,6 +2252,8 @@ mod tests {
         depth_url: "https://example.invalid/depth?symbol=",
         idle_timeout: Duration::from_secs(75),
         depth_continuity: DepthContinuity::FirstUpdateId,
+        funding_urls: None,
+        user_data: None,
     };
 
     static FUTURES: Endpoint = Endpoint {
@@ -1123,6 +2262,8 @@ mod tests {
         depth_url: "https://example.invalid/depth?symbol=",
         idle_timeout: Duration::from_secs(300),
         depth_continuity: DepthContinuity::PrevUpdateId,
+        funding_urls: None,
+        user_data: None,
     };
 
     struct Harness {
@@ -1132,6 +2273,9 @@ mod tests {
         client: reqwest::Client,
         throttler: Throttler,
         tasks: JoinSet<()>,
+        /// The connection after the market ones, as [`run_collection`] numbers
+        /// the user data stream's.
+        account_connection: usize,
     }
 
     impl Harness {
@@ -1149,21 +2293,35 @@ mod tests {
                 // request to Binance from a unit test.
                 throttler: Throttler::new(0),
                 tasks: JoinSet::new(),
+                account_connection: connections,
             }
         }
 
         async fn feed(
             &mut self,
             endpoint: &'static Endpoint,
-            writer_tx: &Sender<WriteRecord>,
+            writer_tx: &RecordSender,
+            raw: &'static [u8],
+        ) -> Result<(), ConnectorError> {
+            self.feed_on(endpoint, writer_tx, 0, raw).await
+        }
+
+        async fn feed_on(
+            &mut self,
+            endpoint: &'static Endpoint,
+            writer_tx: &RecordSender,
+            connection: usize,
             raw: &'static [u8],
         ) -> Result<(), ConnectorError> {
             handle(
+                &Arc::from("spot"),
                 endpoint,
                 &mut self.prev_u_map,
                 writer_tx,
                 &mut self.symbols,
                 &mut self.dedup,
+                self.account_connection,
+                connection,
                 Timestamp::now(),
                 bytes::Bytes::from_static(raw),
                 &self.client,
@@ -1176,35 +2334,309 @@ mod tests {
 
     #[tokio::test]
     async fn routes_force_order_by_stream_symbol() {
-        let (writer_tx, mut writer_rx) = channel(1);
+        let (writer_tx, mut writer_rx) = record_channel(1);
         let mut harness = Harness::new("BTCUSDT");
         let raw = br#"{"stream":"btcusdt@forceOrder","data":{"e":"forceOrder","E":1591154240950,"o":{"s":"BTCUSDT","S":"SELL","o":"LIMIT","f":"IOC","q":"0.014","p":"9910","ap":"9910","X":"FILLED","l":"0.014","z":"0.014","T":1591154240949}}}"#;
 
         harness.feed(&FUTURES, &writer_tx, raw).await.unwrap();
 
-        let (_, symbol, written) = writer_rx.try_recv().unwrap();
+        let WriteRecord {
+            symbol,
+            data: written,
+            connection,
+            stream,
+            ..
+        } = writer_rx.try_recv().unwrap();
         assert_eq!(symbol.as_ref(), "btcusdt");
         assert_eq!(written, bytes::Bytes::from_static(raw));
+        assert_eq!((connection, stream), (Some(0), Stream::Liquidation));
         assert!(harness.prev_u_map.is_empty());
     }
 
+    /// Diff depth and partial-book depth share a prefix but not a meaning.
+    #[test]
+    fn stream_tags_follow_the_stream_name() {
+        for (name, stream) in [
+            ("btcusdt@depth", Stream::Depth),
+            ("btcusdt@depth@100ms", Stream::Depth),
+            ("btcusdt@depth20@100ms", Stream::DepthSnapshot),
+            ("btcusdt@aggTrade", Stream::AggTrade),
+            ("btcusdt@trade", Stream::Trade),
+            ("btcusdt@bookTicker", Stream::Bbo),
+            ("btcusdt@markPrice@1s", Stream::Funding),
+            ("!markPrice@arr", Stream::Funding),
+            ("btcusdt@kline_1m", Stream::Kline),
+            ("btcusdt@ticker", Stream::Other),
+        ] {
+            assert_eq!(stream_of(Some(name)), stream, "{name}");
+        }
+        assert_eq!(stream_of(None), Stream::Other);
+    }
+
+    #[test]
+    fn market_wide_streams_are_listed_once() {
+        let streams = [
+            "$symbol@markPrice@1s".to_owned(),
+            "!markPrice@arr".to_owned(),
+        ];
+        let symbols = ["BTCUSDT".to_owned(), "ethusdt".to_owned()];
+
+        assert_eq!(
+            stream_names(&streams, &symbols),
+            [
+                "btcusdt@markPrice@1s",
+                "ethusdt@markPrice@1s",
+                "!markPrice@arr"
+            ]
+        );
+    }
+
+    fn symbols(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("sym{i}usdt")).collect()
+    }
+
+    #[test]
+    fn large_universes_are_spread_over_groups_within_the_limits() {
+        let streams = [
+            "$symbol@trade".to_owned(),
+            "$symbol@depth@100ms".to_owned(),
+            "!markPrice@arr".to_owned(),
+        ];
+        let symbols = symbols(700);
+        let sharding = Sharding::new(Some(FUTURES.ws_stream_url), &streams, &symbols);
+
+        assert!(sharding.groups.len() > 1);
+        for (group, members) in sharding.groups.iter().enumerate() {
+            let names = stream_names(&sharding.streams(group), members);
+            let url = format!("{}{}", FUTURES.ws_stream_url, names.join("/"));
+            assert!(names.len() <= MAX_STREAMS_PER_CONNECTION);
+            assert!(url.len() <= MAX_URL_LEN);
+            assert_eq!(names.contains(&"!markPrice@arr".to_owned()), group == 0);
+        }
+        assert_eq!(sharding.groups.concat(), symbols);
+    }
+
+    #[test]
+    fn a_reload_only_moves_the_symbols_it_changes() {
+        let streams = ["$symbol@trade".to_owned()];
+        // Exactly one group's worth.
+        let before =
+            Sharding::new(Some(FUTURES.ws_stream_url), &streams, &symbols(2000)).groups[0].clone();
+        let mut sharding = Sharding::new(Some(FUTURES.ws_stream_url), &streams, &before);
+        assert_eq!(sharding.groups.len(), 1);
+
+        // A full group opens another for a new symbol…
+        let mut after = before.clone();
+        after.push("newusdt".to_owned());
+        sharding.apply(&SymbolDiff::between(&before, &after));
+        assert_eq!(sharding.groups[1], ["newusdt"]);
+
+        // …and one that frees room takes the next one back.
+        let before = after.clone();
+        after.retain(|symbol| symbol != "sym0usdt");
+        after.push("otherusdt".to_owned());
+        sharding.apply(&SymbolDiff::between(&before, &after));
+        assert_eq!(sharding.groups.len(), 2);
+        assert_eq!(sharding.groups[0].last().unwrap(), "otherusdt");
+        assert!(!sharding.groups[0].contains(&"sym0usdt".to_owned()));
+        assert_eq!(sharding.groups[1], ["newusdt"]);
+    }
+
+    fn accepted(id: u64) -> Response {
+        Response { id, error: None }
+    }
+
+    fn rejected(id: u64) -> Response {
+        Response {
+            id,
+            error: Some(ResponseError {
+                code: 2,
+                msg: "Invalid request".to_owned(),
+            }),
+        }
+    }
+
+    #[test]
+    fn responses_are_told_from_market_data() {
+        assert_eq!(response(br#"{"result":null,"id":3}"#), Some(accepted(3)));
+        assert_eq!(
+            response(br#"{"error":{"code":2,"msg":"Invalid request"},"id":4}"#),
+            Some(rejected(4))
+        );
+        assert_eq!(
+            response(br#"{"stream":"btcusdt@trade","data":{"e":"trade","s":"BTCUSDT"}}"#),
+            None
+        );
+        assert_eq!(response(ANNOUNCEMENT.as_bytes()), None);
+    }
+
+    #[test]
+    fn a_session_opened_bare_requests_its_streams_in_batches() {
+        let names = symbols(SUBSCRIBE_BATCH + 1);
+        let mut subscriptions = Subscriptions::open(&names);
+
+        let (first, request) = subscriptions.next(Instant::now()).unwrap();
+        assert_eq!(request.method, "SUBSCRIBE");
+        assert_eq!(request.params.len(), SUBSCRIBE_BATCH);
+        let (second, request) = subscriptions.next(Instant::now()).unwrap();
+        assert_eq!(request.params, [names[SUBSCRIBE_BATCH].clone()]);
+        assert!(subscriptions.next(Instant::now()).is_none());
+
+        assert_eq!(
+            subscriptions.on_response(&accepted(first), Instant::now()),
+            Answer::Accepted
+        );
+        assert!(!subscriptions.settled());
+        subscriptions.on_response(&accepted(second), Instant::now());
+        assert!(subscriptions.settled());
+        assert_eq!(
+            subscriptions.on_response(&accepted(second), Instant::now()),
+            Answer::Unknown
+        );
+    }
+
+    #[test]
+    fn a_rejected_request_is_retried_then_given_up() {
+        let mut subscriptions = Subscriptions::open(&["btcusdt@trade".to_owned()]);
+        let (id, _) = subscriptions.next(Instant::now()).unwrap();
+        let now = Instant::now();
+
+        assert_eq!(
+            subscriptions.on_response(&rejected(id), now),
+            Answer::Retrying(Duration::from_secs(1))
+        );
+        subscriptions.requeue_due(now);
+        assert!(!subscriptions.has_queued());
+        subscriptions.requeue_due(now + Duration::from_secs(1));
+        assert_eq!(subscriptions.next(Instant::now()).unwrap().0, id);
+
+        for _ in 1..MAX_SUBSCRIBE_ATTEMPTS {
+            assert!(matches!(
+                subscriptions.on_response(&rejected(id), now),
+                Answer::Retrying(_)
+            ));
+        }
+        assert_eq!(
+            subscriptions.on_response(&rejected(id), now),
+            Answer::GaveUp
+        );
+        assert!(subscriptions.settled());
+    }
+
+    /// Binance does not always answer. A request it leaves unanswered is
+    /// rejected once its answer is overdue, so it is retried and, in the end,
+    /// given up rather than keeping the session unsettled for good.
+    #[test]
+    fn an_unanswered_request_counts_as_rejected() {
+        let mut subscriptions = Subscriptions::open(&["btcusdt@trade".to_owned()]);
+        let sent = Instant::now();
+        let (id, _) = subscriptions.next(sent).unwrap();
+        assert!(subscriptions.is_awaiting());
+
+        assert!(subscriptions.time_out(sent).is_empty());
+        let overdue = sent + ANSWER_TIMEOUT;
+        assert_eq!(
+            subscriptions.time_out(overdue),
+            [(id, Answer::Retrying(Duration::from_secs(1)))]
+        );
+        assert!(!subscriptions.is_awaiting());
+        assert!(
+            subscriptions.time_out(overdue + ANSWER_TIMEOUT).is_empty(),
+            "a request waiting out its retry delay is not timed"
+        );
+
+        let mut now = overdue;
+        for _ in 0..MAX_SUBSCRIBE_ATTEMPTS {
+            now += Duration::from_secs(60);
+            subscriptions.requeue_due(now);
+            subscriptions.next(now).unwrap();
+            now += ANSWER_TIMEOUT;
+            subscriptions.time_out(now);
+        }
+        assert!(subscriptions.settled());
+    }
+
+    /// A reload that removes a symbol whose `SUBSCRIBE` is still waiting for
+    /// a retry must not see it subscribed again afterwards.
+    #[test]
+    fn a_reload_overrides_requests_still_outstanding() {
+        let streams = ["$symbol@trade".to_owned(), "!markPrice@arr".to_owned()];
+        let before = ["btcusdt".to_owned(), "ethusdt".to_owned()];
+        let mut subscriptions = Subscriptions::open(&stream_names(&streams, &before));
+        let (id, _) = subscriptions.next(Instant::now()).unwrap();
+        subscriptions.on_response(&rejected(id), Instant::now());
+
+        let after = ["btcusdt".to_owned(), "solusdt".to_owned()];
+        subscriptions.change(&streams, &SymbolDiff::between(&before, &after));
+        subscriptions.requeue_due(Instant::now() + Duration::from_secs(60));
+
+        let mut sent = Vec::new();
+        while let Some((_, request)) = subscriptions.next(Instant::now()) {
+            sent.push((request.method, request.params.clone()));
+        }
+        assert_eq!(
+            sent,
+            [
+                ("UNSUBSCRIBE", vec!["ethusdt@trade".to_owned()]),
+                ("SUBSCRIBE", vec!["solusdt@trade".to_owned()]),
+                (
+                    "SUBSCRIBE",
+                    vec!["btcusdt@trade".to_owned(), "!markPrice@arr".to_owned()]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_bare_url_drops_the_stream_list() {
+        assert_eq!(SPOT.bare_url(), "wss://example.invalid/stream");
+    }
+
+    /// Only the venue's symbols are kept from an all-market frame, each in its
+    /// own record.
+    #[tokio::test]
+    async fn all_market_frames_are_split_by_symbol() {
+        let (writer_tx, mut writer_rx) = record_channel(4);
+        let mut harness = Harness::new("BTCUSDT");
+        let raw = br#"{"stream":"!markPrice@arr","data":[{"e":"markPriceUpdate","E":1,"s":"ETHUSDT","p":"3000"},{"e":"markPriceUpdate","E":1,"s":"BTCUSDT","p":"60000"}]}"#;
+
+        harness.feed(&FUTURES, &writer_tx, raw).await.unwrap();
+
+        let WriteRecord {
+            symbol,
+            data,
+            stream,
+            ..
+        } = writer_rx.try_recv().unwrap();
+        assert_eq!(symbol.as_ref(), "btcusdt");
+        assert_eq!(
+            data,
+            bytes::Bytes::from_static(
+                br#"{"stream":"!markPrice@arr","data":{"e":"markPriceUpdate","E":1,"s":"BTCUSDT","p":"60000"}}"#
+            )
+        );
+        assert_eq!(stream, Stream::Funding);
+        assert!(writer_rx.try_recv().is_err());
+    }
+
     /// Spot's bookTicker carries no `e`; it must still be recorded.
     #[tokio::test]
     async fn spot_book_ticker_without_event_type_is_recorded() {
-        let (writer_tx, mut writer_rx) = channel(1);
+        let (writer_tx, mut writer_rx) = record_channel(1);
         let mut harness = Harness::new("BTCUSDT");
         let raw = br#"{"stream":"btcusdt@bookTicker","data":{"u":400900217,"s":"BTCUSDT","b":"25.35","B":"31.21","a":"25.36","A":"40.66"}}"#;
 
         harness.feed(&SPOT, &writer_tx, raw).await.unwrap();
 
-        let (_, symbol, _) = writer_rx.try_recv().unwrap();
+        let WriteRecord { symbol, stream, .. } = writer_rx.try_recv().unwrap();
         assert_eq!(symbol.as_ref(), "btcusdt");
+        assert_eq!(stream, Stream::Bbo);
     }
 
     #[tokio::test]
     async fn first_depth_update_is_not_treated_as_a_gap() {
         for endpoint in [&SPOT, &FUTURES] {
-            let (writer_tx, mut writer_rx) = channel(1);
+            let (writer_tx, mut writer_rx) = record_channel(1);
             let mut harness = Harness::new("BTCUSDT");
             let raw = br#"{"stream":"btcusdt@depth","data":{"e":"depthUpdate","E":1,"s":"BTCUSDT","U":2,"u":3,"pu":1,"b":[],"a":[]}}"#;
 
@@ -1227,7 +2659,7 @@ mod tests {
         let seed = br#"{"stream":"btcusdt@depth","data":{"e":"depthUpdate","E":1,"s":"BTCUSDT","U":2,"u":3,"pu":1,"b":[],"a":[]}}"#;
 
         for (endpoint, expect_snapshot) in [(&SPOT, false), (&FUTURES, true)] {
-            let (writer_tx, _writer_rx) = channel(8);
+            let (writer_tx, _writer_rx) = record_channel(8);
             let mut harness = Harness::new("BTCUSDT");
             harness.feed(endpoint, &writer_tx, seed).await.unwrap();
             assert!(harness.tasks.is_empty());
@@ -1279,7 +2711,7 @@ mod tests {
     /// re-stamped and must be suppressed instead of recorded twice.
     #[tokio::test]
     async fn an_e_stamped_second_copy_is_suppressed() {
-        let (writer_tx, mut writer_rx) = channel(4);
+        let (writer_tx, mut writer_rx) = record_channel(4);
         let mut harness = Harness::with_connections("BTCUSDT", 2);
         let first = br#"{"stream":"btcusdt@trade","data":{"e":"trade","E":1785671271715,"s":"BTCUSDT","t":6551542138,"p":"63160.00","q":"0.12","T":1785671271715,"m":false,"M":true}}"#;
         let second = br#"{"stream":"btcusdt@trade","data":{"e":"trade","E":1785671271716,"s":"BTCUSDT","t":6551542138,"p":"63160.00","q":"0.12","T":1785671271715,"m":false,"M":true}}"#;
@@ -1308,7 +2740,7 @@ mod tests {
     /// letting it through would report a gap on every single message.
     #[tokio::test]
     async fn a_second_connections_copy_is_neither_written_nor_read_as_a_gap() {
-        let (writer_tx, mut writer_rx) = channel(16);
+        let (writer_tx, mut writer_rx) = record_channel(16);
         let mut harness = Harness::with_connections("BTCUSDT", 2);
 
         for frame in DEPTH {
@@ -1318,7 +2750,7 @@ mod tests {
         }
 
         let mut written = Vec::new();
-        while let Ok((_, _, data)) = writer_rx.try_recv() {
+        while let Ok(WriteRecord { data, .. }) = writer_rx.try_recv() {
             written.push(data);
         }
         assert_eq!(written.len(), DEPTH.len(), "each frame is recorded once");
@@ -1330,7 +2762,7 @@ mod tests {
     /// leaves no hole, because the other one covers the frames it missed.
     #[tokio::test]
     async fn a_reconnect_on_one_connection_leaves_no_gap() {
-        let (writer_tx, mut writer_rx) = channel(16);
+        let (writer_tx, mut writer_rx) = record_channel(16);
         let mut harness = Harness::with_connections("BTCUSDT", 2);
 
         // Both connections are up for the first two frames.
@@ -1346,7 +2778,7 @@ mod tests {
         harness.feed(&FUTURES, &writer_tx, DEPTH[3]).await.unwrap();
 
         let mut written = Vec::new();
-        while let Ok((_, _, data)) = writer_rx.try_recv() {
+        while let Ok(WriteRecord { data, .. }) = writer_rx.try_recv() {
             written.push(data);
         }
         assert_eq!(written.len(), DEPTH.len(), "the stream is still complete");
@@ -1364,7 +2796,7 @@ mod tests {
     /// update mismatch as well.
     #[tokio::test]
     async fn a_straggler_fills_a_hole_rather_than_reporting_another() {
-        let (writer_tx, _writer_rx) = channel(16);
+        let (writer_tx, _writer_rx) = record_channel(16);
         let mut harness = Harness::with_connections("BTCUSDT", 2);
 
         harness.feed(&FUTURES, &writer_tx, DEPTH[0]).await.unwrap();
@@ -1393,7 +2825,7 @@ mod tests {
     /// above the new stream, or every later update reads as a hole forever.
     #[tokio::test]
     async fn a_restarted_book_resyncs_instead_of_wedging() {
-        let (writer_tx, _writer_rx) = channel(16);
+        let (writer_tx, _writer_rx) = record_channel(16);
         let mut harness = Harness::with_connections("BTCUSDT", 2);
         // Parked in the billions, as Binance ids really are.
         harness
@@ -1419,7 +2851,7 @@ mod tests {
     /// produce a duplicate, and paying for the filter would be pure overhead.
     #[tokio::test]
     async fn a_single_connection_records_every_frame_it_receives() {
-        let (writer_tx, mut writer_rx) = channel(16);
+        let (writer_tx, mut writer_rx) = record_channel(16);
         let mut harness = Harness::new("BTCUSDT");
 
         for frame in DEPTH {
@@ -1435,7 +2867,7 @@ mod tests {
 
     #[tokio::test]
     async fn control_frames_without_data_are_ignored() {
-        let (writer_tx, mut writer_rx) = channel(1);
+        let (writer_tx, mut writer_rx) = record_channel(1);
         let mut harness = Harness::new("BTCUSDT");
 
         harness
@@ -1460,7 +2892,7 @@ mod tests {
     struct SessionHarness {
         server: fastwebsockets::WebSocket<tokio::io::DuplexStream>,
         events: tokio::sync::mpsc::Receiver<Event>,
-        data: tokio::sync::mpsc::Receiver<(Timestamp, bytes::Bytes)>,
+        data: tokio::sync::mpsc::Receiver<Frame>,
         retire: Option<tokio::sync::oneshot::Sender<()>>,
         session: tokio::task::JoinHandle<SessionEnd>,
     }
@@ -1480,6 +2912,7 @@ mod tests {
                 event_tx,
                 retire_rx,
                 max_age,
+                None,
             ));
             Self {
                 server,
@@ -1551,13 +2984,181 @@ mod tests {
         ));
         harness.send_market_data().await;
 
-        let (_, first) = harness.data.recv().await.unwrap();
+        let (_, _, first) = harness.data.recv().await.unwrap();
         assert!(
             !ws::payload_contains(&first, b"serverShutdown"),
             "the announcement must not reach the writer"
         );
     }
 
+    /// Answers to requests go to the supervisor, not into the recording, and
+    /// do not count as the session carrying the feed.
+    #[tokio::test]
+    async fn responses_are_reported_rather_than_recorded() {
+        let mut harness = SessionHarness::start(NEVER);
+
+        harness.send_text(r#"{"result":null,"id":1}"#).await;
+        assert!(matches!(
+            harness.events.recv().await,
+            Some(Event::Response(
+                TEST_SESSION,
+                Response { id: 1, error: None }
+            ))
+        ));
+        harness.send_market_data().await;
+        assert!(matches!(
+            harness.events.recv().await,
+            Some(Event::Live(TEST_SESSION))
+        ));
+
+        let (_, _, first) = harness.data.recv().await.unwrap();
+        assert!(ws::payload_contains(&first, b"btcusdt@trade"));
+    }
+
+    /// A connection slot run by [`keep_connection_with`], every socket it opens
+    /// handed to the test as Binance's end, with the URL it was opened on.
+    struct SlotHarness {
+        peers: tokio::sync::mpsc::UnboundedReceiver<(
+            String,
+            fastwebsockets::WebSocket<tokio::io::DuplexStream>,
+        )>,
+        symbols: watch::Sender<Vec<String>>,
+        data: tokio::sync::mpsc::Receiver<Frame>,
+        slot: tokio::task::JoinHandle<()>,
+    }
+
+    impl SlotHarness {
+        fn start(symbols: &[&str], subscribe: bool) -> Self {
+            let (peer_tx, peers) = tokio::sync::mpsc::unbounded_channel();
+            let (symbols_tx, symbols_rx) =
+                watch::channel(symbols.iter().map(|&symbol| symbol.to_owned()).collect());
+            let (ws_tx, data) = tokio::sync::mpsc::channel(16);
+            let connect = move |url: String| {
+                let (conn, server) = ws::duplex_pair();
+                let _ = peer_tx.send((url, server));
+                async move { Ok(conn) }
+            };
+            let slot = tokio::spawn(keep_connection_with(
+                "spot",
+                &SPOT,
+                vec!["$symbol@trade".to_owned()],
+                symbols_rx,
+                0,
+                0,
+                ws_tx,
+                None,
+                subscribe,
+                connect,
+            ));
+            Self {
+                peers,
+                symbols: symbols_tx,
+                data,
+                slot,
+            }
+        }
+
+        async fn next_peer(
+            &mut self,
+        ) -> (String, fastwebsockets::WebSocket<tokio::io::DuplexStream>) {
+            self.peers.recv().await.unwrap()
+        }
+    }
+
+    impl Drop for SlotHarness {
+        fn drop(&mut self) {
+            self.slot.abort();
+        }
+    }
+
+    async fn send_text(
+        server: &mut fastwebsockets::WebSocket<tokio::io::DuplexStream>,
+        text: &str,
+    ) {
+        server
+            .write_frame(fastwebsockets::Frame::text(fastwebsockets::Payload::Owned(
+                text.as_bytes().to_vec(),
+            )))
+            .await
+            .unwrap();
+    }
+
+    async fn next_request(
+        server: &mut fastwebsockets::WebSocket<tokio::io::DuplexStream>,
+    ) -> serde_json::Value {
+        let frame = server.read_frame().await.unwrap();
+        serde_json::from_slice(&frame.payload).unwrap()
+    }
+
+    /// A reload is carried out on the open socket: the removed symbol's streams
+    /// are unsubscribed and the added one's subscribed, without reconnecting.
+    #[tokio::test]
+    async fn a_reload_subscribes_and_unsubscribes_on_the_open_session() {
+        let mut harness = SlotHarness::start(&["BTCUSDT"], false);
+        let (url, mut server) = harness.next_peer().await;
+        assert_eq!(url, format!("{}btcusdt@trade", SPOT.ws_stream_url));
+
+        harness.symbols.send_replace(vec!["ETHUSDT".to_owned()]);
+        assert_eq!(
+            next_request(&mut server).await,
+            serde_json::json!({ "method": "UNSUBSCRIBE", "params": ["btcusdt@trade"], "id": 1 })
+        );
+        send_text(&mut server, r#"{"result":null,"id":1}"#).await;
+        assert_eq!(
+            next_request(&mut server).await,
+            serde_json::json!({ "method": "SUBSCRIBE", "params": ["ethusdt@trade"], "id": 2 })
+        );
+
+        send_text(
+            &mut server,
+            r#"{"stream":"ethusdt@trade","data":{"e":"trade","E":1,"s":"ETHUSDT"}}"#,
+        )
+        .await;
+        let (_, _, frame) = harness.data.recv().await.unwrap();
+        assert!(ws::payload_contains(&frame, b"ethusdt@trade"));
+        assert!(
+            harness.peers.try_recv().is_err(),
+            "a reload must not open another session"
+        );
+    }
+
+    /// All-market frames are split by the symbols the cache was last seeded
+    /// with, so a reload's removed symbols stop being recorded from them and
+    /// its added ones start.
+    #[tokio::test]
+    async fn a_reseeded_cache_splits_all_market_frames_by_the_new_list() {
+        let (writer_tx, mut writer_rx) = record_channel(4);
+        let mut harness = Harness::new("BTCUSDT");
+        let recorded = |writer_rx: &mut tokio::sync::mpsc::Receiver<WriteRecord>| {
+            let mut symbols = Vec::new();
+            while let Ok(record) = writer_rx.try_recv() {
+                symbols.push(record.symbol.to_string());
+            }
+            symbols
+        };
+
+        harness
+            .feed(
+                &FUTURES,
+                &writer_tx,
+                br#"{"stream":"!markPrice@arr","data":[{"e":"markPriceUpdate","E":1,"s":"BTCUSDT","p":"1"},{"e":"markPriceUpdate","E":1,"s":"ETHUSDT","p":"2"}]}"#,
+            )
+            .await
+            .unwrap();
+        assert_eq!(recorded(&mut writer_rx), ["btcusdt"]);
+
+        harness.symbols.reseed(&["ETHUSDT".to_owned()]);
+        harness
+            .feed(
+                &FUTURES,
+                &writer_tx,
+                br#"{"stream":"!markPrice@arr","data":[{"e":"markPriceUpdate","E":2,"s":"BTCUSDT","p":"1"},{"e":"markPriceUpdate","E":2,"s":"ETHUSDT","p":"2"}]}"#,
+            )
+            .await
+            .unwrap();
+        assert_eq!(recorded(&mut writer_rx), ["ethusdt"]);
+    }
+
     /// Binance drops every connection at 24 hours, so a session must ask to be
     /// replaced before it gets there.
     #[tokio::test]
@@ -1617,6 +3218,7 @@ mod tests {
             &mut conn,
             &ws_tx,
             &mut overflow,
+            0,
             Instant::now() + DRAIN_GRACE,
         )
         .await;
@@ -1640,6 +3242,7 @@ mod tests {
             &mut conn,
             &ws_tx,
             &mut overflow,
+            0,
             Instant::now() + DRAIN_GRACE,
         )
         .await;
@@ -1763,4 +3366,167 @@ mod tests {
         );
         assert!(!is_server_shutdown(padded.as_bytes()));
     }
+
+    // ---- user data stream -------------------------------------------------
+
+    const EXECUTION_REPORT: &str = r#"{"e":"executionReport","E":1499405658658,"s":"BTCUSDT","c":"mUvoqJxFIILMdfAW5iGSOW","S":"BUY","o":"LIMIT","q":"1.00000000","p":"0.10264410","x":"NEW","X":"NEW","i":4293153}"#;
+
+    /// A REST peer on loopback that answers every request with `body` and
+    /// reports each request's head, standing in for Binance's listen key URL.
+    async fn rest_peer(
+        body: &'static str,
+    ) -> (String, tokio::sync::mpsc::UnboundedReceiver<String>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/listenKey", listener.local_addr().unwrap());
+        let (head_tx, heads) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut head = Vec::new();
+                let mut buf = [0; 1024];
+                while !head.windows(4).any(|end| end == b"\r\n\r\n") {
+                    let read = stream.read(&mut buf).await.unwrap();
+                    head.extend_from_slice(&buf[..read]);
+                }
+                let _ = head_tx.send(String::from_utf8_lossy(&head).to_lowercase());
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, heads)
+    }
+
+    /// A key is created with a `POST` and extended with a `PUT` naming it,
+    /// both carrying the API key; a refused request is `None`, not an error.
+    #[tokio::test]
+    async fn listen_keys_are_created_and_extended_with_the_api_key() {
+        let (url, mut heads) = rest_peer(
+            r#"{"listenKey":"pqia91ma19a5s61cv6a81va65sdf19v8a65a1a5s61cv6a81va65sdf19v8a65a1"}"#,
+        )
+        .await;
+        let urls = UserDataUrls {
+            listen_key: url.leak(),
+            ws_url: "wss://example.invalid/ws/",
+        };
+        let client = reqwest::Client::new();
+        let throttler = Throttler::new(10);
+        let credentials = Credentials::new("api-key".to_owned(), "secret".to_owned());
+
+        let key = listen_key(&SPOT, &urls, &client, &throttler, &credentials, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            key,
+            "pqia91ma19a5s61cv6a81va65sdf19v8a65a1a5s61cv6a81va65sdf19v8a65a1"
+        );
+        let created = heads.recv().await.unwrap();
+        assert!(created.starts_with("post /listenkey http/1.1"), "{created}");
+        assert!(created.contains("x-mbx-apikey: api-key"), "{created}");
+
+        let extended = listen_key(&SPOT, &urls, &client, &throttler, &credentials, Some(&key))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(extended, key);
+        let put = heads.recv().await.unwrap();
+        assert!(
+            put.starts_with(&format!("put /listenkey?listenkey={key} http/1.1")),
+            "{put}"
+        );
+        assert!(put.contains("x-mbx-apikey: api-key"), "{put}");
+
+        let refused = listen_key(
+            &SPOT,
+            &urls,
+            &client,
+            &Throttler::new(0),
+            &credentials,
+            None,
+        )
+        .await;
+        assert!(refused.is_none());
+        assert!(heads.try_recv().is_err(), "a refused request is never sent");
+    }
+
+    /// Account events are delivered as they come, and `listenKeyExpired` ends
+    /// the session, after it is delivered, so the next one asks for a new key.
+    #[tokio::test]
+    async fn the_user_data_session_ends_when_the_key_expires() {
+        let (conn, mut server) = ws::duplex_pair();
+        let (ws_tx, mut data) = tokio::sync::mpsc::channel(4);
+        let session = tokio::spawn(async move {
+            read_user_data(&SPOT, conn, std::future::pending(), 3, &ws_tx).await
+        });
+
+        send_text(&mut server, EXECUTION_REPORT).await;
+        let (connection, _, frame) = data.recv().await.unwrap();
+        assert_eq!((connection, &frame[..]), (3, EXECUTION_REPORT.as_bytes()));
+
+        send_text(&mut server, r#"{"e":"listenKeyExpired","E":1576653824250,"listenKey":"OfYGbUzi3PraNagEkdKuFwUHn48brFsItTdsuiIXrucEvD0rhRXZ7I6URWfE8YE8"}"#).await;
+        let (_, _, frame) = data.recv().await.unwrap();
+        assert!(ws::payload_contains(&frame, b"listenKeyExpired"));
+        let error = session.await.unwrap().unwrap_err();
+        assert!(error.to_string().contains("expired"), "{error}");
+    }
+
+    /// A key that cannot be extended will expire under the socket, so the
+    /// session ends as soon as the extension fails.
+    #[tokio::test]
+    async fn a_failed_extension_ends_the_user_data_session() {
+        let (conn, _server) = ws::duplex_pair();
+        let (ws_tx, _data) = tokio::sync::mpsc::channel(1);
+
+        let error = read_user_data(
+            &SPOT,
+            conn,
+            async { Err(anyhow!("-1125 This listenKey does not exist.")) },
+            3,
+            &ws_tx,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(
+            error.to_string().contains("extend the listen key"),
+            "{error}"
+        );
+    }
+
+    /// Account events name a symbol like market data does. Arriving on the
+    /// account's connection, they are filed under the account all the same.
+    #[tokio::test]
+    async fn frames_on_the_account_connection_are_filed_under_the_account() {
+        let (writer_tx, mut writer_rx) = record_channel(2);
+        let mut harness = Harness::new("BTCUSDT");
+        let account_connection = harness.account_connection;
+
+        harness
+            .feed_on(
+                &SPOT,
+                &writer_tx,
+                account_connection,
+                EXECUTION_REPORT.as_bytes(),
+            )
+            .await
+            .unwrap();
+
+        let WriteRecord {
+            symbol,
+            data,
+            connection,
+            stream,
+            ..
+        } = writer_rx.try_recv().unwrap();
+        assert_eq!(symbol.as_ref(), account::SYMBOL);
+        assert_eq!(&data[..], EXECUTION_REPORT.as_bytes());
+        assert_eq!(stream, Stream::Account);
+        assert_eq!(connection, u8::try_from(account_connection).ok());
+        assert!(writer_rx.try_recv().is_err());
+    }
 }
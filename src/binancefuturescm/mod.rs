@@ -1,10 +1,11 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
-use tokio::sync::{mpsc::Sender, watch};
+use tokio::sync::watch;
 
 use crate::{
-    binance_market::{self, DepthContinuity, Endpoint},
-    file::WriteRecord,
+    account::Credentials,
+    binance_market::{self, DepthContinuity, Endpoint, FundingUrls, UserDataUrls},
+    file::RecordSender,
 };
 
 static ENDPOINT: Endpoint = Endpoint {
@@ -14,22 +15,41 @@ static ENDPOINT: Endpoint = Endpoint {
     // Futures pings every 3 minutes; allow well over one missed ping.
     idle_timeout: Duration::from_secs(300),
     depth_continuity: DepthContinuity::PrevUpdateId,
+    funding_urls: Some(FundingUrls {
+        open_interest: "https://dapi.binance.com/dapi/v1/openInterest?symbol=",
+        premium_index: "https://dapi.binance.com/dapi/v1/premiumIndex?symbol=",
+    }),
+    user_data: Some(UserDataUrls {
+        listen_key: "https://dapi.binance.com/dapi/v1/listenKey",
+        ws_url: "wss://dstream.binance.com/ws/",
+    }),
 };
 
+#[allow(clippy::too_many_arguments)]
 pub async fn run_collection(
+    venue: Arc<str>,
     streams: Vec<String>,
-    symbols: Vec<String>,
-    writer_tx: Sender<WriteRecord>,
+    symbols: watch::Receiver<Vec<String>>,
+    writer_tx: RecordSender,
     shutdown: watch::Receiver<bool>,
     connections: usize,
+    snapshot_interval: Duration,
+    funding_interval: Option<Duration>,
+    account: Option<Credentials>,
+    subscribe: bool,
 ) -> Result<(), anyhow::Error> {
     binance_market::run_collection(
+        venue,
         &ENDPOINT,
         streams,
         symbols,
         writer_tx,
         shutdown,
         connections,
+        snapshot_interval,
+        funding_interval,
+        account,
+        subscribe,
     )
     .await
 }
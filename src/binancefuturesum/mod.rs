@@ -1,10 +1,11 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
-use tokio::sync::{mpsc::Sender, watch};
+use tokio::sync::watch;
 
 use crate::{
-    binance_market::{self, DepthContinuity, Endpoint},
-    file::WriteRecord,
+    account::Credentials,
+    binance_market::{self, DepthContinuity, Endpoint, FundingUrls, UserDataUrls},
+    file::RecordSender,
 };
 
 static ENDPOINT: Endpoint = Endpoint {
@@ -14,6 +15,14 @@ static ENDPOINT: Endpoint = Endpoint {
     // Futures pings every 3 minutes; allow well over one missed ping.
     idle_timeout: Duration::from_secs(300),
     depth_continuity: DepthContinuity::PrevUpdateId,
+    funding_urls: Some(FundingUrls {
+        open_interest: "https://fapi.binance.com/fapi/v1/openInterest?symbol=",
+        premium_index: "https://fapi.binance.com/fapi/v1/premiumIndex?symbol=",
+    }),
+    user_data: Some(UserDataUrls {
+        listen_key: "https://fapi.binance.com/fapi/v1/listenKey",
+        ws_url: "wss://fstream.binance.com/ws/",
+    }),
 };
 
 /// Post-CM-migration, `forceOrder` (and `aggTrade`) moved to the
@@ -28,32 +37,55 @@ static MARKET_ENDPOINT: Endpoint = Endpoint {
     depth_url: "https://fapi.binance.com/fapi/v1/depth?symbol=",
     idle_timeout: Duration::from_secs(300),
     depth_continuity: DepthContinuity::PrevUpdateId,
+    // The legacy worker polls, and opens the account's stream; one of each
+    // per market is enough.
+    funding_urls: None,
+    user_data: None,
 };
 
+/// True if `stream` is served only on the `/market/` path: liquidations,
+/// aggregate trades, mark price and klines, per symbol or market-wide.
+fn is_market_stream(stream: &str) -> bool {
+    ["forceOrder", "aggTrade", "markPrice", "kline_"]
+        .iter()
+        .any(|kind| stream.contains(kind))
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn run_collection(
+    venue: Arc<str>,
     streams: Vec<String>,
-    symbols: Vec<String>,
-    writer_tx: Sender<WriteRecord>,
+    symbols: watch::Receiver<Vec<String>>,
+    writer_tx: RecordSender,
     shutdown: watch::Receiver<bool>,
     connections: usize,
+    snapshot_interval: Duration,
+    funding_interval: Option<Duration>,
+    account: Option<Credentials>,
+    subscribe: bool,
 ) -> Result<(), anyhow::Error> {
-    // Split the requested streams by endpoint family: forceOrder (and
-    // aggTrade, if ever requested) must go to the market path.
+    // Split the requested streams by endpoint family; see `is_market_stream`.
     let (market_streams, legacy_streams): (Vec<String>, Vec<String>) = streams
         .into_iter()
-        .partition(|s| s.contains("forceOrder") || s.contains("aggTrade"));
+        .partition(|stream| is_market_stream(stream));
     if !market_streams.is_empty() {
         let symbols = symbols.clone();
         let writer_tx = writer_tx.clone();
         let shutdown = shutdown.clone();
+        let venue = Arc::clone(&venue);
         tokio::spawn(async move {
             if let Err(error) = binance_market::run_collection(
+                venue,
                 &MARKET_ENDPOINT,
                 market_streams,
                 symbols,
                 writer_tx,
                 shutdown,
                 connections,
+                snapshot_interval,
+                None,
+                None,
+                subscribe,
             )
             .await
             {
@@ -63,12 +95,38 @@ pub async fn run_collection(
     }
     let streams = legacy_streams;
     binance_market::run_collection(
+        venue,
         &ENDPOINT,
         streams,
         symbols,
         writer_tx,
         shutdown,
         connections,
+        snapshot_interval,
+        funding_interval,
+        account,
+        subscribe,
     )
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn market_path_streams_are_split_off() {
+        for stream in [
+            "$symbol@forceOrder",
+            "$symbol@aggTrade",
+            "$symbol@markPrice@1s",
+            "!markPrice@arr",
+            "$symbol@kline_1m",
+        ] {
+            assert!(is_market_stream(stream), "{stream}");
+        }
+        for stream in ["$symbol@trade", "$symbol@bookTicker", "$symbol@depth@0ms"] {
+            assert!(!is_market_stream(stream), "{stream}");
+        }
+    }
+}
@@ -0,0 +1,788 @@
+//! Order books rebuilt from recorded depth streams.
+//!
+//! The collector records depth diffs together with the snapshots that anchor
+//! them: Binance REST snapshots from the periodic and gap-triggered fetches,
+//! the snapshot Bybit sends on every `orderbook.N` subscribe, and Hyperliquid
+//! `l2Book` messages, each of which is a whole book. [`BookBuilder`] feeds
+//! those payloads, in recorded order, through each venue's rules and keeps
+//! the book only for as long as it can prove it: every diff must chain onto
+//! the one before it, and a book that loses the chain stays unknown until a
+//! snapshot anchors it again.
+//!
+//! Redundant connections mean a recording can hold late copies of events
+//! already applied. Those are recognised by their ids and skipped; they are
+//! not breaks.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+};
+
+use serde::Deserialize;
+use serde_json::value::RawValue;
+
+/// How a market's depth stream signals that an update was missed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DepthContinuity {
+    /// Spot: each update's `U` must be the previous update's `u` plus one.
+    FirstUpdateId,
+    /// Futures: each update carries the previous update id in `pu`.
+    PrevUpdateId,
+}
+
+/// Which venue's messages a [`BookBuilder`] reads.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Market {
+    /// `@depth` diffs anchored by REST snapshots.
+    Binance(DepthContinuity),
+    /// `orderbook.<depth>` snapshots and deltas. A recording may carry several
+    /// depths for one symbol; `None` follows the first one seen.
+    Bybit { depth: Option<u32> },
+    /// `l2Book`, a whole book per message.
+    Hyperliquid,
+}
+
+/// Diffs held while waiting for a Binance snapshot, oldest dropped first.
+///
+/// A gap-triggered snapshot lands a REST round trip after the gap, and the
+/// diffs recorded meanwhile are what carry the book forward from it. Seconds
+/// of the busiest book fit well within this.
+const MAX_BUFFERED_DIFFS: usize = 10_000;
+
+/// A price as an orderable key. Prices are positive, and the bit patterns of
+/// positive doubles sort like the values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Price(u64);
+
+/// One price level, as the venue wrote it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Level {
+    pub price: String,
+    pub quantity: String,
+}
+
+/// Both sides of a book.
+#[derive(Clone, Debug, Default)]
+pub struct Book {
+    bids: BTreeMap<Price, Level>,
+    asks: BTreeMap<Price, Level>,
+}
+
+impl Book {
+    /// Bids, best first.
+    pub fn bids(&self) -> impl Iterator<Item = &Level> {
+        self.bids.values().rev()
+    }
+
+    /// Asks, best first.
+    pub fn asks(&self) -> impl Iterator<Item = &Level> {
+        self.asks.values()
+    }
+
+    fn clear(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+    }
+
+    fn is_crossed(&self) -> bool {
+        matches!(
+            (self.bids.last_key_value(), self.asks.first_key_value()),
+            (Some((bid, _)), Some((ask, _))) if bid >= ask
+        )
+    }
+
+    fn update<'a>(
+        &mut self,
+        bids: impl IntoIterator<Item = (&'a str, &'a str)>,
+        asks: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<(), Break> {
+        set_levels(&mut self.bids, bids)?;
+        set_levels(&mut self.asks, asks)
+    }
+}
+
+/// Set each level to its new quantity; zero removes it.
+fn set_levels<'a>(
+    side: &mut BTreeMap<Price, Level>,
+    levels: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Result<(), Break> {
+    for (price, quantity) in levels {
+        let key = parse_price(price)?;
+        let size: f64 = quantity
+            .parse()
+            .map_err(|_| Break::Malformed(format!("quantity {quantity:?}")))?;
+        if size == 0.0 {
+            side.remove(&key);
+        } else {
+            side.insert(
+                key,
+                Level {
+                    price: price.to_owned(),
+                    quantity: quantity.to_owned(),
+                },
+            );
+        }
+    }
+    Ok(())
+}
+
+fn parse_price(price: &str) -> Result<Price, Break> {
+    match price.parse::<f64>() {
+        Ok(value) if value.is_finite() && value >= 0.0 => Ok(Price(value.to_bits())),
+        _ => Err(Break::Malformed(format!("price {price:?}"))),
+    }
+}
+
+/// Why a book could not be proven.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Break {
+    /// A diff that does not follow the one before it.
+    Gap { expected: i64, got: i64 },
+    /// A snapshot older than the first diff held for it: the updates between
+    /// the two were never recorded.
+    Unanchored { snapshot: i64, first: i64 },
+    /// The best bid reached the best ask, which no correct book does.
+    Crossed,
+    /// A book message that could not be read.
+    Malformed(String),
+}
+
+impl Break {
+    /// A short name for the kind of break.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Break::Gap { .. } => "gap",
+            Break::Unanchored { .. } => "unanchored",
+            Break::Crossed => "crossed",
+            Break::Malformed(_) => "malformed",
+        }
+    }
+}
+
+impl fmt::Display for Break {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Break::Gap { expected, got } => {
+                write!(f, "update id {got} does not follow {expected}")
+            }
+            Break::Unanchored { snapshot, first } => write!(
+                f,
+                "snapshot at {snapshot} does not reach the first held update {first}"
+            ),
+            Break::Crossed => f.write_str("best bid at or above best ask"),
+            Break::Malformed(what) => write!(f, "unreadable {what}"),
+        }
+    }
+}
+
+/// What one payload did to the book.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Not about this book, or a late copy of something already applied.
+    Ignored,
+    /// A diff held until a snapshot can anchor it.
+    Buffered,
+    /// The book was rebuilt from a snapshot and is proven from here on.
+    Synced,
+    /// A diff, or for Hyperliquid a newer whole book, carried the proven
+    /// book forward.
+    Applied,
+    /// The book can no longer be proven, and is not until the next snapshot
+    /// that anchors it.
+    Broken(Break),
+}
+
+/// A Binance diff, owned so it can wait for a snapshot.
+struct Diff {
+    first: i64,
+    last: i64,
+    prev: Option<i64>,
+    bids: Vec<(String, String)>,
+    asks: Vec<(String, String)>,
+}
+
+enum Chain {
+    Apply,
+    /// Already covered by the book.
+    Stale,
+    Gap {
+        expected: i64,
+        got: i64,
+    },
+}
+
+/// Whether `diff` follows a book at update id `last`. `anchor` is set while
+/// the last id is a snapshot's rather than a diff's.
+fn chain(rule: DepthContinuity, last: i64, anchor: bool, diff: &Diff) -> Chain {
+    match rule {
+        // A window that overlaps the book is applied whole: it carries each
+        // level it touches as of its last update, so re-setting levels the
+        // book already holds is harmless. Redundant spot connections coalesce
+        // their 100 ms windows differently and produce exactly these.
+        DepthContinuity::FirstUpdateId if diff.last <= last => Chain::Stale,
+        DepthContinuity::FirstUpdateId if diff.first <= last + 1 => Chain::Apply,
+        DepthContinuity::FirstUpdateId => Chain::Gap {
+            expected: last + 1,
+            got: diff.first,
+        },
+        // Binance: the first update after a snapshot is the one with
+        // `U <= lastUpdateId <= u`; from then on each `pu` is the previous `u`.
+        DepthContinuity::PrevUpdateId if anchor => {
+            if diff.last < last {
+                Chain::Stale
+            } else if diff.first <= last {
+                Chain::Apply
+            } else {
+                Chain::Gap {
+                    expected: last,
+                    got: diff.first,
+                }
+            }
+        }
+        DepthContinuity::PrevUpdateId if diff.last <= last => Chain::Stale,
+        DepthContinuity::PrevUpdateId => match diff.prev {
+            Some(prev) if prev == last => Chain::Apply,
+            prev => Chain::Gap {
+                expected: last,
+                got: prev.unwrap_or(diff.first),
+            },
+        },
+    }
+}
+
+enum State {
+    /// No proven book. Binance diffs wait here for a snapshot.
+    Unproven { held: VecDeque<Diff> },
+    /// The book is proven up to `last`, an update id (Binance, Bybit) or a
+    /// message time (Hyperliquid).
+    Proven { last: i64, anchor: bool },
+}
+
+/// Rebuilds one symbol's book from its recorded payloads.
+pub struct BookBuilder {
+    market: Market,
+    book: Book,
+    state: State,
+}
+
+impl BookBuilder {
+    pub fn new(market: Market) -> Self {
+        Self {
+            market,
+            book: Book::default(),
+            state: State::Unproven {
+                held: VecDeque::new(),
+            },
+        }
+    }
+
+    pub fn market(&self) -> Market {
+        self.market
+    }
+
+    /// The book, while it is proven.
+    pub fn book(&self) -> Option<&Book> {
+        matches!(self.state, State::Proven { .. }).then_some(&self.book)
+    }
+
+    /// Apply one recorded payload.
+    pub fn apply(&mut self, payload: &[u8]) -> Outcome {
+        let outcome = match self.market {
+            Market::Binance(rule) => self.binance(rule, payload),
+            Market::Bybit { .. } => self.bybit(payload),
+            Market::Hyperliquid => self.hyperliquid(payload),
+        };
+        let outcome = match outcome {
+            Ok(Outcome::Applied | Outcome::Synced) if self.book.is_crossed() => {
+                Outcome::Broken(Break::Crossed)
+            }
+            Ok(outcome) => outcome,
+            Err(broken) => Outcome::Broken(broken),
+        };
+        if matches!(outcome, Outcome::Broken(_)) && matches!(self.state, State::Proven { .. }) {
+            self.state = State::Unproven {
+                held: VecDeque::new(),
+            };
+        }
+        outcome
+    }
+
+    fn binance(&mut self, rule: DepthContinuity, payload: &[u8]) -> Result<Outcome, Break> {
+        let Ok(frame) = serde_json::from_slice::<BinanceFrame>(payload) else {
+            return Ok(Outcome::Ignored);
+        };
+        if let Some(snapshot) = frame.last_update_id {
+            return self.binance_snapshot(rule, snapshot, &frame.bids, &frame.asks);
+        }
+        // Only the diff stream: `depth5`/`depth20` are partial books with no
+        // ids to chain.
+        let (Some(stream), Some(data)) = (frame.stream, frame.data) else {
+            return Ok(Outcome::Ignored);
+        };
+        if stream.split('@').nth(1) != Some("depth") {
+            return Ok(Outcome::Ignored);
+        }
+        let event = serde_json::from_str::<BinanceDiff>(data.get())
+            .map_err(|_| Break::Malformed("depth update".to_owned()))?;
+        let diff = Diff {
+            first: event.first,
+            last: event.last,
+            prev: event.prev,
+            bids: owned(&event.bids),
+            asks: owned(&event.asks),
+        };
+
+        match &mut self.state {
+            State::Unproven { held } => {
+                if held.len() == MAX_BUFFERED_DIFFS {
+                    held.pop_front();
+                }
+                held.push_back(diff);
+                Ok(Outcome::Buffered)
+            }
+            State::Proven { last, anchor } => match chain(rule, *last, *anchor, &diff) {
+                Chain::Stale => Ok(Outcome::Ignored),
+                Chain::Apply => {
+                    *last = diff.last;
+                    *anchor = false;
+                    self.book
+                        .update(borrowed(&diff.bids), borrowed(&diff.asks))?;
+                    Ok(Outcome::Applied)
+                }
+                Chain::Gap { expected, got } => {
+                    // Held for the snapshot that repairs this.
+                    self.state = State::Unproven {
+                        held: VecDeque::from([diff]),
+                    };
+                    Err(Break::Gap { expected, got })
+                }
+            },
+        }
+    }
+
+    fn binance_snapshot(
+        &mut self,
+        rule: DepthContinuity,
+        snapshot: i64,
+        bids: &[[&str; 2]],
+        asks: &[[&str; 2]],
+    ) -> Result<Outcome, Break> {
+        // A proven book has nothing to learn from a snapshot, which was taken
+        // at some unknown point of the stream a REST round trip ago.
+        let State::Unproven { held } = &mut self.state else {
+            return Ok(Outcome::Ignored);
+        };
+        let mut held = std::mem::take(held);
+        self.book.clear();
+        self.book.update(pairs(bids), pairs(asks))?;
+        let (mut last, mut anchor) = (snapshot, true);
+        while let Some(diff) = held.pop_front() {
+            match chain(rule, last, anchor, &diff) {
+                Chain::Stale => {}
+                Chain::Apply => {
+                    self.book
+                        .update(borrowed(&diff.bids), borrowed(&diff.asks))?;
+                    (last, anchor) = (diff.last, false);
+                }
+                Chain::Gap { got, .. } => {
+                    held.push_front(diff);
+                    self.state = State::Unproven { held };
+                    return Err(if anchor {
+                        Break::Unanchored {
+                            snapshot,
+                            first: got,
+                        }
+                    } else {
+                        // The held diffs themselves have a hole.
+                        Break::Gap {
+                            expected: last,
+                            got,
+                        }
+                    });
+                }
+            }
+        }
+        self.state = State::Proven { last, anchor };
+        Ok(Outcome::Synced)
+    }
+
+    fn bybit(&mut self, payload: &[u8]) -> Result<Outcome, Break> {
+        let Ok(frame) = serde_json::from_slice::<BybitFrame>(payload) else {
+            return Ok(Outcome::Ignored);
+        };
+        let (Some(topic), Some(data)) = (frame.topic, frame.data) else {
+            return Ok(Outcome::Ignored);
+        };
+        let Some(depth) = topic
+            .strip_prefix("orderbook.")
+            .and_then(|rest| rest.split('.').next())
+            .and_then(|depth| depth.parse::<u32>().ok())
+        else {
+            return Ok(Outcome::Ignored);
+        };
+        let Market::Bybit { depth: wanted } = &mut self.market else {
+            unreachable!("only called for Bybit");
+        };
+        if *wanted.get_or_insert(depth) != depth {
+            return Ok(Outcome::Ignored);
+        }
+        let book = serde_json::from_str::<BybitBook>(data.get())
+            .map_err(|_| Break::Malformed(format!("{topic} message")))?;
+
+        let last = match self.state {
+            State::Proven { last, .. } => Some(last),
+            State::Unproven { .. } => None,
+        };
+        match frame.kind {
+            Some("snapshot") => {
+                // Each connection gets its own snapshot when it subscribes,
+                // and one from a lagging connection would rewind the book.
+                // `u = 1` is Bybit restarting the book, never a late copy.
+                if last.is_some_and(|last| book.update_id <= last) && book.update_id != 1 {
+                    return Ok(Outcome::Ignored);
+                }
+                self.book.clear();
+                self.book.update(pairs(&book.bids), pairs(&book.asks))?;
+                self.state = State::Proven {
+                    last: book.update_id,
+                    anchor: true,
+                };
+                Ok(Outcome::Synced)
+            }
+            Some("delta") => match last {
+                // Bybit sends a snapshot on every subscribe; until one is seen
+                // there is nothing to apply a delta to.
+                None => Ok(Outcome::Ignored),
+                Some(last) if book.update_id <= last => Ok(Outcome::Ignored),
+                Some(last) if book.update_id == last + 1 => {
+                    self.book.update(pairs(&book.bids), pairs(&book.asks))?;
+                    self.state = State::Proven {
+                        last: book.update_id,
+                        anchor: false,
+                    };
+                    Ok(Outcome::Applied)
+                }
+                Some(last) => Err(Break::Gap {
+                    expected: last + 1,
+                    got: book.update_id,
+                }),
+            },
+            _ => Ok(Outcome::Ignored),
+        }
+    }
+
+    fn hyperliquid(&mut self, payload: &[u8]) -> Result<Outcome, Break> {
+        let Ok(frame) = serde_json::from_slice::<HyperliquidFrame>(payload) else {
+            return Ok(Outcome::Ignored);
+        };
+        let book = match (frame.channel, frame.data) {
+            (Some("l2Book"), Some(data)) => serde_json::from_str::<L2Book>(data.get())
+                .map_err(|_| Break::Malformed("l2Book message".to_owned()))?,
+            // A book fetched from the info endpoint: the same payload without
+            // the channel envelope.
+            (None, None) => match serde_json::from_slice::<L2Book>(payload) {
+                Ok(book) => book,
+                Err(_) => return Ok(Outcome::Ignored),
+            },
+            _ => return Ok(Outcome::Ignored),
+        };
+        // Every message is the whole book, so nothing chains: a newer one
+        // carries a proven book forward, an older one is a lagging
+        // connection's copy.
+        let outcome = match self.state {
+            State::Proven { last, .. } if book.time <= last => return Ok(Outcome::Ignored),
+            State::Proven { .. } => Outcome::Applied,
+            State::Unproven { .. } => Outcome::Synced,
+        };
+        let [bids, asks] = &book.levels;
+        self.book.clear();
+        self.book.update(
+            bids.iter().map(|level| (level.px, level.sz)),
+            asks.iter().map(|level| (level.px, level.sz)),
+        )?;
+        self.state = State::Proven {
+            last: book.time,
+            anchor: true,
+        };
+        Ok(outcome)
+    }
+}
+
+fn pairs<'a>(levels: &'a [[&'a str; 2]]) -> impl Iterator<Item = (&'a str, &'a str)> {
+    levels.iter().map(|[price, quantity]| (*price, *quantity))
+}
+
+fn owned(levels: &[[&str; 2]]) -> Vec<(String, String)> {
+    pairs(levels)
+        .map(|(price, quantity)| (price.to_owned(), quantity.to_owned()))
+        .collect()
+}
+
+fn borrowed(levels: &[(String, String)]) -> impl Iterator<Item = (&str, &str)> {
+    levels
+        .iter()
+        .map(|(price, quantity)| (price.as_str(), quantity.as_str()))
+}
+
+/// A combined-stream frame, or a REST depth snapshot.
+#[derive(Deserialize)]
+struct BinanceFrame<'a> {
+    #[serde(borrow)]
+    stream: Option<&'a str>,
+    #[serde(borrow)]
+    data: Option<&'a RawValue>,
+    #[serde(rename = "lastUpdateId")]
+    last_update_id: Option<i64>,
+    #[serde(borrow, default)]
+    bids: Vec<[&'a str; 2]>,
+    #[serde(borrow, default)]
+    asks: Vec<[&'a str; 2]>,
+}
+
+#[derive(Deserialize)]
+struct BinanceDiff<'a> {
+    #[serde(rename = "U")]
+    first: i64,
+    #[serde(rename = "u")]
+    last: i64,
+    #[serde(rename = "pu")]
+    prev: Option<i64>,
+    #[serde(rename = "b", borrow)]
+    bids: Vec<[&'a str; 2]>,
+    #[serde(rename = "a", borrow)]
+    asks: Vec<[&'a str; 2]>,
+}
+
+#[derive(Deserialize)]
+struct BybitFrame<'a> {
+    #[serde(borrow)]
+    topic: Option<&'a str>,
+    #[serde(borrow, rename = "type")]
+    kind: Option<&'a str>,
+    #[serde(borrow)]
+    data: Option<&'a RawValue>,
+}
+
+#[derive(Deserialize)]
+struct BybitBook<'a> {
+    #[serde(rename = "b", borrow)]
+    bids: Vec<[&'a str; 2]>,
+    #[serde(rename = "a", borrow)]
+    asks: Vec<[&'a str; 2]>,
+    #[serde(rename = "u")]
+    update_id: i64,
+}
+
+#[derive(Deserialize)]
+struct HyperliquidFrame<'a> {
+    #[serde(borrow)]
+    channel: Option<&'a str>,
+    #[serde(borrow)]
+    data: Option<&'a RawValue>,
+}
+
+#[derive(Deserialize)]
+struct L2Book<'a> {
+    time: i64,
+    #[serde(borrow)]
+    levels: [Vec<L2Level<'a>>; 2],
+}
+
+#[derive(Deserialize)]
+struct L2Level<'a> {
+    px: &'a str,
+    sz: &'a str,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn top(builder: &BookBuilder) -> Option<(String, String)> {
+        let book = builder.book()?;
+        Some((
+            book.bids().next()?.price.clone(),
+            book.asks().next()?.price.clone(),
+        ))
+    }
+
+    fn prices(builder: &BookBuilder) -> (Vec<&str>, Vec<&str>) {
+        let book = builder.book().expect("proven");
+        (
+            book.bids().map(|level| level.price.as_str()).collect(),
+            book.asks().map(|level| level.price.as_str()).collect(),
+        )
+    }
+
+    fn spot_diff(first: i64, last: i64, bids: &str, asks: &str) -> Vec<u8> {
+        format!(
+            r#"{{"stream":"btcusdt@depth@100ms","data":{{"e":"depthUpdate","E":1,"s":"BTCUSDT","U":{first},"u":{last},"b":{bids},"a":{asks}}}}}"#
+        )
+        .into_bytes()
+    }
+
+    fn futures_diff(first: i64, last: i64, prev: i64, bids: &str) -> Vec<u8> {
+        format!(
+            r#"{{"stream":"btcusdt@depth@100ms","data":{{"e":"depthUpdate","E":1,"s":"BTCUSDT","U":{first},"u":{last},"pu":{prev},"b":{bids},"a":[]}}}}"#
+        )
+        .into_bytes()
+    }
+
+    const SNAPSHOT: &[u8] =
+        br#"{"lastUpdateId":10,"bids":[["100.0","1"],["99.5","2"]],"asks":[["101","1"]]}"#;
+
+    #[test]
+    fn spot_diffs_held_before_the_snapshot_carry_it_forward() {
+        let mut builder = BookBuilder::new(Market::Binance(DepthContinuity::FirstUpdateId));
+
+        // Recorded while the snapshot request was in flight.
+        for diff in [
+            spot_diff(5, 8, "[]", "[]"),
+            spot_diff(9, 12, r#"[["100","0"]]"#, "[]"),
+        ] {
+            assert_eq!(builder.apply(&diff), Outcome::Buffered);
+        }
+        assert_eq!(builder.apply(SNAPSHOT), Outcome::Synced);
+        assert_eq!(prices(&builder), (vec!["99.5"], vec!["101"]));
+
+        // The other connection's differently coalesced window overlaps.
+        let overlap = spot_diff(11, 14, r#"[["99.8","3"]]"#, "[]");
+        assert_eq!(builder.apply(&overlap), Outcome::Applied);
+        assert_eq!(
+            builder.apply(&spot_diff(12, 13, "[]", "[]")),
+            Outcome::Ignored
+        );
+        assert_eq!(top(&builder), Some(("99.8".into(), "101".into())));
+
+        let hole = spot_diff(16, 17, "[]", "[]");
+        assert_eq!(
+            builder.apply(&hole),
+            Outcome::Broken(Break::Gap {
+                expected: 15,
+                got: 16
+            })
+        );
+        assert!(builder.book().is_none());
+    }
+
+    #[test]
+    fn futures_chain_on_pu_and_resync_from_the_next_snapshot() {
+        let mut builder = BookBuilder::new(Market::Binance(DepthContinuity::PrevUpdateId));
+        assert_eq!(builder.apply(SNAPSHOT), Outcome::Synced);
+        // The first update after the snapshot straddles it; `pu` is not checked.
+        let first = futures_diff(8, 12, 7, r#"[["100.5","1"]]"#);
+        assert_eq!(builder.apply(&first), Outcome::Applied);
+        assert_eq!(
+            builder.apply(&futures_diff(13, 15, 12, "[]")),
+            Outcome::Applied
+        );
+        assert_eq!(
+            builder.apply(&futures_diff(18, 20, 17, "[]")),
+            Outcome::Broken(Break::Gap {
+                expected: 15,
+                got: 17
+            })
+        );
+        assert_eq!(
+            builder.apply(&futures_diff(21, 22, 20, "[]")),
+            Outcome::Buffered
+        );
+
+        // A snapshot from before the hole cannot repair it...
+        assert_eq!(
+            builder.apply(SNAPSHOT),
+            Outcome::Broken(Break::Unanchored {
+                snapshot: 10,
+                first: 18
+            })
+        );
+        // ...one from inside the held diffs can.
+        let repair =
+            br#"{"lastUpdateId":21,"E":1,"T":1,"bids":[["100","1"]],"asks":[["102","1"]]}"#;
+        assert_eq!(builder.apply(repair), Outcome::Synced);
+        assert_eq!(
+            builder.apply(&futures_diff(23, 24, 22, "[]")),
+            Outcome::Applied
+        );
+    }
+
+    #[test]
+    fn bybit_follows_one_depth_and_skips_late_snapshots() {
+        let mut builder = BookBuilder::new(Market::Bybit { depth: None });
+        let message = |kind: &str, depth: u32, u: i64, bids: &str| {
+            format!(
+                r#"{{"topic":"orderbook.{depth}.BTCUSDT","type":"{kind}","ts":1,"data":{{"s":"BTCUSDT","b":{bids},"a":[["101","1"]],"u":{u},"seq":9}},"cts":1}}"#
+            )
+            .into_bytes()
+        };
+
+        assert_eq!(
+            builder.apply(&message("delta", 50, 3, "[]")),
+            Outcome::Ignored
+        );
+        assert_eq!(
+            builder.apply(&message("snapshot", 50, 3, r#"[["100","1"]]"#)),
+            Outcome::Synced
+        );
+        // Another depth of the same symbol is a different book.
+        assert_eq!(
+            builder.apply(&message("snapshot", 1, 90, "[]")),
+            Outcome::Ignored
+        );
+        assert_eq!(
+            builder.apply(&message("delta", 50, 4, r#"[["100","0"],["99","2"]]"#)),
+            Outcome::Applied
+        );
+        // The second connection's subscribe snapshot arrives late.
+        assert_eq!(
+            builder.apply(&message("snapshot", 50, 3, "[]")),
+            Outcome::Ignored
+        );
+        assert_eq!(top(&builder), Some(("99".into(), "101".into())));
+        assert_eq!(
+            builder.apply(&message("delta", 50, 6, "[]")),
+            Outcome::Broken(Break::Gap {
+                expected: 5,
+                got: 6
+            })
+        );
+        assert_eq!(
+            builder.apply(&message("delta", 50, 7, "[]")),
+            Outcome::Ignored
+        );
+        assert_eq!(
+            builder.apply(&message("snapshot", 50, 1, "[]")),
+            Outcome::Synced
+        );
+    }
+
+    #[test]
+    fn hyperliquid_books_replace_each_other_and_crossing_breaks() {
+        let mut builder = BookBuilder::new(Market::Hyperliquid);
+        let l2 = |time: i64, bid: &str| {
+            format!(
+                r#"{{"channel":"l2Book","data":{{"coin":"BTC","time":{time},"levels":[[{{"px":"{bid}","sz":"1","n":1}}],[{{"px":"101","sz":"2","n":1}}]]}}}}"#
+            )
+            .into_bytes()
+        };
+
+        assert_eq!(builder.apply(&l2(2, "100")), Outcome::Synced);
+        assert_eq!(builder.apply(&l2(1, "99")), Outcome::Ignored);
+        assert_eq!(builder.apply(&l2(2, "99")), Outcome::Ignored);
+        assert_eq!(top(&builder), Some(("100".into(), "101".into())));
+        assert_eq!(
+            builder.apply(&l2(3, "101")),
+            Outcome::Broken(Break::Crossed)
+        );
+        assert!(builder.book().is_none());
+        assert_eq!(
+            builder.apply(br#"{"channel":"trades","data":[]}"#),
+            Outcome::Ignored
+        );
+        assert_eq!(builder.apply(&l2(4, "100.5")), Outcome::Synced);
+        // The collector's re-anchor, fetched from the info endpoint.
+        let fetched = br#"{"coin":"BTC","time":5,"levels":[[{"px":"100.6","sz":"1","n":1}],[{"px":"101","sz":"2","n":1}]]}"#;
+        assert_eq!(builder.apply(fetched), Outcome::Applied);
+        assert_eq!(top(&builder), Some(("100.6".into(), "101".into())));
+    }
+}
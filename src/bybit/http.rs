@@ -1,7 +1,8 @@
 use std::{
-    collections::{BTreeSet, HashMap},
+    collections::{BTreeSet, HashMap, VecDeque},
     io,
     io::ErrorKind,
+    sync::Arc,
     time::{Duration, Instant},
 };
 
@@ -18,7 +19,13 @@ use tokio::{
 };
 use tracing::{error, warn};
 
-use crate::ws::{self, Delivery, FrameSender, Overflow};
+use crate::{
+    account::Credentials,
+    metrics,
+    reload::SymbolDiff,
+    spool::Spool,
+    ws::{self, Delivery, FrameSender, Overflow, Priority},
+};
 
 const PING_INTERVAL: Duration = Duration::from_secs(20);
 /// Bybit closes the socket after 20 s without a client ping, and market data on
@@ -27,6 +34,10 @@ const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
 /// Subscribe requests are paced so a large symbol list cannot trip Bybit's
 /// request rate limit.
 const SUBSCRIBE_PACE: Duration = Duration::from_millis(10);
+/// How far ahead of now an `auth` request's signature stays valid, in
+/// milliseconds. Bybit refuses one whose expiry has passed by its own clock, so
+/// this is also the clock skew tolerated.
+const AUTH_VALIDITY_MS: i64 = 10_000;
 /// A group that keeps getting rejected is almost always permanently invalid
 /// (delisted or unsupported symbol); retrying it forever just burns the request
 /// budget and floods the log.
@@ -41,6 +52,20 @@ pub struct SubscriptionRequest {
     topics: Vec<String>,
 }
 
+impl SubscriptionRequest {
+    /// Every topic of one symbol, under the symbol as its `req_id`.
+    fn new(topics: &[String], symbol: &str) -> Self {
+        let symbol = symbol.to_uppercase();
+        Self {
+            topics: topics
+                .iter()
+                .map(|topic| topic.replace("$symbol", &symbol))
+                .collect(),
+            req_id: symbol,
+        }
+    }
+}
+
 /// A frame with the connection it arrived on.
 ///
 /// Subscription rejections are answered by resubscribing, and only the
@@ -49,19 +74,45 @@ pub struct SubscriptionRequest {
 /// loop therefore has to know where a frame came from.
 pub type Frame = (usize, Timestamp, bytes::Bytes);
 
+/// Prefix of the `req_id` of a resubscribe sent to repair an order book gap.
+/// Those are not subscription groups and are not retried as one.
+pub const RESYNC_REQ_PREFIX: &str = "resync:";
+
 async fn send_subscription(
     sender: &FrameSender,
     req_id: &str,
     topics: &[String],
+) -> Result<(), anyhow::Error> {
+    send_op(sender, "subscribe", req_id, topics).await
+}
+
+async fn send_op(
+    sender: &FrameSender,
+    op: &str,
+    req_id: &str,
+    topics: &[String],
 ) -> Result<(), anyhow::Error> {
     let message = serde_json::to_vec(&serde_json::json!({
         "req_id": req_id,
-        "op": "subscribe",
+        "op": op,
         "args": topics,
     }))?;
     sender.text(message).await
 }
 
+/// Authenticate a private connection: an HMAC of `GET/realtime` and an expiry,
+/// under the account's secret.
+async fn send_auth(sender: &FrameSender, credentials: &Credentials) -> Result<(), anyhow::Error> {
+    let expires = Timestamp::now().as_millisecond() + AUTH_VALIDITY_MS;
+    let signature = credentials.sign(&format!("GET/realtime{expires}"));
+    let message = serde_json::to_vec(&serde_json::json!({
+        "req_id": "auth",
+        "op": "auth",
+        "args": [credentials.key, expires, signature],
+    }))?;
+    sender.text(message).await
+}
+
 /// Everything that writes — the initial subscriptions, pings, and retries —
 /// lives here rather than alongside the read loop.
 ///
@@ -71,13 +122,26 @@ async fn send_subscription(
 /// `symbols × SUBSCRIBE_PACE` — seconds on a large symbol list — skewing every
 /// receive timestamp in that window and letting the kernel buffer back up.
 ///
+/// A reload's changes go through here as well: the removed symbols' groups
+/// are unsubscribed and forgotten, and the added ones join the paced queue.
+///
+/// On the private stream, `credentials` authenticate the connection first.
+/// Bybit answers a connection's requests in order, so the subscriptions queued
+/// behind the `auth` are only looked at once it has been accepted.
+///
 /// Returns only when the socket can no longer be written to; the caller treats
 /// that as fatal for the connection.
+#[allow(clippy::too_many_arguments)]
 async fn control_loop(
     sender: FrameSender,
     retry_rx: &mut UnboundedReceiver<String>,
-    request_map: &HashMap<String, Vec<String>>,
+    resync_rx: &mut UnboundedReceiver<String>,
+    symbols: &mut watch::Receiver<Vec<String>>,
+    topics: &[String],
+    mut subscribed: Vec<String>,
+    mut request_map: HashMap<String, Vec<String>>,
     order: Vec<String>,
+    credentials: Option<&Credentials>,
 ) {
     let mut ping_interval = tokio::time::interval(PING_INTERVAL);
     ping_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
@@ -88,11 +152,17 @@ async fn control_loop(
     let mut degraded_report = tokio::time::interval(DEGRADED_REPORT_INTERVAL);
     degraded_report.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
 
-    let mut to_send = order.into_iter();
+    let mut to_send = VecDeque::from(order);
     let mut attempts: HashMap<String, u32> = HashMap::new();
     let mut pending: Vec<(String, Instant)> = Vec::new();
     let mut abandoned: BTreeSet<String> = BTreeSet::new();
 
+    if let Some(credentials) = credentials
+        && send_auth(&sender, credentials).await.is_err()
+    {
+        return;
+    }
+
     loop {
         select! {
             _ = ping_interval.tick() => {
@@ -102,7 +172,7 @@ async fn control_loop(
                 }
             }
             _ = pacer.tick() => {
-                let Some(req_id) = to_send.next() else {
+                let Some(req_id) = to_send.pop_front() else {
                     continue;
                 };
                 if send_subscription(&sender, &req_id, &request_map[&req_id]).await.is_err() {
@@ -132,6 +202,45 @@ async fn control_loop(
                 let delay = Duration::from_secs(1 << (*attempt - 1));
                 pending.push((req_id, Instant::now() + delay));
             }
+            Ok(()) = symbols.changed() => {
+                let next = symbols.borrow_and_update().clone();
+                let diff = SymbolDiff::between(&subscribed, &next);
+                subscribed = next;
+                for symbol in &diff.removed {
+                    let request = SubscriptionRequest::new(topics, symbol);
+                    let req_id = request.req_id;
+                    attempts.remove(&req_id);
+                    pending.retain(|(pending_id, _)| pending_id != &req_id);
+                    abandoned.remove(&req_id);
+                    request_map.remove(&req_id);
+                    // A group still waiting its turn was never subscribed.
+                    let queued = to_send.len();
+                    to_send.retain(|queued_id| queued_id != &req_id);
+                    if to_send.len() == queued
+                        && send_op(&sender, "unsubscribe", &req_id, &request.topics).await.is_err()
+                    {
+                        return;
+                    }
+                }
+                for symbol in &diff.added {
+                    let request = SubscriptionRequest::new(topics, symbol);
+                    to_send.push_back(request.req_id.clone());
+                    request_map.insert(request.req_id, request.topics);
+                }
+            }
+            Some(topic) = resync_rx.recv() => {
+                // Bybit refuses to subscribe a connection to a topic it already
+                // has, and only a subscribe brings a fresh snapshot. Requests on
+                // one connection are handled in order, so the snapshot follows
+                // the unsubscribe.
+                let topics = [topic];
+                let req_id = format!("{RESYNC_REQ_PREFIX}{}", topics[0]);
+                if send_op(&sender, "unsubscribe", &req_id, &topics).await.is_err()
+                    || send_subscription(&sender, &req_id, &topics).await.is_err()
+                {
+                    return;
+                }
+            }
             _ = sweep.tick(), if !pending.is_empty() => {
                 let now = Instant::now();
                 let mut index = 0;
@@ -160,17 +269,36 @@ async fn control_loop(
     }
 }
 
+/// `label` names the connection in logs and metrics, alongside the `venue`
+/// it collects for; `url` is a category's public stream, or the private one.
+#[allow(clippy::too_many_arguments)]
 async fn connect(
-    url: &str,
-    requests: Vec<SubscriptionRequest>,
+    venue: &str,
+    label: &'static str,
+    url: &'static str,
+    topics: &[String],
+    symbols: &mut watch::Receiver<Vec<String>>,
     connection: usize,
     ws_tx: Sender<Frame>,
     retry_rx: &mut UnboundedReceiver<String>,
+    resync_rx: &mut UnboundedReceiver<String>,
     reconnect_rx: &mut watch::Receiver<u64>,
+    credentials: Option<&Credentials>,
+    spool: Option<Arc<Spool>>,
 ) -> Result<(), anyhow::Error> {
-    let mut conn = ws::connect(url).await?;
+    let mut conn = ws::connect(url)
+        .await
+        .inspect_err(|_| metrics::handshake_failed(venue, label, 0, connection))?;
     let sender = conn.sender();
-    let mut overflow = Overflow::new("bybit");
+    let mut overflow = Overflow::new(label).with_spool(spool);
+
+    // Read after the handshake, so a change made while it ran is in the list
+    // rather than sent as a change on top of it.
+    let subscribed = symbols.borrow_and_update().clone();
+    let requests: Vec<SubscriptionRequest> = subscribed
+        .iter()
+        .map(|symbol| SubscriptionRequest::new(topics, symbol))
+        .collect();
 
     let order: Vec<String> = requests
         .iter()
@@ -183,11 +311,23 @@ async fn connect(
 
     // Rejections observed on the *previous* session are still queued in the
     // watch channel and in `retry_rx`. Without this the fresh connection is torn
-    // down again before it reads a single frame.
+    // down again before it reads a single frame. Resubscribes asked for then
+    // are moot: a fresh session subscribes every topic anyway.
     reconnect_rx.mark_unchanged();
     while retry_rx.try_recv().is_ok() {}
+    while resync_rx.try_recv().is_ok() {}
 
-    let control = control_loop(sender.clone(), retry_rx, &request_map, order);
+    let control = control_loop(
+        sender.clone(),
+        retry_rx,
+        resync_rx,
+        symbols,
+        topics,
+        subscribed,
+        request_map,
+        order,
+        credentials,
+    );
     tokio::pin!(control);
 
     loop {
@@ -221,11 +361,16 @@ async fn connect(
                     // subscription ack or rejection would leave that symbol's
                     // topics unsubscribed with nothing left to trigger a retry,
                     // and no degraded-feed report either.
-                    |(_, _, payload)| ws::payload_contains(payload, br#""topic""#),
+                    |(_, _, payload)| match ws::string_field(payload, "topic") {
+                        Some(topic) => {
+                            Priority::of(super::stream_of(topic, ws::string_field(payload, "type")))
+                        }
+                        None => Priority::Control,
+                    },
                 )
                 .await;
                 match delivery {
-                    Delivery::Sent | Delivery::Dropped => {}
+                    Delivery::Sent | Delivery::Spooled | Delivery::Dropped => {}
                     // Receiver dropped: the collector is shutting down.
                     Delivery::Closed => return Ok(()),
                     Delivery::Undeliverable => {
@@ -250,42 +395,49 @@ async fn connect(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn keep_connection(
+    venue: Arc<str>,
+    label: &'static str,
+    url: &'static str,
     topics: Vec<String>,
-    symbol_list: Vec<String>,
+    mut symbols: watch::Receiver<Vec<String>>,
     connection: usize,
     ws_tx: Sender<Frame>,
+    spool: Option<Arc<Spool>>,
     mut retry_rx: UnboundedReceiver<String>,
+    mut resync_rx: UnboundedReceiver<String>,
     mut reconnect_rx: watch::Receiver<u64>,
+    credentials: Option<Credentials>,
 ) {
     let mut error_count = 0;
     loop {
         let connect_time = Instant::now();
-        let requests = symbol_list
-            .iter()
-            .map(|symbol| {
-                let symbol = symbol.to_uppercase();
-                SubscriptionRequest {
-                    topics: topics
-                        .iter()
-                        .map(|topic| topic.replace("$symbol", &symbol))
-                        .collect(),
-                    req_id: symbol,
-                }
-            })
-            .collect::<Vec<_>>();
         if let Err(error) = connect(
-            "wss://stream.bybit.com/v5/public/linear",
-            requests,
+            &venue,
+            label,
+            url,
+            &topics,
+            &mut symbols,
             connection,
             ws_tx.clone(),
             &mut retry_rx,
+            &mut resync_rx,
             &mut reconnect_rx,
+            credentials.as_ref(),
+            spool.clone(),
         )
         .await
         {
             let lifetime = connect_time.elapsed();
-            error!(connection, ?error, ?lifetime, "websocket error");
+            metrics::session_ended(&venue, label, 0, connection, lifetime);
+            error!(
+                category = label,
+                connection,
+                ?error,
+                ?lifetime,
+                "websocket error"
+            );
             error_count += 1;
             if lifetime > Duration::from_secs(30) {
                 error_count = 0;
@@ -302,3 +454,75 @@ pub async fn keep_connection(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use super::*;
+
+    /// The next request the venue receives, pings aside.
+    async fn next_request(
+        server: &mut fastwebsockets::WebSocket<tokio::io::DuplexStream>,
+    ) -> serde_json::Value {
+        loop {
+            let frame = server.read_frame().await.unwrap();
+            let request: serde_json::Value = serde_json::from_slice(&frame.payload).unwrap();
+            if request["op"] != "ping" {
+                return request;
+            }
+        }
+    }
+
+    /// A reload unsubscribes a removed symbol's group on the open socket and
+    /// subscribes an added one; adding the removed symbol back subscribes it
+    /// afresh.
+    #[tokio::test]
+    async fn a_reload_changes_the_subscriptions_in_place() {
+        let (conn, mut server) = ws::duplex_pair();
+        let (symbols_tx, mut symbols) = watch::channel(vec!["BTCUSDT".to_owned()]);
+        let topics = vec!["publicTrade.$symbol".to_owned()];
+        let request = SubscriptionRequest::new(&topics, "BTCUSDT");
+        let sender = conn.sender();
+        let control = tokio::spawn(async move {
+            let (_retry_tx, mut retry_rx) = unbounded_channel();
+            let (_resync_tx, mut resync_rx) = unbounded_channel();
+            let subscribed = symbols.borrow_and_update().clone();
+            control_loop(
+                sender,
+                &mut retry_rx,
+                &mut resync_rx,
+                &mut symbols,
+                &topics,
+                subscribed,
+                HashMap::from([(request.req_id.clone(), request.topics)]),
+                vec![request.req_id],
+                None,
+            )
+            .await;
+        });
+        let request = |op: &str, symbol: &str| serde_json::json!({ "req_id": symbol, "op": op, "args": [format!("publicTrade.{symbol}")] });
+
+        assert_eq!(
+            next_request(&mut server).await,
+            request("subscribe", "BTCUSDT")
+        );
+
+        symbols_tx.send_replace(vec!["ETHUSDT".to_owned()]);
+        assert_eq!(
+            next_request(&mut server).await,
+            request("unsubscribe", "BTCUSDT")
+        );
+        assert_eq!(
+            next_request(&mut server).await,
+            request("subscribe", "ETHUSDT")
+        );
+
+        symbols_tx.send_replace(vec!["ETHUSDT".to_owned(), "BTCUSDT".to_owned()]);
+        assert_eq!(
+            next_request(&mut server).await,
+            request("subscribe", "BTCUSDT")
+        );
+        control.abort();
+    }
+}
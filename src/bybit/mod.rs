@@ -1,29 +1,100 @@
 mod http;
+mod sequence;
+
+use std::sync::Arc;
 
-use http::Frame;
 pub use http::keep_connection;
+use http::{Frame, RESYNC_REQ_PREFIX};
 use jiff::Timestamp;
 use tokio::{
     sync::{
-        mpsc::{Sender, UnboundedSender, channel},
+        mpsc::{UnboundedSender, channel},
         watch,
     },
     task::JoinSet,
 };
 
-use tracing::{error, info};
+use tracing::{error, info, warn};
+
+use collector::record::Stream;
 
 use crate::{
-    dedup::Dedup, error::ConnectorError, feed::Feed, file::WriteRecord, routing::BybitMessage,
+    account::{self, Credentials},
+    dedup::Dedup,
+    error::ConnectorError,
+    feed::Feed,
+    file::{RecordSender, WriteRecord},
+    routing::BybitMessage,
+    spool::Spool,
     symbol::SymbolCache,
 };
+use sequence::{BookUpdate, Sequences, Verdict};
+
+/// One Bybit v5 product category. The public streams only differ by URL; the
+/// protocol, topics and acknowledgements are shared.
+pub struct Category {
+    pub label: &'static str,
+    pub ws_url: &'static str,
+    /// Names the private connection in logs and metrics.
+    pub account_label: &'static str,
+    /// The category's own order, execution and position topics on the private
+    /// stream, which serves every category; the unqualified `order` would
+    /// record the others' too, once per Bybit venue.
+    pub account_topics: &'static [&'static str],
+}
+
+/// The private stream, shared by every category.
+const PRIVATE_URL: &str = "wss://stream.bybit.com/v5/private";
+
+pub static LINEAR: Category = Category {
+    label: "bybit-linear",
+    ws_url: "wss://stream.bybit.com/v5/public/linear",
+    account_label: "bybit-linear-account",
+    account_topics: &["order.linear", "execution.linear", "position.linear"],
+};
+
+pub static SPOT: Category = Category {
+    label: "bybit-spot",
+    ws_url: "wss://stream.bybit.com/v5/public/spot",
+    // Spot holds balances, not positions.
+    account_label: "bybit-spot-account",
+    account_topics: &["order.spot", "execution.spot"],
+};
+
+pub static INVERSE: Category = Category {
+    label: "bybit-inverse",
+    ws_url: "wss://stream.bybit.com/v5/public/inverse",
+    account_label: "bybit-inverse-account",
+    account_topics: &["order.inverse", "execution.inverse", "position.inverse"],
+};
+
+pub static OPTION: Category = Category {
+    label: "bybit-option",
+    ws_url: "wss://stream.bybit.com/v5/public/option",
+    account_label: "bybit-option-account",
+    account_topics: &["order.option", "execution.option", "position.option"],
+};
+
+/// The kind of message a topic carries. An `orderbook` topic opens with a
+/// full book (`"type":"snapshot"`) and continues with deltas.
+fn stream_of(topic: &str, kind: Option<&str>) -> Stream {
+    match topic.split('.').next() {
+        Some("orderbook") if kind == Some("snapshot") => Stream::DepthSnapshot,
+        Some("orderbook") => Stream::Depth,
+        Some("publicTrade") => Stream::Trade,
+        Some("allLiquidation" | "liquidation") => Stream::Liquidation,
+        _ => Stream::Other,
+    }
+}
 
 #[allow(clippy::too_many_arguments)]
 async fn handle(
-    writer_tx: &Sender<WriteRecord>,
+    writer_tx: &RecordSender,
     symbols: &mut SymbolCache,
     dedup: &mut Dedup,
+    sequences: &mut Sequences,
     retry_tx: &UnboundedSender<String>,
+    resync_tx: &UnboundedSender<String>,
     reconnect_tx: &watch::Sender<u64>,
     connection: usize,
     recv_time: Timestamp,
@@ -44,13 +115,26 @@ async fn handle(
     }
 
     let message: BybitMessage<'_> = serde_json::from_slice(&data)?;
-    if let Some(op) = message.op {
+    // The option endpoint acknowledges with `"type":"COMMAND_RESP"` and no
+    // `op`; read as a subscribe ack it is retried like any other rejection.
+    let op = message
+        .op
+        .or((message.kind == Some("COMMAND_RESP")).then_some("subscribe"));
+    if let Some(op) = op {
         if op == "subscribe" {
             match message.success {
                 Some(true) => info!(connection, "subscription succeeded"),
                 Some(false) => {
                     let reason = message.ret_msg.unwrap_or("unknown reason");
-                    if let Some(req_id) = message.req_id.filter(|req_id| !req_id.is_empty()) {
+                    if let Some(topic) = message
+                        .req_id
+                        .and_then(|req_id| req_id.strip_prefix(RESYNC_REQ_PREFIX))
+                    {
+                        // The topic keeps waiting for its snapshot, and asks
+                        // again if it does not come.
+                        warn!(connection, reason, topic, "order book resubscribe rejected");
+                    } else if let Some(req_id) = message.req_id.filter(|req_id| !req_id.is_empty())
+                    {
                         error!(connection, reason, %req_id, "subscription rejected; scheduling retry");
                         if retry_tx.send(req_id.to_owned()).is_err() {
                             return Err(ConnectorError::ConnectionGone);
@@ -83,81 +167,216 @@ async fn handle(
             .ok_or(ConnectorError::FormatError)?;
 
         let symbol = symbols.resolve(symbol_raw);
+        let stream = stream_of(topic, message.kind);
 
+        if matches!(stream, Stream::Depth | Stream::DepthSnapshot) {
+            let update: BookUpdate =
+                serde_json::from_str(message.data.ok_or(ConnectorError::FormatError)?.get())?;
+            let verdict = sequences.observe(connection, topic, message.kind, update, recv_time);
+            if verdict == Verdict::Resubscribe && resync_tx.send(topic.to_owned()).is_err() {
+                return Err(ConnectorError::ConnectionGone);
+            }
+        }
+
+        writer_tx
+            .send(WriteRecord::received(
+                recv_time, symbol, data, connection, stream,
+            ))
+            .await
+            .map_err(|_| ConnectorError::WriterClosed)?;
+    }
+    Ok(())
+}
+
+/// A frame from the private connection. Its acknowledgements are answered
+/// like the public ones, and everything with a `topic` is the account's, filed
+/// under [`account::SYMBOL`].
+async fn handle_account(
+    writer_tx: &RecordSender,
+    retry_tx: &UnboundedSender<String>,
+    reconnect_tx: &watch::Sender<u64>,
+    connection: usize,
+    recv_time: Timestamp,
+    data: bytes::Bytes,
+) -> Result<(), ConnectorError> {
+    let message: BybitMessage<'_> = serde_json::from_slice(&data)?;
+    let reason = message.ret_msg.unwrap_or("unknown reason");
+    match (message.op, message.success) {
+        (Some("auth"), Some(true)) => info!(connection, "authenticated the private stream"),
+        (Some("auth"), _) => {
+            // Nothing on this connection will be delivered; a new one signs
+            // again, which is what a clock-skewed expiry needs.
+            error!(
+                connection,
+                reason, "Bybit refused the API key; reconnecting"
+            );
+            reconnect_tx.send_modify(|version| *version = version.wrapping_add(1));
+        }
+        (Some("subscribe"), Some(false)) => {
+            error!(
+                connection,
+                reason, "private subscription rejected; scheduling retry"
+            );
+            let req_id = message.req_id.unwrap_or_default().to_owned();
+            if retry_tx.send(req_id).is_err() {
+                return Err(ConnectorError::ConnectionGone);
+            }
+        }
+        _ => {}
+    }
+    if message.topic.is_some() {
         writer_tx
-            .send((recv_time, symbol, data))
+            .send(account::record(recv_time, data, connection))
             .await
             .map_err(|_| ConnectorError::WriterClosed)?;
     }
     Ok(())
 }
 
+/// With `account`, the category's private topics are collected too, on a
+/// connection after the last public one; see [`handle_account`].
+#[allow(clippy::too_many_arguments)]
 pub async fn run_collection(
+    venue: Arc<str>,
+    category: &'static Category,
     subscriptions: Vec<String>,
-    symbols: Vec<String>,
-    writer_tx: Sender<WriteRecord>,
+    mut symbols: watch::Receiver<Vec<String>>,
+    writer_tx: RecordSender,
     shutdown: watch::Receiver<bool>,
     connections: usize,
+    account: Option<Credentials>,
 ) -> Result<(), anyhow::Error> {
     let connections = connections.max(1);
-    let mut dedup = Dedup::for_connections(connections);
+    let mut dedup = Dedup::for_connections(connections).with_metrics(category.label);
+    let mut sequences = Sequences::new(category.label);
     // Sized per connection: they share the queue, so the burst each one can
     // absorb stays the same however many there are.
     let (ws_tx, ws_rx) = channel::<Frame>(crate::WS_QUEUE_CAPACITY.saturating_mul(connections));
     let mut feed = Feed::new(ws_rx, shutdown);
     let mut tasks = JoinSet::new();
-    let mut symbol_cache = SymbolCache::new(&symbols);
-    // Each connection subscribes independently, so each one needs its own retry
-    // and reconnect signal — a rejection has to be answered on the connection
-    // that was rejected, not on whichever one happens to be first in the list.
+    let mut symbol_cache = SymbolCache::new(&symbols.borrow_and_update());
+    let spool = Spool::for_feed(category.label, connections);
+    // Each connection subscribes independently, so each one needs its own retry,
+    // resubscribe and reconnect signal — a rejection has to be answered on the
+    // connection that was rejected, and a gap on the one that showed it, not on
+    // whichever one happens to be first in the list.
     let mut retry_txs = Vec::with_capacity(connections);
+    let mut resync_txs = Vec::with_capacity(connections);
     let mut reconnect_txs = Vec::with_capacity(connections);
     for connection in 0..connections {
         let (retry_tx, retry_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (resync_tx, resync_rx) = tokio::sync::mpsc::unbounded_channel();
         let (reconnect_tx, reconnect_rx) = watch::channel(0_u64);
         retry_txs.push(retry_tx);
+        resync_txs.push(resync_tx);
         reconnect_txs.push(reconnect_tx);
 
         let subscriptions = subscriptions.clone();
         let symbols = symbols.clone();
         let ws_tx = ws_tx.clone();
+        let spool = spool.clone();
+        let venue = Arc::clone(&venue);
         tasks.spawn(async move {
             tokio::time::sleep(crate::CONNECT_STAGGER * connection as u32).await;
             keep_connection(
+                venue,
+                category.label,
+                category.ws_url,
                 subscriptions,
                 symbols,
                 connection,
                 ws_tx,
+                spool,
                 retry_rx,
+                resync_rx,
                 reconnect_rx,
+                None,
             )
             .await;
             error!(connection, "the websocket connection task exited");
         });
     }
+    let account_connection = connections;
+    // The private topics take no symbol, so the list they are subscribed under
+    // is a fixed one-entry stand-in; its sender lives as long as the loop.
+    let (_account_symbols_tx, account_symbols) = watch::channel(vec![account::SYMBOL.to_owned()]);
+    if let Some(credentials) = account {
+        let (retry_tx, retry_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (resync_tx, resync_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (reconnect_tx, reconnect_rx) = watch::channel(0_u64);
+        retry_txs.push(retry_tx);
+        resync_txs.push(resync_tx);
+        reconnect_txs.push(reconnect_tx);
+
+        let topics = category
+            .account_topics
+            .iter()
+            .map(|topic| topic.to_string())
+            .collect();
+        let ws_tx = ws_tx.clone();
+        let spool = spool.clone();
+        let venue = Arc::clone(&venue);
+        tasks.spawn(async move {
+            keep_connection(
+                venue,
+                category.account_label,
+                PRIVATE_URL,
+                topics,
+                account_symbols,
+                account_connection,
+                ws_tx,
+                spool,
+                retry_rx,
+                resync_rx,
+                reconnect_rx,
+                Some(credentials),
+            )
+            .await;
+            error!("the private websocket connection task exited");
+        });
+    }
     // The clones above are the only senders that should keep the feed open.
     drop(ws_tx);
 
     while let Some((connection, recv_time, data)) = feed.recv(&mut tasks).await {
         // A frame can only carry the index of a connection this loop started.
-        let (Some(retry_tx), Some(reconnect_tx)) =
-            (retry_txs.get(connection), reconnect_txs.get(connection))
-        else {
+        let (Some(retry_tx), Some(resync_tx), Some(reconnect_tx)) = (
+            retry_txs.get(connection),
+            resync_txs.get(connection),
+            reconnect_txs.get(connection),
+        ) else {
             error!(connection, "frame from an unknown connection; ignoring");
             continue;
         };
-        if let Err(error) = handle(
-            &writer_tx,
-            &mut symbol_cache,
-            &mut dedup,
-            retry_tx,
-            reconnect_tx,
-            connection,
-            recv_time,
-            data,
-        )
-        .await
-        {
+        if symbols.has_changed().unwrap_or(false) {
+            symbol_cache.reseed(&symbols.borrow_and_update());
+        }
+        let result = if connection == account_connection {
+            handle_account(
+                &writer_tx,
+                retry_tx,
+                reconnect_tx,
+                connection,
+                recv_time,
+                data,
+            )
+            .await
+        } else {
+            handle(
+                &writer_tx,
+                &mut symbol_cache,
+                &mut dedup,
+                &mut sequences,
+                retry_tx,
+                resync_tx,
+                reconnect_tx,
+                connection,
+                recv_time,
+                data,
+            )
+            .await
+        };
+        if let Err(error) = result {
             if matches!(&error, ConnectorError::WriterClosed) {
                 return Err(error.into());
             }
@@ -170,13 +389,15 @@ pub async fn run_collection(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::file::record_channel;
 
     #[tokio::test]
     async fn routes_all_liquidation_by_topic_symbol() {
-        let (writer_tx, mut writer_rx) = channel(1);
+        let (writer_tx, mut writer_rx) = record_channel(1);
         let mut symbols = SymbolCache::new(&["BTCUSDT".to_owned()]);
         let mut dedup = Dedup::disabled();
         let (retry_tx, _retry_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (resync_tx, _resync_rx) = tokio::sync::mpsc::unbounded_channel();
         let (reconnect_tx, _reconnect_rx) = watch::channel(0);
         let data = bytes::Bytes::from_static(
             br#"{"topic":"allLiquidation.BTCUSDT","type":"snapshot","ts":1739502303204,"data":[{"T":1739502302929,"s":"BTCUSDT","S":"Sell","v":"2.5","p":"95000"}]}"#,
@@ -186,7 +407,9 @@ mod tests {
             &writer_tx,
             &mut symbols,
             &mut dedup,
+            &mut Sequences::new("bybit-linear"),
             &retry_tx,
+            &resync_tx,
             &reconnect_tx,
             0,
             Timestamp::now(),
@@ -195,17 +418,81 @@ mod tests {
         .await
         .unwrap();
 
-        let (_, symbol, written) = writer_rx.try_recv().unwrap();
+        let WriteRecord {
+            symbol,
+            data: written,
+            connection,
+            stream,
+            ..
+        } = writer_rx.try_recv().unwrap();
         assert_eq!(symbol.as_ref(), "btcusdt");
         assert_eq!(written, data);
+        assert_eq!((connection, stream), (Some(0), Stream::Liquidation));
+    }
+
+    #[tokio::test]
+    async fn private_topics_are_filed_under_the_account() {
+        let (writer_tx, mut writer_rx) = record_channel(2);
+        let (retry_tx, _retry_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (reconnect_tx, reconnect_rx) = watch::channel(0);
+        let execution = bytes::Bytes::from_static(
+            br#"{"id":"5923240c6880ab-c59f-420b-9adb-3639adc9dd90","topic":"execution.linear","creationTime":1672364174455,"data":[{"category":"linear","symbol":"XRPUSDT","execPrice":"0.3374","execQty":"25","side":"Sell"}]}"#,
+        );
+
+        for data in [
+            bytes::Bytes::from_static(
+                br#"{"success":true,"ret_msg":"","op":"auth","conn_id":"cejreaspqfh3sjdnldmg-p"}"#,
+            ),
+            execution.clone(),
+        ] {
+            handle_account(
+                &writer_tx,
+                &retry_tx,
+                &reconnect_tx,
+                1,
+                Timestamp::now(),
+                data,
+            )
+            .await
+            .unwrap();
+        }
+
+        let WriteRecord {
+            symbol,
+            data,
+            connection,
+            stream,
+            ..
+        } = writer_rx.try_recv().unwrap();
+        assert_eq!(symbol.as_ref(), account::SYMBOL);
+        assert_eq!((data, stream), (execution, Stream::Account));
+        assert_eq!(connection, Some(1));
+        assert!(writer_rx.try_recv().is_err(), "only the topic is recorded");
+        assert!(!reconnect_rx.has_changed().unwrap());
+
+        let refused = bytes::Bytes::from_static(
+            br#"{"success":false,"ret_msg":"Params Error","op":"auth","conn_id":"cejreaspqfh3sjdnldmg-p"}"#,
+        );
+        handle_account(
+            &writer_tx,
+            &retry_tx,
+            &reconnect_tx,
+            1,
+            Timestamp::now(),
+            refused,
+        )
+        .await
+        .unwrap();
+        assert!(reconnect_rx.has_changed().unwrap());
     }
 
     #[tokio::test]
     async fn failed_subscription_group_is_retried() {
-        let (writer_tx, _writer_rx) = channel(1);
+        let (writer_tx, _writer_rx) = record_channel(1);
         let mut symbols = SymbolCache::new(&[]);
         let mut dedup = Dedup::disabled();
         let (retry_tx, mut retry_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (resync_tx, _resync_rx) = tokio::sync::mpsc::unbounded_channel();
         let (reconnect_tx, _reconnect_rx) = watch::channel(0);
         let data = bytes::Bytes::from_static(
             br#"{"success":false,"ret_msg":"rate limited","req_id":"BTCUSDT","op":"subscribe"}"#,
@@ -215,7 +502,9 @@ mod tests {
             &writer_tx,
             &mut symbols,
             &mut dedup,
+            &mut Sequences::new("bybit-linear"),
             &retry_tx,
+            &resync_tx,
             &reconnect_tx,
             0,
             Timestamp::now(),
@@ -227,17 +516,49 @@ mod tests {
         assert_eq!(retry_rx.recv().await.as_deref(), Some("BTCUSDT"));
     }
 
+    #[tokio::test]
+    async fn option_command_response_rejection_is_retried() {
+        let (writer_tx, _writer_rx) = record_channel(1);
+        let mut symbols = SymbolCache::new(&[]);
+        let mut dedup = Dedup::disabled();
+        let (retry_tx, mut retry_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (resync_tx, _resync_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (reconnect_tx, _reconnect_rx) = watch::channel(0);
+        let data = bytes::Bytes::from_static(
+            br#"{"success":false,"ret_msg":"handler not found","conn_id":"c1","req_id":"BTC","type":"COMMAND_RESP","data":{"failTopics":["publicTrade.BTC"],"successTopics":[]}}"#,
+        );
+
+        handle(
+            &writer_tx,
+            &mut symbols,
+            &mut dedup,
+            &mut Sequences::new("bybit-linear"),
+            &retry_tx,
+            &resync_tx,
+            &reconnect_tx,
+            0,
+            Timestamp::now(),
+            data,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(retry_rx.recv().await.as_deref(), Some("BTC"));
+    }
+
     /// Redundant connections deliver the same market data, which is collapsed —
     /// but a rejection is per-connection state and must reach its own
     /// connection's retry channel every time.
     #[tokio::test]
     async fn market_data_is_collapsed_while_each_connection_retries_for_itself() {
-        let (writer_tx, mut writer_rx) = channel(8);
+        let (writer_tx, mut writer_rx) = record_channel(8);
         let mut symbols = SymbolCache::new(&["BTCUSDT".to_owned()]);
         let mut dedup = Dedup::for_connections(2);
         let (retry_tx_0, mut retry_rx_0) = tokio::sync::mpsc::unbounded_channel();
         let (retry_tx_1, mut retry_rx_1) = tokio::sync::mpsc::unbounded_channel();
+        let (resync_tx, _resync_rx) = tokio::sync::mpsc::unbounded_channel();
         let (reconnect_tx, _reconnect_rx) = watch::channel(0);
+        let mut sequences = Sequences::new("bybit-linear");
         let trade = bytes::Bytes::from_static(
             br#"{"topic":"publicTrade.BTCUSDT","ts":1,"data":[{"i":"7","p":"95000","v":"1"}]}"#,
         );
@@ -255,7 +576,9 @@ mod tests {
                 &writer_tx,
                 &mut symbols,
                 &mut dedup,
+                &mut sequences,
                 retry_tx,
+                &resync_tx,
                 &reconnect_tx,
                 connection,
                 Timestamp::now(),
@@ -265,11 +588,75 @@ mod tests {
             .unwrap();
         }
 
-        let (_, _, written) = writer_rx.try_recv().unwrap();
+        let WriteRecord { data: written, .. } = writer_rx.try_recv().unwrap();
         assert_eq!(written, trade);
         assert!(writer_rx.try_recv().is_err(), "the copy is dropped");
         // Both connections need resubscribing, so both must have been told.
         assert_eq!(retry_rx_0.recv().await.as_deref(), Some("BTCUSDT"));
         assert_eq!(retry_rx_1.recv().await.as_deref(), Some("BTCUSDT"));
     }
+
+    /// A delta that skips ahead is still recorded, and the topic is
+    /// resubscribed on the connection that delivered it so a fresh snapshot
+    /// follows it into the file.
+    #[tokio::test]
+    async fn skipped_orderbook_delta_resubscribes_on_its_connection() {
+        let (writer_tx, mut writer_rx) = record_channel(8);
+        let mut symbols = SymbolCache::new(&["BTCUSDT".to_owned()]);
+        let mut dedup = Dedup::for_connections(2);
+        let mut sequences = Sequences::new("bybit-linear");
+        let (retry_tx, _retry_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (resync_tx_0, mut resync_rx_0) = tokio::sync::mpsc::unbounded_channel();
+        let (resync_tx_1, mut resync_rx_1) = tokio::sync::mpsc::unbounded_channel();
+        let (reconnect_tx, _reconnect_rx) = watch::channel(0);
+        let book = |kind: &str, u: i64| {
+            bytes::Bytes::from(format!(
+                r#"{{"topic":"orderbook.50.BTCUSDT","type":"{kind}","ts":{u},"data":{{"s":"BTCUSDT","b":[],"a":[],"u":{u},"seq":{}}},"cts":{u}}}"#,
+                u * 10
+            ))
+        };
+        let rejection = bytes::Bytes::from_static(
+            br#"{"success":false,"ret_msg":"already subscribed","req_id":"resync:orderbook.50.BTCUSDT","op":"subscribe"}"#,
+        );
+
+        for (connection, resync_tx, data) in [
+            (0, &resync_tx_0, book("snapshot", 5)),
+            (0, &resync_tx_0, book("delta", 6)),
+            (1, &resync_tx_1, book("delta", 6)),
+            (1, &resync_tx_1, book("delta", 8)),
+            (1, &resync_tx_1, rejection),
+        ] {
+            handle(
+                &writer_tx,
+                &mut symbols,
+                &mut dedup,
+                &mut sequences,
+                &retry_tx,
+                resync_tx,
+                &reconnect_tx,
+                connection,
+                Timestamp::now(),
+                data,
+            )
+            .await
+            .unwrap();
+        }
+
+        let written: Vec<_> = std::iter::from_fn(|| writer_rx.try_recv().ok())
+            .map(|record| record.stream)
+            .collect();
+        assert_eq!(
+            written,
+            [Stream::DepthSnapshot, Stream::Depth, Stream::Depth]
+        );
+        assert_eq!(
+            resync_rx_1.recv().await.as_deref(),
+            Some("orderbook.50.BTCUSDT")
+        );
+        assert!(resync_rx_0.try_recv().is_err());
+        assert!(
+            resync_rx_1.try_recv().is_err(),
+            "a rejection is not retried at once"
+        );
+    }
 }
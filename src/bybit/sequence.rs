@@ -0,0 +1,211 @@
+//! Order book continuity on the collected Bybit stream.
+//!
+//! An `orderbook.N` topic opens with a snapshot and continues with deltas,
+//! each stamped with the book's update id `u` and the cross sequence `seq`.
+//! `u` counts the topic's own pushes one by one, so a delta whose `u` skips
+//! ahead means one was lost, and the book built from the recording is wrong
+//! until the next snapshot — which Bybit only sends on subscribe. Spotting the
+//! jump here lets the connection that showed it resubscribe the topic at once
+//! instead of leaving the hole open until the next reconnect.
+//!
+//! The check runs on what is recorded: the deduplicated union of every
+//! connection's frames. A delta one connection missed is not a gap as long as
+//! another delivered it first.
+//!
+//! `seq` is shared by the depths of a symbol and keeps growing across a
+//! service restart, where `u` starts again from 1. It orders snapshots and
+//! deltas from different connections: one that is not newer than the last is
+//! a late copy, not news.
+
+use std::{collections::HashMap, time::Duration};
+
+use jiff::Timestamp;
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::metrics::{self, Counter};
+
+/// How long a resubscribe may take to produce its snapshot before the next
+/// delta asks again. A rejected or lost request produces no snapshot at all.
+const RESYNC_RETRY: Duration = Duration::from_secs(10);
+
+/// The continuity fields of an order book message's `data`.
+#[derive(Deserialize)]
+pub struct BookUpdate {
+    pub u: i64,
+    pub seq: Option<i64>,
+}
+
+struct BookState {
+    last: i64,
+    seq: Option<i64>,
+    /// When a resubscribe was asked for, while the topic waits for its
+    /// snapshot.
+    resyncing: Option<Timestamp>,
+}
+
+/// What the collection loop should do about an order book message.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    InOrder,
+    /// Resubscribe the topic on the connection that delivered the message.
+    Resubscribe,
+}
+
+/// Last update seen per order book topic.
+pub struct Sequences {
+    books: HashMap<String, BookState>,
+    gaps: Counter,
+    resubscribes: Counter,
+}
+
+impl Sequences {
+    pub fn new(endpoint: &str) -> Self {
+        let labels = [("endpoint", endpoint)];
+        Self {
+            books: HashMap::new(),
+            gaps: metrics::counter(
+                "collector_depth_gaps_total",
+                "Depth update sequence gaps detected while collecting.",
+                &labels,
+            ),
+            resubscribes: metrics::counter(
+                "collector_depth_resubscribes_total",
+                "Order book topics resubscribed to recover from a gap.",
+                &labels,
+            ),
+        }
+    }
+
+    /// Check an order book message against the topic's last update.
+    pub fn observe(
+        &mut self,
+        connection: usize,
+        topic: &str,
+        kind: Option<&str>,
+        update: BookUpdate,
+        recv_time: Timestamp,
+    ) -> Verdict {
+        if kind == Some("snapshot") {
+            match self.books.get_mut(topic) {
+                Some(book) if is_late(book, &update) => {}
+                Some(book) => {
+                    *book = BookState {
+                        last: update.u,
+                        seq: update.seq,
+                        resyncing: None,
+                    };
+                }
+                None => {
+                    self.books.insert(
+                        topic.to_owned(),
+                        BookState {
+                            last: update.u,
+                            seq: update.seq,
+                            resyncing: None,
+                        },
+                    );
+                }
+            }
+            return Verdict::InOrder;
+        }
+
+        // Nothing to chain onto until the subscribe snapshot arrives.
+        let Some(book) = self.books.get_mut(topic) else {
+            return Verdict::InOrder;
+        };
+        if let Some(asked) = book.resyncing {
+            let waited = recv_time.duration_since(asked).unsigned_abs();
+            if waited < RESYNC_RETRY {
+                return Verdict::InOrder;
+            }
+            warn!(
+                connection,
+                topic,
+                ?waited,
+                "no snapshot after resubscribing; asking again"
+            );
+            book.resyncing = Some(recv_time);
+            self.resubscribes.inc();
+            return Verdict::Resubscribe;
+        }
+        if is_late(book, &update) {
+            return Verdict::InOrder;
+        }
+        if update.u != book.last + 1 {
+            warn!(
+                connection,
+                topic,
+                expected = book.last + 1,
+                got = update.u,
+                "missing order book delta; resubscribing"
+            );
+            book.resyncing = Some(recv_time);
+            self.gaps.inc();
+            self.resubscribes.inc();
+            return Verdict::Resubscribe;
+        }
+        book.last = update.u;
+        book.seq = update.seq.or(book.seq);
+        Verdict::InOrder
+    }
+}
+
+/// Whether `update` is no newer than what the book already holds. Without a
+/// `seq` to go by, only `u` can tell, and `u = 1` is always a restart.
+fn is_late(book: &BookState, update: &BookUpdate) -> bool {
+    match (book.seq, update.seq) {
+        (Some(last), Some(seq)) => seq <= last,
+        _ => update.u <= book.last && update.u != 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOPIC: &str = "orderbook.50.BTCUSDT";
+
+    fn at(seconds: i64) -> Timestamp {
+        Timestamp::from_second(seconds).unwrap()
+    }
+
+    fn update(u: i64, seq: i64) -> BookUpdate {
+        BookUpdate { u, seq: Some(seq) }
+    }
+
+    #[test]
+    fn a_skipped_delta_resubscribes_once_until_the_snapshot() {
+        let mut sequences = Sequences::new("bybit-linear");
+        let mut observe =
+            |kind, update, seconds| sequences.observe(0, TOPIC, Some(kind), update, at(seconds));
+
+        assert_eq!(observe("delta", update(7, 70), 0), Verdict::InOrder);
+        assert_eq!(observe("snapshot", update(10, 100), 0), Verdict::InOrder);
+        assert_eq!(observe("delta", update(11, 110), 0), Verdict::InOrder);
+        // A second connection's copy that dedup did not catch.
+        assert_eq!(observe("delta", update(11, 110), 0), Verdict::InOrder);
+        assert_eq!(observe("delta", update(13, 130), 1), Verdict::Resubscribe);
+        assert_eq!(observe("delta", update(14, 140), 2), Verdict::InOrder);
+        // The snapshot never came.
+        assert_eq!(observe("delta", update(15, 150), 12), Verdict::Resubscribe);
+        assert_eq!(observe("snapshot", update(20, 200), 13), Verdict::InOrder);
+        assert_eq!(observe("delta", update(21, 210), 13), Verdict::InOrder);
+    }
+
+    #[test]
+    fn late_snapshots_are_ignored_and_a_restart_is_followed() {
+        let mut sequences = Sequences::new("bybit-linear");
+        let mut observe = |kind, update| sequences.observe(1, TOPIC, Some(kind), update, at(0));
+
+        assert_eq!(observe("snapshot", update(10, 100)), Verdict::InOrder);
+        assert_eq!(observe("delta", update(11, 110)), Verdict::InOrder);
+        // The other connection subscribed later but its snapshot is older.
+        assert_eq!(observe("snapshot", update(9, 90)), Verdict::InOrder);
+        assert_eq!(observe("delta", update(12, 120)), Verdict::InOrder);
+        // Bybit restarted the book: `u` starts over, `seq` does not.
+        assert_eq!(observe("snapshot", update(1, 130)), Verdict::InOrder);
+        assert_eq!(observe("delta", update(2, 140)), Verdict::InOrder);
+        assert_eq!(observe("delta", update(4, 150)), Verdict::Resubscribe);
+    }
+}
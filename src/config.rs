@@ -0,0 +1,534 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, bail};
+use serde::Deserialize;
+
+use collector::record::Format;
+
+use crate::{
+    file::{self, Compression, Dictionary, FileOptions, Rotation},
+    universe::Universe,
+};
+
+/// A deployment described in TOML: one `[[venue]]` block per collection.
+///
+/// ```toml
+/// [[venue]]
+/// exchange = "binancefutures"
+/// path = "/data/raw/binance/futures/um"
+/// symbols = ["btcusdt", "ethusdt"]
+/// connections = 2
+/// snapshot_interval_secs = 1800
+/// rotation = "hourly"
+/// format = "binary"
+/// zstd = { level = 9, long_window = 27, workers = 2 }
+///
+/// [[venue]]
+/// exchange = "bybit"
+/// path = "/data/raw/bybit"
+/// symbols = ["BTCUSDT"]
+/// streams = ["orderbook.50.$symbol", "publicTrade.$symbol"]
+/// account = { key_env = "BYBIT_API_KEY", secret_env = "BYBIT_API_SECRET" }
+///
+/// [[venue]]
+/// exchange = "hyperliquid"
+/// path = "/data/raw/hyperliquid"
+/// universe = { contract_type = "perpetual", top = 50 }
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(rename = "venue", default)]
+    pub venues: Vec<Venue>,
+}
+
+/// Everything one `run_collection` needs.
+///
+/// The command line without `--config` is read into the same shape, so both
+/// modes go through one spawn path.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Venue {
+    /// Selects this block with `--venue`. Defaults to `exchange`, which is
+    /// enough unless one file lists the same exchange twice.
+    pub name: Option<String>,
+    pub exchange: String,
+    /// Directory the venue's files are written to.
+    pub path: String,
+    /// Empty when the venue has a `universe` instead.
+    #[serde(default)]
+    pub symbols: Vec<String>,
+    /// Discover the symbols from the exchange instead, keeping those that
+    /// pass these filters; see [`crate::universe`].
+    pub universe: Option<Universe>,
+    /// Stream names (Binance), topics (Bybit), channels (OKX) or subscription
+    /// types (Hyperliquid), with `$symbol` where the venue expects one. Omitted
+    /// means the exchange's default list; see [`crate::streams`] for what each
+    /// venue accepts.
+    pub streams: Option<Vec<String>>,
+    #[serde(default = "default_connections")]
+    pub connections: u8,
+    /// Seconds between periodic REST polls: depth snapshots on Binance, and
+    /// asset contexts and `l2Book` snapshots on Hyperliquid.
+    pub snapshot_interval_secs: Option<u64>,
+    /// Seconds between REST polls of open interest and the premium index.
+    /// Binance futures venues only; off unless set.
+    pub funding_interval_secs: Option<u64>,
+    /// Seconds between checks of the exchange's instrument list for symbols
+    /// it has stopped trading, which are then dropped; off unless set. Not on
+    /// OKX or Bybit options, whose lists are not read; see
+    /// [`crate::lifecycle`].
+    pub status_interval_secs: Option<u64>,
+    /// Record an `l2Book` from the info endpoint when a coin's websocket book
+    /// stops moving forward. Hyperliquid venues only.
+    #[serde(default)]
+    pub reanchor_books: bool,
+    /// Open each session on the bare combined-stream endpoint and subscribe
+    /// its streams by request, rather than listing them in the URL. Binance
+    /// venues only.
+    #[serde(default)]
+    pub subscribe: bool,
+    /// `text` (the default) or `binary`; see [`collector::record`].
+    #[serde(default)]
+    pub format: Format,
+    /// `daily` (the default), `hourly`, `<N>m` or a size such as `512MiB`.
+    #[serde(default)]
+    pub rotation: Rotation,
+    /// Bounds on an open zstd frame, the most a hard kill can lose per symbol:
+    /// seconds since its first row, and uncompressed MiB.
+    #[serde(default = "default_frame_secs")]
+    pub frame_secs: u64,
+    #[serde(default = "default_frame_mib")]
+    pub frame_mib: u64,
+    /// Writer threads for this venue, each owning the files of the symbols
+    /// that hash to it. One unless asked for: every shard is a thread and a
+    /// queue of its own.
+    #[serde(default = "default_writer_shards")]
+    pub writer_shards: u8,
+    #[serde(default)]
+    pub zstd: Zstd,
+    /// Also record the account's own orders, fills and positions, with the
+    /// API key these variables hold. Binance and Bybit venues only.
+    pub account: Option<Account>,
+}
+
+/// A venue's `account = { … }` table: the names of the environment variables
+/// holding an API key and its secret; see [`crate::account`].
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Account {
+    pub key_env: String,
+    pub secret_env: String,
+}
+
+/// A venue's `zstd = { … }` table; see [`Compression`] for what each does.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields, default)]
+pub struct Zstd {
+    pub level: i32,
+    pub long_window: Option<u32>,
+    pub workers: u32,
+    /// Path to a trained dictionary.
+    pub dictionary: Option<PathBuf>,
+}
+
+impl Default for Zstd {
+    fn default() -> Self {
+        let Compression {
+            level,
+            long_window,
+            workers,
+            ..
+        } = Compression::default();
+        Self {
+            level,
+            long_window,
+            workers,
+            dictionary: None,
+        }
+    }
+}
+
+/// The largest `workers` zstd accepts on a 64-bit build.
+const MAX_ZSTD_WORKERS: u32 = 200;
+
+impl Zstd {
+    /// Ranges zstd would otherwise only reject when the first file is opened,
+    /// long after startup. Shared by the config and the command line.
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        let levels = zstd::compression_level_range();
+        if !levels.contains(&self.level) {
+            bail!(
+                "zstd level must be between {} and {}, got {}",
+                levels.start(),
+                levels.end(),
+                self.level
+            );
+        }
+        if let Some(window) = self.long_window
+            && !(10..=31).contains(&window)
+        {
+            bail!("zstd long_window must be between 10 and 31, got {window}");
+        }
+        if self.workers > MAX_ZSTD_WORKERS {
+            bail!(
+                "zstd workers must be at most {MAX_ZSTD_WORKERS}, got {}",
+                self.workers
+            );
+        }
+        Ok(())
+    }
+}
+
+fn default_connections() -> u8 {
+    1
+}
+
+pub const MAX_WRITER_SHARDS: u8 = 16;
+
+pub fn default_writer_shards() -> u8 {
+    1
+}
+
+pub fn default_frame_secs() -> u64 {
+    file::FRAME_INTERVAL.as_secs()
+}
+
+pub fn default_frame_mib() -> u64 {
+    file::FRAME_BYTES >> 20
+}
+
+impl Venue {
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.exchange)
+    }
+
+    pub fn snapshot_interval(&self) -> Option<Duration> {
+        self.snapshot_interval_secs.map(Duration::from_secs)
+    }
+
+    pub fn funding_interval(&self) -> Option<Duration> {
+        self.funding_interval_secs.map(Duration::from_secs)
+    }
+
+    pub fn status_interval(&self) -> Option<Duration> {
+        self.status_interval_secs.map(Duration::from_secs)
+    }
+
+    /// The writer settings, with the dictionary read from disk.
+    pub fn file_options(&self) -> Result<FileOptions, anyhow::Error> {
+        let dictionary = match &self.zstd.dictionary {
+            Some(path) => {
+                let dictionary = std::fs::read(path).with_context(|| {
+                    format!("venue {}: couldn't read {}", self.name(), path.display())
+                })?;
+                let Some(dictionary) = Dictionary::new(&dictionary, self.zstd.level) else {
+                    bail!(
+                        "venue {}: {} is not a trained zstd dictionary",
+                        self.name(),
+                        path.display()
+                    );
+                };
+                Some(dictionary)
+            }
+            None => None,
+        };
+        Ok(FileOptions {
+            format: self.format,
+            rotation: self.rotation,
+            frame_interval: Duration::from_secs(self.frame_secs),
+            frame_bytes: self.frame_mib << 20,
+            compression: Compression {
+                level: self.zstd.level,
+                long_window: self.zstd.long_window,
+                workers: self.zstd.workers,
+                dictionary,
+            },
+        })
+    }
+
+    /// The checks clap applies to the same values on the command line.
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        if !(1..=8).contains(&self.connections) {
+            bail!(
+                "venue {}: connections must be between 1 and 8, got {}",
+                self.name(),
+                self.connections
+            );
+        }
+        match (self.symbols.is_empty(), &self.universe) {
+            (true, None) => bail!("venue {}: no symbols", self.name()),
+            (false, Some(_)) => bail!(
+                "venue {}: symbols and universe cannot both be given",
+                self.name()
+            ),
+            _ => {}
+        }
+        if self.snapshot_interval_secs == Some(0) {
+            bail!(
+                "venue {}: snapshot_interval_secs must be positive",
+                self.name()
+            );
+        }
+        if self.funding_interval_secs == Some(0) {
+            bail!(
+                "venue {}: funding_interval_secs must be positive",
+                self.name()
+            );
+        }
+        if self.status_interval_secs == Some(0) {
+            bail!(
+                "venue {}: status_interval_secs must be positive",
+                self.name()
+            );
+        }
+        if !(1..=MAX_WRITER_SHARDS).contains(&self.writer_shards) {
+            bail!(
+                "venue {}: writer_shards must be between 1 and {MAX_WRITER_SHARDS}, got {}",
+                self.name(),
+                self.writer_shards
+            );
+        }
+        if self.frame_secs == 0 || self.frame_mib == 0 {
+            bail!(
+                "venue {}: frame_secs and frame_mib must be positive",
+                self.name()
+            );
+        }
+        self.zstd
+            .validate()
+            .with_context(|| format!("venue {}", self.name()))?;
+        Ok(())
+    }
+}
+
+/// Whether two configured paths name the same directory, spelled differently
+/// or not (`data/bybit`, `./data/bybit/`).
+fn same_directory(a: &str, b: &str) -> bool {
+    let normalize = |path: &str| {
+        Path::new(path)
+            .components()
+            .filter(|component| *component != Component::CurDir)
+            .collect::<PathBuf>()
+    };
+    normalize(a) == normalize(b)
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("couldn't read {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("invalid config {}", path.display()))
+    }
+
+    fn parse(text: &str) -> Result<Self, anyhow::Error> {
+        let config: Config = toml::from_str(text)?;
+        if config.venues.is_empty() {
+            bail!("no [[venue]] blocks");
+        }
+        for (index, venue) in config.venues.iter().enumerate() {
+            venue.validate()?;
+            if config.venues[..index]
+                .iter()
+                .any(|other| other.name() == venue.name())
+            {
+                bail!(
+                    "venue name {} is used twice; give the blocks distinct `name`s",
+                    venue.name()
+                );
+            }
+            // Files are named by symbol alone, so two venues writing into one
+            // directory would append to each other's files.
+            if let Some(other) = config.venues[..index]
+                .iter()
+                .find(|other| same_directory(&other.path, &venue.path))
+            {
+                bail!(
+                    "venues {} and {} both write to {}",
+                    other.name(),
+                    venue.name(),
+                    venue.path
+                );
+            }
+        }
+        Ok(config)
+    }
+
+    /// The venues this process collects: the ones named, in file order, or all
+    /// of them when no names are given.
+    pub fn select(self, names: &[String]) -> Result<Vec<Venue>, anyhow::Error> {
+        if let Some(unknown) = names.iter().find(|name| {
+            !self
+                .venues
+                .iter()
+                .any(|venue| venue.name() == name.as_str())
+        }) {
+            bail!("no venue named {unknown} in the config");
+        }
+        Ok(self
+            .venues
+            .into_iter()
+            .filter(|venue| names.is_empty() || names.iter().any(|name| name == venue.name()))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEPLOYMENT: &str = r#"
+        [[venue]]
+        exchange = "binancefutures"
+        path = "/data/um"
+        symbols = ["btcusdt"]
+        connections = 2
+        snapshot_interval_secs = 1800
+        rotation = "hourly"
+        format = "binary"
+        zstd = { level = 9, long_window = 27, workers = 2 }
+
+        [[venue]]
+        exchange = "bybit"
+        path = "/data/bybit"
+        symbols = ["BTCUSDT"]
+        streams = ["publicTrade.$symbol"]
+    "#;
+
+    #[test]
+    fn venue_is_selected_by_name_with_defaults_filled_in() {
+        let venues = Config::parse(DEPLOYMENT)
+            .unwrap()
+            .select(&["bybit".to_owned()])
+            .unwrap();
+
+        assert_eq!(venues.len(), 1);
+        let venue = &venues[0];
+        assert_eq!(venue.path, "/data/bybit");
+        assert_eq!(venue.connections, 1);
+        assert_eq!(venue.streams, Some(vec!["publicTrade.$symbol".to_owned()]));
+        assert_eq!(venue.snapshot_interval(), None);
+        assert_eq!(venue.file_options().unwrap(), FileOptions::default());
+    }
+
+    #[test]
+    fn every_venue_runs_unless_narrowed() {
+        let config = Config::parse(DEPLOYMENT).unwrap();
+        let names: Vec<String> = config
+            .select(&[])
+            .unwrap()
+            .iter()
+            .map(|venue| venue.name().to_owned())
+            .collect();
+
+        assert_eq!(names, ["binancefutures", "bybit"]);
+        let error = Config::parse(DEPLOYMENT)
+            .unwrap()
+            .select(&["okx".to_owned()])
+            .unwrap_err();
+        assert!(error.to_string().contains("okx"), "{error}");
+    }
+
+    #[test]
+    fn zstd_table_reaches_the_writer() {
+        let venues = Config::parse(DEPLOYMENT)
+            .unwrap()
+            .select(&["binancefutures".to_owned()])
+            .unwrap();
+        let options = venues[0].file_options().unwrap();
+
+        assert_eq!(options.rotation, Rotation::Minutes(60));
+        assert_eq!(options.format, Format::Binary);
+        assert_eq!(
+            options.compression,
+            Compression {
+                level: 9,
+                long_window: Some(27),
+                workers: 2,
+                dictionary: None,
+            }
+        );
+    }
+
+    #[test]
+    fn untrained_dictionary_is_rejected() {
+        let path = std::env::temp_dir().join(format!("collector-dict-{}", std::process::id()));
+        std::fs::write(&path, b"just some bytes, not a dictionary").unwrap();
+        let mut venue = Config::parse(DEPLOYMENT).unwrap().venues.remove(0);
+        venue.zstd.dictionary = Some(path.clone());
+
+        let error = venue.file_options().unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.to_string().contains("not a trained"), "{error}");
+    }
+
+    #[test]
+    fn a_universe_stands_in_for_the_symbols() {
+        let text = "[[venue]]\nexchange = \"binancefutures\"\npath = \"p\"\n\
+                    universe = { quote = \"USDT\", contract_type = \"PERPETUAL\", top = 20 }";
+        let venue = Config::parse(text).unwrap().venues.remove(0);
+        assert!(venue.symbols.is_empty());
+        assert_eq!(
+            venue.universe,
+            Some(Universe {
+                quote: Some("USDT".to_owned()),
+                contract_type: Some("PERPETUAL".to_owned()),
+                status: None,
+                top: Some(20),
+            })
+        );
+
+        let both = format!("{text}\nsymbols = [\"btcusdt\"]");
+        assert!(Config::parse(&both).is_err());
+    }
+
+    #[test]
+    fn reanchoring_is_off_unless_asked_for() {
+        let text = "[[venue]]\nexchange = \"hyperliquid\"\npath = \"p\"\nsymbols = [\"BTC\"]\n";
+        let venue = Config::parse(text).unwrap().venues.remove(0);
+        assert!(!venue.reanchor_books);
+
+        let venue = Config::parse(&format!("{text}reanchor_books = true"))
+            .unwrap()
+            .venues
+            .remove(0);
+        assert!(venue.reanchor_books);
+    }
+
+    #[test]
+    fn invalid_blocks_are_rejected() {
+        for text in [
+            // Misspelled keys must not silently fall back to defaults.
+            "[[venue]]\nexchange = \"bybit\"\npath = \"p\"\nsymbols = [\"a\"]\nconection = 2",
+            "[[venue]]\nexchange = \"bybit\"\npath = \"p\"\nsymbols = [\"a\"]\nconnections = 9",
+            "[[venue]]\nexchange = \"bybit\"\npath = \"p\"\nsymbols = []",
+            "[[venue]]\nexchange = \"bybit\"\npath = \"p\"\nsymbols = [\"a\"]\n\
+             [[venue]]\nexchange = \"bybit\"\npath = \"q\"\nsymbols = [\"a\"]",
+            // One directory, spelled two ways.
+            "[[venue]]\nexchange = \"bybit\"\npath = \"data/p\"\nsymbols = [\"a\"]\n\
+             [[venue]]\nexchange = \"okx\"\npath = \"./data/p/\"\nsymbols = [\"a\"]",
+            "[[venue]]\nexchange = \"binancefutures\"\npath = \"p\"\nsymbols = [\"a\"]\n\
+             funding_interval_secs = 0",
+            "[[venue]]\nexchange = \"bybit\"\npath = \"p\"\nsymbols = [\"a\"]\nstatus_interval_secs = 0",
+            "[[venue]]\nexchange = \"bybit\"\npath = \"p\"\nsymbols = [\"a\"]\nrotation = \"7m\"",
+            "[[venue]]\nexchange = \"bybit\"\npath = \"p\"\nsymbols = [\"a\"]\nframe_secs = 0",
+            "[[venue]]\nexchange = \"bybit\"\npath = \"p\"\nsymbols = [\"a\"]\nwriter_shards = 0",
+            "[[venue]]\nexchange = \"bybit\"\npath = \"p\"\nsymbols = [\"a\"]\nzstd = { level = 23 }",
+            "[[venue]]\nexchange = \"bybit\"\npath = \"p\"\nsymbols = [\"a\"]\nzstd = { long_window = 40 }",
+            "[[venue]]\nexchange = \"bybit\"\npath = \"p\"\nsymbols = [\"a\"]\nzstd = { lvl = 3 }",
+            "[[venue]]\nexchange = \"bybit\"\npath = \"p\"\nsymbols = [\"a\"]\naccount = { key = \"k\" }",
+        ] {
+            assert!(Config::parse(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn shipped_deployment_parses() {
+        let config = Config::parse(include_str!("../scripts/collector.toml")).unwrap();
+
+        assert!(config.venues.iter().all(|venue| venue.connections == 2));
+    }
+}
@@ -65,6 +65,8 @@ use std::{
 
 use tracing::{info, warn};
 
+use crate::metrics;
+
 /// How far back duplicates are remembered.
 ///
 /// Only has to cover the delivery skew between connections. They share one
@@ -121,6 +123,9 @@ pub struct Dedup {
     duplicate: u64,
     last_report: Instant,
     last_ceiling_warning: Option<Instant>,
+    /// `(unique, duplicate)` totals for the metrics endpoint. `unique` and
+    /// `duplicate` above reset at every report; these never do.
+    exported: Option<(metrics::Counter, metrics::Counter)>,
 }
 
 impl Dedup {
@@ -164,7 +169,30 @@ impl Dedup {
             duplicate: 0,
             last_report: now,
             last_ceiling_warning: None,
+            exported: None,
+        }
+    }
+
+    /// Export this filter's counts under `endpoint`. Only enabled filters are
+    /// exported: a single connection has nothing to suppress, and a zero there
+    /// would read as redundancy that is not delivering.
+    pub fn with_metrics(mut self, endpoint: &str) -> Self {
+        if self.enabled {
+            let labels = [("endpoint", endpoint)];
+            self.exported = Some((
+                metrics::counter(
+                    "collector_dedup_unique_total",
+                    "Messages kept by duplicate suppression.",
+                    &labels,
+                ),
+                metrics::counter(
+                    "collector_dedup_duplicates_total",
+                    "Redundant copies discarded by duplicate suppression.",
+                    &labels,
+                ),
+            ));
         }
+        self
     }
 
     /// True if this payload has already been seen inside the window.
@@ -197,9 +225,15 @@ impl Dedup {
         // collision here silently discards a real message.
         if self.previous.contains(&key) || !self.current.insert(key) {
             self.duplicate += 1;
+            if let Some((_, duplicates)) = &self.exported {
+                duplicates.inc();
+            }
             true
         } else {
             self.unique += 1;
+            if let Some((unique, _)) = &self.exported {
+                unique.inc();
+            }
             false
         }
     }
@@ -1,62 +1,505 @@
-use std::{borrow::Cow, collections::HashMap, fs::File, io, io::Write, time::Duration};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{self, Read, Seek, Write},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
-use bytes::BufMut;
-use jiff::Timestamp;
+use jiff::{Timestamp, tz::TimeZone};
+use serde::Deserialize;
+use tokio::sync::mpsc::{Sender, error::SendError};
 use tracing::{error, info, warn};
-use zstd::stream::write::Encoder as ZstdEncoder;
+use xxhash_rust::xxh3::xxh3_64;
+use zstd::{dict::EncoderDictionary, stream::write::Encoder as ZstdEncoder};
+
+use collector::{
+    record::{self, Format, Header, NO_CONNECTION, Stream},
+    replay::encode_symbol,
+};
 
 use crate::symbol::Symbol;
 
-pub type WriteRecord = (Timestamp, Symbol, bytes::Bytes);
+/// One message on its way to disk.
+#[derive(Debug)]
+pub struct WriteRecord {
+    pub recv_time: Timestamp,
+    pub symbol: Symbol,
+    pub data: bytes::Bytes,
+    /// The redundant connection that delivered it first; `None` for data
+    /// fetched over REST. Only the binary format keeps it.
+    pub connection: Option<u8>,
+    /// What kind of message it is; like `connection`, dropped by the text
+    /// format.
+    pub stream: Stream,
+}
+
+impl WriteRecord {
+    /// A record of websocket data, which always arrives on a connection.
+    pub fn received(
+        recv_time: Timestamp,
+        symbol: Symbol,
+        data: bytes::Bytes,
+        connection: usize,
+        stream: Stream,
+    ) -> Self {
+        Self {
+            recv_time,
+            symbol,
+            data,
+            // Connections are capped at 8; the saturated value reads as "none".
+            connection: Some(u8::try_from(connection).unwrap_or(NO_CONNECTION)),
+            stream,
+        }
+    }
+
+    /// A record of data fetched over REST, such as a depth snapshot.
+    pub fn fetched(
+        recv_time: Timestamp,
+        symbol: Symbol,
+        data: bytes::Bytes,
+        stream: Stream,
+    ) -> Self {
+        Self {
+            recv_time,
+            symbol,
+            data,
+            connection: None,
+            stream,
+        }
+    }
+
+    /// A note of the collector's own about `symbol`, such as the lifecycle
+    /// marker written when it is delisted.
+    pub fn lifecycle(recv_time: Timestamp, symbol: Symbol, data: bytes::Bytes) -> Self {
+        Self {
+            recv_time,
+            symbol,
+            data,
+            connection: None,
+            stream: Stream::Lifecycle,
+        }
+    }
+}
+
+/// Hands records to a venue's writer shards.
+///
+/// Each shard is a thread with its own queue and [`Writer`], and a symbol
+/// always hashes to the same one, so a symbol's rows stay in order while a
+/// shard stuck in a slow `fsync` at rotation only holds up the symbols it owns
+/// — not every symbol of the venue, and not the connections feeding them.
+#[derive(Clone)]
+pub struct RecordSender {
+    shards: Arc<[Sender<WriteRecord>]>,
+}
+
+impl RecordSender {
+    pub fn new(shards: Vec<Sender<WriteRecord>>) -> Self {
+        assert!(
+            !shards.is_empty(),
+            "a venue needs at least one writer shard"
+        );
+        Self {
+            shards: shards.into(),
+        }
+    }
+
+    fn shard(&self, symbol: &str) -> &Sender<WriteRecord> {
+        &self.shards[shard_of(symbol, self.shards.len())]
+    }
+
+    /// Queue a record, waiting only if its own shard is full.
+    pub async fn send(&self, record: WriteRecord) -> Result<(), SendError<WriteRecord>> {
+        self.shard(&record.symbol).send(record).await
+    }
+}
+
+/// The shard a symbol's records go to. Stable for the life of the process,
+/// which is all per-symbol ordering needs.
+fn shard_of(symbol: &str, shards: usize) -> usize {
+    (xxh3_64(symbol.as_bytes()) % shards as u64) as usize
+}
+
+/// A single-shard sender and its queue, for driving a connector in tests.
+#[cfg(test)]
+pub fn record_channel(capacity: usize) -> (RecordSender, tokio::sync::mpsc::Receiver<WriteRecord>) {
+    let (tx, rx) = tokio::sync::mpsc::channel(capacity);
+    (RecordSender::new(vec![tx]), rx)
+}
 
 const SYNC_ATTEMPTS: u32 = 3;
+/// Default bounds on an open zstd frame: what a hard kill can cost a symbol.
+pub const FRAME_INTERVAL: Duration = Duration::from_secs(10);
+pub const FRAME_BYTES: u64 = 8 << 20;
+/// How often [`Writer::end_due_frames`] looks at every file.
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+const NANOS_PER_MINUTE: i64 = 60_000_000_000;
+const MINUTES_PER_DAY: u32 = 24 * 60;
 
-/// Characters left as-is in a filename.
+/// When a symbol's file is closed and the next one started.
 ///
-/// Deliberately permissive: the point is to keep the exchange's own identifier
-/// readable on disk, so only genuinely path-hostile bytes get escaped. `@` is
-/// the obvious one to allow — Hyperliquid names 315 of its 316 spot pairs `@1`
-/// … `@315` — and `+`/`=` show up in dated contracts elsewhere. All of these
-/// are legal filename characters on Linux, macOS and Windows alike.
-fn is_safe_in_filename(byte: u8) -> bool {
-    byte.is_ascii_alphanumeric() || matches!(byte, b'.' | b'_' | b'-' | b'@' | b'+' | b'=')
+/// Every policy also rotates at UTC midnight, so no file ever straddles two
+/// dates and the date in its name is always the date of every row inside it.
+/// Names sort in time order within a policy, and across policies too: a daily
+/// `btcusdt_20240101.zst` sorts before the `btcusdt_20240101T0000.zst` an
+/// hourly restart would write next to it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Rotation {
+    /// `<symbol>_<YYYYMMDD>.zst`, one per UTC day.
+    #[default]
+    Daily,
+    /// `<symbol>_<YYYYMMDD>T<HHMM>.zst`, one per aligned window of this many
+    /// minutes. The window divides the day, so every day starts a new one.
+    Minutes(u32),
+    /// `<symbol>_<YYYYMMDD>T<HHMMSS>.zst`, started by the first row after the
+    /// compressed file reached this many bytes. Checked after each row, so a
+    /// file overshoots by up to one zstd block (128 KiB) and one row. A file
+    /// that fills within the second it was opened is followed by
+    /// `<symbol>_<YYYYMMDD>T<HHMMSS>_001.zst` and so on, which sort after it.
+    Bytes(u64),
 }
 
-/// Encode an exchange-supplied symbol into a filename component.
+impl Rotation {
+    /// The start of the period containing `nanos` and the start of the next.
+    fn period(self, nanos: i64) -> (i64, i64) {
+        let length = match self {
+            Rotation::Minutes(minutes) => i64::from(minutes) * NANOS_PER_MINUTE,
+            Rotation::Daily | Rotation::Bytes(_) => i64::from(MINUTES_PER_DAY) * NANOS_PER_MINUTE,
+        };
+        // Unix time has no leap seconds, so UTC midnight is always a multiple
+        // of the day length and aligned windows fall on the wall clock.
+        let start = nanos - nanos.rem_euclid(length);
+        (start, start + length)
+    }
+
+    /// The file name suffix for a file opened at `nanos`.
+    fn suffix(self, nanos: i64) -> String {
+        let (start, _) = self.period(nanos);
+        let (at, format) = match self {
+            Rotation::Daily => (start, "%Y%m%d"),
+            Rotation::Minutes(_) => (start, "%Y%m%dT%H%M"),
+            Rotation::Bytes(_) => (nanos, "%Y%m%dT%H%M%S"),
+        };
+        Timestamp::from_nanosecond(i128::from(at))
+            .unwrap_or(Timestamp::UNIX_EPOCH)
+            .to_zoned(TimeZone::UTC)
+            .strftime(format)
+            .to_string()
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    /// `daily`, `hourly`, `<N>m` for N-minute windows, or `<N>MiB` / `<N>GiB`
+    /// for a size limit.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || format!("invalid rotation {text:?}: expected daily, hourly, <N>m, <N>MiB or <N>GiB");
+        match text {
+            "daily" => return Ok(Rotation::Daily),
+            "hourly" => return Ok(Rotation::Minutes(60)),
+            _ => {}
+        }
+        if let Some(minutes) = text.strip_suffix('m') {
+            let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+            if minutes == 0 || !MINUTES_PER_DAY.is_multiple_of(minutes) {
+                return Err(format!(
+                    "invalid rotation {text:?}: the window must divide a day evenly"
+                ));
+            }
+            return Ok(Rotation::Minutes(minutes));
+        }
+        let (count, unit) = if let Some(count) = text.strip_suffix("MiB") {
+            (count, 1 << 20)
+        } else if let Some(count) = text.strip_suffix("GiB") {
+            (count, 1 << 30)
+        } else {
+            return Err(invalid());
+        };
+        match count.parse::<u64>() {
+            Ok(count) if count > 0 => count
+                .checked_mul(unit)
+                .map(Rotation::Bytes)
+                .ok_or_else(invalid),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl TryFrom<String> for Rotation {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl fmt::Display for Rotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Rotation::Daily => f.write_str("daily"),
+            Rotation::Minutes(60) => f.write_str("hourly"),
+            Rotation::Minutes(minutes) => write!(f, "{minutes}m"),
+            Rotation::Bytes(bytes) if bytes.is_multiple_of(1 << 30) => {
+                write!(f, "{}GiB", bytes >> 30)
+            }
+            Rotation::Bytes(bytes) => write!(f, "{}MiB", bytes >> 20),
+        }
+    }
+}
+
+/// The file under a zstd encoder, counting what the encoder has flushed to it.
+struct CountingFile {
+    file: File,
+    written: u64,
+}
+
+impl Write for CountingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// zstd settings, trading the writer thread's CPU for disk.
 ///
-/// Some symbols are not valid path components — Hyperliquid reports spot pairs
-/// as `PURR/USDC`, which would open a file inside a directory that was never
-/// created and take the whole collector down with it.
+/// The default — level 1, no long window, no workers, no dictionary — keeps up
+/// with the busiest venue on one core; archival hosts can afford more.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Compression {
+    pub level: i32,
+    /// log2 of the long-distance-matching window, e.g. 27 for 128 MiB. Order
+    /// books repeat themselves over minutes rather than kilobytes, which is
+    /// what a long window finds. Readers must allow a window this large;
+    /// `gap_detector` allows any.
+    pub long_window: Option<u32>,
+    /// zstd worker threads compressing beside the writer thread. 0 compresses
+    /// on the writer thread itself.
+    pub workers: u32,
+    /// A dictionary trained on this venue's rows (`zstd --train`). Every frame
+    /// names it by id, and reading the file back needs it.
+    pub dictionary: Option<Dictionary>,
+}
+
+/// A trained zstd dictionary, digested once for the venue's level and shared
+/// by every file and frame the venue's writers start.
+#[derive(Clone)]
+pub struct Dictionary {
+    id: u32,
+    prepared: Arc<EncoderDictionary<'static>>,
+}
+
+impl Dictionary {
+    /// Prepare `raw` for compressing at `level`; `None` unless it is a trained
+    /// dictionary, since a raw-content one leaves no id in the frames and
+    /// nothing reading the files could tell which one they need.
+    pub fn new(raw: &[u8], level: i32) -> Option<Self> {
+        let id = zstd::zstd_safe::get_dict_id_from_dict(raw)?.get();
+        Some(Self {
+            id,
+            prepared: Arc::new(EncoderDictionary::copy(raw, level)),
+        })
+    }
+}
+
+/// Trained dictionaries are told apart by their id, as the frames do.
+impl PartialEq for Dictionary {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Dictionary {}
+
+impl fmt::Debug for Dictionary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dictionary").field("id", &self.id).finish()
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            level: 1,
+            long_window: None,
+            workers: 0,
+            dictionary: None,
+        }
+    }
+}
+
+impl Compression {
+    fn encoder(&self, file: CountingFile) -> io::Result<ZstdEncoder<'static, CountingFile>> {
+        let mut encoder = match &self.dictionary {
+            // The prepared dictionary carries the level.
+            Some(dictionary) => ZstdEncoder::with_prepared_dictionary(file, &dictionary.prepared)?,
+            None => ZstdEncoder::new(file, self.level)?,
+        };
+        if let Some(window) = self.long_window {
+            encoder.long_distance_matching(true)?;
+            encoder.window_log(window)?;
+        }
+        if self.workers > 0 {
+            encoder.multithread(self.workers)?;
+        }
+        Ok(encoder)
+    }
+}
+
+/// How a venue's files are written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileOptions {
+    pub format: Format,
+    pub rotation: Rotation,
+    /// End the open zstd frame once its first row is this old…
+    pub frame_interval: Duration,
+    /// …or once this many uncompressed bytes went into it, whichever is first.
+    pub frame_bytes: u64,
+    pub compression: Compression,
+}
+
+impl Default for FileOptions {
+    fn default() -> Self {
+        Self {
+            format: Format::Text,
+            rotation: Rotation::Daily,
+            frame_interval: FRAME_INTERVAL,
+            frame_bytes: FRAME_BYTES,
+            compression: Compression::default(),
+        }
+    }
+}
+
+/// Reads zstd frame and block headers, seeking over everything else.
+struct FrameWalker<'a> {
+    reader: io::BufReader<&'a mut File>,
+    position: u64,
+    len: u64,
+}
+
+impl FrameWalker<'_> {
+    const MAGIC: u32 = 0xFD2F_B528;
+    const SKIPPABLE_MASK: u32 = 0xFFFF_FFF0;
+    const SKIPPABLE_MAGIC: u32 = 0x184D_2A50;
+
+    /// A little-endian integer of `n` bytes.
+    fn read(&mut self, n: usize) -> io::Result<u64> {
+        let mut bytes = [0u8; 8];
+        self.reader.read_exact(&mut bytes[..n])?;
+        self.position += n as u64;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn skip(&mut self, n: u64) -> io::Result<()> {
+        if self.position + n > self.len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.reader.seek_relative(n as i64)?;
+        self.position += n;
+        Ok(())
+    }
+
+    /// Step over one whole frame.
+    fn frame(&mut self) -> io::Result<()> {
+        let invalid = |what| io::Error::new(io::ErrorKind::InvalidData, what);
+        let magic = self.read(4)? as u32;
+        if magic & Self::SKIPPABLE_MASK == Self::SKIPPABLE_MAGIC {
+            let size = self.read(4)?;
+            return self.skip(size);
+        }
+        if magic != Self::MAGIC {
+            return Err(invalid("not a zstd frame"));
+        }
+        let descriptor = self.read(1)? as u8;
+        let single_segment = descriptor & 0x20 != 0;
+        let window = if single_segment { 0 } else { 1 };
+        let dictionary = [0, 1, 2, 4][usize::from(descriptor & 0x03)];
+        let content_size = match descriptor >> 6 {
+            0 if single_segment => 1,
+            0 => 0,
+            1 => 2,
+            2 => 4,
+            _ => 8,
+        };
+        self.skip(window + dictionary + content_size)?;
+        loop {
+            let header = self.read(3)?;
+            match (header >> 1) & 0x03 {
+                // Raw and compressed blocks carry their size in bytes, RLE one.
+                0 | 2 => self.skip(header >> 3)?,
+                1 => self.skip(1)?,
+                _ => return Err(invalid("reserved block type")),
+            }
+            if header & 1 != 0 {
+                break;
+            }
+        }
+        if descriptor & 0x04 != 0 {
+            self.skip(4)?;
+        }
+        Ok(())
+    }
+}
+
+/// Length of the prefix of `file` made of complete zstd frames.
 ///
-/// The encoding must be **injective**. Folding unsafe bytes to a single `_`
-/// would map `purr/usdc` and `purr_usdc` to the same name, and since each
-/// symbol gets its own `RotatingFile`, two independent zstd encoders would
-/// append interleaved frames to one file and render it undecodable. Percent
-/// escaping avoids that: `%` is itself unsafe, so it is always escaped and no
-/// two distinct symbols can collide.
-fn encode_symbol(symbol: &str) -> Cow<'_, str> {
-    if symbol.bytes().all(is_safe_in_filename) {
-        return Cow::Borrowed(symbol);
-    }
-    const HEX: &[u8; 16] = b"0123456789ABCDEF";
-    let mut encoded = String::with_capacity(symbol.len() + 8);
-    for byte in symbol.bytes() {
-        if is_safe_in_filename(byte) {
-            encoded.push(byte as char);
-        } else {
-            encoded.push('%');
-            encoded.push(HEX[(byte >> 4) as usize] as char);
-            encoded.push(HEX[(byte & 0x0f) as usize] as char);
+/// Walks headers only, so a multi-gigabyte file written before frames were
+/// bounded costs a few reads per block rather than a decode. Everything from
+/// the first header that does not parse, or whose contents run past the end of
+/// the file, is the torn tail of a frame that was being written when the
+/// process died.
+fn complete_frames_len(file: &mut File) -> io::Result<u64> {
+    let len = file.metadata()?.len();
+    file.seek(io::SeekFrom::Start(0))?;
+    let mut walker = FrameWalker {
+        reader: io::BufReader::new(file),
+        position: 0,
+        len,
+    };
+    let mut complete = 0;
+    while complete < len {
+        match walker.frame() {
+            Ok(()) => complete = walker.position,
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData
+                ) =>
+            {
+                break;
+            }
+            Err(error) => return Err(error),
         }
     }
-    Cow::Owned(encoded)
+    Ok(complete)
 }
 
 pub struct RotatingFile {
+    options: FileOptions,
     next_rotation: i64,
     path: String,
-    file: Option<ZstdEncoder<'static, File>>,
-    buf: bytes::BytesMut,
+    /// The file currently open, `path` plus its period's suffix.
+    name: String,
+    file: Option<ZstdEncoder<'static, CountingFile>>,
+    /// Uncompressed bytes written to `name` by this process, and its size when
+    /// opened, for the compression ratio reported when it is closed.
+    raw_bytes: u64,
+    opened_size: u64,
+    /// Receive time of the first row in the open frame; `None` while it is
+    /// empty, so a quiet symbol does not end a frame with nothing in it.
+    frame_started: Option<i64>,
+    frame_bytes: u64,
+    buf: Vec<u8>,
     /// Set when a rotation could not be finalized, so the already-rotated file
     /// may be missing its zstd footer. Collection continues, but the process
     /// must not report a clean exit.
@@ -65,51 +508,139 @@ pub struct RotatingFile {
 
 impl RotatingFile {
     fn create(
+        options: &FileOptions,
         timestamp: Timestamp,
         path: &str,
-    ) -> Result<(ZstdEncoder<'static, File>, i64), io::Error> {
-        let zoned = timestamp.to_zoned(jiff::tz::TimeZone::UTC);
-        let date_str = zoned.date().strftime("%Y%m%d");
-        let file = File::options()
-            .create(true)
-            .append(true)
-            .open(format!("{path}_{date_str}.zst"))?;
-
-        let next_rotation = zoned
-            .date()
-            .tomorrow()
-            .map_err(io::Error::other)?
-            .at(0, 0, 0, 0)
-            .to_zoned(jiff::tz::TimeZone::UTC)
-            .map_err(io::Error::other)?
-            .timestamp()
-            .as_nanosecond();
-
-        Ok((ZstdEncoder::new(file, 1)?, next_rotation as i64))
-    }
-
-    pub fn new(timestamp: Timestamp, path: String) -> Result<Self, io::Error> {
-        let (file, next_rotation) = Self::create(timestamp, &path)?;
+    ) -> Result<(ZstdEncoder<'static, CountingFile>, i64, String), io::Error> {
+        let nanos = timestamp.as_nanosecond() as i64;
+        let suffix = options.rotation.suffix(nanos);
+        let mut sequence = 0;
+        let (name, file, complete) = loop {
+            let name = match sequence {
+                0 => format!("{path}_{suffix}.zst"),
+                sequence => format!("{path}_{suffix}_{sequence:03}.zst"),
+            };
+            let mut file = File::options()
+                .create(true)
+                .read(true)
+                .append(true)
+                .open(&name)?;
+            // A file we are about to append to may end in the frame a killed
+            // process was writing. Frames appended after it would decode as
+            // part of that garbage, so cut back to the last complete frame
+            // first.
+            let len = file.metadata()?.len();
+            let complete = complete_frames_len(&mut file)?;
+            if complete < len {
+                warn!(
+                    path = %name,
+                    dropped_bytes = len - complete,
+                    "truncating a torn zstd frame left by an unclean exit"
+                );
+                file.set_len(complete)?;
+            }
+            // A size-limited file named to the second can fill within that
+            // second; reopening it would rotate again at every row until the
+            // clock ticks over, so the next row goes to a numbered sibling.
+            match options.rotation {
+                Rotation::Bytes(limit) if complete >= limit => sequence += 1,
+                _ => break (name, file, complete),
+            }
+        };
+        let (_, next_rotation) = options.rotation.period(nanos);
+        let encoder = options.compression.encoder(CountingFile {
+            file,
+            // Appending to a file from before a restart continues its count,
+            // so the size limit is the file's, not the session's.
+            written: complete,
+        })?;
+
+        Ok((encoder, next_rotation, name))
+    }
+
+    pub fn new(
+        timestamp: Timestamp,
+        path: String,
+        options: FileOptions,
+    ) -> Result<Self, io::Error> {
+        let (file, next_rotation, name) = Self::create(&options, timestamp, &path)?;
         Ok(Self {
+            options,
             next_rotation,
+            name,
+            opened_size: file.get_ref().written,
+            raw_bytes: 0,
             file: Some(file),
+            frame_started: None,
+            frame_bytes: 0,
             path,
-            buf: bytes::BytesMut::with_capacity(8 * 1024),
+            buf: Vec::with_capacity(8 * 1024),
             degraded: false,
         })
     }
 
+    fn is_full(&self) -> bool {
+        match (self.options.rotation, &self.file) {
+            (Rotation::Bytes(limit), Some(encoder)) => encoder.get_ref().written >= limit,
+            _ => false,
+        }
+    }
+
+    /// Close the open frame and start the next one in the same file.
+    ///
+    /// Everything up to a frame end is in the kernel's hands once `finish`
+    /// returns, so an abort, OOM kill or `kill -9` loses at most the rows of
+    /// the open frame. Not synced: surviving power loss is what rotation's
+    /// `sync_all` is for, and an fsync per frame per symbol would cost more
+    /// than the data is worth.
+    fn end_frame(&mut self) -> io::Result<()> {
+        let Some(encoder) = self.file.take() else {
+            return Ok(());
+        };
+        let file = encoder.finish().map_err(|error| {
+            io::Error::new(
+                error.kind(),
+                format!("failed to end zstd frame {}: {error}", self.name),
+            )
+        })?;
+        self.file = Some(self.options.compression.encoder(file)?);
+        self.frame_started = None;
+        self.frame_bytes = 0;
+        Ok(())
+    }
+
+    /// End the open frame if it has reached either bound by `now` (nanos).
+    fn end_frame_if_due(&mut self, now: i64) -> io::Result<()> {
+        let Some(started) = self.frame_started else {
+            return Ok(());
+        };
+        if now.saturating_sub(started) >= self.options.frame_interval.as_nanos() as i64
+            || self.frame_bytes >= self.options.frame_bytes
+        {
+            self.end_frame()?;
+        }
+        Ok(())
+    }
+
     pub fn finalize(&mut self) -> io::Result<()> {
         let Some(encoder) = self.file.take() else {
             return Ok(());
         };
 
-        let file = encoder.finish().map_err(|error| {
+        let CountingFile { file, written } = encoder.finish().map_err(|error| {
             io::Error::new(
                 error.kind(),
-                format!("failed to finish zstd stream {}: {error}", self.path),
+                format!("failed to finish zstd stream {}: {error}", self.name),
             )
         })?;
+        let compressed = written.saturating_sub(self.opened_size);
+        info!(
+            path = %self.name,
+            raw_bytes = self.raw_bytes,
+            compressed_bytes = compressed,
+            ratio = format_args!("{:.2}", self.raw_bytes as f64 / compressed.max(1) as f64),
+            "file closed"
+        );
 
         // Retry a transient fsync failure, then let the last attempt speak for
         // itself — no unreachable arm to fall out of sync with the bound.
@@ -117,7 +648,7 @@ impl RotatingFile {
             match file.sync_all() {
                 Ok(()) => return Ok(()),
                 Err(error) => {
-                    warn!(path = %self.path, attempt, %error, "sync_all failed; retrying");
+                    warn!(path = %self.name, attempt, %error, "sync_all failed; retrying");
                     std::thread::sleep(Duration::from_millis(25 * u64::from(attempt)));
                 }
             }
@@ -127,90 +658,150 @@ impl RotatingFile {
                 error.kind(),
                 format!(
                     "failed to sync {} after {SYNC_ATTEMPTS} attempts: {error}",
-                    self.path
+                    self.name
                 ),
             )
         })
     }
 
-    pub fn write(&mut self, timestamp: Timestamp, data: bytes::Bytes) -> Result<(), io::Error> {
+    pub fn write(&mut self, record: &WriteRecord) -> Result<(), io::Error> {
+        let timestamp = record.recv_time;
         let ts_nanos = timestamp.as_nanosecond();
-        if ts_nanos >= self.next_rotation as i128 {
+        let reopening = self.file.is_none();
+        if reopening || ts_nanos >= self.next_rotation as i128 || self.is_full() {
+            self.frame_started = None;
+            self.frame_bytes = 0;
             if let Err(error) = self.finalize() {
-                // Failing to close yesterday's file must not stop today's data
+                // Failing to close the last file must not stop the next one's data
                 // for this symbol, let alone for every other symbol sharing the
                 // writer thread. `degraded` carries the failure to the exit code.
                 error!(
-                    path = %self.path,
+                    path = %self.name,
                     %error,
                     "failed to finalize file on rotation; continuing with the new file"
                 );
                 self.degraded = true;
             }
-            let (new_file, next_rotation) = Self::create(timestamp, &self.path)?;
+            let (new_file, next_rotation, name) =
+                Self::create(&self.options, timestamp, &self.path)?;
+            self.opened_size = new_file.get_ref().written;
+            self.raw_bytes = 0;
             self.file = Some(new_file);
             self.next_rotation = next_rotation;
-            info!(%self.path, "date is changed, file rotated");
+            self.name = name;
+            if reopening {
+                info!(path = %self.name, "file reopened");
+            } else {
+                info!(path = %self.name, rotation = %self.options.rotation, "file rotated");
+            }
         }
 
         self.buf.clear();
-        let mut itoa_buf = itoa::Buffer::new();
-        self.buf
-            .put_slice(itoa_buf.format(ts_nanos as i64).as_bytes());
-
-        self.buf.put_u8(b' ');
-        self.buf.put(data);
-        self.buf.put_u8(b'\n');
+        record::encode(
+            &mut self.buf,
+            self.options.format,
+            &Header {
+                recv_ns: ts_nanos as i64,
+                connection: record.connection,
+                stream: record.stream,
+            },
+            &record.data,
+        );
 
         // Never `unwrap`: the release profile is `panic = "abort"`, so a panic
         // here would skip every `Drop` and truncate all the other symbols' files.
         let file = self
             .file
             .as_mut()
-            .ok_or_else(|| io::Error::other(format!("{} has no open file", self.path)))?;
-        file.write_all(&self.buf)
+            .ok_or_else(|| io::Error::other(format!("{} has no open file", self.name)))?;
+        file.write_all(&self.buf)?;
+        self.frame_bytes += self.buf.len() as u64;
+        self.raw_bytes += self.buf.len() as u64;
+        let now = ts_nanos as i64;
+        self.frame_started.get_or_insert(now);
+        self.end_frame_if_due(now)
+    }
+}
+
+impl RotatingFile {
+    /// Finalize the open file now instead of at rotation, because nothing more
+    /// is expected for its symbol. Should a row arrive after all, it opens the
+    /// period's file again and appends a new frame.
+    fn close_early(&mut self) {
+        self.frame_started = None;
+        self.frame_bytes = 0;
+        if let Err(error) = self.finalize() {
+            error!(path = %self.name, %error, "failed to finalize file closed early");
+            self.degraded = true;
+        }
     }
 }
 
 impl Drop for RotatingFile {
     fn drop(&mut self) {
         if let Err(error) = self.finalize() {
-            warn!(path = %self.path, %error, "failed to finalize file on drop");
+            warn!(path = %self.name, %error, "failed to finalize file on drop");
         }
     }
 }
 
 pub struct Writer {
     path: String,
+    options: FileOptions,
     files: HashMap<Symbol, RotatingFile>,
+    next_sweep: i64,
 }
 
 impl Writer {
-    pub fn new(path: &str) -> Self {
+    pub fn new(path: &str, options: FileOptions) -> Self {
         Self {
             path: path.to_string(),
+            options,
             files: Default::default(),
+            next_sweep: 0,
         }
     }
 
-    pub fn write(
-        &mut self,
-        recv_time: Timestamp,
-        symbol: Symbol,
-        data: bytes::Bytes,
-    ) -> Result<(), anyhow::Error> {
+    /// End every frame that is due by `now`.
+    ///
+    /// A frame is otherwise only checked when its own symbol writes, and the
+    /// rows of a symbol that has gone quiet would sit in its encoder — not on
+    /// disk — until it spoke again. Cheap to call often: it looks at the files
+    /// at most once per [`SWEEP_INTERVAL`].
+    pub fn end_due_frames(&mut self, now: Timestamp) -> Result<(), anyhow::Error> {
+        let now = now.as_nanosecond() as i64;
+        if now < self.next_sweep {
+            return Ok(());
+        }
+        self.next_sweep = now + SWEEP_INTERVAL.as_nanos() as i64;
+        for file in self.files.values_mut() {
+            file.end_frame_if_due(now)?;
+        }
+        Ok(())
+    }
+
+    /// A [`Stream::Lifecycle`] record is the last its symbol is expected to
+    /// write, so its file is closed and synced right after it rather than left
+    /// open until the next rotation or the process exits.
+    pub fn write(&mut self, record: WriteRecord) -> Result<(), anyhow::Error> {
         // Keyed by the encoded name, not the raw symbol: one `RotatingFile` per
         // file on disk is what keeps two encoders from ever sharing an fd.
-        let name = encode_symbol(&symbol);
+        let name = encode_symbol(&record.symbol);
         if let Some(rotating_file) = self.files.get_mut(name.as_ref()) {
-            rotating_file.write(recv_time, data)?;
+            rotating_file.write(&record)?;
         } else {
             let path = format!("{}/{}", self.path, name);
-            let mut rotating_file = RotatingFile::new(recv_time, path)?;
-            rotating_file.write(recv_time, data)?;
+            let mut rotating_file =
+                RotatingFile::new(record.recv_time, path, self.options.clone())?;
+            rotating_file.write(&record)?;
             self.files
                 .insert(Symbol::from(name.as_ref()), rotating_file);
         }
+        if record.stream == Stream::Lifecycle
+            && let Some(rotating_file) = self.files.get_mut(name.as_ref())
+        {
+            rotating_file.close_early();
+        }
         Ok(())
     }
 
@@ -248,54 +839,554 @@ impl Writer {
 mod tests {
     use super::*;
 
-    /// Every symbol shape the collector actually sees must survive untouched —
-    /// no allocation, and the exchange's own identifier readable on disk.
+    fn record(at: Timestamp, symbol: &str, data: bytes::Bytes) -> WriteRecord {
+        WriteRecord::received(at, Symbol::from(symbol), data, 0, Stream::Other)
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "collector-{name}-{}-{}",
+            std::process::id(),
+            Timestamp::now().as_nanosecond()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn written_names(dir: &std::path::Path) -> Vec<String> {
+        let mut written: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        written.sort();
+        written
+    }
+
     #[test]
-    fn real_exchange_symbols_are_not_rewritten() {
-        for symbol in [
-            "btcusdt",     // Binance spot / Bybit
-            "btcusd_perp", // Binance COIN-M
-            "btc-usdt",    // OKX style
-            "btc",         // Hyperliquid perp
-            "@1",          // Hyperliquid spot, 315 of its 316 pairs
-            "@315",
+    fn rotation_policies_parse_and_round_trip() {
+        for (text, rotation) in [
+            ("daily", Rotation::Daily),
+            ("hourly", Rotation::Minutes(60)),
+            ("15m", Rotation::Minutes(15)),
+            ("512MiB", Rotation::Bytes(512 << 20)),
+            ("2GiB", Rotation::Bytes(2 << 30)),
         ] {
-            assert!(
-                matches!(encode_symbol(symbol), Cow::Borrowed(_)),
-                "{symbol} should pass through unchanged"
-            );
-            assert_eq!(encode_symbol(symbol), symbol);
+            assert_eq!(text.parse::<Rotation>(), Ok(rotation), "{text}");
+            assert_eq!(rotation.to_string(), text);
+        }
+        // A window that does not divide the day would drift off the hour.
+        for text in ["7m", "0m", "0MiB", "10MB", "weekly", ""] {
+            assert!(text.parse::<Rotation>().is_err(), "{text}");
         }
     }
 
     #[test]
-    fn path_separators_in_symbols_are_escaped() {
-        assert_eq!(encode_symbol("purr/usdc"), "purr%2Fusdc");
-        assert_eq!(encode_symbol("../../etc/passwd"), "..%2F..%2Fetc%2Fpasswd");
+    fn names_sort_in_time_order() {
+        let at = |text: &str| text.parse::<Timestamp>().unwrap().as_nanosecond() as i64;
+        let names = [
+            Rotation::Daily.suffix(at("2024-01-01T12:34:56Z")),
+            Rotation::Minutes(60).suffix(at("2024-01-01T09:59:59Z")),
+            Rotation::Minutes(15).suffix(at("2024-01-01T10:14:00Z")),
+            Rotation::Bytes(1).suffix(at("2024-01-01T10:15:01Z")),
+            Rotation::Daily.suffix(at("2024-01-02T00:00:00Z")),
+        ];
+
+        assert_eq!(
+            names,
+            [
+                "20240101",
+                "20240101T0900",
+                "20240101T1000",
+                "20240101T101501",
+                "20240102"
+            ]
+        );
+        assert!(names.is_sorted());
     }
 
-    /// Two distinct symbols must never produce the same filename: they each get
-    /// their own zstd encoder, and sharing a file would interleave frames.
     #[test]
-    fn encoding_is_injective() {
-        let symbols = [
-            "purr/usdc",
-            "purr_usdc",
-            "purr%2Fusdc",
-            "purr%usdc",
-            "PURR/USDC",
-            "purr usdc",
-            "purr-usdc",
-            "",
-        ];
-        let mut encoded: Vec<String> = symbols
+    fn hourly_rotation_starts_a_file_per_window() {
+        let dir = temp_dir("hourly-test");
+        let mut writer = Writer::new(
+            dir.to_str().unwrap(),
+            FileOptions {
+                rotation: Rotation::Minutes(60),
+                ..Default::default()
+            },
+        );
+        for at in [
+            "2024-01-01T00:59:59Z",
+            "2024-01-01T01:00:00Z",
+            "2024-01-01T01:30:00Z",
+        ] {
+            writer
+                .write(record(
+                    at.parse().unwrap(),
+                    "btcusdt",
+                    bytes::Bytes::from_static(b"{}"),
+                ))
+                .unwrap();
+        }
+        writer.close().unwrap();
+
+        assert_eq!(
+            written_names(&dir),
+            ["btcusdt_20240101T0000.zst", "btcusdt_20240101T0100.zst"]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_lifecycle_record_closes_its_symbols_file() {
+        let dir = temp_dir("lifecycle-test");
+        let mut writer = Writer::new(dir.to_str().unwrap(), FileOptions::default());
+        let at = |text: &str| text.parse::<Timestamp>().unwrap();
+        writer
+            .write(record(
+                at("2024-01-01T10:00:00Z"),
+                "lunausdt",
+                bytes::Bytes::from_static(b"{}"),
+            ))
+            .unwrap();
+        writer
+            .write(WriteRecord::lifecycle(
+                at("2024-01-01T10:00:01Z"),
+                Symbol::from("lunausdt"),
+                bytes::Bytes::from_static(br#"{"type":"lifecycle","event":"delisted"}"#),
+            ))
+            .unwrap();
+
+        assert!(writer.files["lunausdt"].file.is_none());
+        // Nothing is lost if the exchange speaks again: the file reopens.
+        writer
+            .write(record(
+                at("2024-01-01T10:00:02Z"),
+                "lunausdt",
+                bytes::Bytes::from_static(b"[]"),
+            ))
+            .unwrap();
+        writer.close().unwrap();
+
+        let bytes = std::fs::read(dir.join("lunausdt_20240101.zst")).unwrap();
+        let text = String::from_utf8(zstd::decode_all(&bytes[..]).unwrap()).unwrap();
+        assert!(text.contains(r#"{"type":"lifecycle","event":"delisted"}"#));
+        assert!(text.ends_with("[]\n"), "{text}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn size_rotation_starts_a_file_once_the_limit_is_reached() {
+        let dir = temp_dir("size-test");
+        let mut writer = Writer::new(
+            dir.to_str().unwrap(),
+            FileOptions {
+                rotation: Rotation::Bytes(1),
+                ..Default::default()
+            },
+        );
+        // Incompressible rows large enough to make zstd flush a block each.
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut row = || {
+            let data: Vec<u8> = (0..256 * 1024)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    b'a' + (state % 26) as u8
+                })
+                .collect();
+            bytes::Bytes::from(data)
+        };
+        for at in [
+            "2024-01-01T10:00:00Z",
+            "2024-01-01T10:00:01Z",
+            "2024-01-02T00:00:02Z",
+        ] {
+            writer
+                .write(record(at.parse().unwrap(), "btcusdt", row()))
+                .unwrap();
+        }
+        writer.close().unwrap();
+
+        let written = written_names(&dir);
+        assert_eq!(
+            written,
+            [
+                "btcusdt_20240101T100000.zst",
+                "btcusdt_20240101T100001.zst",
+                "btcusdt_20240102T000002.zst"
+            ]
+        );
+        for name in &written {
+            let bytes = std::fs::read(dir.join(name)).unwrap();
+            let rows = zstd::decode_all(bytes.as_slice()).unwrap();
+            assert_eq!(rows.iter().filter(|&&b| b == b'\n').count(), 1, "{name}");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Files that fill within the second they were opened in are followed by
+    /// numbered siblings, rather than by the same full file again.
+    #[test]
+    fn size_rotation_within_one_second_numbers_the_files() {
+        let dir = temp_dir("size-same-second-test");
+        let mut writer = Writer::new(
+            dir.to_str().unwrap(),
+            FileOptions {
+                rotation: Rotation::Bytes(1),
+                ..Default::default()
+            },
+        );
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let mut row = || {
+            let data: Vec<u8> = (0..256 * 1024)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    b'a' + (state % 26) as u8
+                })
+                .collect();
+            bytes::Bytes::from(data)
+        };
+        for at in [
+            "2024-01-01T10:00:00.1Z",
+            "2024-01-01T10:00:00.2Z",
+            "2024-01-01T10:00:00.3Z",
+        ] {
+            writer
+                .write(record(at.parse().unwrap(), "btcusdt", row()))
+                .unwrap();
+        }
+        writer.close().unwrap();
+
+        let written = written_names(&dir);
+        assert_eq!(
+            written,
+            [
+                "btcusdt_20240101T100000.zst",
+                "btcusdt_20240101T100000_001.zst",
+                "btcusdt_20240101T100000_002.zst"
+            ]
+        );
+        // Readers take them in the order they were written.
+        let mut files: Vec<_> = written
             .iter()
-            .map(|symbol| encode_symbol(symbol).into_owned())
+            .rev()
+            .map(|name| collector::replay::RecordingFile::from_path(&dir.join(name)).unwrap())
             .collect();
-        let total = encoded.len();
-        encoded.sort();
-        encoded.dedup();
-        assert_eq!(encoded.len(), total, "collision: {encoded:?}");
+        files.sort();
+        let stamps: Vec<&str> = files.iter().map(|file| file.stamp.as_str()).collect();
+        assert_eq!(
+            stamps,
+            [
+                "20240101T100000",
+                "20240101T100000_001",
+                "20240101T100000_002"
+            ]
+        );
+        for name in &written {
+            let bytes = std::fs::read(dir.join(name)).unwrap();
+            let rows = zstd::decode_all(bytes.as_slice()).unwrap();
+            assert_eq!(rows.iter().filter(|&&b| b == b'\n').count(), 1, "{name}");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn rows(bytes: &[u8]) -> Vec<String> {
+        String::from_utf8(zstd::decode_all(bytes).unwrap())
+            .unwrap()
+            .lines()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    /// `mem::forget` stands in for `kill -9`: no `Drop`, no `finish`.
+    #[test]
+    fn a_hard_kill_loses_only_the_open_frame() {
+        let dir = temp_dir("frame-test");
+        let path = dir.join("btcusdt").to_str().unwrap().to_owned();
+        let at = |secs: i64| Timestamp::from_second(1_704_067_200 + secs).unwrap();
+
+        let mut file = RotatingFile::new(at(0), path.clone(), FileOptions::default()).unwrap();
+        for secs in [0, 1, 10, 11] {
+            file.write(&record(
+                at(secs),
+                "btcusdt",
+                bytes::Bytes::from_static(b"{}"),
+            ))
+            .unwrap();
+        }
+        std::mem::forget(file);
+
+        let written = std::fs::read(format!("{path}_20240101.zst")).unwrap();
+        assert_eq!(rows(&written).len(), 3, "the row at 10s ends the frame");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_quiet_symbol_is_flushed_by_the_sweep() {
+        let dir = temp_dir("sweep-test");
+        let at = |secs: i64| Timestamp::from_second(1_704_067_200 + secs).unwrap();
+
+        let mut writer = Writer::new(dir.to_str().unwrap(), FileOptions::default());
+        writer
+            .write(record(at(0), "ethusdt", bytes::Bytes::from_static(b"{}")))
+            .unwrap();
+        writer.end_due_frames(at(5)).unwrap();
+        // Swept too recently to look again, however late it is.
+        writer.end_due_frames(at(5)).unwrap();
+        let mut open = File::open(dir.join("ethusdt_20240101.zst")).unwrap();
+        assert_eq!(complete_frames_len(&mut open).unwrap(), 0);
+
+        writer.end_due_frames(at(10)).unwrap();
+        std::mem::forget(writer);
+
+        let written = std::fs::read(dir.join("ethusdt_20240101.zst")).unwrap();
+        assert_eq!(rows(&written).len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_torn_tail_is_cut_back_before_appending() {
+        let dir = temp_dir("torn-test");
+        let path = dir.join("btcusdt").to_str().unwrap().to_owned();
+        let name = format!("{path}_20240101.zst");
+        let mut torn = zstd::encode_all(&b"1 {\"a\":1}\n"[..], 1).unwrap();
+        let next = zstd::encode_all(&b"2 {\"a\":2}\n"[..], 1).unwrap();
+        torn.extend_from_slice(&next[..next.len() / 2]);
+        std::fs::write(&name, &torn).unwrap();
+
+        let at = Timestamp::from_second(1_704_067_200).unwrap();
+        let mut file = RotatingFile::new(at, path, FileOptions::default()).unwrap();
+        file.write(&record(
+            at,
+            "btcusdt",
+            bytes::Bytes::from_static(b"{\"a\":3}"),
+        ))
+        .unwrap();
+        file.finalize().unwrap();
+
+        let written = rows(&std::fs::read(&name).unwrap());
+        assert_eq!(written, ["1 {\"a\":1}", "1704067200000000000 {\"a\":3}"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Every cut through a mix of multi-block, checksummed and skippable frames
+    /// keeps exactly the frames that end before it.
+    #[test]
+    fn complete_frames_are_found_at_every_cut() {
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let noise: Vec<u8> = (0..300 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let mut checksummed = ZstdEncoder::new(Vec::new(), 3).unwrap();
+        checksummed.include_checksum(true).unwrap();
+        checksummed.write_all(&noise).unwrap();
+        checksummed.write_all(&[b'x'; 4096]).unwrap();
+        let mut skippable = 0x184D_2A53u32.to_le_bytes().to_vec();
+        skippable.extend_from_slice(&5u32.to_le_bytes());
+        skippable.extend_from_slice(b"hello");
+        let frames = [
+            checksummed.finish().unwrap(),
+            skippable,
+            zstd::encode_all(&b"small"[..], 1).unwrap(),
+        ];
+        let mut bytes = Vec::new();
+        let mut boundaries = vec![0u64];
+        for frame in &frames {
+            bytes.extend_from_slice(frame);
+            boundaries.push(bytes.len() as u64);
+        }
+
+        let dir = temp_dir("cut-test");
+        let name = dir.join("cut.zst");
+        let cuts = (0..bytes.len())
+            .step_by(997)
+            .chain(
+                boundaries
+                    .iter()
+                    .flat_map(|&b| [b as usize, b as usize + 1]),
+            )
+            .chain([1, 4, 5, 7, bytes.len() - 1])
+            .filter(|&cut| cut <= bytes.len());
+        for cut in cuts {
+            std::fs::write(&name, &bytes[..cut]).unwrap();
+            let mut file = File::open(&name).unwrap();
+            let expected = *boundaries.iter().rfind(|&&b| b <= cut as u64).unwrap();
+            assert_eq!(
+                complete_frames_len(&mut file).unwrap(),
+                expected,
+                "cut at {cut}"
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A small dictionary trained on depth-like rows.
+    fn trained_dictionary() -> Vec<u8> {
+        let samples: Vec<String> = (0..2000)
+            .map(|i| {
+                format!(
+                    r#"{{"stream":"btcusdt@depth@100ms","data":{{"e":"depthUpdate","E":{},"U":{i},"u":{},"b":[["{}.10","0.{}"]],"a":[]}}}}"#,
+                    1_700_000_000_000u64 + i * 100,
+                    i + 3,
+                    60_000 + i % 97,
+                    i % 13
+                )
+            })
+            .collect();
+        zstd::dict::from_samples(&samples, 4096).unwrap()
+    }
+
+    #[test]
+    fn every_compression_setting_round_trips() {
+        let dir = temp_dir("compression-test");
+        let dictionary = trained_dictionary();
+        let options = FileOptions {
+            compression: Compression {
+                level: 19,
+                long_window: Some(27),
+                workers: 2,
+                dictionary: Some(Dictionary::new(&dictionary, 19).unwrap()),
+            },
+            ..Default::default()
+        };
+        let at = |secs: i64| Timestamp::from_second(1_704_067_200 + secs).unwrap();
+
+        let mut writer = Writer::new(dir.to_str().unwrap(), options);
+        // The frame ends at 10s, so the file holds two frames to decode.
+        for secs in [0, 10, 11] {
+            writer
+                .write(record(
+                    at(secs),
+                    "btcusdt",
+                    bytes::Bytes::from(format!(r#"{{"u":{secs}}}"#)),
+                ))
+                .unwrap();
+        }
+        writer.close().unwrap();
+
+        let file = File::open(dir.join("btcusdt_20240101.zst")).unwrap();
+        let mut decoder =
+            zstd::stream::read::Decoder::with_dictionary(io::BufReader::new(file), &dictionary)
+                .unwrap();
+        decoder.window_log_max(31).unwrap();
+        let mut decoded = String::new();
+        decoder.read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded.lines().count(), 3, "{decoded}");
+        assert!(decoded.ends_with(
+            r#"1704067211000000000 {"u":11}
+"#
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Binary records keep the connection and stream, and a restart with the
+    /// other format appends to the same file without breaking either.
+    #[test]
+    fn binary_records_read_back_beside_text() {
+        let dir = temp_dir("binary-test");
+        let at = |secs: i64| Timestamp::from_second(1_704_067_200 + secs).unwrap();
+        let binary = FileOptions {
+            format: Format::Binary,
+            ..Default::default()
+        };
+
+        let mut writer = Writer::new(dir.to_str().unwrap(), binary);
+        let symbol = Symbol::from("btcusdt");
+        let trade = bytes::Bytes::from_static(b"{\"t\":1}");
+        let snapshot = bytes::Bytes::from_static(b"{\"lastUpdateId\":2}");
+        writer
+            .write(WriteRecord::received(
+                at(0),
+                Symbol::clone(&symbol),
+                trade.clone(),
+                1,
+                Stream::Trade,
+            ))
+            .unwrap();
+        writer
+            .write(WriteRecord::fetched(
+                at(1),
+                Symbol::clone(&symbol),
+                snapshot.clone(),
+                Stream::DepthSnapshot,
+            ))
+            .unwrap();
+        writer.close().unwrap();
+        let mut writer = Writer::new(dir.to_str().unwrap(), FileOptions::default());
+        writer
+            .write(record(at(2), "btcusdt", trade.clone()))
+            .unwrap();
+        writer.close().unwrap();
+
+        let file = File::open(dir.join("btcusdt_20240101.zst")).unwrap();
+        let decoder = zstd::stream::read::Decoder::new(file).unwrap();
+        let mut reader = record::Reader::new(io::BufReader::new(decoder));
+        let mut payload = Vec::new();
+        let mut records = Vec::new();
+        while let Some(header) = reader.read(&mut payload).unwrap() {
+            records.push((header, payload.clone()));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let header = |secs: i64, connection, stream| Header {
+            recv_ns: at(secs).as_nanosecond() as i64,
+            connection,
+            stream,
+        };
+        assert_eq!(
+            records,
+            [
+                (header(0, Some(1), Stream::Trade), trade.to_vec()),
+                (header(1, None, Stream::DepthSnapshot), snapshot.to_vec()),
+                (header(2, None, Stream::Other), trade.to_vec()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn a_full_shard_holds_up_only_its_own_symbols() {
+        let (a_tx, mut a_rx) = tokio::sync::mpsc::channel(1);
+        let (b_tx, mut b_rx) = tokio::sync::mpsc::channel(1);
+        let sender = RecordSender::new(vec![a_tx, b_tx]);
+        let symbols = ["btcusdt", "ethusdt", "solusdt", "xrpusdt", "dogeusdt"];
+        let stuck = symbols
+            .iter()
+            .find(|symbol| shard_of(symbol, 2) == 0)
+            .unwrap();
+        let free = symbols
+            .iter()
+            .find(|symbol| shard_of(symbol, 2) == 1)
+            .unwrap();
+        let record = |symbol: &str, n: u8| {
+            record(Timestamp::UNIX_EPOCH, symbol, bytes::Bytes::from(vec![n]))
+        };
+
+        sender.send(record(stuck, 0)).await.unwrap();
+        let blocked = sender.send(record(stuck, 1));
+        tokio::pin!(blocked);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(20), &mut blocked)
+                .await
+                .is_err()
+        );
+        tokio::time::timeout(Duration::from_millis(20), sender.send(record(free, 2)))
+            .await
+            .expect("the other shard must not wait")
+            .unwrap();
+
+        // Draining the stuck shard lets it through, in order.
+        assert_eq!(a_rx.recv().await.unwrap().data[0], 0);
+        blocked.await.unwrap();
+        assert_eq!(a_rx.recv().await.unwrap().data[0], 1);
+        assert_eq!(&*b_rx.recv().await.unwrap().symbol, *free);
     }
 
     #[test]
@@ -307,14 +1398,14 @@ mod tests {
         ));
         std::fs::create_dir_all(&dir).unwrap();
 
-        let mut writer = Writer::new(dir.to_str().unwrap());
+        let mut writer = Writer::new(dir.to_str().unwrap(), FileOptions::default());
         for symbol in ["purr/usdc", "purr_usdc"] {
             writer
-                .write(
+                .write(record(
                     Timestamp::now(),
-                    Symbol::from(symbol),
+                    symbol,
                     bytes::Bytes::from_static(b"{}"),
-                )
+                ))
                 .unwrap();
         }
         writer.close().unwrap();
@@ -346,13 +1437,13 @@ mod tests {
         ));
         std::fs::create_dir_all(&dir).unwrap();
 
-        let mut writer = Writer::new(dir.to_str().unwrap());
+        let mut writer = Writer::new(dir.to_str().unwrap(), FileOptions::default());
         writer
-            .write(
+            .write(record(
                 Timestamp::now(),
-                Symbol::from("purr/usdc"),
+                "purr/usdc",
                 bytes::Bytes::from_static(b"{}"),
-            )
+            ))
             .unwrap();
         writer.close().unwrap();
 
@@ -0,0 +1,288 @@
+//! Live checks on what the Hyperliquid stream records.
+//!
+//! Hyperliquid gives its streams no sequence numbers. Every `l2Book` frame is
+//! the whole book stamped with its `time`, so the book itself cannot have a
+//! hole, but the recording can still go wrong: a lagging connection's older
+//! book lands after a newer one, or the same book keeps arriving while the
+//! market moves on. Trades carry a `tid`, but it is a hash of the two order
+//! ids rather than a counter, so a missing trade leaves no mark in the ids.
+//! What the ids and times do show is a trade delivered twice and a batch that
+//! goes back in time, which is how a replayed or reordered stretch of trades
+//! looks.
+//!
+//! Each of these is counted per kind and restated on a timer, like
+//! [`Rejections`](super::Rejections): a stream that is healthy apart from an
+//! occasional stale book should not log on every frame, and one that stays
+//! broken should not fall silent after the first message.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use jiff::Timestamp;
+use tracing::{debug, warn};
+
+use crate::{
+    metrics::{self, Counter},
+    routing::TradeId,
+};
+
+/// How often to restate the anomalies seen so far.
+const ANOMALY_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Books in a row that did not move forward before the book is re-anchored.
+/// One is a lagging copy and harmless; a run of them is a stuck stream.
+const STALE_BOOKS_BEFORE_ANCHOR: u32 = 3;
+
+/// The least time between two REST re-anchors of one coin. `l2Book` costs
+/// weight 2 of the 1200 per minute the info endpoint allows an IP.
+const ANCHOR_MIN_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Trade ids remembered per coin to recognise a repeat. A repeat arrives
+/// within seconds, and the busiest coin trades far fewer times than this in
+/// that window.
+const RECENT_TIDS: usize = 4_096;
+
+/// Anomaly totals, shared with [`report_anomalies`].
+#[derive(Default)]
+pub struct Anomalies {
+    book_regressions: AtomicU64,
+    stale_books: AtomicU64,
+    repeated_trades: AtomicU64,
+    trade_regressions: AtomicU64,
+}
+
+pub async fn report_anomalies(anomalies: Arc<Anomalies>) {
+    let mut ticker = tokio::time::interval(ANOMALY_REPORT_INTERVAL);
+    // `interval` yields immediately on the first tick; skip it.
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let book_regressions = anomalies.book_regressions.load(Ordering::Relaxed);
+        let stale_books = anomalies.stale_books.load(Ordering::Relaxed);
+        let repeated_trades = anomalies.repeated_trades.load(Ordering::Relaxed);
+        let trade_regressions = anomalies.trade_regressions.load(Ordering::Relaxed);
+        if book_regressions + stale_books + repeated_trades + trade_regressions > 0 {
+            warn!(
+                book_regressions,
+                stale_books,
+                repeated_trades,
+                trade_regressions,
+                "Hyperliquid stream anomalies since startup"
+            );
+        }
+    }
+}
+
+/// What the collection loop should do about a book.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    InOrder,
+    /// Fetch the book from the info endpoint and record it.
+    Reanchor,
+}
+
+#[derive(Default)]
+struct Coin {
+    book_time: Option<i64>,
+    /// Books in a row whose time did not move forward.
+    stale_run: u32,
+    anchored_at: Option<Timestamp>,
+    trade_time: Option<i64>,
+    tids: HashSet<u64>,
+    tid_order: VecDeque<u64>,
+}
+
+/// Per-coin book and trade tracking.
+pub struct Continuity {
+    coins: HashMap<String, Coin>,
+    anomalies: Arc<Anomalies>,
+    counters: [Counter; 4],
+}
+
+impl Continuity {
+    pub fn new(anomalies: Arc<Anomalies>) -> Self {
+        let counter = |kind| {
+            metrics::counter(
+                "collector_stream_anomalies_total",
+                "Out-of-order, stale or repeated messages on streams without sequence numbers.",
+                &[("endpoint", "hyperliquid"), ("kind", kind)],
+            )
+        };
+        Self {
+            coins: HashMap::new(),
+            anomalies,
+            counters: [
+                counter("book_regression"),
+                counter("stale_book"),
+                counter("repeated_trade"),
+                counter("trade_regression"),
+            ],
+        }
+    }
+
+    fn coin(&mut self, coin: &str) -> &mut Coin {
+        if !self.coins.contains_key(coin) {
+            self.coins.insert(coin.to_owned(), Coin::default());
+        }
+        self.coins.get_mut(coin).expect("inserted above")
+    }
+
+    /// Check an `l2Book` frame's time against the coin's last book.
+    ///
+    /// `may_anchor` says whether a REST re-anchor is wanted at all; without
+    /// it every verdict is [`Verdict::InOrder`] and the anomaly is only counted.
+    pub fn book(
+        &mut self,
+        connection: usize,
+        coin: &str,
+        time: i64,
+        recv_time: Timestamp,
+        may_anchor: bool,
+    ) -> Verdict {
+        let state = self.coin(coin);
+        let previous = state.book_time;
+        if previous.is_none_or(|previous| time > previous) {
+            state.book_time = Some(time);
+            state.stale_run = 0;
+            return Verdict::InOrder;
+        }
+        state.stale_run += 1;
+        let stale_run = state.stale_run;
+        let anchor_due = may_anchor
+            && stale_run >= STALE_BOOKS_BEFORE_ANCHOR
+            && state.anchored_at.is_none_or(|at| {
+                recv_time.duration_since(at).unsigned_abs() >= ANCHOR_MIN_INTERVAL
+            });
+        if anchor_due {
+            state.anchored_at = Some(recv_time);
+        }
+
+        let (total, counter) = if previous.is_some_and(|previous| time < previous) {
+            (&self.anomalies.book_regressions, &self.counters[0])
+        } else {
+            (&self.anomalies.stale_books, &self.counters[1])
+        };
+        total.fetch_add(1, Ordering::Relaxed);
+        counter.inc();
+        debug!(
+            connection,
+            coin,
+            time,
+            ?previous,
+            stale_run,
+            "l2Book did not move forward"
+        );
+        if anchor_due {
+            warn!(
+                connection,
+                coin,
+                stale_run,
+                "l2Book is not moving forward; re-anchoring from the info endpoint"
+            );
+            Verdict::Reanchor
+        } else {
+            Verdict::InOrder
+        }
+    }
+
+    /// Check a `trades` batch against the coin's recent trades.
+    pub fn trades(&mut self, connection: usize, coin: &str, trades: &[TradeId]) {
+        let state = self.coin(coin);
+        let mut repeated = 0_u64;
+        let mut regressed = false;
+        for trade in trades {
+            if !state.tids.insert(trade.tid) {
+                repeated += 1;
+                continue;
+            }
+            state.tid_order.push_back(trade.tid);
+            if state.tid_order.len() > RECENT_TIDS
+                && let Some(oldest) = state.tid_order.pop_front()
+            {
+                state.tids.remove(&oldest);
+            }
+            if state.trade_time.is_some_and(|last| trade.time < last) {
+                regressed = true;
+            }
+            state.trade_time = state.trade_time.max(Some(trade.time));
+        }
+
+        if repeated > 0 {
+            self.anomalies
+                .repeated_trades
+                .fetch_add(repeated, Ordering::Relaxed);
+            self.counters[2].add(repeated);
+            debug!(connection, coin, repeated, "trades delivered again");
+        }
+        if regressed {
+            self.anomalies
+                .trade_regressions
+                .fetch_add(1, Ordering::Relaxed);
+            self.counters[3].inc();
+            debug!(connection, coin, "trade batch went back in time");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: i64) -> Timestamp {
+        Timestamp::from_second(seconds).unwrap()
+    }
+
+    #[test]
+    fn a_stuck_book_is_reanchored_at_most_once_per_interval() {
+        let anomalies = Arc::new(Anomalies::default());
+        let mut continuity = Continuity::new(Arc::clone(&anomalies));
+        let mut book = |time, seconds| continuity.book(0, "BTC", time, at(seconds), true);
+
+        assert_eq!(book(10, 0), Verdict::InOrder);
+        assert_eq!(book(9, 0), Verdict::InOrder);
+        assert_eq!(book(10, 1), Verdict::InOrder);
+        assert_eq!(book(10, 2), Verdict::Reanchor);
+        assert_eq!(book(10, 3), Verdict::InOrder, "rate limited");
+        assert_eq!(book(11, 4), Verdict::InOrder);
+        for seconds in 5..7 {
+            assert_eq!(book(11, seconds), Verdict::InOrder);
+        }
+        assert_eq!(book(11, 40), Verdict::Reanchor);
+
+        assert_eq!(anomalies.book_regressions.load(Ordering::Relaxed), 1);
+        assert_eq!(anomalies.stale_books.load(Ordering::Relaxed), 6);
+    }
+
+    #[test]
+    fn anchoring_is_only_asked_for_when_enabled() {
+        let mut continuity = Continuity::new(Arc::default());
+        for seconds in 0..10 {
+            assert_eq!(
+                continuity.book(0, "ETH", 1, at(seconds), false),
+                Verdict::InOrder
+            );
+        }
+    }
+
+    #[test]
+    fn repeated_and_backward_trades_are_counted() {
+        let anomalies = Arc::new(Anomalies::default());
+        let mut continuity = Continuity::new(Arc::clone(&anomalies));
+        let trade = |tid, time| TradeId { tid, time };
+
+        continuity.trades(0, "BTC", &[trade(900, 5), trade(17, 6)]);
+        // Ids are hashes: a lower one later is not a regression.
+        continuity.trades(1, "BTC", &[trade(3, 7)]);
+        continuity.trades(1, "BTC", &[trade(17, 6), trade(44, 4)]);
+        continuity.trades(0, "ETH", &[trade(17, 1)]);
+
+        assert_eq!(anomalies.repeated_trades.load(Ordering::Relaxed), 1);
+        assert_eq!(anomalies.trade_regressions.load(Ordering::Relaxed), 1);
+    }
+}
@@ -1,16 +1,31 @@
 use std::{
+    collections::VecDeque,
     io,
     io::ErrorKind,
+    sync::Arc,
     time::{Duration, Instant},
 };
 
 use anyhow::Error;
 use fastwebsockets::OpCode;
 use jiff::Timestamp;
-use tokio::{select, sync::mpsc::Sender, time::timeout};
+use tokio::{
+    select,
+    sync::{mpsc::Sender, watch},
+    time::timeout,
+};
 use tracing::{debug, error, info, warn};
 
-use crate::ws::{self, Delivery, FrameSender, Overflow};
+use crate::{
+    metrics,
+    reload::SymbolDiff,
+    spool::Spool,
+    ws::{self, Delivery, FrameSender, Overflow, Priority},
+};
+
+/// A frame with the connection it arrived on, so the recording can say which
+/// redundant connection delivered the copy that was kept.
+pub type Frame = (usize, Timestamp, bytes::Bytes);
 
 const PING_INTERVAL: Duration = Duration::from_secs(30);
 /// Every ping is answered with a `{"channel":"pong"}` frame, so the socket is
@@ -35,18 +50,42 @@ fn subscribe_pace(connections: usize) -> Duration {
     SUBSCRIBE_PACE * connections.max(1) as u32
 }
 
+/// The `subscribe` or `unsubscribe` request for every subscription type of
+/// every coin in `coins`.
+fn requests(method: &str, subscription_types: &[String], coins: &[String]) -> Vec<String> {
+    coins
+        .iter()
+        .flat_map(|coin| {
+            subscription_types.iter().map(move |sub_type| {
+                format!(
+                    r#"{{"method":"{method}","subscription":{{"type":"{sub_type}","coin":"{coin}"}}}}"#
+                )
+            })
+        })
+        .collect()
+}
+
 /// The subscriptions and the ping timer both live off the read path.
 ///
 /// Sending 1000 paced subscriptions inline before the first read would leave
 /// the socket unread for ~35 s, skewing every receive timestamp in that window
 /// and letting the kernel buffer back up. Running them here means data is being
 /// read from the very first frame.
-async fn control_loop(sender: FrameSender, subscriptions: Vec<String>, connections: usize) {
+///
+/// A reload's `unsubscribe`s and `subscribe`s join the same paced queue: they
+/// spend the same per-IP message budget.
+async fn control_loop(
+    sender: FrameSender,
+    subscription_types: &[String],
+    symbols: &mut watch::Receiver<Vec<String>>,
+    connections: usize,
+) {
     let mut ping_interval = tokio::time::interval(PING_INTERVAL);
     ping_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
     let mut pacer = tokio::time::interval(subscribe_pace(connections));
     pacer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
-    let mut to_send = subscriptions.into_iter();
+    let mut subscribed = symbols.borrow_and_update().clone();
+    let mut to_send = VecDeque::from(requests("subscribe", subscription_types, &subscribed));
 
     loop {
         select! {
@@ -55,8 +94,15 @@ async fn control_loop(sender: FrameSender, subscriptions: Vec<String>, connectio
                     return;
                 }
             }
+            Ok(()) = symbols.changed() => {
+                let next = symbols.borrow_and_update().clone();
+                let diff = SymbolDiff::between(&subscribed, &next);
+                subscribed = next;
+                to_send.extend(requests("unsubscribe", subscription_types, &diff.removed));
+                to_send.extend(requests("subscribe", subscription_types, &diff.added));
+            }
             _ = pacer.tick() => {
-                let Some(text) = to_send.next() else {
+                let Some(text) = to_send.pop_front() else {
                     continue;
                 };
                 debug!(%text, "sending subscription");
@@ -68,18 +114,24 @@ async fn control_loop(sender: FrameSender, subscriptions: Vec<String>, connectio
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn connect(
+    venue: &str,
     url: &str,
-    subscriptions: Vec<String>,
+    subscription_types: &[String],
+    symbols: &mut watch::Receiver<Vec<String>>,
     connection: usize,
     connections: usize,
-    ws_tx: Sender<(Timestamp, bytes::Bytes)>,
+    ws_tx: Sender<Frame>,
+    spool: Option<Arc<Spool>>,
 ) -> Result<(), anyhow::Error> {
-    let mut conn = ws::connect(url).await?;
+    let mut conn = ws::connect(url)
+        .await
+        .inspect_err(|_| metrics::handshake_failed(venue, "hyperliquid", 0, connection))?;
     let sender = conn.sender();
-    let mut overflow = Overflow::new("hyperliquid");
+    let mut overflow = Overflow::new("hyperliquid").with_spool(spool);
 
-    let control = control_loop(sender.clone(), subscriptions, connections);
+    let control = control_loop(sender.clone(), subscription_types, symbols, connections);
     tokio::pin!(control);
 
     loop {
@@ -104,14 +156,21 @@ pub async fn connect(
                 let delivery = ws::deliver(
                     &ws_tx,
                     &mut overflow,
-                    (recv_time, message.payload),
+                    (connection, recv_time, message.payload),
                     // Rejections arrive on the `error` channel. Shedding one
                     // would hide a permanently incomplete feed.
-                    |(_, payload)| !ws::payload_contains(payload, br#""error""#),
+                    |(_, _, payload)| {
+                        if ws::payload_contains(payload, br#""error""#) {
+                            Priority::Control
+                        } else {
+                            let channel = ws::string_field(payload, "channel");
+                            Priority::of(super::stream_of(channel.unwrap_or_default()))
+                        }
+                    },
                 )
                 .await;
                 match delivery {
-                    Delivery::Sent | Delivery::Dropped => {}
+                    Delivery::Sent | Delivery::Spooled | Delivery::Dropped => {}
                     // Receiver dropped: the collector is shutting down.
                     Delivery::Closed => return Ok(()),
                     Delivery::Undeliverable => {
@@ -136,26 +195,19 @@ pub async fn connect(
     }
 }
 
+/// `symbols` is read at every connect and watched while connected; see
+/// [`control_loop`].
 pub async fn keep_connection(
+    venue: Arc<str>,
     subscription_types: Vec<String>,
-    symbol_list: Vec<String>,
+    mut symbols: watch::Receiver<Vec<String>>,
     connection: usize,
     connections: usize,
-    ws_tx: Sender<(Timestamp, bytes::Bytes)>,
+    ws_tx: Sender<Frame>,
+    spool: Option<Arc<Spool>>,
 ) {
-    let subscriptions: Vec<String> = symbol_list
-        .iter()
-        .flat_map(|symbol| {
-            subscription_types.iter().map(move |sub_type| {
-                format!(
-                    r#"{{"method":"subscribe","subscription":{{"type":"{sub_type}","coin":"{symbol}"}}}}"#
-                )
-            })
-        })
-        .collect();
-
     info!(
-        subscriptions = subscriptions.len(),
+        subscriptions = subscription_types.len() * symbols.borrow().len(),
         connection,
         subscribe_pace = ?subscribe_pace(connections),
         "connecting to the Hyperliquid websocket"
@@ -165,15 +217,19 @@ pub async fn keep_connection(
     loop {
         let connect_time = Instant::now();
         if let Err(error) = connect(
+            &venue,
             "wss://api.hyperliquid.xyz/ws",
-            subscriptions.clone(),
+            &subscription_types,
+            &mut symbols,
             connection,
             connections,
             ws_tx.clone(),
+            spool.clone(),
         )
         .await
         {
             let lifetime = connect_time.elapsed();
+            metrics::session_ended(&venue, "hyperliquid", 0, connection, lifetime);
             error!(connection, ?error, ?lifetime, "websocket error");
             error_count += 1;
             if lifetime > Duration::from_secs(30) {
@@ -196,3 +252,55 @@ pub async fn keep_connection(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The next request the venue receives, pings aside.
+    async fn next_request(
+        server: &mut fastwebsockets::WebSocket<tokio::io::DuplexStream>,
+    ) -> serde_json::Value {
+        loop {
+            let frame = server.read_frame().await.unwrap();
+            let request: serde_json::Value = serde_json::from_slice(&frame.payload).unwrap();
+            if request["method"] != "ping" {
+                return request;
+            }
+        }
+    }
+
+    /// A reload's unsubscribes for the removed coins go out on the open socket,
+    /// ahead of the subscribes for the added ones.
+    #[tokio::test]
+    async fn a_reload_queues_unsubscribes_before_subscribes() {
+        let (conn, mut server) = ws::duplex_pair();
+        let (symbols_tx, mut symbols) = watch::channel(vec!["BTC".to_owned()]);
+        let sender = conn.sender();
+        let control = tokio::spawn(async move {
+            let types = ["trades".to_owned(), "l2Book".to_owned()];
+            control_loop(sender, &types, &mut symbols, 1).await;
+        });
+        let request = |method: &str, kind: &str, coin: &str| serde_json::json!({ "method": method, "subscription": { "type": kind, "coin": coin } });
+
+        assert_eq!(
+            next_request(&mut server).await,
+            request("subscribe", "trades", "BTC")
+        );
+        assert_eq!(
+            next_request(&mut server).await,
+            request("subscribe", "l2Book", "BTC")
+        );
+
+        symbols_tx.send_replace(vec!["ETH".to_owned()]);
+        for expected in [
+            request("unsubscribe", "trades", "BTC"),
+            request("unsubscribe", "l2Book", "BTC"),
+            request("subscribe", "trades", "ETH"),
+            request("subscribe", "l2Book", "ETH"),
+        ] {
+            assert_eq!(next_request(&mut server).await, expected);
+        }
+        control.abort();
+    }
+}
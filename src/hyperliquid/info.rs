@@ -0,0 +1,382 @@
+//! Periodic recording from Hyperliquid's info endpoint.
+//!
+//! The websocket carries books and trades but not what funding research needs:
+//! funding, open interest, and mark and oracle prices only come from
+//! `metaAndAssetCtxs`, which answers for every perpetual at once. Each round
+//! fetches that once and records each configured coin's share of it, then
+//! records an `l2Book` per coin, which a reader can anchor a replay on the same
+//! way as a Binance depth snapshot. Both go into the coin's own file, so a
+//! day's file holds everything about that coin.
+//!
+//! A round's books are capped at half the weight budget, leaving the rest to
+//! the re-anchors. A universe larger than that is covered over several rounds,
+//! each taking up where the last left off.
+
+use std::{collections::HashMap, time::Duration};
+
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use tokio::sync::watch;
+use tracing::{error, warn};
+
+use collector::record::Stream;
+
+use super::limiter::{INFO_WEIGHT_PER_MINUTE, InfoLimiter};
+use crate::{
+    file::{RecordSender, WriteRecord},
+    metrics,
+    symbol::Symbol,
+};
+
+pub const INFO_URL: &str = "https://api.hyperliquid.xyz/info";
+
+/// Request weights, from Hyperliquid's rate limit documentation.
+const L2_BOOK_WEIGHT: u32 = 2;
+const ASSET_CONTEXTS_WEIGHT: u32 = 20;
+
+/// Spacing between the `l2Book` requests of one round, so a long coin list
+/// does not arrive at the endpoint as a burst.
+const INFO_PACE: Duration = Duration::from_millis(100);
+
+/// Let the websocket connections come up before the first round.
+const INFO_START_DELAY: Duration = Duration::from_secs(5);
+
+/// The default time between rounds. Funding accrues hourly, but open interest
+/// and the mark price move continuously, and a round of a hundred coins costs
+/// a fifth of the minute's weight.
+pub const INFO_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The most books one round may fetch: what half the weight budget buys over
+/// the interval, after the asset contexts. The rolling window is a minute, and
+/// a round's requests all fall in one, so longer intervals buy no more.
+fn books_per_round(interval: Duration) -> usize {
+    let window = interval.min(Duration::from_secs(60));
+    let weight = u128::from(INFO_WEIGHT_PER_MINUTE / 2) * window.as_millis() / 60_000;
+    let weight = u32::try_from(weight).unwrap_or(u32::MAX);
+    (weight.saturating_sub(ASSET_CONTEXTS_WEIGHT) / L2_BOOK_WEIGHT).max(1) as usize
+}
+
+/// The positions of the `count` coins whose books a round fetches: at most
+/// `cap` of them, starting at `cursor` and wrapping around.
+fn book_round(count: usize, cursor: usize, cap: usize) -> impl Iterator<Item = usize> {
+    let start = if count == 0 { 0 } else { cursor % count };
+    (0..count.min(cap)).map(move |offset| (start + offset) % count)
+}
+
+/// The info endpoint client, sharing one weight budget between the periodic
+/// rounds and the re-anchors asked for by the collection loop.
+#[derive(Clone)]
+pub struct InfoClient {
+    client: reqwest::Client,
+    limiter: InfoLimiter,
+}
+
+impl InfoClient {
+    pub fn new(limiter: InfoLimiter) -> Result<Self, anyhow::Error> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(10))
+            .timeout(Duration::from_secs(30))
+            .build()?;
+        Ok(Self { client, limiter })
+    }
+
+    /// The coin's book, as the response body; `None` when the budget is spent.
+    ///
+    /// The body is the websocket `l2Book` payload without the channel envelope:
+    /// `{"coin":…,"time":…,"levels":[bids, asks]}`.
+    pub async fn l2_book(&self, coin: &str) -> Option<Result<bytes::Bytes, anyhow::Error>> {
+        self.post(
+            L2_BOOK_WEIGHT,
+            serde_json::json!({ "type": "l2Book", "coin": coin }),
+        )
+        .await
+    }
+
+    /// Every perpetual's metadata and context, as the response body; `None`
+    /// when the budget is spent.
+    pub async fn asset_contexts(&self) -> Option<Result<bytes::Bytes, anyhow::Error>> {
+        self.post(
+            ASSET_CONTEXTS_WEIGHT,
+            serde_json::json!({ "type": "metaAndAssetCtxs" }),
+        )
+        .await
+    }
+
+    async fn post(
+        &self,
+        weight: u32,
+        request: serde_json::Value,
+    ) -> Option<Result<bytes::Bytes, anyhow::Error>> {
+        self.limiter
+            .execute(weight, async {
+                let response = self.client.post(INFO_URL).json(&request).send().await?;
+                let status = response.status();
+                let body = response.bytes().await?;
+                if !status.is_success() {
+                    if status.as_u16() == 429 {
+                        self.limiter.note_rate_limited(Timestamp::now());
+                    }
+                    let preview = &body[..body.len().min(1024)];
+                    anyhow::bail!(
+                        "Hyperliquid info returned {status}: {}",
+                        String::from_utf8_lossy(preview)
+                    );
+                }
+                Ok(body)
+            })
+            .await
+    }
+}
+
+/// The `metaAndAssetCtxs` response: the perpetual universe and, in the same
+/// order, each asset's context.
+#[derive(Deserialize)]
+struct MetaAndAssetCtxs<'a>(
+    #[serde(borrow)] Meta<'a>,
+    #[serde(borrow)] Vec<&'a RawValue>,
+);
+
+#[derive(Deserialize)]
+struct Meta<'a> {
+    #[serde(borrow)]
+    universe: Vec<&'a RawValue>,
+}
+
+#[derive(Deserialize)]
+struct AssetName<'a> {
+    #[serde(borrow)]
+    name: &'a str,
+}
+
+/// What is recorded for one coin: its entry in the universe and its context,
+/// both as Hyperliquid sent them.
+#[derive(Serialize)]
+struct AssetContext<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    coin: &'a str,
+    asset: &'a RawValue,
+    ctx: &'a RawValue,
+}
+
+/// Each coin's share of a `metaAndAssetCtxs` body, in the order of `coins`;
+/// `None` for a coin the universe does not list, such as a spot pair.
+fn split_asset_contexts(
+    body: &[u8],
+    coins: &[(String, Symbol)],
+) -> Result<Vec<Option<bytes::Bytes>>, serde_json::Error> {
+    let MetaAndAssetCtxs(meta, contexts) = serde_json::from_slice(body)?;
+    let mut assets = HashMap::with_capacity(meta.universe.len());
+    for (asset, ctx) in meta.universe.into_iter().zip(contexts) {
+        let AssetName { name } = serde_json::from_str(asset.get())?;
+        assets.insert(name, (asset, ctx));
+    }
+    coins
+        .iter()
+        .map(|(coin, _)| {
+            let Some((asset, ctx)) = assets.get(coin.as_str()) else {
+                return Ok(None);
+            };
+            let payload = serde_json::to_vec(&AssetContext {
+                kind: "metaAndAssetCtxs",
+                coin,
+                asset,
+                ctx,
+            })?;
+            Ok(Some(payload.into()))
+        })
+        .collect()
+}
+
+pub fn count_snapshot(outcome: &str) {
+    metrics::counter(
+        "collector_depth_snapshots_total",
+        "REST depth snapshot requests by outcome.",
+        &[("endpoint", "hyperliquid"), ("outcome", outcome)],
+    )
+    .inc();
+}
+
+fn count_asset_contexts(outcome: &str) {
+    metrics::counter(
+        "collector_funding_requests_total",
+        "REST funding and open interest requests by outcome.",
+        &[
+            ("endpoint", "hyperliquid"),
+            ("request", "metaAndAssetCtxs"),
+            ("outcome", outcome),
+        ],
+    )
+    .inc();
+}
+
+/// Record the asset contexts and a book for every coin, once per `interval`.
+///
+/// Each round covers the coins the venue has when it starts, at most
+/// [`books_per_round`] books of them. Returns only when the writer is gone.
+pub async fn info_loop(
+    symbols: watch::Receiver<Vec<String>>,
+    writer_tx: RecordSender,
+    info: InfoClient,
+    interval: Duration,
+) {
+    tokio::time::sleep(INFO_START_DELAY).await;
+
+    let mut ticker = tokio::time::interval(interval);
+    // A round that overruns the interval must not then fire the backlog at once.
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut pacer = tokio::time::interval(INFO_PACE);
+    pacer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut unlisted_reported: Vec<String> = Vec::new();
+    let cap = books_per_round(interval);
+    let mut cursor = 0;
+    let mut capped_reported = false;
+
+    loop {
+        ticker.tick().await;
+
+        // Each coin as Hyperliquid names it, with the symbol its file is
+        // written under.
+        let coins: Vec<(String, Symbol)> = symbols
+            .borrow()
+            .iter()
+            .map(|coin| (coin.clone(), Symbol::from(coin.to_ascii_lowercase())))
+            .collect();
+        match info.asset_contexts().await {
+            Some(Ok(body)) => {
+                count_asset_contexts("ok");
+                let recv_time = Timestamp::now();
+                match split_asset_contexts(&body, &coins) {
+                    Ok(payloads) => {
+                        let mut unlisted = Vec::new();
+                        for ((coin, symbol), payload) in coins.iter().zip(payloads) {
+                            let Some(payload) = payload else {
+                                unlisted.push(coin.clone());
+                                continue;
+                            };
+                            let record = WriteRecord::fetched(
+                                recv_time,
+                                Symbol::clone(symbol),
+                                payload,
+                                Stream::Funding,
+                            );
+                            if writer_tx.send(record).await.is_err() {
+                                return;
+                            }
+                        }
+                        // Spot pairs have no funding; the same list comes
+                        // back every round, so it is said once per list.
+                        if !unlisted.is_empty() && unlisted != unlisted_reported {
+                            warn!(
+                                ?unlisted,
+                                "no perpetual context for these coins; only their books are polled"
+                            );
+                            unlisted_reported = unlisted;
+                        }
+                    }
+                    Err(error) => {
+                        error!(%error, "couldn't split the metaAndAssetCtxs response");
+                    }
+                }
+            }
+            Some(Err(error)) => {
+                count_asset_contexts("error");
+                error!(%error, "failed to fetch metaAndAssetCtxs");
+            }
+            None => {
+                count_asset_contexts("rate_limited");
+                warn!("metaAndAssetCtxs fetch rate-limited, skipping");
+            }
+        }
+
+        if coins.len() > cap && !capped_reported {
+            warn!(
+                coins = coins.len(),
+                books_per_round = cap,
+                "more coins than one round's budget; their books are polled in turns"
+            );
+        }
+        capped_reported = coins.len() > cap;
+        let mut rate_limited = 0;
+        let mut fetched = 0;
+        for position in book_round(coins.len(), cursor, cap) {
+            let (coin, symbol) = &coins[position];
+            fetched += 1;
+            pacer.tick().await;
+            match info.l2_book(coin).await {
+                Some(Ok(data)) => {
+                    count_snapshot("ok");
+                    let record = WriteRecord::fetched(
+                        Timestamp::now(),
+                        Symbol::clone(symbol),
+                        data,
+                        Stream::DepthSnapshot,
+                    );
+                    if writer_tx.send(record).await.is_err() {
+                        return;
+                    }
+                }
+                Some(Err(error)) => {
+                    count_snapshot("error");
+                    error!(coin, %error, "failed to fetch the l2Book snapshot");
+                }
+                None => {
+                    count_snapshot("rate_limited");
+                    rate_limited += 1;
+                }
+            }
+        }
+        cursor = cursor.wrapping_add(fetched);
+        if rate_limited > 0 {
+            warn!(
+                skipped = rate_limited,
+                books = fetched,
+                "l2Book fetches rate-limited this round"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_round_fits_half_the_budget() {
+        let cap = books_per_round(INFO_INTERVAL);
+        assert_eq!(cap, 240);
+        let weight = ASSET_CONTEXTS_WEIGHT + cap as u32 * L2_BOOK_WEIGHT;
+        assert!(weight <= INFO_WEIGHT_PER_MINUTE / 2);
+        assert_eq!(books_per_round(Duration::from_secs(600)), cap);
+        assert_eq!(books_per_round(Duration::from_secs(6)), 15);
+        assert_eq!(books_per_round(Duration::from_millis(1)), 1);
+    }
+
+    /// A universe larger than the cap is covered over consecutive rounds.
+    #[test]
+    fn rounds_take_turns_over_a_large_universe() {
+        assert_eq!(book_round(5, 0, 3).collect::<Vec<_>>(), [0, 1, 2]);
+        assert_eq!(book_round(5, 3, 3).collect::<Vec<_>>(), [3, 4, 0]);
+        assert_eq!(book_round(5, 6, 3).collect::<Vec<_>>(), [1, 2, 3]);
+        assert_eq!(book_round(2, 7, 3).collect::<Vec<_>>(), [1, 0]);
+        assert_eq!(book_round(0, 7, 3).count(), 0);
+    }
+
+    #[test]
+    fn asset_contexts_are_split_per_coin() {
+        let body = br#"[{"universe":[{"name":"BTC","szDecimals":5},{"name":"ETH","szDecimals":4}]},[{"funding":"0.0000125","openInterest":"100.5","markPx":"60000.0","oraclePx":"60010.0"},{"funding":"-0.00001","openInterest":"2000.0","markPx":"3000.0","oraclePx":"3001.0"}]]"#;
+        let coins = [
+            ("ETH".to_owned(), Symbol::from("eth")),
+            ("PURR/USDC".to_owned(), Symbol::from("purr/usdc")),
+        ];
+
+        let payloads = split_asset_contexts(body, &coins).unwrap();
+
+        assert_eq!(
+            payloads[0].as_deref(),
+            Some(&br#"{"type":"metaAndAssetCtxs","coin":"ETH","asset":{"name":"ETH","szDecimals":4},"ctx":{"funding":"-0.00001","openInterest":"2000.0","markPx":"3000.0","oraclePx":"3001.0"}}"#[..])
+        );
+        assert_eq!(payloads[1], None, "spot pairs are not in the universe");
+    }
+}
@@ -0,0 +1,111 @@
+//! Pacing for Hyperliquid's info endpoint.
+//!
+//! Hyperliquid meters REST by weight rather than by request: 1200 per minute
+//! per IP, with `l2Book` costing 2 and `metaAndAssetCtxs` 20. So the window
+//! below sums weights where [`Throttler`](crate::throttler::Throttler) counts
+//! requests. A 429 carries no expiry, and the budget it refers to is the same
+//! rolling minute, so it pauses every request for one window instead of
+//! being retried into.
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    sync::{
+        Arc,
+        atomic::{AtomicI64, Ordering},
+    },
+};
+
+use jiff::Timestamp;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+const WINDOW_NANOS: i64 = 60_000_000_000;
+
+/// The weight this collector allows itself per minute. Hyperliquid allows
+/// 1200 per IP; the rest is left for anything else on the host.
+pub const INFO_WEIGHT_PER_MINUTE: u32 = 1_000;
+
+#[derive(Clone)]
+pub struct InfoLimiter {
+    /// When each request in the window was sent, in epoch nanoseconds, and
+    /// its weight.
+    spent: Arc<Mutex<VecDeque<(i64, u32)>>>,
+    /// When a 429 pause ends, in epoch nanoseconds; 0 when not paused.
+    paused_until: Arc<AtomicI64>,
+    budget: u32,
+}
+
+impl InfoLimiter {
+    pub fn new(budget: u32) -> Self {
+        Self {
+            spent: Default::default(),
+            paused_until: Arc::new(AtomicI64::new(0)),
+            budget,
+        }
+    }
+
+    /// Record a 429, so nothing is sent until the window it refers to has
+    /// passed.
+    pub fn note_rate_limited(&self, now: Timestamp) {
+        let until_nanos = now.as_nanosecond() as i64 + WINDOW_NANOS;
+        if self.paused_until.fetch_max(until_nanos, Ordering::Relaxed) < now.as_nanosecond() as i64
+        {
+            warn!("Hyperliquid rate-limited the info endpoint; pausing requests for a minute");
+        }
+    }
+
+    /// Execute `fut` unless requests are paused or `weight` would overrun the
+    /// window.
+    pub async fn execute<Fut, T>(&self, weight: u32, fut: Fut) -> Option<T>
+    where
+        Fut: Future<Output = T>,
+    {
+        let cur_ts = Timestamp::now().as_nanosecond() as i64;
+        if self.paused_until.load(Ordering::Relaxed) > cur_ts {
+            return None;
+        }
+        {
+            let mut spent = self.spent.lock().await;
+            while spent
+                .front()
+                .is_some_and(|(ts, _)| *ts <= cur_ts - WINDOW_NANOS)
+            {
+                spent.pop_front();
+            }
+            let total: u32 = spent.iter().map(|(_, weight)| weight).sum();
+            if total + weight > self.budget {
+                return None;
+            }
+            spent.push_back((cur_ts, weight));
+        }
+        Some(fut.await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The budget is in weight: one heavy request can spend what many light
+    /// ones would, and a request that does not fit is refused whole.
+    #[tokio::test]
+    async fn the_budget_is_a_ceiling_on_weight() {
+        let limiter = InfoLimiter::new(24);
+        assert_eq!(limiter.execute(20, async { 1 }).await, Some(1));
+        assert_eq!(limiter.execute(2, async { 2 }).await, Some(2));
+        assert_eq!(limiter.execute(20, async { 3 }).await, None);
+        assert_eq!(limiter.execute(2, async { 4 }).await, Some(4));
+        assert_eq!(limiter.execute(2, async { 5 }).await, None);
+
+        assert_eq!(InfoLimiter::new(0).execute(2, async { 1 }).await, None);
+    }
+
+    #[tokio::test]
+    async fn a_rate_limit_reply_pauses_every_request() {
+        let limiter = InfoLimiter::new(INFO_WEIGHT_PER_MINUTE);
+        limiter.note_rate_limited(Timestamp::now());
+
+        assert_eq!(limiter.execute(2, async { 1 }).await, None);
+    }
+}
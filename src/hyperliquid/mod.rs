@@ -1,5 +1,11 @@
 pub use http::keep_connection;
+pub use info::{INFO_INTERVAL, INFO_URL};
+mod continuity;
 mod http;
+mod info;
+mod limiter;
+
+use http::Frame;
 
 use std::{
     sync::{
@@ -11,19 +17,51 @@ use std::{
 
 use jiff::Timestamp;
 use tokio::{
-    sync::{
-        mpsc::{Sender, channel},
-        watch,
-    },
+    sync::{mpsc::channel, watch},
     task::JoinSet,
 };
 
-use tracing::error;
+use tracing::{error, warn};
+
+use collector::record::Stream;
 
 use crate::{
-    dedup::Dedup, error::ConnectorError, feed::Feed, file::WriteRecord,
-    routing::HyperliquidMessage, symbol::SymbolCache,
+    dedup::Dedup,
+    error::ConnectorError,
+    feed::Feed,
+    file::{RecordSender, WriteRecord},
+    routing::HyperliquidMessage,
+    spool::Spool,
+    symbol::{Symbol, SymbolCache},
 };
+use continuity::{Anomalies, Continuity, Verdict, report_anomalies};
+use info::{InfoClient, count_snapshot, info_loop};
+use limiter::{INFO_WEIGHT_PER_MINUTE, InfoLimiter};
+
+/// Hyperliquid's cap on websocket subscriptions, per IP across all connections.
+pub const MAX_SUBSCRIPTIONS: usize = 1_000;
+
+/// Refuse a symbol list that would need more than [`MAX_SUBSCRIPTIONS`].
+///
+/// Hyperliquid's caps are per IP "across all websocket connections", not per
+/// socket, so redundancy spends the same budget N times over: each connection
+/// subscribes to the full set for itself.
+pub fn check_subscription_count(
+    subscription_types: usize,
+    symbols: usize,
+    connections: usize,
+) -> Result<(), anyhow::Error> {
+    let per_connection = subscription_types.saturating_mul(symbols);
+    let subscription_count = per_connection.saturating_mul(connections.max(1));
+    if subscription_count > MAX_SUBSCRIPTIONS {
+        anyhow::bail!(
+            "Hyperliquid allows at most {MAX_SUBSCRIPTIONS} websocket subscriptions per IP across all \
+             connections; {connections} connection(s) x {per_connection} subscriptions = \
+             {subscription_count}"
+        );
+    }
+    Ok(())
+}
 
 /// How often to restate that requests were rejected, so an incomplete feed
 /// stays visible instead of scrolling away after the initial error.
@@ -59,13 +97,72 @@ async fn report_rejections(rejections: Arc<Rejections>) {
     }
 }
 
+/// The record stream tag for a frame on `channel`. `l2Book` pushes the whole
+/// book every time, so each frame is a snapshot.
+fn stream_of(channel: &str) -> Stream {
+    match channel {
+        "l2Book" => Stream::DepthSnapshot,
+        "trades" => Stream::Trade,
+        "bbo" => Stream::Bbo,
+        _ => Stream::Other,
+    }
+}
+
+/// Record the coin's book from the info endpoint, off the collection loop.
+fn spawn_reanchor(
+    tasks: &mut JoinSet<()>,
+    info: &InfoClient,
+    writer_tx: &RecordSender,
+    coin: &str,
+    symbol: Symbol,
+) {
+    // Finished fetches stay in the set until joined; a long-running collector
+    // would otherwise keep every one of them.
+    while let Some(result) = tasks.try_join_next() {
+        if let Err(error) = result
+            && !error.is_cancelled()
+        {
+            error!(?error, "background task failed");
+        }
+    }
+    let info = info.clone();
+    let writer_tx = writer_tx.clone();
+    let coin = coin.to_owned();
+    tasks.spawn(async move {
+        let outcome = match info.l2_book(&coin).await {
+            Some(Ok(data)) => {
+                let record =
+                    WriteRecord::fetched(Timestamp::now(), symbol, data, Stream::DepthSnapshot);
+                let _ = writer_tx.send(record).await;
+                "ok"
+            }
+            Some(Err(error)) => {
+                error!(coin, ?error, "couldn't fetch the l2Book snapshot.");
+                "error"
+            }
+            None => {
+                warn!(coin, "l2Book re-anchor rate-limited, skipping");
+                "rate_limited"
+            }
+        };
+        count_snapshot(outcome);
+    });
+}
+
+/// `reanchor` is the info client to fetch books with when the stream stalls,
+/// if that is enabled; fetches are spawned onto `tasks`.
+#[allow(clippy::too_many_arguments)]
 async fn handle(
-    writer_tx: &Sender<WriteRecord>,
+    writer_tx: &RecordSender,
     symbols: &mut SymbolCache,
     rejections: &Rejections,
+    continuity: &mut Continuity,
     dedup: &mut Dedup,
+    connection: usize,
     recv_time: Timestamp,
     data: bytes::Bytes,
+    reanchor: Option<&InfoClient>,
+    tasks: &mut JoinSet<()>,
 ) -> Result<(), ConnectorError> {
     let message: HyperliquidMessage<'_> = serde_json::from_slice(&data)?;
     if message.channel == "error" {
@@ -92,66 +189,107 @@ async fn handle(
     }
 
     let symbol = symbols.resolve(symbol_raw);
+    let stream = stream_of(message.channel);
+
+    match (message.channel, message.time) {
+        ("l2Book", Some(time)) => {
+            let verdict =
+                continuity.book(connection, symbol_raw, time, recv_time, reanchor.is_some());
+            if let (Verdict::Reanchor, Some(info)) = (verdict, reanchor) {
+                spawn_reanchor(tasks, info, writer_tx, symbol_raw, Symbol::clone(&symbol));
+            }
+        }
+        ("trades", _) => continuity.trades(connection, symbol_raw, &message.trades),
+        _ => {}
+    }
 
     writer_tx
-        .send((recv_time, symbol, data))
+        .send(WriteRecord::received(
+            recv_time, symbol, data, connection, stream,
+        ))
         .await
         .map_err(|_| ConnectorError::WriterClosed)?;
     Ok(())
 }
 
+/// `info_interval` is the time between rounds of the info endpoint poller.
+#[allow(clippy::too_many_arguments)]
 pub async fn run_collection(
+    venue: Arc<str>,
     subscriptions: Vec<String>,
-    symbols: Vec<String>,
-    writer_tx: Sender<WriteRecord>,
+    mut symbols: watch::Receiver<Vec<String>>,
+    writer_tx: RecordSender,
     shutdown: watch::Receiver<bool>,
     connections: usize,
+    reanchor_books: bool,
+    info_interval: Duration,
 ) -> Result<(), anyhow::Error> {
     let connections = connections.max(1);
-    // Hyperliquid's caps are per IP "across all websocket connections", not per
-    // socket, so redundancy spends the same budget N times over: each
-    // connection subscribes to the full set for itself.
-    let per_connection = subscriptions.len().saturating_mul(symbols.len());
-    let subscription_count = per_connection.saturating_mul(connections);
-    if subscription_count > 1_000 {
-        anyhow::bail!(
-            "Hyperliquid allows at most 1000 websocket subscriptions per IP across all \
-             connections; {connections} connection(s) x {per_connection} subscriptions = \
-             {subscription_count}"
-        );
-    }
+    check_subscription_count(subscriptions.len(), symbols.borrow().len(), connections)?;
 
-    let mut dedup = Dedup::for_connections(connections);
+    let mut dedup = Dedup::for_connections(connections).with_metrics("hyperliquid");
     // Sized per connection: they share the queue, so the burst each one can
     // absorb stays the same however many there are.
-    let (ws_tx, ws_rx) =
-        channel::<(Timestamp, bytes::Bytes)>(crate::WS_QUEUE_CAPACITY.saturating_mul(connections));
+    let (ws_tx, ws_rx) = channel::<Frame>(crate::WS_QUEUE_CAPACITY.saturating_mul(connections));
     let mut feed = Feed::new(ws_rx, shutdown);
     let mut tasks = JoinSet::new();
-    let mut symbol_cache = SymbolCache::new(&symbols);
+    let mut symbol_cache = SymbolCache::new(&symbols.borrow_and_update());
     let rejections = Arc::new(Rejections::default());
+    let anomalies = Arc::new(Anomalies::default());
+    let mut continuity = Continuity::new(Arc::clone(&anomalies));
+    // The poller and the re-anchors spend one weight budget between them.
+    let info = InfoClient::new(InfoLimiter::new(INFO_WEIGHT_PER_MINUTE))?;
+    let reanchor = reanchor_books.then(|| info.clone());
+    let spool = Spool::for_feed("hyperliquid", connections);
     for connection in 0..connections {
         let subscriptions = subscriptions.clone();
         let symbols = symbols.clone();
         let ws_tx = ws_tx.clone();
+        let spool = spool.clone();
+        let venue = Arc::clone(&venue);
         tasks.spawn(async move {
             tokio::time::sleep(crate::CONNECT_STAGGER * connection as u32).await;
-            keep_connection(subscriptions, symbols, connection, connections, ws_tx).await;
+            keep_connection(
+                venue,
+                subscriptions,
+                symbols,
+                connection,
+                connections,
+                ws_tx,
+                spool,
+            )
+            .await;
             error!(connection, "the websocket connection task exited");
         });
     }
     // The clones above are the only senders that should keep the feed open.
     drop(ws_tx);
     tasks.spawn(report_rejections(Arc::clone(&rejections)));
+    tasks.spawn(report_anomalies(anomalies));
+    {
+        let coins = symbols.clone();
+        let writer_tx = writer_tx.clone();
+        tasks.spawn(async move {
+            info_loop(coins, writer_tx, info, info_interval).await;
+            error!("the info endpoint poller exited");
+        });
+    }
 
-    while let Some((recv_time, data)) = feed.recv(&mut tasks).await {
+    while let Some((connection, recv_time, data)) = feed.recv(&mut tasks).await {
+        if symbols.has_changed().unwrap_or(false) {
+            symbol_cache.reseed(&symbols.borrow_and_update());
+        }
         if let Err(error) = handle(
             &writer_tx,
             &mut symbol_cache,
             &rejections,
+            &mut continuity,
             &mut dedup,
+            connection,
             recv_time,
             data,
+            reanchor.as_ref(),
+            &mut tasks,
         )
         .await
         {
@@ -167,10 +305,11 @@ pub async fn run_collection(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::file::record_channel;
 
     #[tokio::test]
     async fn error_frame_is_reported_without_dropping_the_connection() {
-        let (writer_tx, mut writer_rx) = channel(1);
+        let (writer_tx, mut writer_rx) = record_channel(1);
         let mut symbols = SymbolCache::new(&[]);
         let rejections = Rejections::default();
         let mut dedup = Dedup::disabled();
@@ -182,9 +321,13 @@ mod tests {
             &writer_tx,
             &mut symbols,
             &rejections,
+            &mut Continuity::new(Arc::default()),
             &mut dedup,
+            0,
             Timestamp::now(),
             data,
+            None,
+            &mut JoinSet::new(),
         )
         .await
         .unwrap();
@@ -197,7 +340,7 @@ mod tests {
 
     #[tokio::test]
     async fn routes_trade_by_coin() {
-        let (writer_tx, mut writer_rx) = channel(1);
+        let (writer_tx, mut writer_rx) = record_channel(1);
         let mut symbols = SymbolCache::new(&["BTC".to_owned()]);
         let rejections = Rejections::default();
         let mut dedup = Dedup::disabled();
@@ -209,16 +352,26 @@ mod tests {
             &writer_tx,
             &mut symbols,
             &rejections,
+            &mut Continuity::new(Arc::default()),
             &mut dedup,
+            0,
             Timestamp::now(),
             data.clone(),
+            None,
+            &mut JoinSet::new(),
         )
         .await
         .unwrap();
 
-        let (_, symbol, written) = writer_rx.try_recv().unwrap();
+        let WriteRecord {
+            symbol,
+            data: written,
+            stream,
+            ..
+        } = writer_rx.try_recv().unwrap();
         assert_eq!(symbol.as_ref(), "btc");
         assert_eq!(written, data);
+        assert_eq!(stream, Stream::Trade);
     }
 
     /// Market data is collapsed across connections; a rejection is not, because
@@ -226,7 +379,7 @@ mod tests {
     /// degraded connections.
     #[tokio::test]
     async fn redundant_copies_are_collapsed_but_rejections_are_not() {
-        let (writer_tx, mut writer_rx) = channel(8);
+        let (writer_tx, mut writer_rx) = record_channel(8);
         let mut symbols = SymbolCache::new(&["BTC".to_owned()]);
         let rejections = Rejections::default();
         let mut dedup = Dedup::for_connections(2);
@@ -241,15 +394,19 @@ mod tests {
                 &writer_tx,
                 &mut symbols,
                 &rejections,
+                &mut Continuity::new(Arc::default()),
                 &mut dedup,
+                0,
                 Timestamp::now(),
                 data,
+                None,
+                &mut JoinSet::new(),
             )
             .await
             .unwrap();
         }
 
-        let (_, _, written) = writer_rx.try_recv().unwrap();
+        let WriteRecord { data: written, .. } = writer_rx.try_recv().unwrap();
         assert_eq!(written, trade);
         assert!(writer_rx.try_recv().is_err(), "the copy is dropped");
         assert_eq!(rejections.total.load(Ordering::Relaxed), 2);
@@ -0,0 +1,29 @@
+//! Reading the collector's recordings.
+//!
+//! The collector writes one zstd file per symbol per rotation period, as
+//! `<recv_ns> <json>` text lines or the binary records described in
+//! [`record`]. [`replay`] finds a symbol's files and reads them back in order
+//! as one stream of records, whichever format each was written in:
+//!
+//! ```no_run
+//! use collector::replay::{self, Dictionaries};
+//!
+//! let dictionaries = Dictionaries::new();
+//! for series in replay::series("/data/raw/binance/futures/um".as_ref())? {
+//!     for record in series.records(&dictionaries) {
+//!         let record = record?;
+//!         println!("{} {} {}", record.recv_time, record.symbol, record.payload.len());
+//!     }
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [`book`] rebuilds order books from those records, as the `book_builder`
+//! binary does.
+//!
+//! The collector itself is the `collector` binary; nothing here is needed to
+//! run it.
+
+pub mod book;
+pub mod record;
+pub mod replay;
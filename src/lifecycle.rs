@@ -0,0 +1,311 @@
+//! Noticing that a symbol has stopped trading.
+//!
+//! A delisted symbol goes quiet, and a quiet file looks the same as a
+//! collection that broke. So a venue with a `status_interval_secs` polls its
+//! exchange's instrument list, and a symbol the exchange no longer lists, or
+//! lists with a status that means it has stopped for good, is dropped from the
+//! venue's symbol list — the
+//! same `watch` channel a SIGHUP reload uses, so the connections unsubscribe it
+//! and the snapshot rounds skip it. Its file then gets one last row, a
+//! [`Stream::Lifecycle`](collector::record::Stream::Lifecycle) marker saying
+//! what happened, and is closed.
+//!
+//! A status has to hold for [`CONFIRMATIONS`] polls in a row before anything
+//! is dropped: a truncated response, or a halt that lifts after a few minutes,
+//! must not cost a symbol its subscription until the next restart. A symbol
+//! missing from the list only counts as ended where the list holds every
+//! status; see [`universe::lists_every_status`].
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use jiff::Timestamp;
+use serde::Serialize;
+use tokio::{select, sync::watch, time::MissedTickBehavior};
+use tracing::{info, warn};
+
+use crate::{
+    file::{RecordSender, WriteRecord},
+    symbol::Symbol,
+    universe,
+};
+
+/// Consecutive polls a symbol must be missing or ended in before it is dropped.
+const CONFIRMATIONS: u32 = 3;
+/// Statuses, as the exchanges spell them in any case, after which a symbol
+/// does not trade again: Binance's `BREAK`, `SETTLING` and `CLOSE`, Bybit's
+/// `Delivering` and `Closed`, and the `delisted` of Hyperliquid's perpetuals.
+/// Anything else, such as a `HALT` or a `PreLaunch`, is waited out.
+const ENDED: &[&str] = &[
+    "break",
+    "settling",
+    "close",
+    "delivering",
+    "delivered",
+    "closed",
+    "delisted",
+];
+
+/// Whether `status` means the symbol has stopped for good; `None`, not listed,
+/// only does when `missing_ends`.
+fn has_ended(status: Option<&str>, missing_ends: bool) -> bool {
+    match status {
+        Some(status) => ENDED.iter().any(|ended| ended.eq_ignore_ascii_case(status)),
+        None => missing_ends,
+    }
+}
+
+/// The row written at the end of a delisted symbol's file.
+#[derive(Serialize, Debug, PartialEq)]
+struct Marker<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    event: &'static str,
+    exchange: &'a str,
+    symbol: &'a str,
+    /// The exchange's last word on it; `None` when it no longer lists it.
+    status: Option<&'a str>,
+}
+
+/// A symbol to drop, with the status that ended it.
+#[derive(Debug, PartialEq)]
+struct Ended {
+    symbol: String,
+    status: Option<String>,
+    /// Already marked and dropped once, and put back since, as by a reload of
+    /// a config that still lists it: it is dropped again without a second
+    /// marker.
+    marked: bool,
+}
+
+/// Polls that have seen each symbol ended, and the symbols already marked.
+#[derive(Default)]
+struct Tracker {
+    strikes: HashMap<String, u32>,
+    marked: HashSet<String>,
+    /// The exchange lists every status, so a symbol it leaves out is gone.
+    missing_ends: bool,
+}
+
+impl Tracker {
+    fn new(missing_ends: bool) -> Self {
+        Self {
+            missing_ends,
+            ..Self::default()
+        }
+    }
+
+    /// Count this poll's `statuses`, keyed by lowercased symbol, against the
+    /// venue's current `symbols`, and return those to drop now.
+    fn observe(&mut self, symbols: &[String], statuses: &HashMap<String, String>) -> Vec<Ended> {
+        self.strikes.retain(|symbol, _| symbols.contains(symbol));
+        let mut ended = Vec::new();
+        for symbol in symbols {
+            let status = statuses.get(&symbol.to_ascii_lowercase());
+            if !has_ended(status.map(String::as_str), self.missing_ends) {
+                self.strikes.remove(symbol);
+                self.marked.remove(symbol);
+                continue;
+            }
+            let marked = self.marked.contains(symbol);
+            let strikes = self.strikes.entry(symbol.clone()).or_default();
+            *strikes += 1;
+            if marked || *strikes >= CONFIRMATIONS {
+                self.strikes.remove(symbol);
+                self.marked.insert(symbol.clone());
+                ended.push(Ended {
+                    symbol: symbol.clone(),
+                    status: status.cloned(),
+                    marked,
+                });
+            }
+        }
+        ended
+    }
+}
+
+/// Poll `exchange`'s instrument list every `interval` and drop the symbols it
+/// has stopped trading from `symbols`, marking each one's file; until
+/// `shutdown` is set or the writer is gone.
+pub async fn watch_listings(
+    exchange: String,
+    venue: String,
+    symbols: watch::Sender<Vec<String>>,
+    writer_tx: RecordSender,
+    interval: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // The symbols were just subscribed, from a list fetched moments ago when
+    // discovered: the first poll can wait a whole interval.
+    ticker.tick().await;
+    let mut tracker = Tracker::new(universe::lists_every_status(&exchange));
+    loop {
+        let statuses = select! {
+            _ = shutdown.changed() => return,
+            statuses = async {
+                ticker.tick().await;
+                universe::statuses(&exchange).await
+            } => statuses,
+        };
+        let statuses = match statuses {
+            Ok(statuses) if !statuses.is_empty() => statuses,
+            Ok(_) => {
+                warn!(
+                    venue,
+                    "the exchange listed no instruments; skipping this status check"
+                );
+                continue;
+            }
+            Err(error) => {
+                warn!(
+                    venue,
+                    error = format!("{error:#}"),
+                    "couldn't check the instrument statuses"
+                );
+                continue;
+            }
+        };
+
+        let ended = tracker.observe(&symbols.borrow(), &statuses);
+        if ended.is_empty() {
+            continue;
+        }
+        let is_ended = |symbol: &String| ended.iter().any(|ended| ended.symbol == *symbol);
+        // The connections have no way to subscribe to nothing, so a venue
+        // whose every symbol has ended keeps them, marked, until it is
+        // reconfigured.
+        let emptied = symbols.borrow().iter().all(is_ended);
+        if emptied {
+            warn!(
+                venue,
+                "every symbol has stopped trading; keeping the subscriptions"
+            );
+        } else {
+            symbols.send_modify(|symbols| symbols.retain(|symbol| !is_ended(symbol)));
+        }
+        for ended in ended {
+            if ended.marked {
+                if !emptied {
+                    info!(
+                        venue,
+                        symbol = ended.symbol,
+                        "dropping a delisted symbol again"
+                    );
+                }
+                continue;
+            }
+            warn!(
+                venue,
+                symbol = ended.symbol,
+                status = ended.status,
+                "the exchange has stopped trading the symbol; dropping it"
+            );
+            let marker = Marker {
+                kind: "lifecycle",
+                event: "delisted",
+                exchange: &exchange,
+                symbol: &ended.symbol,
+                status: ended.status.as_deref(),
+            };
+            let Ok(data) = serde_json::to_vec(&marker) else {
+                continue;
+            };
+            let record = WriteRecord::lifecycle(
+                Timestamp::now(),
+                Symbol::from(ended.symbol.to_ascii_lowercase()),
+                data.into(),
+            );
+            if writer_tx.send(record).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statuses(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(symbol, status)| (symbol.to_string(), status.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn a_symbol_is_dropped_once_it_stays_ended() {
+        let symbols = vec!["BTCUSDT".to_owned(), "LUNAUSDT".to_owned()];
+        let settling = statuses(&[("btcusdt", "TRADING"), ("lunausdt", "SETTLING")]);
+        let mut tracker = Tracker::new(true);
+
+        assert!(tracker.observe(&symbols, &settling).is_empty());
+        // A status that recovers starts the count over.
+        let halted = statuses(&[("btcusdt", "TRADING"), ("lunausdt", "HALT")]);
+        assert!(tracker.observe(&symbols, &halted).is_empty());
+        for _ in 1..CONFIRMATIONS {
+            assert!(tracker.observe(&symbols, &settling).is_empty());
+        }
+        assert_eq!(
+            tracker.observe(&symbols, &settling),
+            [Ended {
+                symbol: "LUNAUSDT".to_owned(),
+                status: Some("SETTLING".to_owned()),
+                marked: false,
+            }]
+        );
+
+        // Put back by a reload while still unlisted: dropped at once, unmarked.
+        let unlisted = statuses(&[("btcusdt", "TRADING")]);
+        assert_eq!(
+            tracker.observe(&symbols, &unlisted),
+            [Ended {
+                symbol: "LUNAUSDT".to_owned(),
+                status: None,
+                marked: true,
+            }]
+        );
+    }
+
+    /// Where the list leaves some statuses out, such as Bybit spot's, a symbol
+    /// missing from it may only be waiting to trade, and is kept.
+    #[test]
+    fn a_missing_symbol_is_kept_unless_the_list_is_complete() {
+        let symbols = vec!["NEWUSDT".to_owned()];
+        let unlisted = statuses(&[("btcusdt", "Trading")]);
+        let mut tracker = Tracker::new(false);
+
+        for _ in 0..CONFIRMATIONS * 2 {
+            assert!(tracker.observe(&symbols, &unlisted).is_empty());
+        }
+        let closed = statuses(&[("newusdt", "Closed")]);
+        for _ in 1..CONFIRMATIONS {
+            assert!(tracker.observe(&symbols, &closed).is_empty());
+        }
+        assert_eq!(tracker.observe(&symbols, &closed).len(), 1);
+
+        assert!(universe::lists_every_status("bybitlinear"));
+        assert!(!universe::lists_every_status("bybitspot"));
+        assert!(!universe::lists_every_status("okx"));
+    }
+
+    #[test]
+    fn the_marker_names_the_symbol_and_its_status() {
+        let marker = Marker {
+            kind: "lifecycle",
+            event: "delisted",
+            exchange: "bybit",
+            symbol: "LUNAUSDT",
+            status: Some("Closed"),
+        };
+
+        assert_eq!(
+            serde_json::to_string(&marker).unwrap(),
+            r#"{"type":"lifecycle","event":"delisted","exchange":"bybit","symbol":"LUNAUSDT","status":"Closed"}"#
+        );
+    }
+}
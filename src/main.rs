@@ -1,28 +1,49 @@
-use std::time::Duration;
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
 
-use anyhow::anyhow;
+use anyhow::{Context, anyhow};
 use clap::Parser;
+use futures_util::future::select_all;
+use jiff::Timestamp;
 use tokio::{
     self, select, signal,
-    sync::{mpsc::channel, oneshot, watch},
+    sync::{
+        mpsc::{Receiver, UnboundedSender, channel, error::TryRecvError},
+        watch,
+    },
+    task::JoinHandle,
 };
 use tracing::{error, info};
 
-use crate::file::Writer;
+use collector::record::Format;
 
+use crate::{
+    config::{Config, Venue, Zstd},
+    file::{FileOptions, RecordSender, Rotation, Writer},
+    universe::Universe,
+};
+
+mod account;
 mod binance;
 mod binance_market;
 mod binancefuturescm;
 mod binancefuturesum;
 mod bybit;
+mod config;
 mod dedup;
 mod error;
 mod feed;
 mod file;
 mod hyperliquid;
+mod lifecycle;
+mod metrics;
+mod okx;
+mod reload;
 mod routing;
+mod spool;
+mod streams;
 mod symbol;
 mod throttler;
+mod universe;
 mod ws;
 
 const WRITER_QUEUE_CAPACITY: usize = 65_536;
@@ -43,14 +64,48 @@ const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
 #[command(version, about, long_about = None)]
 struct Args {
     /// Path for the files where collected data will be written.
-    path: String,
+    #[arg(required_unless_present = "config")]
+    path: Option<String>,
 
     /// Name of the exchange
-    exchange: String,
+    #[arg(required_unless_present = "config")]
+    exchange: Option<String>,
 
     /// Symbols for which data will be collected.
     symbols: Vec<String>,
 
+    /// Streams to record instead of the exchange's defaults, comma-separated
+    /// or repeated, as the venue names them with `$symbol` where it expects
+    /// one: `$symbol@bookTicker` on Binance, `orderbook.50.$symbol` on Bybit,
+    /// `bbo` on Hyperliquid. Checked against the streams the venue is known
+    /// to offer.
+    #[arg(long, visible_alias = "topics", value_delimiter = ',')]
+    streams: Vec<String>,
+
+    /// Discover the symbols from the exchange's instrument list instead of
+    /// naming them: everything trading, narrowed by the filters below.
+    /// SIGHUP discovers them again without restarting.
+    #[arg(long, conflicts_with = "symbols")]
+    universe: bool,
+
+    /// Keep only instruments quoted in this asset, e.g. `USDT`.
+    #[arg(long, requires = "universe")]
+    quote: Option<String>,
+
+    /// Keep only this contract type, as the exchange names it: `PERPETUAL` on
+    /// Binance, `LinearPerpetual` on Bybit, `perpetual` or `spot` on
+    /// Hyperliquid.
+    #[arg(long, requires = "universe")]
+    contract_type: Option<String>,
+
+    /// Keep only instruments in this status instead of the trading ones.
+    #[arg(long, requires = "universe")]
+    status: Option<String>,
+
+    /// Keep only the N most traded over the last 24 hours.
+    #[arg(long, requires = "universe", value_name = "N")]
+    top: Option<usize>,
+
     /// Number of redundant websocket connections to the exchange.
     ///
     /// Every connection subscribes to the same streams and duplicate messages
@@ -64,204 +119,596 @@ struct Args {
         value_parser = clap::value_parser!(u8).range(1..=8),
     )]
     connections: u8,
-}
 
-#[tokio::main(flavor = "multi_thread")]
-async fn main() -> Result<(), anyhow::Error> {
-    let args = Args::parse();
+    /// How rows are laid out in the files: `text` lines, or `binary` records
+    /// that also keep the connection each row arrived on and its stream.
+    #[arg(long, default_value_t = Format::Text)]
+    format: Format,
 
-    tracing_subscriber::fmt::init();
+    /// When each symbol's file is closed and the next one started: `daily`,
+    /// `hourly`, `<N>m` for N-minute windows that divide the day, or a
+    /// compressed size such as `512MiB` or `2GiB`. Every policy also rotates
+    /// at UTC midnight.
+    #[arg(long, default_value_t = Rotation::Daily)]
+    rotation: Rotation,
 
-    let connections = usize::from(args.connections);
-    if connections > 1 {
-        info!(
-            connections,
-            "redundant collection enabled; duplicate messages will be discarded"
-        );
-    }
+    /// End each file's zstd frame after this many seconds, so a hard kill
+    /// leaves everything but the last few seconds decodable.
+    #[arg(
+        long,
+        default_value_t = config::default_frame_secs(),
+        value_parser = clap::value_parser!(u64).range(1..),
+    )]
+    frame_secs: u64,
 
-    std::fs::create_dir_all(&args.path)?;
-    let (writer_tx, mut writer_rx) = channel::<file::WriteRecord>(WRITER_QUEUE_CAPACITY);
-    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    /// End each file's zstd frame after this many MiB of uncompressed rows,
+    /// whichever of the two comes first.
+    #[arg(
+        long,
+        default_value_t = config::default_frame_mib(),
+        value_parser = clap::value_parser!(u64).range(1..),
+    )]
+    frame_mib: u64,
 
-    let mut handle = match args.exchange.as_str() {
-        "binancefutures" | "binancefuturesum" => {
-            let streams = [
-                "$symbol@trade",
-                "$symbol@bookTicker",
-                "$symbol@depth@0ms",
-                "$symbol@forceOrder",
-                // "$symbol@@markPrice@1s"
-            ]
-            .iter()
-            .map(|stream| stream.to_string())
-            .collect();
-
-            tokio::spawn(binancefuturesum::run_collection(
-                streams,
-                args.symbols,
-                writer_tx,
-                shutdown_rx,
-                connections,
-            ))
-        }
-        "binancefuturescm" => {
-            let streams = [
-                "$symbol@trade",
-                "$symbol@bookTicker",
-                // COIN-M only offers 100ms/250ms/500ms depth; @0ms is USD-M only
-                // and gets the whole connection rejected.
-                "$symbol@depth@100ms",
-                "$symbol@forceOrder",
-                // "$symbol@@markPrice@1s"
-            ]
-            .iter()
-            .map(|stream| stream.to_string())
-            .collect();
-
-            tokio::spawn(binancefuturescm::run_collection(
-                streams,
-                args.symbols,
-                writer_tx,
-                shutdown_rx,
-                connections,
-            ))
-        }
-        "binance" | "binancespot" => {
-            let streams = ["$symbol@trade", "$symbol@bookTicker", "$symbol@depth@100ms"]
-                .iter()
-                .map(|stream| stream.to_string())
-                .collect();
+    /// Writer threads, each with its own queue and files; symbols are spread
+    /// across them by hash. A slow disk flush then holds up only one shard's
+    /// symbols, at the cost of a thread and a full queue per shard.
+    #[arg(
+        long,
+        default_value_t = config::default_writer_shards(),
+        value_parser = clap::value_parser!(u8).range(1..=config::MAX_WRITER_SHARDS as i64),
+    )]
+    writer_shards: u8,
 
-            tokio::spawn(binance::run_collection(
-                streams,
-                args.symbols,
-                writer_tx,
-                shutdown_rx,
-                connections,
-            ))
+    /// zstd compression level; higher trades the writer's CPU for disk.
+    #[arg(long, default_value_t = 1, allow_negative_numbers = true)]
+    zstd_level: i32,
+
+    /// Enable zstd long-distance matching with a window of 2^N bytes (10-31).
+    #[arg(long, value_name = "N")]
+    zstd_long_window: Option<u32>,
+
+    /// zstd worker threads compressing beside the writer thread; 0 = none.
+    #[arg(long, default_value_t = 0)]
+    zstd_workers: u32,
+
+    /// A trained zstd dictionary (`zstd --train`); readers need it too.
+    #[arg(long)]
+    zstd_dictionary: Option<PathBuf>,
+
+    /// Read the venues from a TOML file of `[[venue]]` blocks instead of the
+    /// positional arguments. Every venue in the file is collected by this one
+    /// process unless `--venue` narrows it down. SIGHUP re-reads the file and
+    /// changes each venue's symbols without restarting it.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "path", "exchange", "symbols", "streams", "universe", "connections", "format", "rotation", "frame_secs", "frame_mib",
+            "zstd_level", "zstd_long_window", "zstd_workers", "zstd_dictionary", "writer_shards",
+        ]
+    )]
+    config: Option<PathBuf>,
+
+    /// Collect only this `[[venue]]` block of the config, by `name` (or
+    /// `exchange` where no name is given). May be repeated.
+    #[arg(long, requires = "config")]
+    venue: Vec<String>,
+
+    /// Serve collector health counters in the Prometheus text format on
+    /// `http://<addr>/metrics`, e.g. `127.0.0.1:9100`.
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Spill frames to files in this directory while the writer cannot keep
+    /// up, instead of dropping them, and replay them once it catches up.
+    #[arg(long)]
+    spool_dir: Option<PathBuf>,
+
+    /// Spool space per connection, in MiB. A venue's connections share one
+    /// file of that many times this size; frames are dropped once it is full.
+    #[arg(long, default_value_t = spool::DEFAULT_CAPACITY_MIB, value_parser = clap::value_parser!(u64).range(1..))]
+    spool_mib: u64,
+}
+
+impl Args {
+    fn into_venues(self) -> Result<Vec<Venue>, anyhow::Error> {
+        match self.config {
+            Some(config) => Config::load(&config)?.select(&self.venue),
+            None => {
+                let venue = Venue {
+                    name: None,
+                    // Both are required by clap whenever `--config` is absent.
+                    exchange: self.exchange.unwrap_or_default(),
+                    path: self.path.unwrap_or_default(),
+                    symbols: self.symbols,
+                    streams: (!self.streams.is_empty()).then_some(self.streams),
+                    universe: self.universe.then_some(Universe {
+                        quote: self.quote,
+                        contract_type: self.contract_type,
+                        status: self.status,
+                        top: self.top,
+                    }),
+                    connections: self.connections,
+                    snapshot_interval_secs: None,
+                    funding_interval_secs: None,
+                    status_interval_secs: None,
+                    reanchor_books: false,
+                    subscribe: false,
+                    format: self.format,
+                    rotation: self.rotation,
+                    frame_secs: self.frame_secs,
+                    frame_mib: self.frame_mib,
+                    writer_shards: self.writer_shards,
+                    zstd: Zstd {
+                        level: self.zstd_level,
+                        long_window: self.zstd_long_window,
+                        workers: self.zstd_workers,
+                        dictionary: self.zstd_dictionary,
+                    },
+                    account: None,
+                };
+                venue.zstd.validate()?;
+                Ok(vec![venue])
+            }
         }
-        "bybit" => {
-            let topics = [
-                "orderbook.1.$symbol",
-                "orderbook.50.$symbol",
-                "orderbook.200.$symbol",
-                "publicTrade.$symbol",
-                "allLiquidation.$symbol",
-            ]
-            .iter()
-            .map(|topic| topic.to_string())
-            .collect();
+    }
+}
 
-            tokio::spawn(bybit::run_collection(
-                topics,
-                args.symbols,
-                writer_tx,
-                shutdown_rx,
-                connections,
-            ))
+/// `symbols` carries the venue's symbol list, and its changes while running.
+fn spawn_collection(
+    venue: Venue,
+    symbols: watch::Receiver<Vec<String>>,
+    writer_tx: RecordSender,
+    shutdown_rx: watch::Receiver<bool>,
+) -> Result<JoinHandle<Result<(), anyhow::Error>>, anyhow::Error> {
+    let connections = usize::from(venue.connections);
+    let Some(catalogue) = streams::catalogue(&venue.exchange) else {
+        return Err(anyhow!("{} is not supported.", venue.exchange));
+    };
+    let streams = catalogue
+        .select(venue.streams.clone())
+        .map_err(|error| anyhow!("{}: {error}", venue.name()))?;
+    let snapshot_interval = venue.snapshot_interval();
+    if snapshot_interval.is_some()
+        && !venue.exchange.starts_with("binance")
+        && venue.exchange != "hyperliquid"
+    {
+        return Err(anyhow!(
+            "{}: snapshot_interval_secs only applies to Binance and Hyperliquid venues",
+            venue.name()
+        ));
+    }
+    let funding_interval = venue.funding_interval();
+    if funding_interval.is_some() && !venue.exchange.starts_with("binancefutures") {
+        return Err(anyhow!(
+            "{}: funding_interval_secs only applies to Binance futures venues",
+            venue.name()
+        ));
+    }
+    if venue.status_interval().is_some() && !universe::lists_instruments(&venue.exchange) {
+        return Err(anyhow!(
+            "{}: status_interval_secs only applies to Binance, Bybit and Hyperliquid venues, \
+             not to Bybit options",
+            venue.name()
+        ));
+    }
+    let account = match &venue.account {
+        Some(_)
+            if !venue.exchange.starts_with("binance") && !venue.exchange.starts_with("bybit") =>
+        {
+            return Err(anyhow!(
+                "{}: account only applies to Binance and Bybit venues",
+                venue.name()
+            ));
         }
-        "hyperliquid" => {
-            let subscriptions = ["trades", "l2Book", "bbo"]
-                .iter()
-                .map(|sub| sub.to_string())
-                .collect();
+        Some(account) => Some(
+            account::Credentials::from_env(account)
+                .with_context(|| format!("{}: couldn't read the API key", venue.name()))?,
+        ),
+        None => None,
+    };
+    if venue.reanchor_books && venue.exchange != "hyperliquid" {
+        return Err(anyhow!(
+            "{}: reanchor_books only applies to Hyperliquid venues",
+            venue.name()
+        ));
+    }
+    if venue.subscribe && !venue.exchange.starts_with("binance") {
+        return Err(anyhow!(
+            "{}: subscribe only applies to Binance venues",
+            venue.name()
+        ));
+    }
 
-            tokio::spawn(hyperliquid::run_collection(
-                subscriptions,
-                args.symbols,
+    let name: Arc<str> = Arc::from(venue.name());
+    let handle = match venue.exchange.as_str() {
+        "binancefutures" | "binancefuturesum" => tokio::spawn(binancefuturesum::run_collection(
+            name,
+            streams,
+            symbols,
+            writer_tx,
+            shutdown_rx,
+            connections,
+            snapshot_interval.unwrap_or(binance_market::SNAPSHOT_INTERVAL),
+            funding_interval,
+            account,
+            venue.subscribe,
+        )),
+        "binancefuturescm" => tokio::spawn(binancefuturescm::run_collection(
+            name,
+            streams,
+            symbols,
+            writer_tx,
+            shutdown_rx,
+            connections,
+            snapshot_interval.unwrap_or(binance_market::SNAPSHOT_INTERVAL),
+            funding_interval,
+            account,
+            venue.subscribe,
+        )),
+        "binance" | "binancespot" => tokio::spawn(binance::run_collection(
+            name,
+            streams,
+            symbols,
+            writer_tx,
+            shutdown_rx,
+            connections,
+            snapshot_interval.unwrap_or(binance_market::SNAPSHOT_INTERVAL),
+            account,
+            venue.subscribe,
+        )),
+        "bybit" | "bybitlinear" | "bybitinverse" => {
+            let category = if venue.exchange == "bybitinverse" {
+                &bybit::INVERSE
+            } else {
+                &bybit::LINEAR
+            };
+
+            tokio::spawn(bybit::run_collection(
+                name,
+                category,
+                streams,
+                symbols,
                 writer_tx,
                 shutdown_rx,
                 connections,
+                account,
             ))
         }
+        "bybitspot" => tokio::spawn(bybit::run_collection(
+            name,
+            &bybit::SPOT,
+            streams,
+            symbols,
+            writer_tx,
+            shutdown_rx,
+            connections,
+            account,
+        )),
+        "bybitoption" => tokio::spawn(bybit::run_collection(
+            name,
+            &bybit::OPTION,
+            streams,
+            symbols,
+            writer_tx,
+            shutdown_rx,
+            connections,
+            account,
+        )),
+        "okx" => tokio::spawn(okx::run_collection(
+            name,
+            streams,
+            venue.symbols,
+            writer_tx,
+            shutdown_rx,
+            connections,
+        )),
+        "hyperliquid" => tokio::spawn(hyperliquid::run_collection(
+            name,
+            streams,
+            symbols,
+            writer_tx,
+            shutdown_rx,
+            connections,
+            venue.reanchor_books,
+            snapshot_interval.unwrap_or(hyperliquid::INFO_INTERVAL),
+        )),
         exchange => {
             return Err(anyhow!("{exchange} is not supported."));
         }
     };
+    Ok(handle)
+}
 
-    let (writer_done_tx, writer_done_rx) = oneshot::channel();
-    let writer_thread = {
-        let path = args.path.clone();
-        std::thread::spawn(move || -> Result<(), anyhow::Error> {
-            let mut writer = Writer::new(&path);
-            let result = loop {
-                match writer_rx.blocking_recv() {
-                    Some((recv_time, symbol, data)) => {
-                        if let Err(error) = writer.write(recv_time, symbol, data) {
-                            break Err(error);
-                        }
-                    }
-                    None => break Ok(()),
+/// A venue's collection task and the writer shards its records go to.
+struct Collection {
+    name: String,
+    handle: JoinHandle<Result<(), anyhow::Error>>,
+    writer_threads: Vec<std::thread::JoinHandle<Result<(), anyhow::Error>>>,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_writer(
+    name: &str,
+    shard: usize,
+    path: String,
+    options: FileOptions,
+    mut writer_rx: Receiver<file::WriteRecord>,
+    index: usize,
+    writer_done_tx: UnboundedSender<usize>,
+) -> std::thread::JoinHandle<Result<(), anyhow::Error>> {
+    let written = metrics::counter(
+        "collector_records_written_total",
+        "Records handed to the file writer.",
+        &[("venue", name)],
+    );
+    // Waiting with a timeout needs the runtime's timer; the thread itself
+    // stays off the runtime so a slow disk never blocks a worker.
+    let runtime = tokio::runtime::Handle::current();
+    std::thread::spawn(move || -> Result<(), anyhow::Error> {
+        let mut writer = Writer::new(&path, options);
+        let result = loop {
+            // A busy queue is drained without touching the runtime; only an
+            // empty one is worth a timer.
+            let received = match writer_rx.try_recv() {
+                Ok(record) => Ok(Some(record)),
+                Err(TryRecvError::Disconnected) => Ok(None),
+                Err(TryRecvError::Empty) => {
+                    runtime.block_on(tokio::time::timeout(file::SWEEP_INTERVAL, writer_rx.recv()))
                 }
             };
-            let result = result.and(writer.close());
-            let _ = writer_done_tx.send(());
-            result
+            match received {
+                Ok(Some(record)) => {
+                    if let Err(error) = writer.write(record) {
+                        break Err(error);
+                    }
+                    written.inc();
+                }
+                Ok(None) => break Ok(()),
+                // Nothing arrived; only the frames below need attention.
+                Err(_) => {}
+            }
+            if let Err(error) = writer.end_due_frames(Timestamp::now()) {
+                break Err(error);
+            }
+        };
+        // Close even after a failed write: the other files' rows are fine.
+        let result = result.and(writer.close());
+        let _ = writer_done_tx.send(index);
+        result.map_err(|error| error.context(format!("writer shard {shard}")))
+    })
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> Result<(), anyhow::Error> {
+    let mut args = Args::parse();
+    let metrics_addr = args.metrics_addr.take();
+    let spool_dir = args.spool_dir.take();
+    let spool_mib = args.spool_mib;
+    let config = args.config.clone();
+    let selected = args.venue.clone();
+    let venues = args.into_venues()?;
+
+    tracing_subscriber::fmt::init();
+
+    if let Some(dir) = spool_dir {
+        spool::enable(dir.clone(), spool_mib << 20)
+            .with_context(|| format!("couldn't create the spool directory {}", dir.display()))?;
+    }
+
+    if let Some(addr) = metrics_addr {
+        tokio::spawn(metrics::serve(metrics::bind(addr).await?));
+    }
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    // Every collection is spawned before any writer starts, so a venue that
+    // cannot be started fails the process before a single file is opened.
+    let mut pending = Vec::with_capacity(venues.len());
+    let mut running = Vec::with_capacity(venues.len());
+    for mut venue in venues {
+        if let Some(universe) = &venue.universe {
+            venue.symbols = universe::discover(&venue, universe)
+                .await
+                .with_context(|| format!("{}: couldn't discover the symbols", venue.name()))?;
+        }
+        let connections = usize::from(venue.connections);
+        if connections > 1 {
+            info!(
+                venue = venue.name(),
+                connections, "redundant collection enabled; duplicate messages will be discarded"
+            );
+        }
+        std::fs::create_dir_all(&venue.path)?;
+        let name = venue.name().to_owned();
+        let path = venue.path.clone();
+        let options = venue.file_options()?;
+        let mut shards = Vec::with_capacity(usize::from(venue.writer_shards));
+        let mut writer_rxs = Vec::with_capacity(shards.capacity());
+        for shard in 0..venue.writer_shards {
+            let (shard_tx, shard_rx) = channel::<file::WriteRecord>(WRITER_QUEUE_CAPACITY);
+            // Weak, so the gauge cannot keep the writer's queue open at shutdown.
+            let queue = shard_tx.downgrade();
+            metrics::sampled_gauge(
+                "collector_writer_queue_depth",
+                "Records waiting for the file writer.",
+                &[("venue", &name), ("shard", &shard.to_string())],
+                move || {
+                    queue
+                        .upgrade()
+                        .map_or(0, |tx| (tx.max_capacity() - tx.capacity()) as i64)
+                },
+            );
+            shards.push(shard_tx);
+            writer_rxs.push(shard_rx);
+        }
+        let writer_tx = RecordSender::new(shards);
+        let (symbols_tx, symbols_rx) = watch::channel(venue.symbols.clone());
+        // Opt-in: the watcher rewrites the venue's subscriptions unasked.
+        let watcher = venue.status_interval().map(|interval| {
+            lifecycle::watch_listings(
+                venue.exchange.clone(),
+                name.clone(),
+                symbols_tx.clone(),
+                writer_tx.clone(),
+                interval,
+                shutdown_rx.clone(),
+            )
+        });
+        let handle = spawn_collection(venue.clone(), symbols_rx, writer_tx, shutdown_rx.clone())?;
+        // Only once the venue is known to start; detached, as it ends with
+        // the shutdown signal and holds nothing that needs draining.
+        if let Some(watcher) = watcher {
+            tokio::spawn(watcher);
+        }
+        pending.push((name, path, options, handle, writer_rxs));
+        running.push(reload::Running {
+            venue,
+            symbols: symbols_tx,
+        });
+    }
+
+    // Writers per venue: a venue's slow disk or failed write must not stall
+    // the others, and `Writer` names files by symbol alone, so venues sharing
+    // one would collide on every common symbol.
+    let (writer_done_tx, mut writer_done_rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut collections: Vec<Collection> = pending
+        .into_iter()
+        .enumerate()
+        .map(|(index, (name, path, options, handle, writer_rxs))| {
+            let writer_threads = writer_rxs
+                .into_iter()
+                .enumerate()
+                .map(|(shard, writer_rx)| {
+                    spawn_writer(
+                        &name,
+                        shard,
+                        path.clone(),
+                        options.clone(),
+                        writer_rx,
+                        index,
+                        writer_done_tx.clone(),
+                    )
+                })
+                .collect();
+            Collection {
+                name,
+                handle,
+                writer_threads,
+            }
         })
-    };
+        .collect();
+    drop(writer_done_tx);
 
     enum Shutdown {
         Signal,
         Writer,
-        Collection(Result<(), anyhow::Error>),
+        Collection(usize, Result<(), anyhow::Error>),
     }
 
-    let shutdown = select! {
-        result = shutdown_signal() => {
-            let signal = result?;
-            info!(signal, "shutdown signal received");
-            Shutdown::Signal
-        }
-        _ = writer_done_rx => {
-            error!("writer stopped; shutting down collection");
-            Shutdown::Writer
-        }
-        result = &mut handle => {
-            Shutdown::Collection(match result {
-                Ok(result) => result,
-                Err(error) => Err(anyhow!("collection task failed: {error}")),
-            })
-        }
+    let mut hangups = reload::Hangups::new()?;
+    // Pinned outside the loop so a signal that arrives while a reload runs is
+    // still seen once it finishes.
+    let shutdown_signal = shutdown_signal();
+    tokio::pin!(shutdown_signal);
+    let shutdown = loop {
+        break select! {
+            result = &mut shutdown_signal => {
+                let signal = result?;
+                info!(signal, "shutdown signal received");
+                Shutdown::Signal
+            }
+            _ = hangups.recv() => {
+                info!("SIGHUP received; reloading the symbols");
+                reload::reload(config.as_deref(), &selected, &running).await;
+                continue;
+            }
+            Some(index) = writer_done_rx.recv() => {
+                error!(venue = %collections[index].name, "writer stopped; shutting down collection");
+                Shutdown::Writer
+            }
+            (result, index, _) = select_all(collections.iter_mut().map(|collection| &mut collection.handle)) => {
+                Shutdown::Collection(index, match result {
+                    Ok(result) => result,
+                    Err(error) => Err(anyhow!("collection task failed: {error}")),
+                })
+            }
+        };
     };
 
-    let collection_result = match shutdown {
-        Shutdown::Signal | Shutdown::Writer => {
-            // Ask the collection task to stop reading and flush what it already
-            // has, rather than aborting it with a full queue.
-            shutdown_tx.send_replace(true);
-            match tokio::time::timeout(DRAIN_TIMEOUT, &mut handle).await {
-                Ok(Ok(result)) => result,
-                Ok(Err(error)) => Err(anyhow!("collection task failed: {error}")),
-                Err(_) => {
-                    handle.abort();
-                    let _ = handle.await;
-                    // Whatever was still queued is gone. Exiting 0 here would
-                    // make an incomplete dump look like a clean shutdown.
-                    Err(anyhow!(
-                        "collection task did not finish draining within {DRAIN_TIMEOUT:?}; \
-                         queued records were discarded"
-                    ))
-                }
-            }
+    // Any one venue stopping stops them all: the process exits as a unit, so
+    // whatever supervises it restarts every venue together rather than leaving
+    // a partial deployment running unnoticed.
+    //
+    // Ask the collection tasks to stop reading and flush what they already
+    // have, rather than aborting them with full queues.
+    shutdown_tx.send_replace(true);
+    let deadline = tokio::time::Instant::now() + DRAIN_TIMEOUT;
+    let mut collection_results = Vec::with_capacity(collections.len());
+    let finished = match shutdown {
+        Shutdown::Signal | Shutdown::Writer => None,
+        // The collection that ended first is the likeliest root cause, so its
+        // result is reported ahead of the others.
+        Shutdown::Collection(index, result) => {
+            collection_results.push((index, result));
+            Some(index)
         }
-        Shutdown::Collection(result) => result,
     };
+    for (index, collection) in collections.iter_mut().enumerate() {
+        if Some(index) == finished {
+            continue;
+        }
+        let result = match tokio::time::timeout_at(deadline, &mut collection.handle).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(error)) => Err(anyhow!("collection task failed: {error}")),
+            Err(_) => {
+                collection.handle.abort();
+                let _ = (&mut collection.handle).await;
+                // Whatever was still queued is gone. Exiting 0 here would
+                // make an incomplete dump look like a clean shutdown.
+                Err(anyhow!(
+                    "collection task did not finish draining within {DRAIN_TIMEOUT:?}; \
+                     queued records were discarded"
+                ))
+            }
+        };
+        collection_results.push((index, result));
+    }
 
-    let writer_result = match writer_thread.join() {
-        Ok(result) => result,
-        Err(_) => Err(anyhow!("writer thread panicked")),
-    };
+    let mut collection_errors = Vec::new();
+    for (index, result) in collection_results {
+        if let Err(error) = result {
+            collection_errors.push(error.context(format!("venue {}", collections[index].name)));
+        }
+    }
+    // Every shard is joined and every failure kept: one shard's degraded
+    // rotation must fail the exit even when another shard failed louder.
+    let mut writer_errors = Vec::new();
+    for collection in collections {
+        for writer_thread in collection.writer_threads {
+            let result = match writer_thread.join() {
+                Ok(result) => result,
+                Err(_) => Err(anyhow!("writer thread panicked")),
+            };
+            if let Err(error) = result {
+                writer_errors.push(error.context(format!("venue {}", collection.name)));
+            }
+        }
+    }
 
     // The collection error is the root cause; a writer close failure is usually
     // a symptom of the same underlying problem, so it must not mask it.
-    match (collection_result, writer_result) {
-        (Err(collection), Err(writer)) => {
-            error!(%writer, "the writer also failed while shutting down");
-            Err(collection)
+    let mut errors = collection_errors.into_iter().chain(writer_errors);
+    match errors.next() {
+        Some(root) => {
+            for error in errors {
+                error!(
+                    error = format!("{error:#}"),
+                    "also failed while shutting down"
+                );
+            }
+            Err(root)
         }
-        (Err(error), Ok(())) | (Ok(()), Err(error)) => Err(error),
-        (Ok(()), Ok(())) => Ok(()),
+        None => Ok(()),
     }
 }
 
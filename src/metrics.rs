@@ -0,0 +1,379 @@
+//! Process-wide health counters, served in the Prometheus text format.
+//!
+//! Everything here used to exist only as `tracing` lines — a shed frame, a
+//! depth gap, a reconnect — which is enough to investigate an incident but not
+//! to page on one. The counters are plain atomics behind a registry that is
+//! only locked when a series is first created or the endpoint is scraped, so
+//! the hot paths (every frame through [`crate::ws::deliver`], every dedup
+//! lookup) pay one relaxed add.
+//!
+//! Handles are looked up by name and labels, and the same pair always returns
+//! the same series. That is what lets a per-session object such as
+//! [`crate::ws::Overflow`] be rebuilt on every reconnect without resetting the
+//! totals it reports into.
+
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fmt::Write as _,
+    net::SocketAddr,
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicI64, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::{Request, Response, StatusCode, body::Incoming, service::service_fn};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+use tracing::{debug, info, warn};
+
+/// A monotonically increasing count.
+#[derive(Clone)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    #[cfg(test)]
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value that can go up and down.
+#[derive(Clone)]
+pub struct Gauge(Arc<AtomicI64>);
+
+impl Gauge {
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+}
+
+enum Series {
+    Counter(Arc<AtomicU64>),
+    Gauge(Arc<AtomicI64>),
+    /// Read at scrape time, for values owned by something else — a queue's
+    /// depth is the channel's to know, and mirroring it on every send would put
+    /// a store on the hot path for a number read once every few seconds.
+    Sampled(Box<dyn Fn() -> i64 + Send + Sync>),
+}
+
+struct Family {
+    help: &'static str,
+    kind: &'static str,
+    /// Keyed by the rendered label set, so identical labels share a series and
+    /// the output is in a stable order.
+    series: BTreeMap<String, Series>,
+}
+
+static REGISTRY: LazyLock<Mutex<BTreeMap<&'static str, Family>>> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let mut out = String::from("{");
+    for (index, (name, value)) in labels.iter().enumerate() {
+        if index > 0 {
+            out.push(',');
+        }
+        out.push_str(name);
+        out.push_str("=\"");
+        for c in value.chars() {
+            match c {
+                '\\' => out.push_str("\\\\"),
+                '"' => out.push_str("\\\""),
+                '\n' => out.push_str("\\n"),
+                c => out.push(c),
+            }
+        }
+        out.push('"');
+    }
+    out.push('}');
+    out
+}
+
+fn with_family<T>(
+    name: &'static str,
+    help: &'static str,
+    kind: &'static str,
+    f: impl FnOnce(&mut Family) -> T,
+) -> T {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|poison| poison.into_inner());
+    let family = registry.entry(name).or_insert_with(|| Family {
+        help,
+        kind,
+        series: BTreeMap::new(),
+    });
+    debug_assert_eq!(family.kind, kind, "{name} registered with two types");
+    f(family)
+}
+
+pub fn counter(name: &'static str, help: &'static str, labels: &[(&str, &str)]) -> Counter {
+    with_family(name, help, "counter", |family| {
+        match family
+            .series
+            .entry(render_labels(labels))
+            .or_insert_with(|| Series::Counter(Arc::default()))
+        {
+            Series::Counter(value) => Counter(Arc::clone(value)),
+            // A programming error, not something to fail collection over: the
+            // handle still counts, it is just not exported.
+            _ => Counter(Arc::default()),
+        }
+    })
+}
+
+pub fn gauge(name: &'static str, help: &'static str, labels: &[(&str, &str)]) -> Gauge {
+    with_family(name, help, "gauge", |family| {
+        match family
+            .series
+            .entry(render_labels(labels))
+            .or_insert_with(|| Series::Gauge(Arc::default()))
+        {
+            Series::Gauge(value) => Gauge(Arc::clone(value)),
+            _ => Gauge(Arc::default()),
+        }
+    })
+}
+
+/// Export a gauge whose value is computed when scraped. Registering the same
+/// series again replaces the previous function.
+pub fn sampled_gauge(
+    name: &'static str,
+    help: &'static str,
+    labels: &[(&str, &str)],
+    sample: impl Fn() -> i64 + Send + Sync + 'static,
+) {
+    with_family(name, help, "gauge", |family| {
+        family
+            .series
+            .insert(render_labels(labels), Series::Sampled(Box::new(sample)));
+    });
+}
+
+/// Account for a websocket session that has ended, however it ended.
+///
+/// Reconnects are the count to alert on; the lifetime of the last session is
+/// what separates a venue recycling a healthy connection from one that drops
+/// every few seconds, exactly as it does in the logs. The series are kept per
+/// venue and per connection `group` as well as per connection: two venues on
+/// one exchange share an `endpoint`, and Binance numbers the connections of
+/// every group from zero, so without them one flapping connection would be
+/// indistinguishable from another. Connectors that do not spread their
+/// streams over groups have the one group, `0`.
+pub fn session_ended(
+    venue: &str,
+    endpoint: &str,
+    group: usize,
+    connection: usize,
+    lifetime: Duration,
+) {
+    let (group, connection) = (group.to_string(), connection.to_string());
+    let labels = [
+        ("venue", venue),
+        ("endpoint", endpoint),
+        ("group", group.as_str()),
+        ("connection", connection.as_str()),
+    ];
+    counter(
+        "collector_sessions_ended_total",
+        "Websocket sessions that ended and were replaced or reconnected.",
+        &labels,
+    )
+    .inc();
+    gauge(
+        "collector_last_session_seconds",
+        "Lifetime of the most recently ended websocket session.",
+        &labels,
+    )
+    .set(lifetime.as_secs() as i64);
+}
+
+/// Account for a connection attempt that never produced a session, labelled
+/// like [`session_ended`].
+pub fn handshake_failed(venue: &str, endpoint: &str, group: usize, connection: usize) {
+    counter(
+        "collector_handshake_failures_total",
+        "Websocket connection attempts that failed before a session started.",
+        &[
+            ("venue", venue),
+            ("endpoint", endpoint),
+            ("group", &group.to_string()),
+            ("connection", &connection.to_string()),
+        ],
+    )
+    .inc();
+}
+
+/// Every registered series, in the Prometheus text exposition format.
+pub fn render() -> String {
+    let registry = REGISTRY.lock().unwrap_or_else(|poison| poison.into_inner());
+    let mut out = String::new();
+    for (name, family) in registry.iter() {
+        let _ = writeln!(out, "# HELP {name} {}", family.help);
+        let _ = writeln!(out, "# TYPE {name} {}", family.kind);
+        for (labels, series) in &family.series {
+            let _ = match series {
+                Series::Counter(value) => {
+                    writeln!(out, "{name}{labels} {}", value.load(Ordering::Relaxed))
+                }
+                Series::Gauge(value) => {
+                    writeln!(out, "{name}{labels} {}", value.load(Ordering::Relaxed))
+                }
+                Series::Sampled(sample) => writeln!(out, "{name}{labels} {}", sample()),
+            };
+        }
+    }
+    out
+}
+
+fn respond(request: &Request<Incoming>) -> Response<Full<Bytes>> {
+    let response = Response::builder();
+    if request.uri().path() != "/metrics" {
+        return response
+            .status(StatusCode::NOT_FOUND)
+            .body(Full::new(Bytes::from_static(b"not found\n")))
+            .unwrap_or_default();
+    }
+    response
+        .header("Content-Type", "text/plain; version=0.0.4")
+        .body(Full::new(Bytes::from(render())))
+        .unwrap_or_default()
+}
+
+/// Bind the metrics listener. Separate from [`serve`] so a bad address fails
+/// startup instead of surfacing as a log line once collection is running.
+pub async fn bind(addr: SocketAddr) -> Result<TcpListener, anyhow::Error> {
+    let listener = TcpListener::bind(addr).await?;
+    info!(addr = %listener.local_addr()?, "serving metrics on /metrics");
+    Ok(listener)
+}
+
+/// Answer scrapes until the task is dropped.
+///
+/// Never returns an error: a broken scrape is the monitoring system's problem
+/// to notice, and must not take collection down with it.
+pub async fn serve(listener: TcpListener) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(error) => {
+                warn!(%error, "couldn't accept a metrics connection");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        tokio::spawn(async move {
+            let service = service_fn(|request: Request<Incoming>| async move {
+                Ok::<_, Infallible>(respond(&request))
+            });
+            if let Err(error) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                debug!(%peer, %error, "metrics connection ended with an error");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_same_labels_share_one_series() {
+        let a = counter("test_shared_total", "Test.", &[("endpoint", "x")]);
+        let b = counter("test_shared_total", "Test.", &[("endpoint", "x")]);
+        let other = counter("test_shared_total", "Test.", &[("endpoint", "y")]);
+        a.inc();
+        b.add(2);
+
+        assert_eq!(a.get(), 3);
+        assert_eq!(other.get(), 0);
+    }
+
+    /// Binance numbers every group's connections from zero, and venues on one
+    /// exchange share an endpoint; neither may fold into another's series.
+    #[test]
+    fn sessions_are_kept_apart_by_venue_and_group() {
+        session_ended("test-a", "binance-spot", 0, 0, Duration::from_secs(1));
+        session_ended("test-a", "binance-spot", 1, 0, Duration::from_secs(2));
+        session_ended("test-a", "binance-spot", 1, 0, Duration::from_secs(3));
+        handshake_failed("test-b", "binance-spot", 1, 0);
+        let ended = |venue, group| {
+            counter(
+                "collector_sessions_ended_total",
+                "Websocket sessions that ended and were replaced or reconnected.",
+                &[
+                    ("venue", venue),
+                    ("endpoint", "binance-spot"),
+                    ("group", group),
+                    ("connection", "0"),
+                ],
+            )
+            .get()
+        };
+
+        assert_eq!(ended("test-a", "0"), 1);
+        assert_eq!(ended("test-a", "1"), 2);
+        assert_eq!(ended("test-b", "1"), 0);
+        let text = render();
+        assert!(
+            text.contains(
+                "collector_handshake_failures_total{venue=\"test-b\",endpoint=\"binance-spot\",group=\"1\",connection=\"0\"} 1\n"
+            ),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn exposition_format_escapes_label_values() {
+        counter("test_render_total", "Rendered.", &[("path", "a\"b\\c")]).add(7);
+        sampled_gauge("test_render_depth", "Sampled.", &[], || 42);
+
+        let text = render();
+        assert!(
+            text.contains("# TYPE test_render_total counter\n"),
+            "{text}"
+        );
+        assert!(
+            text.contains("test_render_total{path=\"a\\\"b\\\\c\"} 7\n"),
+            "{text}"
+        );
+        assert!(text.contains("test_render_depth 42\n"), "{text}");
+    }
+
+    #[tokio::test]
+    async fn scrape_is_served_over_http() {
+        counter("test_served_total", "Served.", &[]).inc();
+        let listener = bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener));
+
+        let body = reqwest::get(format!("http://{addr}/metrics"))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let missing = reqwest::get(format!("http://{addr}/other")).await.unwrap();
+
+        assert!(body.contains("test_served_total 1\n"), "{body}");
+        assert_eq!(missing.status(), reqwest::StatusCode::NOT_FOUND);
+        server.abort();
+    }
+}
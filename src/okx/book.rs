@@ -0,0 +1,303 @@
+//! OKX's order book checksum, checked against a book kept from the stream.
+//!
+//! Every frame of an incremental book carries a `checksum`: the CRC-32 of the
+//! top 25 levels of the book *after* the frame is applied, bids and asks
+//! interleaved as `bidPx:bidSz:askPx:askSz:…`, the shorter side simply running
+//! out. `seqId` chaining proves that no frame went missing; only the checksum
+//! proves that the frames which did arrive add up to OKX's book. A frame that
+//! chains correctly but was corrupted, or a snapshot whose updates were
+//! misapplied, passes the sequence check and fails this one.
+//!
+//! So the consumer keeps each incremental book, per symbol and channel, and
+//! applies to it exactly the frames the sequence check accepts: a snapshot
+//! that is not behind, and updates that continue the last `seqId`. A book the
+//! sequence check has lost track of is dropped, and nothing is checked until
+//! the snapshot the repair brings.
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BTreeMap, HashMap},
+};
+
+use serde::Deserialize;
+
+use crate::{error::ConnectorError, symbol::Symbol};
+
+/// Levels per side the checksum covers.
+const CHECKSUM_DEPTH: usize = 25;
+
+/// The book fields of an order book frame's first `data` entry.
+#[derive(Deserialize)]
+struct BookFrame<'a> {
+    #[serde(borrow)]
+    data: Vec<BookData<'a>>,
+}
+
+/// Levels are `[price, size, "0", orders]`; only price and size count.
+#[derive(Deserialize)]
+struct BookData<'a> {
+    #[serde(borrow, default)]
+    asks: Vec<Vec<&'a str>>,
+    #[serde(borrow, default)]
+    bids: Vec<Vec<&'a str>>,
+    checksum: Option<i64>,
+}
+
+/// A price, ordered by value. OKX quotes a level's price as the same text in
+/// every frame, so the value identifies the level.
+#[derive(Clone, Copy, Debug)]
+struct Price(f64);
+
+impl PartialEq for Price {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Price {}
+
+impl PartialOrd for Price {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Price {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// A level as OKX wrote it; the checksum is over the text, not the values.
+#[derive(Debug)]
+struct Level {
+    price: String,
+    size: String,
+}
+
+/// One instrument's book on one channel.
+#[derive(Default, Debug)]
+struct Book {
+    bids: BTreeMap<Reverse<Price>, Level>,
+    asks: BTreeMap<Price, Level>,
+}
+
+impl Book {
+    /// Set, or with a size of zero remove, each level in `bids` and `asks`.
+    fn apply(&mut self, bids: &[Vec<&str>], asks: &[Vec<&str>]) -> Result<(), ConnectorError> {
+        for level in bids {
+            let (price, level) = parse_level(level)?;
+            match level {
+                Some(level) => self.bids.insert(Reverse(price), level),
+                None => self.bids.remove(&Reverse(price)),
+            };
+        }
+        for level in asks {
+            let (price, level) = parse_level(level)?;
+            match level {
+                Some(level) => self.asks.insert(price, level),
+                None => self.asks.remove(&price),
+            };
+        }
+        Ok(())
+    }
+
+    /// The text OKX's checksum is computed over.
+    fn checksum_text(&self) -> String {
+        let mut bids = self.bids.values().take(CHECKSUM_DEPTH);
+        let mut asks = self.asks.values().take(CHECKSUM_DEPTH);
+        let mut fields = Vec::with_capacity(CHECKSUM_DEPTH * 4);
+        loop {
+            let (bid, ask) = (bids.next(), asks.next());
+            if bid.is_none() && ask.is_none() {
+                break;
+            }
+            for level in [bid, ask].into_iter().flatten() {
+                fields.push(level.price.as_str());
+                fields.push(level.size.as_str());
+            }
+        }
+        fields.join(":")
+    }
+
+    /// OKX's checksum of this book: the CRC-32 of [`Self::checksum_text`], as
+    /// a signed integer.
+    fn checksum(&self) -> i64 {
+        i64::from(crc32fast::hash(self.checksum_text().as_bytes()) as i32)
+    }
+}
+
+/// A level's price, and the level itself unless its size is zero.
+fn parse_level(level: &[&str]) -> Result<(Price, Option<Level>), ConnectorError> {
+    let [price, size, ..] = level else {
+        return Err(ConnectorError::FormatError);
+    };
+    let value: f64 = price.parse().map_err(|_| ConnectorError::FormatError)?;
+    let amount: f64 = size.parse().map_err(|_| ConnectorError::FormatError)?;
+    let level = (amount != 0.0).then(|| Level {
+        price: (*price).to_owned(),
+        size: (*size).to_owned(),
+    });
+    Ok((Price(value), level))
+}
+
+/// What the sequence check made of a frame, and so what it does to the book.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Continuity {
+    /// A snapshot that is not behind: the book starts again from it.
+    Anchor,
+    /// An update continuing the last `seqId`.
+    Next,
+    /// A late snapshot or a straggling update, already superseded.
+    Stale,
+    /// The sequence lost track of the book: a gap or a restart.
+    Broken,
+}
+
+/// What checking a frame's checksum found.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Check {
+    Matched,
+    /// The book no longer adds up to OKX's, and has been dropped.
+    Mismatched {
+        expected: i64,
+        actual: i64,
+    },
+    /// No book to check against, or no checksum on the frame.
+    Unchecked,
+}
+
+/// The incremental books being checked, keyed like the `seqId`s.
+#[derive(Default)]
+pub struct Books(HashMap<(Symbol, &'static str), Book>);
+
+impl Books {
+    /// Apply `frame` to the book at `key` as `continuity` says, and check the
+    /// result against the frame's checksum.
+    pub fn apply(
+        &mut self,
+        key: (Symbol, &'static str),
+        continuity: Continuity,
+        frame: &[u8],
+    ) -> Result<Check, ConnectorError> {
+        let book = match continuity {
+            Continuity::Anchor => {
+                let book = self.0.entry(key.clone()).or_default();
+                *book = Book::default();
+                book
+            }
+            Continuity::Next => match self.0.get_mut(&key) {
+                Some(book) => book,
+                None => return Ok(Check::Unchecked),
+            },
+            Continuity::Stale => return Ok(Check::Unchecked),
+            Continuity::Broken => {
+                self.0.remove(&key);
+                return Ok(Check::Unchecked);
+            }
+        };
+        let frame: BookFrame<'_> = serde_json::from_slice(frame)?;
+        let Some(data) = frame.data.first() else {
+            return Err(ConnectorError::FormatError);
+        };
+        book.apply(&data.bids, &data.asks)?;
+        let Some(expected) = data.checksum else {
+            return Ok(Check::Unchecked);
+        };
+        let actual = book.checksum();
+        if actual == expected {
+            return Ok(Check::Matched);
+        }
+        self.0.remove(&key);
+        Ok(Check::Mismatched { expected, actual })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels<'a>(levels: &[[&'a str; 4]]) -> Vec<Vec<&'a str>> {
+        levels.iter().map(|level| level.to_vec()).collect()
+    }
+
+    /// The example in OKX's documentation: bids and asks interleaved, each
+    /// level as `price:size`.
+    #[test]
+    fn the_checksum_interleaves_the_sides() {
+        let mut book = Book::default();
+        book.apply(
+            &levels(&[["3366.1", "7", "0", "3"], ["3366", "6", "3", "4"]]),
+            &levels(&[["3366.8", "9", "10", "3"], ["3368", "8", "3", "4"]]),
+        )
+        .unwrap();
+
+        assert_eq!(book.checksum_text(), "3366.1:7:3366.8:9:3366:6:3368:8");
+        assert_eq!(
+            book.checksum(),
+            i64::from(crc32fast::hash(b"3366.1:7:3366.8:9:3366:6:3368:8") as i32)
+        );
+    }
+
+    /// Updates replace and remove levels by price, the longer side runs on
+    /// alone, and only the top 25 of each side count.
+    #[test]
+    fn updates_change_the_levels_they_name() {
+        let mut book = Book::default();
+        let bids: Vec<[String; 4]> = (0..30)
+            .map(|n| {
+                [
+                    format!("{}", 100 - n),
+                    "1".to_owned(),
+                    "0".to_owned(),
+                    "1".to_owned(),
+                ]
+            })
+            .collect();
+        let bids: Vec<Vec<&str>> = bids
+            .iter()
+            .map(|level| level.iter().map(String::as_str).collect())
+            .collect();
+        book.apply(&bids, &levels(&[["101", "2", "0", "1"]]))
+            .unwrap();
+        book.apply(
+            &levels(&[["100", "0", "0", "0"], ["99", "5", "0", "2"]]),
+            &levels(&[["102", "3", "0", "1"]]),
+        )
+        .unwrap();
+
+        let text = book.checksum_text();
+        assert!(
+            text.starts_with("99:5:101:2:98:1:102:3:97:1:96:1"),
+            "{text}"
+        );
+        assert_eq!(text.split(':').count(), (CHECKSUM_DEPTH + 2) * 2);
+        assert!(text.ends_with("75:1"), "{text}");
+    }
+
+    #[test]
+    fn a_mismatch_drops_the_book_until_the_next_snapshot() {
+        let key = (Symbol::from("btc-usdt"), "books");
+        let mut books = Books::default();
+        let snapshot = br#"{"arg":{"channel":"books","instId":"BTC-USDT"},"action":"snapshot","data":[{"asks":[],"bids":[],"ts":"1","checksum":0,"prevSeqId":-1,"seqId":10}]}"#;
+        let corrupt = br#"{"arg":{"channel":"books","instId":"BTC-USDT"},"action":"update","data":[{"asks":[["3366.8","9","0","3"]],"bids":[],"ts":"2","checksum":12345,"prevSeqId":10,"seqId":11}]}"#;
+
+        assert_eq!(
+            books
+                .apply(key.clone(), Continuity::Anchor, snapshot)
+                .unwrap(),
+            Check::Matched
+        );
+        assert!(matches!(
+            books.apply(key.clone(), Continuity::Next, corrupt).unwrap(),
+            Check::Mismatched {
+                expected: 12345,
+                ..
+            }
+        ));
+        assert_eq!(
+            books.apply(key, Continuity::Next, corrupt).unwrap(),
+            Check::Unchecked
+        );
+    }
+}
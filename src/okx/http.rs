@@ -0,0 +1,560 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    io,
+    io::ErrorKind,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Error;
+use fastwebsockets::OpCode;
+use jiff::Timestamp;
+use tokio::{
+    select,
+    sync::mpsc::{Sender, UnboundedReceiver},
+    time::timeout,
+};
+use tracing::{error, warn};
+
+use crate::{
+    metrics,
+    spool::Spool,
+    ws::{self, Delivery, FrameSender, Overflow, Priority},
+};
+
+/// OKX drops a connection that has carried nothing for 30 s, and answers the
+/// literal text `ping` with `pong`.
+const PING_INTERVAL: Duration = Duration::from_secs(20);
+/// Every ping is answered, so two ping periods of silence means the socket is
+/// dead.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(45);
+/// OKX allows three subscribe requests per second per connection; one request
+/// carries every channel of one symbol.
+const SUBSCRIBE_PACE: Duration = Duration::from_millis(400);
+/// A group that keeps getting rejected is almost always permanently invalid
+/// (delisted or unsupported instrument); retrying it forever just burns the
+/// hourly subscription budget.
+const MAX_SUBSCRIBE_ATTEMPTS: u32 = 5;
+const RETRY_SWEEP_INTERVAL: Duration = Duration::from_millis(250);
+/// How often to restate which groups are missing, so an incomplete feed stays
+/// visible instead of scrolling away after the last retry.
+const DEGRADED_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+/// Shortest spacing between two repairs of the same book on one connection.
+///
+/// Each repair spends two of the 480 subscription operations OKX allows a
+/// connection per hour. A burst of gaps — the consumer falling behind and
+/// shedding depth — must not spend that budget on one book over and over.
+const RESUBSCRIBE_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// One subscribe request: every channel of one symbol, under a request id.
+///
+/// OKX only accepts alphanumeric ids, so the instrument name (which contains
+/// `-`) cannot double as the id the way Bybit's symbol does.
+pub struct SubscriptionRequest {
+    pub id: String,
+    pub args: Vec<serde_json::Value>,
+}
+
+/// A frame with the connection it arrived on.
+///
+/// Rejections and depth repairs are answered by resubscribing, and only the
+/// connection concerned may be resubscribed — redundant connections each carry
+/// their own subscription state.
+pub type Frame = (usize, Timestamp, bytes::Bytes);
+
+/// Work for a connection's control loop, raised by the consumer.
+#[derive(Debug, PartialEq)]
+pub enum Control {
+    /// A subscribe request was rejected; send it again after a backoff.
+    Retry(String),
+    /// A book lost continuity. Unsubscribing and subscribing again makes OKX
+    /// send a fresh snapshot, which re-anchors the recording.
+    Resubscribe(serde_json::Value),
+}
+
+async fn send_op(
+    sender: &FrameSender,
+    op: &str,
+    id: &str,
+    args: &[serde_json::Value],
+) -> Result<(), anyhow::Error> {
+    let message = serde_json::to_vec(&serde_json::json!({
+        "id": id,
+        "op": op,
+        "args": args,
+    }))?;
+    sender.text(message).await
+}
+
+/// Everything that writes — the initial subscriptions, pings, retries and book
+/// repairs — lives here rather than alongside the read loop, for the same
+/// reasons as Bybit's: `Connection::read` is not cancel-safe, and subscribing
+/// inline would leave the socket unread for the whole paced burst.
+///
+/// Returns only when the socket can no longer be written to; the caller treats
+/// that as fatal for the connection.
+async fn control_loop(
+    sender: FrameSender,
+    control_rx: &mut UnboundedReceiver<Control>,
+    mut request_map: HashMap<String, Vec<serde_json::Value>>,
+    order: Vec<String>,
+) {
+    let mut ping_interval = tokio::time::interval(PING_INTERVAL);
+    ping_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut pacer = tokio::time::interval(SUBSCRIBE_PACE);
+    pacer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut sweep = tokio::time::interval(RETRY_SWEEP_INTERVAL);
+    sweep.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut degraded_report = tokio::time::interval(DEGRADED_REPORT_INTERVAL);
+    degraded_report.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    let mut to_send = order.into_iter();
+    let mut attempts: HashMap<String, u32> = HashMap::new();
+    let mut pending: Vec<(String, Instant)> = Vec::new();
+    let mut abandoned: BTreeSet<String> = BTreeSet::new();
+    let mut repaired: HashMap<String, Instant> = HashMap::new();
+    let mut next_repair = 0_u64;
+
+    loop {
+        select! {
+            _ = ping_interval.tick() => {
+                if sender.text(b"ping".to_vec()).await.is_err() {
+                    return;
+                }
+            }
+            _ = pacer.tick() => {
+                let Some(id) = to_send.next() else {
+                    continue;
+                };
+                if send_op(&sender, "subscribe", &id, &request_map[&id]).await.is_err() {
+                    return;
+                }
+            }
+            Some(control) = control_rx.recv() => match control {
+                Control::Retry(id) => {
+                    if !request_map.contains_key(&id) {
+                        warn!(%id, "cannot retry unknown subscription group");
+                        continue;
+                    }
+                    let attempt = attempts.entry(id.clone()).or_insert(0);
+                    *attempt += 1;
+                    if *attempt > MAX_SUBSCRIBE_ATTEMPTS {
+                        error!(
+                            %id,
+                            attempts = *attempt - 1,
+                            "subscription group rejected repeatedly; giving up until the next reconnect"
+                        );
+                        abandoned.insert(id);
+                        continue;
+                    }
+                    if pending.iter().any(|(pending_id, _)| pending_id == &id) {
+                        continue;
+                    }
+                    // 1s, 2s, 4s, 8s, 16s.
+                    let delay = Duration::from_secs(1 << (*attempt - 1));
+                    pending.push((id, Instant::now() + delay));
+                }
+                Control::Resubscribe(arg) => {
+                    let key = arg.to_string();
+                    let now = Instant::now();
+                    if repaired
+                        .get(&key)
+                        .is_some_and(|last| now.duration_since(*last) < RESUBSCRIBE_COOLDOWN)
+                    {
+                        continue;
+                    }
+                    repaired.insert(key, now);
+                    // Registered like any other group, so a rejected repair is
+                    // retried through the same path as a rejected subscription.
+                    next_repair += 1;
+                    let id = format!("r{next_repair}");
+                    let args = vec![arg];
+                    if send_op(&sender, "unsubscribe", &id, &args).await.is_err()
+                        || send_op(&sender, "subscribe", &id, &args).await.is_err()
+                    {
+                        return;
+                    }
+                    request_map.insert(id, args);
+                }
+            },
+            _ = sweep.tick(), if !pending.is_empty() => {
+                let now = Instant::now();
+                let mut index = 0;
+                while index < pending.len() {
+                    if pending[index].1 > now {
+                        index += 1;
+                        continue;
+                    }
+                    let (id, _) = pending.remove(index);
+                    warn!(%id, "retrying rejected subscription group");
+                    if send_op(&sender, "subscribe", &id, &request_map[&id]).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            _ = degraded_report.tick(), if !abandoned.is_empty() => {
+                // The connection is otherwise healthy, so nothing else would
+                // ever reveal that these channels are not being collected.
+                error!(
+                    groups = ?abandoned,
+                    count = abandoned.len(),
+                    "feed is incomplete: these subscription groups are not subscribed"
+                );
+            }
+        }
+    }
+}
+
+/// Read one websocket session to its end, delivering frames into `ws_tx`.
+///
+/// Generic over the transport so the subscribe-and-deliver path can be run
+/// against an in-memory peer in tests.
+async fn run_session<S>(
+    mut conn: ws::Connection<S>,
+    requests: Vec<SubscriptionRequest>,
+    connection: usize,
+    ws_tx: Sender<Frame>,
+    control_rx: &mut UnboundedReceiver<Control>,
+    spool: Option<Arc<Spool>>,
+) -> Result<(), anyhow::Error>
+where
+    S: tokio::io::AsyncRead + Unpin,
+{
+    let sender = conn.sender();
+    let mut overflow = Overflow::new("okx").with_spool(spool);
+
+    let order: Vec<String> = requests.iter().map(|request| request.id.clone()).collect();
+    let request_map: HashMap<String, Vec<serde_json::Value>> = requests
+        .into_iter()
+        .map(|request| (request.id, request.args))
+        .collect();
+
+    // Rejections and repairs raised against the *previous* session are still
+    // queued. This session subscribes everything afresh, so they are moot.
+    while control_rx.try_recv().is_ok() {}
+
+    let control = control_loop(sender.clone(), control_rx, request_map, order);
+    tokio::pin!(control);
+
+    loop {
+        // `read` is not cancel-safe, so every arm racing it must be terminal.
+        let message = select! {
+            biased;
+            _ = &mut control => {
+                return Err(anyhow::anyhow!("websocket writer stopped"));
+            }
+            result = timeout(IDLE_TIMEOUT, conn.read()) => match result {
+                Ok(message) => message?,
+                Err(_) => {
+                    warn!(connection, ?IDLE_TIMEOUT, "no websocket frame received; reconnecting");
+                    return Err(Error::from(io::Error::new(ErrorKind::TimedOut, "idle")));
+                }
+            },
+        };
+
+        match message.opcode {
+            OpCode::Text => {
+                // The reply to our own keepalive proves the socket and nothing
+                // else; it is not JSON and has no place in the recording.
+                if &message.payload[..] == b"pong" {
+                    continue;
+                }
+                let recv_time = Timestamp::now();
+                let delivery = ws::deliver(
+                    &ws_tx,
+                    &mut overflow,
+                    (connection, recv_time, message.payload),
+                    // Only frames carrying `data` are market data. Shedding an
+                    // ack or a rejection would leave that group unsubscribed
+                    // with nothing left to trigger a retry.
+                    |(_, _, payload)| {
+                        if ws::payload_contains(payload, br#""data""#) {
+                            Priority::of(super::stream_of(
+                                ws::string_field(payload, "channel"),
+                                ws::string_field(payload, "action"),
+                            ))
+                        } else {
+                            Priority::Control
+                        }
+                    },
+                )
+                .await;
+                match delivery {
+                    Delivery::Sent | Delivery::Spooled | Delivery::Dropped => {}
+                    // Receiver dropped: the collector is shutting down.
+                    Delivery::Closed => return Ok(()),
+                    Delivery::Undeliverable => {
+                        return Err(anyhow::anyhow!(
+                            "a subscription response could not be delivered; reconnecting"
+                        ));
+                    }
+                }
+            }
+            OpCode::Ping => {
+                sender.pong(message.payload.to_vec()).await?;
+            }
+            OpCode::Close => {
+                warn!(connection, "connection closed by server");
+                conn.flush_close().await;
+                return Err(Error::from(io::Error::new(
+                    ErrorKind::ConnectionAborted,
+                    "closed",
+                )));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The OKX instrument type of an instrument id, as `liquidation-orders` wants
+/// it: `BTC-USDT-SWAP`, `BTC-USD-250328`, `BTC-USD-250328-90000-C`, and plain
+/// spot pairs, whose liquidations are margin liquidations.
+pub fn instrument_type(inst_id: &str) -> &'static str {
+    let parts: Vec<&str> = inst_id.split('-').collect();
+    match parts.as_slice() {
+        [.., "SWAP"] => "SWAP",
+        [_, _, _, _, "C" | "P"] => "OPTION",
+        [_, _, expiry] if expiry.bytes().all(|byte| byte.is_ascii_digit()) => "FUTURES",
+        _ => "MARGIN",
+    }
+}
+
+/// One request per symbol with every per-instrument channel, plus one request
+/// per instrument type for `liquidation-orders`, which OKX only offers for a
+/// whole instrument type at once.
+pub fn subscription_requests(channels: &[String], symbols: &[String]) -> Vec<SubscriptionRequest> {
+    let (by_type, by_instrument): (Vec<&String>, Vec<&String>) = channels
+        .iter()
+        .partition(|channel| channel.as_str() == "liquidation-orders");
+
+    let mut requests: Vec<SubscriptionRequest> = symbols
+        .iter()
+        .enumerate()
+        .filter(|_| !by_instrument.is_empty())
+        .map(|(index, symbol)| {
+            let inst_id = symbol.to_uppercase();
+            SubscriptionRequest {
+                id: format!("s{index}"),
+                args: by_instrument
+                    .iter()
+                    .map(|channel| serde_json::json!({ "channel": channel, "instId": inst_id }))
+                    .collect(),
+            }
+        })
+        .collect();
+
+    let inst_types: BTreeSet<&'static str> = symbols
+        .iter()
+        .map(|symbol| instrument_type(&symbol.to_uppercase()))
+        .collect();
+    for channel in by_type {
+        for inst_type in &inst_types {
+            requests.push(SubscriptionRequest {
+                id: format!("t{}", inst_type.to_lowercase()),
+                args: vec![serde_json::json!({ "channel": channel, "instType": inst_type })],
+            });
+        }
+    }
+    requests
+}
+
+pub async fn keep_connection(
+    venue: Arc<str>,
+    channels: Vec<String>,
+    symbol_list: Vec<String>,
+    connection: usize,
+    ws_tx: Sender<Frame>,
+    spool: Option<Arc<Spool>>,
+    mut control_rx: UnboundedReceiver<Control>,
+) {
+    let mut error_count = 0;
+    loop {
+        let connect_time = Instant::now();
+        let result = match ws::connect("wss://ws.okx.com:8443/ws/v5/public").await {
+            Ok(conn) => {
+                run_session(
+                    conn,
+                    subscription_requests(&channels, &symbol_list),
+                    connection,
+                    ws_tx.clone(),
+                    &mut control_rx,
+                    spool.clone(),
+                )
+                .await
+            }
+            Err(error) => {
+                metrics::handshake_failed(&venue, "okx", 0, connection);
+                Err(error)
+            }
+        };
+        if let Err(error) = result {
+            let lifetime = connect_time.elapsed();
+            metrics::session_ended(&venue, "okx", 0, connection, lifetime);
+            error!(connection, ?error, ?lifetime, "websocket error");
+            error_count += 1;
+            if lifetime > Duration::from_secs(30) {
+                error_count = 0;
+            }
+            if error_count > 20 {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            } else if error_count > 10 {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            } else if error_count > 3 {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        } else {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instrument_types_follow_the_id_shape() {
+        assert_eq!(instrument_type("BTC-USDT-SWAP"), "SWAP");
+        assert_eq!(instrument_type("BTC-USD-250328"), "FUTURES");
+        assert_eq!(instrument_type("BTC-USD-250328-90000-C"), "OPTION");
+        assert_eq!(instrument_type("BTC-USDT"), "MARGIN");
+    }
+
+    #[test]
+    fn liquidations_are_requested_once_per_instrument_type() {
+        let channels = ["trades", "books", "liquidation-orders"].map(String::from);
+        let symbols = ["btc-usdt-swap", "eth-usdt-swap", "btc-usdt"].map(String::from);
+
+        let requests = subscription_requests(&channels, &symbols);
+
+        assert_eq!(requests.len(), 5, "three symbols plus two instrument types");
+        assert_eq!(
+            requests[0].args,
+            vec![
+                serde_json::json!({"channel": "trades", "instId": "BTC-USDT-SWAP"}),
+                serde_json::json!({"channel": "books", "instId": "BTC-USDT-SWAP"}),
+            ]
+        );
+        // Ids must be alphanumeric, which the instrument names are not.
+        assert!(
+            requests
+                .iter()
+                .all(|request| request.id.bytes().all(|byte| byte.is_ascii_alphanumeric()))
+        );
+        assert!(requests.iter().any(|request| request.args
+            == vec![serde_json::json!({"channel": "liquidation-orders", "instType": "SWAP"})]));
+    }
+
+    /// End to end against a stand-in venue: the session subscribes, answers
+    /// nothing but market data into the queue, and keeps the keepalive reply
+    /// out of the recording.
+    #[tokio::test]
+    async fn a_session_subscribes_and_delivers_market_data() {
+        use fastwebsockets::{Frame as WsFrame, Payload};
+
+        let (conn, mut server) = ws::duplex_pair();
+        let (ws_tx, mut ws_rx) = tokio::sync::mpsc::channel(8);
+        let (_control_tx, mut control_rx) = tokio::sync::mpsc::unbounded_channel();
+        let requests = subscription_requests(&["trades".to_owned()], &["btc-usdt".to_owned()]);
+
+        let session = tokio::spawn(async move {
+            let _ = run_session(conn, requests, 3, ws_tx, &mut control_rx, None).await;
+        });
+
+        // The first frame from the client is its keepalive or its subscribe,
+        // depending on which timer fires first; find the subscribe.
+        let subscribe = loop {
+            let frame = server.read_frame().await.unwrap();
+            if &frame.payload[..] != b"ping" {
+                break serde_json::from_slice::<serde_json::Value>(&frame.payload).unwrap();
+            }
+        };
+        assert_eq!(subscribe["op"], "subscribe");
+        assert_eq!(subscribe["args"][0]["instId"], "BTC-USDT");
+
+        let trade = br#"{"arg":{"channel":"trades","instId":"BTC-USDT"},"data":[{"instId":"BTC-USDT","tradeId":"1","px":"1","sz":"1","side":"buy","ts":"1"}]}"#;
+        for payload in [b"pong".as_slice(), trade.as_slice()] {
+            server
+                .write_frame(WsFrame::text(Payload::Owned(payload.to_vec())))
+                .await
+                .unwrap();
+        }
+
+        let (connection, _, data) = ws_rx.recv().await.unwrap();
+        assert_eq!(connection, 3);
+        assert_eq!(&data[..], trade.as_slice());
+        session.abort();
+    }
+
+    /// A book frame whose checksum disagrees with the book built so far is
+    /// answered like a sequence gap: the connection that delivered it
+    /// unsubscribes and subscribes that one book again.
+    #[tokio::test]
+    async fn a_bad_checksum_resubscribes_the_book() {
+        use fastwebsockets::{Frame as WsFrame, Payload};
+
+        let (conn, mut server) = ws::duplex_pair();
+        let (ws_tx, mut ws_rx) = tokio::sync::mpsc::channel(8);
+        let (control_tx, mut control_rx) = tokio::sync::mpsc::unbounded_channel();
+        let requests = subscription_requests(&["books".to_owned()], &["btc-usdt".to_owned()]);
+        let session = tokio::spawn(async move {
+            let _ = run_session(conn, requests, 0, ws_tx, &mut control_rx, None).await;
+        });
+        let subscribe = loop {
+            let frame = server.read_frame().await.unwrap();
+            if &frame.payload[..] != b"ping" {
+                break serde_json::from_slice::<serde_json::Value>(&frame.payload).unwrap();
+            }
+        };
+        assert_eq!(subscribe["op"], "subscribe");
+
+        let snapshot = br#"{"arg":{"channel":"books","instId":"BTC-USDT"},"action":"snapshot","data":[{"asks":[["3366.8","9","0","3"]],"bids":[["3366.1","7","0","3"]],"ts":"1","checksum":-1,"prevSeqId":-1,"seqId":10}]}"#;
+        let corrupt = br#"{"arg":{"channel":"books","instId":"BTC-USDT"},"action":"update","data":[{"asks":[["3366.8","8","0","3"]],"bids":[],"ts":"2","checksum":-1,"prevSeqId":10,"seqId":11}]}"#;
+        // The snapshot's checksum is made to match, so only the update fails.
+        let checksum = crc32fast::hash(b"3366.1:7:3366.8:9") as i32;
+        let snapshot = String::from_utf8_lossy(snapshot)
+            .replace(r#""checksum":-1"#, &format!(r#""checksum":{checksum}"#));
+        for payload in [snapshot.as_bytes(), corrupt.as_slice()] {
+            server
+                .write_frame(WsFrame::text(Payload::Owned(payload.to_vec())))
+                .await
+                .unwrap();
+        }
+
+        let (writer_tx, _writer_rx) = crate::file::record_channel(4);
+        let mut symbols = crate::symbol::SymbolCache::new(&["btc-usdt".to_owned()]);
+        let requested = std::collections::HashSet::from(["BTC-USDT".to_owned()]);
+        let mut seq_ids = crate::okx::SeqIds::new();
+        let mut books = crate::okx::Books::default();
+        for _ in 0..2 {
+            let (connection, recv_time, data) = ws_rx.recv().await.unwrap();
+            crate::okx::handle(
+                &writer_tx,
+                &mut symbols,
+                &requested,
+                &mut seq_ids,
+                &mut books,
+                &mut crate::dedup::Dedup::disabled(),
+                &control_tx,
+                connection,
+                recv_time,
+                data,
+            )
+            .await
+            .unwrap();
+        }
+
+        let book = serde_json::json!([{ "channel": "books", "instId": "BTC-USDT" }]);
+        let mut requests = Vec::new();
+        while requests.len() < 2 {
+            let frame = server.read_frame().await.unwrap();
+            if &frame.payload[..] != b"ping" {
+                requests.push(serde_json::from_slice::<serde_json::Value>(&frame.payload).unwrap());
+            }
+        }
+        assert_eq!(requests[0]["op"], "unsubscribe");
+        assert_eq!(requests[1]["op"], "subscribe");
+        assert!(requests.iter().all(|request| request["args"] == book));
+        session.abort();
+    }
+}
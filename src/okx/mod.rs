@@ -0,0 +1,452 @@
+mod book;
+mod http;
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use book::{Books, Check, Continuity};
+pub use http::keep_connection;
+use http::{Control, Frame};
+use jiff::Timestamp;
+use tokio::{
+    sync::{
+        mpsc::{UnboundedSender, channel},
+        watch,
+    },
+    task::JoinSet,
+};
+
+use tracing::{error, info, warn};
+
+use collector::record::Stream;
+
+use crate::{
+    dedup::Dedup,
+    error::ConnectorError,
+    feed::Feed,
+    file::{RecordSender, WriteRecord},
+    metrics,
+    routing::OkxMessage,
+    spool::Spool,
+    symbol::{Symbol, SymbolCache},
+};
+
+/// How far a book's `seqId` may fall below the last one seen before the book is
+/// treated as restarted rather than merely reordered.
+///
+/// OKX resets sequence ids during maintenance, and a reset lands far below the
+/// high-water mark. Redundant connections reorder by at most the dedup window's
+/// worth of updates. Anything between is read as a reorder.
+const RESYNC_BACKSTEP: i64 = 1_000_000;
+
+/// The order book channels whose frames are chained by `prevSeqId`.
+///
+/// `books5` and `bbo-tbt` push a complete book every time, so there is nothing
+/// to keep continuous; they are recorded but not tracked.
+///
+/// These are also the books whose `checksum` is verified against a book kept
+/// from the stream; see [`book`].
+fn incremental_book(channel: &str) -> Option<&'static str> {
+    match channel {
+        "books" => Some("books"),
+        "books-l2-tbt" => Some("books-l2-tbt"),
+        "books50-l2-tbt" => Some("books50-l2-tbt"),
+        _ => None,
+    }
+}
+
+/// The record stream tag for a frame on `channel`.
+///
+/// Incremental books open with a `snapshot` action and continue with
+/// `update`s; the full-book channels (`books5`, `bbo-tbt`) are tagged by what
+/// they carry rather than how they are pushed.
+fn stream_of(channel: Option<&str>, action: Option<&str>) -> Stream {
+    match channel {
+        Some(channel) if incremental_book(channel).is_some() => {
+            if action == Some("snapshot") {
+                Stream::DepthSnapshot
+            } else {
+                Stream::Depth
+            }
+        }
+        Some("books5") => Stream::DepthSnapshot,
+        Some("bbo-tbt") => Stream::Bbo,
+        Some("trades" | "trades-all") => Stream::Trade,
+        Some("liquidation-orders") => Stream::Liquidation,
+        _ => Stream::Other,
+    }
+}
+
+/// The last `seqId` seen per book. Keyed by channel as well as symbol because
+/// `books` and `books-l2-tbt` on one instrument are separate sequences.
+type SeqIds = HashMap<(Symbol, &'static str), i64>;
+
+#[allow(clippy::too_many_arguments)]
+async fn handle(
+    writer_tx: &RecordSender,
+    symbols: &mut SymbolCache,
+    requested: &HashSet<String>,
+    seq_ids: &mut SeqIds,
+    books: &mut Books,
+    dedup: &mut Dedup,
+    control_tx: &UnboundedSender<Control>,
+    connection: usize,
+    recv_time: Timestamp,
+    data: bytes::Bytes,
+) -> Result<(), ConnectorError> {
+    // Only frames with `data` are market data. Acks and rejections are
+    // per-connection state and must not be collapsed. Deduplicating before
+    // sequence tracking also matters: a second copy of an update carries the
+    // `prevSeqId` the first copy already advanced past.
+    if dedup.is_enabled()
+        && crate::ws::payload_contains(&data, br#""data""#)
+        && dedup.is_duplicate(&data)
+    {
+        return Ok(());
+    }
+
+    let message: OkxMessage<'_> = serde_json::from_slice(&data)?;
+    if let Some(event) = message.event {
+        match event {
+            "subscribe" => {
+                info!(connection, channel = ?message.channel(), "subscription succeeded")
+            }
+            "unsubscribe" => {}
+            "error" => {
+                let reason = message.msg.unwrap_or("unknown reason");
+                let code = message.code.unwrap_or_default();
+                if let Some(id) = message.id.filter(|id| !id.is_empty()) {
+                    error!(connection, reason, code, %id, "subscription rejected; scheduling retry");
+                    if control_tx.send(Control::Retry(id.to_owned())).is_err() {
+                        return Err(ConnectorError::ConnectionGone);
+                    }
+                } else {
+                    error!(connection, reason, code, "OKX reported an error");
+                }
+            }
+            // Service upgrades are announced with `notice` ahead of the
+            // disconnect; the reconnect loop handles the disconnect itself.
+            _ => warn!(
+                connection,
+                payload = %String::from_utf8_lossy(&data),
+                "OKX event"
+            ),
+        }
+        return Ok(());
+    }
+
+    let Some(symbol_raw) = message.symbol() else {
+        return Ok(());
+    };
+    // `liquidation-orders` is subscribed per instrument type and so delivers
+    // every instrument of that type. Only the requested ones are recorded.
+    if !requested.contains(symbol_raw) {
+        return Ok(());
+    }
+    let symbol = symbols.resolve(symbol_raw);
+
+    if let Some(book) = message.channel().and_then(incremental_book)
+        && let Some(entry) = message.entry()
+    {
+        let seq_id = entry.seq_id.ok_or(ConnectorError::FormatError)?;
+        let key = (Symbol::clone(&symbol), book);
+        let continuity = if message.action == Some("snapshot") {
+            // A snapshot is a complete book and needs no predecessor. Still
+            // forward-only: a repair snapshot on one connection can trail what
+            // another connection has already delivered.
+            let anchor = seq_ids.entry(key.clone()).or_insert(seq_id);
+            if seq_id < anchor.saturating_sub(RESYNC_BACKSTEP) {
+                *anchor = seq_id;
+                Continuity::Anchor
+            } else if seq_id < *anchor {
+                Continuity::Stale
+            } else {
+                *anchor = seq_id;
+                Continuity::Anchor
+            }
+        } else {
+            let prev_seq_id = entry.prev_seq_id.ok_or(ConnectorError::FormatError)?;
+            match seq_ids.get_mut(&key) {
+                Some(last) if seq_id < last.saturating_sub(RESYNC_BACKSTEP) => {
+                    warn!(
+                        symbol = %symbol,
+                        book,
+                        last = *last,
+                        seq_id,
+                        "book sequence restarted well below the last seen; resyncing"
+                    );
+                    *last = seq_id;
+                    Continuity::Broken
+                }
+                Some(last) => {
+                    // Only an update that begins beyond what we already hold
+                    // leaves a hole; see the Binance consumer for why stragglers
+                    // from a lagging connection are not gaps.
+                    let continuity = if prev_seq_id > *last {
+                        warn!(
+                            symbol = %symbol,
+                            book,
+                            last = *last,
+                            prev_seq_id,
+                            "missing depth feed has been detected; resubscribing"
+                        );
+                        metrics::counter(
+                            "collector_depth_gaps_total",
+                            "Depth update sequence gaps detected while collecting.",
+                            &[("endpoint", "okx")],
+                        )
+                        .inc();
+                        resubscribe(control_tx, book, symbol_raw)?;
+                        Continuity::Broken
+                    } else if prev_seq_id == *last {
+                        Continuity::Next
+                    } else {
+                        Continuity::Stale
+                    };
+                    *last = seq_id.max(*last);
+                    continuity
+                }
+                // An update before any snapshot: the subscribe reply is always
+                // a snapshot, so this is only seen when the snapshot itself was
+                // shed. Anchor here; the next gap check works from this point,
+                // but there is no book to check until a snapshot arrives.
+                None => {
+                    seq_ids.insert(key.clone(), seq_id);
+                    Continuity::Broken
+                }
+            }
+        };
+        if let Check::Mismatched { expected, actual } = books.apply(key, continuity, &data)? {
+            warn!(
+                symbol = %symbol,
+                book,
+                expected,
+                actual,
+                "book checksum mismatch; resubscribing"
+            );
+            metrics::counter(
+                "collector_depth_checksum_mismatches_total",
+                "Order books whose checksum disagreed with the book built from the stream.",
+                &[("endpoint", "okx")],
+            )
+            .inc();
+            resubscribe(control_tx, book, symbol_raw)?;
+        }
+    }
+
+    let stream = stream_of(message.channel(), message.action);
+    writer_tx
+        .send(WriteRecord::received(
+            recv_time, symbol, data, connection, stream,
+        ))
+        .await
+        .map_err(|_| ConnectorError::WriterClosed)?;
+    Ok(())
+}
+
+/// Ask the connection that showed a broken book to subscribe it again, which
+/// makes OKX send a fresh snapshot.
+fn resubscribe(
+    control_tx: &UnboundedSender<Control>,
+    book: &str,
+    inst_id: &str,
+) -> Result<(), ConnectorError> {
+    let arg = serde_json::json!({ "channel": book, "instId": inst_id });
+    control_tx
+        .send(Control::Resubscribe(arg))
+        .map_err(|_| ConnectorError::ConnectionGone)
+}
+
+pub async fn run_collection(
+    venue: Arc<str>,
+    channels: Vec<String>,
+    symbols: Vec<String>,
+    writer_tx: RecordSender,
+    shutdown: watch::Receiver<bool>,
+    connections: usize,
+) -> Result<(), anyhow::Error> {
+    let connections = connections.max(1);
+    let mut dedup = Dedup::for_connections(connections).with_metrics("okx");
+    // Sized per connection: they share the queue, so the burst each one can
+    // absorb stays the same however many there are.
+    let (ws_tx, ws_rx) = channel::<Frame>(crate::WS_QUEUE_CAPACITY.saturating_mul(connections));
+    let mut feed = Feed::new(ws_rx, shutdown);
+    let mut tasks = JoinSet::new();
+    let mut symbol_cache = SymbolCache::new(&symbols);
+    let requested: HashSet<String> = symbols.iter().map(|symbol| symbol.to_uppercase()).collect();
+    let mut seq_ids = SeqIds::new();
+    let mut books = Books::default();
+    // Each connection subscribes independently, so rejections and book repairs
+    // have to be answered on the connection they concern.
+    let mut control_txs = Vec::with_capacity(connections);
+    let spool = Spool::for_feed("okx", connections);
+    for connection in 0..connections {
+        let (control_tx, control_rx) = tokio::sync::mpsc::unbounded_channel();
+        control_txs.push(control_tx);
+
+        let channels = channels.clone();
+        let symbols = symbols.clone();
+        let ws_tx = ws_tx.clone();
+        let spool = spool.clone();
+        let venue = Arc::clone(&venue);
+        tasks.spawn(async move {
+            tokio::time::sleep(crate::CONNECT_STAGGER * connection as u32).await;
+            keep_connection(
+                venue, channels, symbols, connection, ws_tx, spool, control_rx,
+            )
+            .await;
+            error!(connection, "the websocket connection task exited");
+        });
+    }
+    // The clones above are the only senders that should keep the feed open.
+    drop(ws_tx);
+
+    while let Some((connection, recv_time, data)) = feed.recv(&mut tasks).await {
+        // A frame can only carry the index of a connection this loop started.
+        let Some(control_tx) = control_txs.get(connection) else {
+            error!(connection, "frame from an unknown connection; ignoring");
+            continue;
+        };
+        if let Err(error) = handle(
+            &writer_tx,
+            &mut symbol_cache,
+            &requested,
+            &mut seq_ids,
+            &mut books,
+            &mut dedup,
+            control_tx,
+            connection,
+            recv_time,
+            data,
+        )
+        .await
+        {
+            if matches!(&error, ConnectorError::WriterClosed) {
+                return Err(error.into());
+            }
+            error!(?error, "couldn't handle the received data.");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::record_channel;
+
+    async fn feed(
+        frames: &[&'static [u8]],
+        dedup: &mut Dedup,
+        seq_ids: &mut SeqIds,
+    ) -> (
+        tokio::sync::mpsc::Receiver<crate::file::WriteRecord>,
+        tokio::sync::mpsc::UnboundedReceiver<Control>,
+    ) {
+        let (writer_tx, writer_rx) = record_channel(16);
+        let (control_tx, control_rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut symbols = SymbolCache::new(&["btc-usdt".to_owned()]);
+        let requested = HashSet::from(["BTC-USDT".to_owned()]);
+        let mut books = Books::default();
+        for frame in frames {
+            handle(
+                &writer_tx,
+                &mut symbols,
+                &requested,
+                seq_ids,
+                &mut books,
+                dedup,
+                &control_tx,
+                0,
+                Timestamp::now(),
+                bytes::Bytes::from_static(frame),
+            )
+            .await
+            .unwrap();
+        }
+        (writer_rx, control_rx)
+    }
+
+    const SNAPSHOT: &[u8] = br#"{"arg":{"channel":"books","instId":"BTC-USDT"},"action":"snapshot","data":[{"asks":[],"bids":[],"ts":"1","checksum":0,"prevSeqId":-1,"seqId":10}]}"#;
+    const NEXT: &[u8] = br#"{"arg":{"channel":"books","instId":"BTC-USDT"},"action":"update","data":[{"asks":[],"bids":[],"ts":"2","checksum":0,"prevSeqId":10,"seqId":11}]}"#;
+    const AFTER_HOLE: &[u8] = br#"{"arg":{"channel":"books","instId":"BTC-USDT"},"action":"update","data":[{"asks":[],"bids":[],"ts":"3","checksum":0,"prevSeqId":15,"seqId":16}]}"#;
+
+    #[tokio::test]
+    async fn contiguous_book_is_recorded_without_repair() {
+        let mut seq_ids = SeqIds::new();
+        let (mut writer_rx, mut control_rx) =
+            feed(&[SNAPSHOT, NEXT], &mut Dedup::disabled(), &mut seq_ids).await;
+
+        let WriteRecord { symbol, stream, .. } = writer_rx.try_recv().unwrap();
+        assert_eq!(symbol.as_ref(), "btc-usdt");
+        assert_eq!(stream, Stream::DepthSnapshot);
+        assert_eq!(writer_rx.try_recv().unwrap().stream, Stream::Depth);
+        assert!(control_rx.try_recv().is_err());
+        assert_eq!(seq_ids.values().copied().collect::<Vec<_>>(), [11]);
+    }
+
+    /// A hole is answered by resubscribing that one book on the connection it
+    /// was seen on, and the frame after the hole is still recorded.
+    #[tokio::test]
+    async fn sequence_gap_resubscribes_the_book() {
+        let mut seq_ids = SeqIds::new();
+        let (mut writer_rx, mut control_rx) = feed(
+            &[SNAPSHOT, NEXT, AFTER_HOLE],
+            &mut Dedup::disabled(),
+            &mut seq_ids,
+        )
+        .await;
+
+        assert_eq!(
+            control_rx.try_recv().unwrap(),
+            Control::Resubscribe(serde_json::json!({"channel": "books", "instId": "BTC-USDT"}))
+        );
+        for _ in 0..3 {
+            assert!(writer_rx.try_recv().is_ok());
+        }
+    }
+
+    /// The second connection's copy of an update is dropped before it can be
+    /// mistaken for a sequence regression.
+    #[tokio::test]
+    async fn redundant_copies_are_not_gaps() {
+        let mut seq_ids = SeqIds::new();
+        let (mut writer_rx, mut control_rx) = feed(
+            &[SNAPSHOT, NEXT, SNAPSHOT, NEXT],
+            &mut Dedup::for_connections(2),
+            &mut seq_ids,
+        )
+        .await;
+
+        assert!(writer_rx.try_recv().is_ok());
+        assert!(writer_rx.try_recv().is_ok());
+        assert!(writer_rx.try_recv().is_err(), "the copies are dropped");
+        assert!(control_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn rejection_is_retried_and_unrequested_liquidations_are_dropped() {
+        let mut seq_ids = SeqIds::new();
+        let (mut writer_rx, mut control_rx) = feed(
+            &[
+                br#"{"event":"error","code":"60018","msg":"doesn't exist","id":"s0"}"#,
+                br#"{"arg":{"channel":"liquidation-orders","instType":"MARGIN"},"data":[{"instId":"ETH-USDT","details":[]}]}"#,
+                br#"{"arg":{"channel":"liquidation-orders","instType":"MARGIN"},"data":[{"instId":"BTC-USDT","details":[]}]}"#,
+            ],
+            &mut Dedup::disabled(),
+            &mut seq_ids,
+        )
+        .await;
+
+        assert_eq!(
+            control_rx.try_recv().unwrap(),
+            Control::Retry("s0".to_owned())
+        );
+        let WriteRecord { symbol, stream, .. } = writer_rx.try_recv().unwrap();
+        assert_eq!(symbol.as_ref(), "btc-usdt");
+        assert_eq!(stream, Stream::Liquidation);
+        assert!(writer_rx.try_recv().is_err());
+    }
+}
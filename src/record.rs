@@ -0,0 +1,413 @@
+//! The on-disk record formats, and a reader for both.
+//!
+//! The text format is the original one: `<recv_ns> <payload>\n`, one line per
+//! record. It is easy to eyeball with `zstdcat`, but every reader has to split
+//! on space and newline and re-parse the timestamp, and cannot tell a trade
+//! from a depth update without parsing the JSON.
+//!
+//! The binary format puts that in a fixed-width header in front of a
+//! length-prefixed payload:
+//!
+//! | offset | size | field                                             |
+//! |-------:|-----:|---------------------------------------------------|
+//! | 0      | 1    | [`MARKER`]                                        |
+//! | 1      | 1    | [`VERSION`]                                       |
+//! | 2      | 1    | connection index, [`NO_CONNECTION`] for REST data |
+//! | 3      | 1    | [`Stream`] tag                                    |
+//! | 4      | 8    | receive time, ns since the epoch, little-endian   |
+//! | 12     | 4    | payload length, little-endian                     |
+//! | 16     | n    | payload, as received                              |
+//!
+//! The marker can never start a text record, which always begins with an
+//! ASCII digit, so [`Reader`] tells the two apart record by record. A file
+//! appended to by collectors run with different `--format`s still reads back
+//! in order.
+
+use std::{
+    fmt,
+    io::{self, BufRead, Read},
+    str::FromStr,
+};
+
+use serde::Deserialize;
+
+/// First byte of every binary record.
+pub const MARKER: u8 = 0xC5;
+/// Layout of the header that follows [`MARKER`].
+pub const VERSION: u8 = 1;
+/// Bytes in front of a binary record's payload.
+pub const HEADER_LEN: usize = 16;
+/// The connection byte of a record that did not come off a websocket.
+pub const NO_CONNECTION: u8 = u8::MAX;
+
+/// How a venue's records are laid out in its files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// `<recv_ns> <payload>\n`.
+    #[default]
+    Text,
+    /// The header described in the module docs, then the payload.
+    Binary,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "text" => Ok(Format::Text),
+            "binary" => Ok(Format::Binary),
+            _ => Err(format!("invalid format {text:?}: expected text or binary")),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Format::Text => "text",
+            Format::Binary => "binary",
+        })
+    }
+}
+
+/// What kind of message a record holds, so a replay can skip the ones it does
+/// not want without parsing them.
+///
+/// Tags are stored on disk: never renumber one, only add.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Stream {
+    /// Anything not classified below, and every text record.
+    #[default]
+    Other = 0,
+    /// Incremental order book updates.
+    Depth = 1,
+    /// A full order book: REST snapshots, and websocket snapshots such as
+    /// Bybit's first `orderbook` message or every Hyperliquid `l2Book`.
+    DepthSnapshot = 2,
+    /// Individual trades.
+    Trade = 3,
+    /// Trades aggregated by price and taker (Binance `aggTrade`).
+    AggTrade = 4,
+    /// Best bid and offer.
+    Bbo = 5,
+    Liquidation = 6,
+    /// Funding, mark price and open interest of a perpetual, such as
+    /// Hyperliquid's asset contexts or Binance's `markPrice` stream.
+    Funding = 7,
+    /// Candlesticks.
+    Kline = 8,
+    /// A note from the collector rather than the venue, such as the
+    /// `{"type":"lifecycle","event":"delisted",…}` that ends a symbol's file
+    /// when the exchange stops listing it.
+    Lifecycle = 9,
+    /// The account's own orders, fills and positions, from an authenticated
+    /// stream.
+    Account = 10,
+}
+
+impl Stream {
+    pub fn from_tag(tag: u8) -> Self {
+        match tag {
+            1 => Stream::Depth,
+            2 => Stream::DepthSnapshot,
+            3 => Stream::Trade,
+            4 => Stream::AggTrade,
+            5 => Stream::Bbo,
+            6 => Stream::Liquidation,
+            7 => Stream::Funding,
+            8 => Stream::Kline,
+            9 => Stream::Lifecycle,
+            10 => Stream::Account,
+            _ => Stream::Other,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Stream::Other => "other",
+            Stream::Depth => "depth",
+            Stream::DepthSnapshot => "depth_snapshot",
+            Stream::Trade => "trade",
+            Stream::AggTrade => "agg_trade",
+            Stream::Bbo => "bbo",
+            Stream::Liquidation => "liquidation",
+            Stream::Funding => "funding",
+            Stream::Kline => "kline",
+            Stream::Lifecycle => "lifecycle",
+            Stream::Account => "account",
+        }
+    }
+}
+
+/// Everything about a record but its payload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub recv_ns: i64,
+    /// The redundant connection that delivered it. Unknown for text records.
+    pub connection: Option<u8>,
+    pub stream: Stream,
+}
+
+/// Append one record to `out` in `format`.
+pub fn encode(out: &mut Vec<u8>, format: Format, header: &Header, payload: &[u8]) {
+    match format {
+        Format::Text => {
+            out.extend_from_slice(itoa::Buffer::new().format(header.recv_ns).as_bytes());
+            out.push(b' ');
+            out.extend_from_slice(payload);
+            out.push(b'\n');
+        }
+        Format::Binary => {
+            out.reserve(HEADER_LEN + payload.len());
+            out.push(MARKER);
+            out.push(VERSION);
+            out.push(header.connection.unwrap_or(NO_CONNECTION));
+            out.push(header.stream as u8);
+            out.extend_from_slice(&header.recv_ns.to_le_bytes());
+            // Websocket frames are capped far below 4 GiB by every venue.
+            out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            out.extend_from_slice(payload);
+        }
+    }
+}
+
+/// A record that is not in either format. Carried inside the `io::Error`
+/// [`Reader`] returns, so it can be told apart from a failed read.
+#[derive(Debug)]
+struct Malformed(&'static str);
+
+impl fmt::Display for Malformed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "malformed record: {}", self.0)
+    }
+}
+
+impl std::error::Error for Malformed {}
+
+fn malformed(what: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, Malformed(what))
+}
+
+/// Whether `error` is a record [`Reader`] could not make sense of, as opposed
+/// to the underlying stream failing.
+///
+/// A malformed text record has been consumed, its line left in the payload,
+/// and reading can go on with the next one. A malformed binary header leaves
+/// the payload empty and nothing to resynchronise on.
+pub fn is_malformed(error: &io::Error) -> bool {
+    error.get_ref().is_some_and(|inner| inner.is::<Malformed>())
+}
+
+/// Reads records of either format from a decoded file.
+pub struct Reader<R> {
+    inner: R,
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+
+    /// The next record's header, with its payload in `payload`; `None` at the
+    /// end of the stream.
+    ///
+    /// On a malformed text record `payload` holds the offending line, for a
+    /// caller that wants to show or classify it.
+    pub fn read(&mut self, payload: &mut Vec<u8>) -> io::Result<Option<Header>> {
+        self.read_if(payload, |_| true)
+    }
+
+    /// Like [`read`](Self::read), but leaves `payload` empty for a binary
+    /// record `want` turns down, skipping its bytes without copying them.
+    /// Text records are read whole either way: finding their end is the scan
+    /// the binary format exists to avoid.
+    pub fn read_if(
+        &mut self,
+        payload: &mut Vec<u8>,
+        mut want: impl FnMut(&Header) -> bool,
+    ) -> io::Result<Option<Header>> {
+        payload.clear();
+        let first = loop {
+            match self.inner.fill_buf()?.first() {
+                // A blank line is not a record in either format.
+                Some(b'\n') => self.inner.consume(1),
+                Some(&first) => break first,
+                None => return Ok(None),
+            }
+        };
+        if first != MARKER {
+            return self.read_text(payload).map(Some);
+        }
+
+        let mut header = [0u8; HEADER_LEN];
+        self.inner.read_exact(&mut header)?;
+        if header[1] != VERSION {
+            return Err(malformed("unknown binary record version"));
+        }
+        let decoded = Header {
+            recv_ns: i64::from_le_bytes(header[4..12].try_into().expect("8 bytes")),
+            connection: (header[2] != NO_CONNECTION).then_some(header[2]),
+            stream: Stream::from_tag(header[3]),
+        };
+        let len = u64::from(u32::from_le_bytes(
+            header[12..16].try_into().expect("4 bytes"),
+        ));
+        let mut body = (&mut self.inner).take(len);
+        let read = if want(&decoded) {
+            body.read_to_end(payload)? as u64
+        } else {
+            io::copy(&mut body, &mut io::sink())?
+        };
+        if read < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(Some(decoded))
+    }
+
+    fn read_text(&mut self, line: &mut Vec<u8>) -> io::Result<Header> {
+        self.inner.read_until(b'\n', line)?;
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+        let space = line
+            .iter()
+            .position(|&b| b == b' ')
+            .ok_or_else(|| malformed("text record without a space"))?;
+        let recv_ns = std::str::from_utf8(&line[..space])
+            .ok()
+            .and_then(|digits| digits.parse().ok())
+            .ok_or_else(|| malformed("text record without a receive time"))?;
+        line.drain(..=space);
+        Ok(Header {
+            recv_ns,
+            connection: None,
+            stream: Stream::Other,
+        })
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(recv_ns: i64, connection: Option<u8>, stream: Stream) -> Header {
+        Header {
+            recv_ns,
+            connection,
+            stream,
+        }
+    }
+
+    #[test]
+    fn both_formats_read_back_from_one_stream() {
+        let mut bytes = Vec::new();
+        encode(
+            &mut bytes,
+            Format::Text,
+            &header(1, Some(3), Stream::Trade),
+            br#"{"a":1}"#,
+        );
+        encode(
+            &mut bytes,
+            Format::Binary,
+            &header(2, Some(1), Stream::Depth),
+            b"{\"b\":\n2}",
+        );
+        encode(
+            &mut bytes,
+            Format::Binary,
+            &header(-3, None, Stream::DepthSnapshot),
+            b"",
+        );
+        encode(
+            &mut bytes,
+            Format::Text,
+            &header(4, None, Stream::Other),
+            br#"{"c":3}"#,
+        );
+
+        let mut reader = Reader::new(bytes.as_slice());
+        let mut payload = Vec::new();
+        let mut records = Vec::new();
+        while let Some(header) = reader.read(&mut payload).unwrap() {
+            records.push((header, String::from_utf8(payload.clone()).unwrap()));
+        }
+
+        assert_eq!(
+            records,
+            [
+                // Text keeps neither the connection nor the stream.
+                (header(1, None, Stream::Other), r#"{"a":1}"#.to_owned()),
+                (header(2, Some(1), Stream::Depth), "{\"b\":\n2}".to_owned()),
+                (header(-3, None, Stream::DepthSnapshot), String::new()),
+                (header(4, None, Stream::Other), r#"{"c":3}"#.to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unwanted_binary_payloads_are_skipped() {
+        let mut bytes = Vec::new();
+        for (recv_ns, stream) in [(1, Stream::Depth), (2, Stream::Trade), (3, Stream::Depth)] {
+            encode(
+                &mut bytes,
+                Format::Binary,
+                &header(recv_ns, Some(0), stream),
+                format!("payload {recv_ns}").as_bytes(),
+            );
+        }
+
+        let mut reader = Reader::new(bytes.as_slice());
+        let mut payload = Vec::new();
+        let mut trades = Vec::new();
+        while let Some(header) = reader
+            .read_if(&mut payload, |header| header.stream == Stream::Trade)
+            .unwrap()
+        {
+            trades.push((header.recv_ns, payload.clone()));
+        }
+
+        assert_eq!(
+            trades,
+            [(1, Vec::new()), (2, b"payload 2".to_vec()), (3, Vec::new())]
+        );
+    }
+
+    #[test]
+    fn malformed_text_is_reported_and_skipped() {
+        let bytes = b"not a record\n\n5 {}\n";
+        let mut reader = Reader::new(&bytes[..]);
+        let mut payload = Vec::new();
+
+        let error = reader.read(&mut payload).unwrap_err();
+        assert!(is_malformed(&error), "{error}");
+        assert_eq!(payload, b"not a record");
+        assert_eq!(reader.read(&mut payload).unwrap().unwrap().recv_ns, 5);
+        assert!(reader.read(&mut payload).unwrap().is_none());
+    }
+
+    #[test]
+    fn a_truncated_binary_record_is_not_malformed() {
+        let mut bytes = Vec::new();
+        encode(
+            &mut bytes,
+            Format::Binary,
+            &header(1, Some(0), Stream::Bbo),
+            b"0123456789",
+        );
+        bytes.truncate(bytes.len() - 3);
+
+        let error = Reader::new(bytes.as_slice())
+            .read(&mut Vec::new())
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(!is_malformed(&error));
+    }
+}
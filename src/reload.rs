@@ -0,0 +1,216 @@
+//! Changing a running venue's symbols.
+//!
+//! Restarting to add or drop one symbol costs every other symbol a reconnect's
+//! worth of data. Instead, SIGHUP re-reads the config file — or, for a venue
+//! with a `universe`, the exchange's instrument list — and hands each venue its
+//! new symbol list through a `watch` channel. The connection tasks answer by
+//! subscribing and unsubscribing on the sockets they already hold, and by
+//! opening their next session with the new list; the REST pollers take it up
+//! on their next round.
+//!
+//! Only the symbols change. Anything else edited in a venue's block is
+//! reported and left for the next restart, as are venues added to or removed
+//! from the file. OKX numbers its subscription groups by position in the list,
+//! so it keeps its symbols until the next restart too.
+
+use std::path::Path;
+
+use anyhow::{Context, bail};
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+use crate::{
+    config::{Config, Venue},
+    hyperliquid, streams, universe,
+};
+
+/// What changed between two symbol lists, compared as the venue spells them.
+#[derive(Debug, Default, PartialEq)]
+pub struct SymbolDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl SymbolDiff {
+    pub fn between(old: &[String], new: &[String]) -> Self {
+        Self {
+            added: new
+                .iter()
+                .filter(|symbol| !old.contains(symbol))
+                .cloned()
+                .collect(),
+            removed: old
+                .iter()
+                .filter(|symbol| !new.contains(symbol))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// A venue as it was started, and the channel its collection reads its symbols
+/// from.
+pub struct Running {
+    pub venue: Venue,
+    pub symbols: watch::Sender<Vec<String>>,
+}
+
+impl Running {
+    /// Hand the collection the symbols `venue` now lists.
+    async fn apply(&self, venue: &Venue) -> Result<(), anyhow::Error> {
+        let blank = |venue: &Venue| Venue {
+            symbols: Vec::new(),
+            universe: None,
+            ..venue.clone()
+        };
+        if blank(venue) != blank(&self.venue) {
+            warn!(
+                venue = self.venue.name(),
+                "only the symbols are reloaded; the venue's other changes need a restart"
+            );
+        }
+
+        let symbols = match &venue.universe {
+            // Discovered with the running settings: the stream count decides
+            // how many coins fit under Hyperliquid's cap.
+            Some(universe) => universe::discover(&self.venue, universe)
+                .await
+                .context("couldn't discover the symbols")?,
+            None => venue.symbols.clone(),
+        };
+        let diff = SymbolDiff::between(&self.symbols.borrow(), &symbols);
+        if diff.is_empty() {
+            info!(venue = self.venue.name(), "symbols unchanged");
+            return Ok(());
+        }
+        match self.venue.exchange.as_str() {
+            "okx" => bail!("OKX venues only change their symbols on a restart"),
+            "hyperliquid" => {
+                let subscriptions = streams::catalogue(&self.venue.exchange)
+                    .map(|catalogue| catalogue.select(self.venue.streams.clone()))
+                    .transpose()?
+                    .map_or(0, |streams| streams.len());
+                hyperliquid::check_subscription_count(
+                    subscriptions,
+                    symbols.len(),
+                    usize::from(self.venue.connections),
+                )?;
+            }
+            _ => {}
+        }
+
+        info!(
+            venue = self.venue.name(),
+            added = ?diff.added,
+            removed = ?diff.removed,
+            "changing the symbols"
+        );
+        self.symbols.send_replace(symbols);
+        Ok(())
+    }
+}
+
+/// Re-read the symbols of every running venue: from `config` when the venues
+/// came from a file, narrowed by `selected` as at startup, and from the
+/// exchange for a venue with a `universe`.
+///
+/// Nothing here stops a collection. A file that no longer parses, or a venue
+/// whose new list cannot be used, keeps the symbols it has.
+pub async fn reload(config: Option<&Path>, selected: &[String], running: &[Running]) {
+    let venues = match config {
+        Some(path) => match Config::load(path).and_then(|config| config.select(selected)) {
+            Ok(venues) => venues,
+            Err(error) => {
+                error!(
+                    error = format!("{error:#}"),
+                    "couldn't reload the config; keeping the running symbols"
+                );
+                return;
+            }
+        },
+        // Only a discovered universe can change without a file.
+        None => running
+            .iter()
+            .map(|running| running.venue.clone())
+            .collect(),
+    };
+
+    for venue in &venues {
+        if !running
+            .iter()
+            .any(|running| running.venue.name() == venue.name())
+        {
+            warn!(
+                venue = venue.name(),
+                "the venue is not running; starting it needs a restart"
+            );
+        }
+    }
+    for running in running {
+        let Some(venue) = venues
+            .iter()
+            .find(|venue| venue.name() == running.venue.name())
+        else {
+            warn!(
+                venue = running.venue.name(),
+                "the venue is no longer configured; stopping it needs a restart"
+            );
+            continue;
+        };
+        if let Err(error) = running.apply(venue).await {
+            error!(
+                venue = running.venue.name(),
+                error = format!("{error:#}"),
+                "couldn't change the symbols; keeping the running ones"
+            );
+        }
+    }
+}
+
+/// SIGHUP, where the platform has it.
+pub struct Hangups {
+    #[cfg(unix)]
+    signal: tokio::signal::unix::Signal,
+}
+
+impl Hangups {
+    pub fn new() -> std::io::Result<Self> {
+        Ok(Self {
+            #[cfg(unix)]
+            signal: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?,
+        })
+    }
+
+    /// The next SIGHUP; never, without one.
+    pub async fn recv(&mut self) {
+        #[cfg(unix)]
+        self.signal.recv().await;
+        #[cfg(not(unix))]
+        std::future::pending::<()>().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owned(symbols: &[&str]) -> Vec<String> {
+        symbols.iter().map(|symbol| symbol.to_string()).collect()
+    }
+
+    #[test]
+    fn the_diff_lists_what_each_side_lacks() {
+        let diff = SymbolDiff::between(
+            &owned(&["btcusdt", "ethusdt", "solusdt"]),
+            &owned(&["ethusdt", "dogeusdt", "btcusdt"]),
+        );
+
+        assert_eq!(diff.added, ["dogeusdt"]);
+        assert_eq!(diff.removed, ["solusdt"]);
+        assert!(SymbolDiff::between(&owned(&["BTC"]), &owned(&["BTC"])).is_empty());
+    }
+}
@@ -0,0 +1,531 @@
+//! Reading a recording back: one symbol's rotated files, oldest first, as a
+//! single stream of records.
+//!
+//! The collector writes `<symbol>_<stamp>.zst` into each venue's directory, one
+//! file per symbol per rotation period. The stamp is `YYYYMMDD` under daily
+//! rotation, the period's start `YYYYMMDDTHHMM` under hourly and N-minute
+//! rotation, and the opening second `YYYYMMDDTHHMMSS` under size-based
+//! rotation, followed by `_001`, `_002`… for further files opened within that
+//! second. All three sort by [`RecordingFile::start`], so a series that
+//! changed policy between runs still reads back in time order.
+//!
+//! A file is a run of zstd frames, possibly ending in a torn one if the
+//! collector was killed or is still writing it. [`Records`] reports the torn
+//! tail as an error and carries on with the next file, so a reader sees every
+//! complete record without having to special-case the newest file.
+
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use jiff::{Timestamp, civil};
+use zstd::stream::read::Decoder;
+
+use crate::record::{self, Stream};
+
+/// Characters left as-is in a filename.
+///
+/// Deliberately permissive: the point is to keep the exchange's own identifier
+/// readable on disk, so only genuinely path-hostile bytes get escaped. `@` is
+/// the obvious one to allow — Hyperliquid names 315 of its 316 spot pairs `@1`
+/// … `@315` — and `+`/`=` show up in dated contracts elsewhere. All of these
+/// are legal filename characters on Linux, macOS and Windows alike.
+fn is_safe_in_filename(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'.' | b'_' | b'-' | b'@' | b'+' | b'=')
+}
+
+/// Encode an exchange-supplied symbol into a filename component.
+///
+/// Some symbols are not valid path components — Hyperliquid reports spot pairs
+/// as `PURR/USDC`, which would open a file inside a directory that was never
+/// created and take the whole collector down with it.
+///
+/// The encoding must be **injective**. Folding unsafe bytes to a single `_`
+/// would map `purr/usdc` and `purr_usdc` to the same name, and since each
+/// symbol gets its own `RotatingFile`, two independent zstd encoders would
+/// append interleaved frames to one file and render it undecodable. Percent
+/// escaping avoids that: `%` is itself unsafe, so it is always escaped and no
+/// two distinct symbols can collide.
+pub fn encode_symbol(symbol: &str) -> Cow<'_, str> {
+    if symbol.bytes().all(is_safe_in_filename) {
+        return Cow::Borrowed(symbol);
+    }
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    let mut encoded = String::with_capacity(symbol.len() + 8);
+    for byte in symbol.bytes() {
+        if is_safe_in_filename(byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push('%');
+            encoded.push(HEX[(byte >> 4) as usize] as char);
+            encoded.push(HEX[(byte & 0x0f) as usize] as char);
+        }
+    }
+    Cow::Owned(encoded)
+}
+
+/// The symbol [`encode_symbol`] turned into `encoded`; `None` for a name it
+/// cannot have produced.
+pub fn decode_symbol(encoded: &str) -> Option<Cow<'_, str>> {
+    if !encoded.contains('%') {
+        return Some(Cow::Borrowed(encoded));
+    }
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut bytes = encoded.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte != b'%' {
+            decoded.push(byte);
+            continue;
+        }
+        let high = (bytes.next()? as char).to_digit(16)?;
+        let low = (bytes.next()? as char).to_digit(16)?;
+        decoded.push((high << 4 | low) as u8);
+    }
+    String::from_utf8(decoded).ok().map(Cow::Owned)
+}
+
+/// One `.zst` file the collector wrote, identified by its name alone.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordingFile {
+    pub path: PathBuf,
+    /// The symbol as the exchange spells it, with the filename escaping undone.
+    pub symbol: String,
+    /// When the file's period starts, in UTC; orders several files of one date.
+    pub start: civil::DateTime,
+    /// The name's time stamp as written.
+    pub stamp: String,
+}
+
+impl RecordingFile {
+    /// Recognise `path` as `<symbol>_<YYYYMMDD>[T<HHMM>[SS[_NNN]]].zst`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        if path.extension().is_none_or(|ext| ext != "zst") {
+            return None;
+        }
+        let stem = path.file_stem()?.to_str()?;
+        // Symbols may contain `_`; stamps never do, save for the `_NNN` that
+        // numbers size-limited files opened within the same second.
+        let (mut symbol, mut stamp) = stem.rsplit_once('_')?;
+        let mut time = stamp;
+        if stamp.len() == 3 && stamp.bytes().all(|byte| byte.is_ascii_digit()) {
+            (symbol, time) = symbol.rsplit_once('_')?;
+            if time.len() != 15 {
+                return None;
+            }
+            stamp = &stem[symbol.len() + 1..];
+        }
+        let start = match time.len() {
+            8 => civil::Date::strptime("%Y%m%d", time)
+                .ok()?
+                .to_datetime(civil::Time::midnight()),
+            13 => civil::DateTime::strptime("%Y%m%dT%H%M", time).ok()?,
+            15 => civil::DateTime::strptime("%Y%m%dT%H%M%S", time).ok()?,
+            _ => return None,
+        };
+        Some(Self {
+            path: path.to_path_buf(),
+            symbol: decode_symbol(symbol)?.into_owned(),
+            start,
+            stamp: stamp.to_owned(),
+        })
+    }
+
+    /// The UTC date the file belongs to.
+    pub fn date(&self) -> civil::Date {
+        self.start.date()
+    }
+}
+
+/// Oldest first; the path breaks ties, which puts size-limited files opened
+/// within one second in the order of their `_NNN`.
+impl Ord for RecordingFile {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.start
+            .cmp(&other.start)
+            .then_with(|| self.path.cmp(&other.path))
+    }
+}
+
+impl PartialOrd for RecordingFile {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Trained zstd dictionaries, by the id their frames carry.
+///
+/// Files written with `zstd.dictionary` cannot be decoded without it; every
+/// other file ignores this.
+#[derive(Clone, Debug, Default)]
+pub struct Dictionaries {
+    by_id: HashMap<u32, Vec<u8>>,
+}
+
+impl Dictionaries {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a dictionary and return its id, or `None` for raw content: it names
+    /// no id, so no frame could ask for it.
+    pub fn insert(&mut self, dictionary: Vec<u8>) -> Option<u32> {
+        let id = zstd::zstd_safe::get_dict_id_from_dict(&dictionary)?.get();
+        self.by_id.insert(id, dictionary);
+        Some(id)
+    }
+}
+
+/// Why a file, or the rest of one, could not be read.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("needs zstd dictionary {0}")]
+    MissingDictionary(u32),
+    #[error("not a zstd stream")]
+    NotZstd,
+}
+
+/// The records of one decoded file.
+pub type FileReader = record::Reader<BufReader<Decoder<'static, BufReader<File>>>>;
+
+/// Open one file for reading, with the dictionary its first frame names.
+pub fn open(path: &Path, dictionaries: &Dictionaries) -> Result<FileReader, Error> {
+    let mut file = File::open(path)?;
+    let mut header = [0u8; 18];
+    let read = file.read(&mut header)?;
+    file.seek(SeekFrom::Start(0))?;
+    let reader = BufReader::new(file);
+    let mut decoder = match zstd::zstd_safe::get_dict_id_from_frame(&header[..read]) {
+        Some(id) => {
+            let dictionary = dictionaries
+                .by_id
+                .get(&id.get())
+                .ok_or(Error::MissingDictionary(id.get()))?;
+            Decoder::with_dictionary(reader, dictionary)
+        }
+        None => Decoder::with_buffer(reader),
+    }
+    .map_err(|_| Error::NotZstd)?;
+    // The collector's long-distance window can exceed the default limit.
+    decoder.window_log_max(31)?;
+    Ok(record::Reader::new(BufReader::with_capacity(
+        1 << 20,
+        decoder,
+    )))
+}
+
+/// One symbol's files in one directory, oldest first.
+#[derive(Clone, Debug)]
+pub struct Series {
+    pub symbol: String,
+    pub files: Vec<RecordingFile>,
+}
+
+impl Series {
+    /// Every record of the series, in file order.
+    pub fn records<'a>(&'a self, dictionaries: &'a Dictionaries) -> Records<'a> {
+        Records {
+            files: self.files.iter(),
+            dictionaries,
+            symbol: Arc::from(self.symbol.as_str()),
+            current: None,
+        }
+    }
+}
+
+/// Every series in `dir`, by symbol. Anything not named the way the collector
+/// names its files is left alone, and subdirectories are not entered: each
+/// venue has a directory of its own.
+pub fn series(dir: &Path) -> io::Result<Vec<Series>> {
+    let mut by_symbol: BTreeMap<String, Vec<RecordingFile>> = BTreeMap::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        if let Some(file) = RecordingFile::from_path(&entry.path()) {
+            by_symbol.entry(file.symbol.clone()).or_default().push(file);
+        }
+    }
+    Ok(by_symbol
+        .into_iter()
+        .map(|(symbol, mut files)| {
+            files.sort();
+            Series { symbol, files }
+        })
+        .collect())
+}
+
+/// One record read back.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub recv_time: Timestamp,
+    pub symbol: Arc<str>,
+    /// Known for binary records only; see [`record::Header`].
+    pub connection: Option<u8>,
+    pub stream: Stream,
+    pub payload: Vec<u8>,
+}
+
+/// A read that failed, with the file it failed in.
+#[derive(Debug, thiserror::Error)]
+#[error("{}: {source}", .path.display())]
+pub struct ReadError {
+    pub path: PathBuf,
+    #[source]
+    pub source: Error,
+}
+
+/// The records of a [`Series`]; see [`Series::records`].
+///
+/// An error does not end the iteration. A malformed text line is skipped and
+/// reading goes on after it; anything else — a missing dictionary, a torn
+/// frame — gives up on the rest of that file and moves to the next one.
+pub struct Records<'a> {
+    files: std::slice::Iter<'a, RecordingFile>,
+    dictionaries: &'a Dictionaries,
+    symbol: Arc<str>,
+    current: Option<(&'a Path, FileReader)>,
+}
+
+impl Iterator for Records<'_> {
+    type Item = Result<Record, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let Some((path, reader)) = &mut self.current else {
+                let file = self.files.next()?;
+                match open(&file.path, self.dictionaries) {
+                    Ok(reader) => self.current = Some((&file.path, reader)),
+                    Err(source) => {
+                        return Some(Err(ReadError {
+                            path: file.path.clone(),
+                            source,
+                        }));
+                    }
+                }
+                continue;
+            };
+            let mut payload = Vec::new();
+            match reader.read(&mut payload) {
+                Ok(Some(header)) => {
+                    return Some(Ok(Record {
+                        // Every i64 of nanoseconds is a valid timestamp.
+                        recv_time: Timestamp::from_nanosecond(i128::from(header.recv_ns))
+                            .expect("i64 nanoseconds are in range"),
+                        symbol: Arc::clone(&self.symbol),
+                        connection: header.connection,
+                        stream: header.stream,
+                        payload,
+                    }));
+                }
+                Ok(None) => self.current = None,
+                Err(error) => {
+                    let path = path.to_path_buf();
+                    // A malformed text line leaves its bytes in `payload` and
+                    // the reader past it; nothing else can be resumed.
+                    let resumable = record::is_malformed(&error) && !payload.is_empty();
+                    if !resumable {
+                        self.current = None;
+                    }
+                    return Some(Err(ReadError {
+                        path,
+                        source: error.into(),
+                    }));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::{Format, Header};
+
+    /// Every symbol shape the collector actually sees must survive untouched —
+    /// no allocation, and the exchange's own identifier readable on disk.
+    #[test]
+    fn real_exchange_symbols_are_not_rewritten() {
+        for symbol in [
+            "btcusdt",     // Binance spot / Bybit
+            "btcusd_perp", // Binance COIN-M
+            "btc-usdt",    // OKX style
+            "btc",         // Hyperliquid perp
+            "@1",          // Hyperliquid spot, 315 of its 316 pairs
+            "@315",
+        ] {
+            assert!(
+                matches!(encode_symbol(symbol), Cow::Borrowed(_)),
+                "{symbol} should pass through unchanged"
+            );
+            assert_eq!(encode_symbol(symbol), symbol);
+        }
+    }
+
+    #[test]
+    fn path_separators_in_symbols_are_escaped() {
+        assert_eq!(encode_symbol("purr/usdc"), "purr%2Fusdc");
+        assert_eq!(encode_symbol("../../etc/passwd"), "..%2F..%2Fetc%2Fpasswd");
+    }
+
+    /// Two distinct symbols must never produce the same filename: they each get
+    /// their own zstd encoder, and sharing a file would interleave frames. And
+    /// every name decodes back to the symbol it came from.
+    #[test]
+    fn encoding_is_injective() {
+        let symbols = [
+            "purr/usdc",
+            "purr_usdc",
+            "purr%2Fusdc",
+            "purr%usdc",
+            "PURR/USDC",
+            "purr usdc",
+            "purr-usdc",
+            "",
+        ];
+        for symbol in symbols {
+            assert_eq!(decode_symbol(&encode_symbol(symbol)).unwrap(), symbol);
+        }
+        let mut encoded: Vec<String> = symbols
+            .iter()
+            .map(|symbol| encode_symbol(symbol).into_owned())
+            .collect();
+        let total = encoded.len();
+        encoded.sort();
+        encoded.dedup();
+        assert_eq!(encoded.len(), total, "collision: {encoded:?}");
+        assert_eq!(decode_symbol("purr%2"), None);
+    }
+
+    #[test]
+    fn every_rotation_naming_is_read() {
+        let start = |name: &str| {
+            RecordingFile::from_path(Path::new(name))
+                .map(|file| (file.date().to_string(), file.start.to_string(), file.symbol))
+        };
+        let owned = |symbol: &str, date: &str, start: &str| {
+            Some((date.to_owned(), start.to_owned(), symbol.to_owned()))
+        };
+
+        assert_eq!(
+            start("btcusdt_20240101.zst"),
+            owned("btcusdt", "2024-01-01", "2024-01-01T00:00:00")
+        );
+        assert_eq!(
+            start("btcusdt_20240101T1300.zst"),
+            owned("btcusdt", "2024-01-01", "2024-01-01T13:00:00")
+        );
+        assert_eq!(
+            start("purr%2Fusdc_20240101T130512.zst"),
+            owned("purr/usdc", "2024-01-01", "2024-01-01T13:05:12")
+        );
+        assert_eq!(
+            start("d/btc_usdt_20240101T0915.zst"),
+            owned("btc_usdt", "2024-01-01", "2024-01-01T09:15:00")
+        );
+        assert_eq!(
+            start("btc_usdt_20240101T130512_002.zst"),
+            owned("btc_usdt", "2024-01-01", "2024-01-01T13:05:12")
+        );
+        for name in [
+            "btcusdt_2024010.zst",
+            "btcusdt_20240101T13.zst",
+            "btcusdt_20240101T1300_001.zst",
+            "btcusdt_001.zst",
+            "btcusdt_20241301.zst",
+            "btcusdt_20240101.json",
+            "btcusdt.zst",
+        ] {
+            assert_eq!(start(name), None, "{name}");
+        }
+    }
+
+    fn write_file(path: &Path, records: &[(Format, i64)], torn: bool) {
+        let mut raw = Vec::new();
+        for &(format, recv_ns) in records {
+            let header = Header {
+                recv_ns,
+                connection: Some(1),
+                stream: Stream::Trade,
+            };
+            record::encode(
+                &mut raw,
+                format,
+                &header,
+                format!("{{\"t\":{recv_ns}}}").as_bytes(),
+            );
+        }
+        let mut compressed = zstd::encode_all(raw.as_slice(), 1).unwrap();
+        if torn {
+            // A second frame the writer never finished.
+            let next = zstd::encode_all(&b"9 {}\n"[..], 1).unwrap();
+            compressed.extend_from_slice(&next[..next.len() - 4]);
+        }
+        fs::write(path, compressed).unwrap();
+    }
+
+    /// Files of several rotation policies and both formats read back as one
+    /// series in time order, and a torn tail costs only itself.
+    #[test]
+    fn a_series_reads_back_in_order_across_files() {
+        let dir = std::env::temp_dir().join(format!("collector-replay-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        write_file(
+            &dir.join("btcusdt_20240101.zst"),
+            &[(Format::Text, 1), (Format::Binary, 2)],
+            false,
+        );
+        write_file(
+            &dir.join("btcusdt_20240102T0000.zst"),
+            &[(Format::Binary, 3)],
+            true,
+        );
+        write_file(
+            &dir.join("btcusdt_20240102T010000.zst"),
+            &[(Format::Text, 4)],
+            false,
+        );
+        write_file(
+            &dir.join("ethusdt_20240101.zst"),
+            &[(Format::Text, 5)],
+            false,
+        );
+        fs::write(dir.join("notes.txt"), "not a recording").unwrap();
+
+        let series = series(&dir).unwrap();
+        let mut read = Vec::new();
+        let mut errors = Vec::new();
+        for record in series[0].records(&Dictionaries::new()) {
+            match record {
+                Ok(record) => read.push((
+                    record.recv_time.as_nanosecond(),
+                    record.connection,
+                    String::from_utf8(record.payload).unwrap(),
+                )),
+                Err(error) => errors.push(error.path.file_name().unwrap().to_owned()),
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+
+        let symbols: Vec<&str> = series.iter().map(|series| series.symbol.as_str()).collect();
+        assert_eq!(symbols, ["btcusdt", "ethusdt"]);
+        assert_eq!(
+            read,
+            [
+                (1, None, r#"{"t":1}"#.to_owned()),
+                (2, Some(1), r#"{"t":2}"#.to_owned()),
+                (3, Some(1), r#"{"t":3}"#.to_owned()),
+                (4, None, r#"{"t":4}"#.to_owned()),
+            ]
+        );
+        assert_eq!(errors, ["btcusdt_20240102T0000.zst"]);
+    }
+}
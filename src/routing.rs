@@ -29,6 +29,32 @@ impl BinanceMessage<'_> {
     }
 }
 
+/// A frame from an all-market stream such as `!markPrice@arr`: one event for
+/// every symbol on the market.
+#[derive(Deserialize)]
+pub struct BinanceArrayMessage<'a> {
+    #[serde(borrow)]
+    pub stream: &'a str,
+    #[serde(borrow)]
+    pub data: Vec<&'a RawValue>,
+}
+
+impl BinanceArrayMessage<'_> {
+    /// True if `payload` is an all-market frame. Their stream names start with
+    /// `!`, and the combined-stream envelope puts the name first, so this is
+    /// decided without a parse.
+    pub fn matches(payload: &[u8]) -> bool {
+        payload.starts_with(br#"{"stream":"!"#)
+    }
+}
+
+/// The symbol of one event in a [`BinanceArrayMessage`].
+#[derive(Deserialize)]
+pub struct BinanceSymbol<'a> {
+    #[serde(rename = "s", borrow)]
+    pub symbol: &'a str,
+}
+
 #[derive(Deserialize)]
 pub struct BybitMessage<'a> {
     #[serde(borrow)]
@@ -40,30 +66,59 @@ pub struct BybitMessage<'a> {
     #[serde(borrow)]
     pub req_id: Option<&'a str>,
     pub success: Option<bool>,
+    #[serde(rename = "type", borrow)]
+    pub kind: Option<&'a str>,
+    #[serde(borrow)]
+    pub data: Option<&'a RawValue>,
 }
 
 pub struct HyperliquidMessage<'a> {
     pub channel: &'a str,
     symbol: Option<&'a str>,
+    /// The book or quote time of an `l2Book` or `bbo` frame.
+    pub time: Option<i64>,
+    /// Every trade of a `trades` frame.
+    pub trades: Vec<TradeId>,
+}
+
+/// Where a Hyperliquid trade sits in the stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TradeId {
+    pub tid: u64,
+    pub time: i64,
 }
 
 #[derive(Deserialize)]
 struct HyperliquidCoin<'a> {
     #[serde(borrow)]
     coin: Option<&'a str>,
+    time: Option<i64>,
 }
 
-struct FirstHyperliquidCoin<'a>(Option<&'a str>);
+#[derive(Deserialize)]
+struct HyperliquidTrade<'a> {
+    #[serde(borrow)]
+    coin: Option<&'a str>,
+    tid: Option<u64>,
+    time: Option<i64>,
+}
 
-impl<'de> Deserialize<'de> for FirstHyperliquidCoin<'de> {
+/// A `trades` batch: the first trade's coin, which speaks for the batch, and
+/// the id and time of each trade.
+struct HyperliquidTrades<'a> {
+    coin: Option<&'a str>,
+    ids: Vec<TradeId>,
+}
+
+impl<'de> Deserialize<'de> for HyperliquidTrades<'de> {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        struct FirstCoinVisitor;
+        struct TradesVisitor;
 
-        impl<'de> Visitor<'de> for FirstCoinVisitor {
-            type Value = FirstHyperliquidCoin<'de>;
+        impl<'de> Visitor<'de> for TradesVisitor {
+            type Value = HyperliquidTrades<'de>;
 
             fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
                 formatter.write_str("an array of Hyperliquid trade objects")
@@ -73,43 +128,54 @@ impl<'de> Deserialize<'de> for FirstHyperliquidCoin<'de> {
             where
                 A: SeqAccess<'de>,
             {
-                let coin = sequence
-                    .next_element::<HyperliquidCoin<'de>>()?
-                    .and_then(|trade| trade.coin);
-                while sequence.next_element::<IgnoredAny>()?.is_some() {}
-                Ok(FirstHyperliquidCoin(coin))
+                let mut trades = HyperliquidTrades {
+                    coin: None,
+                    ids: Vec::with_capacity(sequence.size_hint().unwrap_or(0)),
+                };
+                while let Some(trade) = sequence.next_element::<HyperliquidTrade<'de>>()? {
+                    trades.coin = trades.coin.or(trade.coin);
+                    if let (Some(tid), Some(time)) = (trade.tid, trade.time) {
+                        trades.ids.push(TradeId { tid, time });
+                    }
+                }
+                Ok(trades)
             }
         }
 
-        deserializer.deserialize_seq(FirstCoinVisitor)
+        deserializer.deserialize_seq(TradesVisitor)
     }
 }
 
-/// Resolve the symbol from an already-captured `data` payload.
+/// Read an already-captured `data` payload.
 ///
 /// This is the only place unknown channels are interpreted; the fast path in
 /// `visit_map` below defers anything it does not recognise to here, so a new
 /// subscription type only has to be taught to one dispatch.
-fn hyperliquid_symbol_from_raw<'a>(
-    channel: &str,
+fn hyperliquid_data_from_raw<'a>(
+    message: &mut HyperliquidMessage<'a>,
     data: &'a RawValue,
-) -> Result<Option<&'a str>, serde_json::Error> {
-    match channel {
+) -> Result<(), serde_json::Error> {
+    match message.channel {
         "trades" => {
-            let FirstHyperliquidCoin(coin) = serde_json::from_str(data.get())?;
-            Ok(coin)
+            let trades: HyperliquidTrades<'a> = serde_json::from_str(data.get())?;
+            message.symbol = trades.coin;
+            message.trades = trades.ids;
         }
         "l2Book" | "bbo" => {
             let data: HyperliquidCoin<'a> = serde_json::from_str(data.get())?;
-            Ok(data.coin)
+            message.symbol = data.coin;
+            message.time = data.time;
         }
         // Route any other channel on a `coin` field if it has one. Payloads that
         // are not objects at all (the `error` channel carries a bare string) are
         // simply unroutable, not malformed.
-        _ => Ok(serde_json::from_str::<HyperliquidCoin<'a>>(data.get())
-            .ok()
-            .and_then(|data| data.coin)),
+        _ => {
+            message.symbol = serde_json::from_str::<HyperliquidCoin<'a>>(data.get())
+                .ok()
+                .and_then(|data| data.coin);
+        }
     }
+    Ok(())
 }
 
 impl<'de> Deserialize<'de> for HyperliquidMessage<'de> {
@@ -132,6 +198,8 @@ impl<'de> Deserialize<'de> for HyperliquidMessage<'de> {
             {
                 let mut channel = None;
                 let mut symbol = None;
+                let mut time = None;
+                let mut trades = Vec::new();
                 let mut deferred_data: Option<Option<&'de RawValue>> = None;
 
                 while let Some(key) = map.next_key::<&str>()? {
@@ -141,13 +209,17 @@ impl<'de> Deserialize<'de> for HyperliquidMessage<'de> {
                         // order) the payload is read straight into the shape that
                         // channel uses, without materialising it. Anything else
                         // is captured raw and handed to
-                        // `hyperliquid_symbol_from_raw` after the loop.
+                        // `hyperliquid_data_from_raw` after the loop.
                         "data" => match channel {
                             Some("trades") => {
-                                symbol = map.next_value::<FirstHyperliquidCoin<'de>>()?.0
+                                let batch = map.next_value::<HyperliquidTrades<'de>>()?;
+                                symbol = batch.coin;
+                                trades = batch.ids;
                             }
                             Some("l2Book") | Some("bbo") => {
-                                symbol = map.next_value::<HyperliquidCoin<'de>>()?.coin
+                                let data = map.next_value::<HyperliquidCoin<'de>>()?;
+                                symbol = data.coin;
+                                time = data.time;
                             }
                             _ => deferred_data = Some(map.next_value()?),
                         },
@@ -158,14 +230,16 @@ impl<'de> Deserialize<'de> for HyperliquidMessage<'de> {
                 }
 
                 let channel = channel.ok_or_else(|| A::Error::missing_field("channel"))?;
-                if symbol.is_none()
-                    && let Some(Some(data)) = deferred_data
-                {
-                    symbol =
-                        hyperliquid_symbol_from_raw(channel, data).map_err(A::Error::custom)?;
+                let mut message = HyperliquidMessage {
+                    channel,
+                    symbol,
+                    time,
+                    trades,
+                };
+                if let Some(Some(data)) = deferred_data {
+                    hyperliquid_data_from_raw(&mut message, data).map_err(A::Error::custom)?;
                 }
-
-                Ok(HyperliquidMessage { channel, symbol })
+                Ok(message)
             }
         }
 
@@ -179,6 +253,98 @@ impl<'a> HyperliquidMessage<'a> {
     }
 }
 
+#[derive(Deserialize)]
+pub struct OkxMessage<'a> {
+    /// Present on control frames only: `subscribe`, `unsubscribe`, `error`.
+    #[serde(borrow)]
+    pub event: Option<&'a str>,
+    #[serde(borrow)]
+    pub id: Option<&'a str>,
+    #[serde(borrow)]
+    pub code: Option<&'a str>,
+    #[serde(borrow)]
+    pub msg: Option<&'a str>,
+    #[serde(borrow)]
+    pub arg: Option<OkxArg<'a>>,
+    /// `snapshot` or `update` on order book channels.
+    #[serde(borrow)]
+    pub action: Option<&'a str>,
+    #[serde(borrow, default)]
+    data: FirstOkxEntry<'a>,
+}
+
+#[derive(Deserialize)]
+pub struct OkxArg<'a> {
+    #[serde(borrow)]
+    pub channel: Option<&'a str>,
+    #[serde(rename = "instId", borrow)]
+    pub inst_id: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+pub struct OkxEntry<'a> {
+    #[serde(rename = "instId", borrow)]
+    pub inst_id: Option<&'a str>,
+    #[serde(rename = "seqId")]
+    pub seq_id: Option<i64>,
+    #[serde(rename = "prevSeqId")]
+    pub prev_seq_id: Option<i64>,
+}
+
+/// Only the first `data` entry is read. Per-instrument channels carry a single
+/// book or a batch of one instrument's trades, and `liquidation-orders` pushes
+/// one instrument per frame, so the first entry speaks for the whole frame.
+#[derive(Default)]
+struct FirstOkxEntry<'a>(Option<OkxEntry<'a>>);
+
+impl<'de: 'a, 'a> Deserialize<'de> for FirstOkxEntry<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct FirstEntryVisitor<'a>(std::marker::PhantomData<&'a ()>);
+
+        impl<'de: 'a, 'a> Visitor<'de> for FirstEntryVisitor<'a> {
+            type Value = FirstOkxEntry<'a>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+                formatter.write_str("an array of OKX data objects")
+            }
+
+            fn visit_seq<A>(self, mut sequence: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let entry = sequence.next_element::<OkxEntry<'a>>()?;
+                while sequence.next_element::<IgnoredAny>()?.is_some() {}
+                Ok(FirstOkxEntry(entry))
+            }
+        }
+
+        deserializer.deserialize_seq(FirstEntryVisitor(std::marker::PhantomData))
+    }
+}
+
+impl<'a> OkxMessage<'a> {
+    pub fn channel(&self) -> Option<&'a str> {
+        self.arg.as_ref()?.channel
+    }
+
+    /// The instrument a market-data frame belongs to: the subscription's
+    /// `instId` where there is one, and otherwise the first entry's, which is
+    /// how instrument-type subscriptions such as `liquidation-orders` route.
+    pub fn symbol(&self) -> Option<&'a str> {
+        self.arg
+            .as_ref()
+            .and_then(|arg| arg.inst_id)
+            .or_else(|| self.entry()?.inst_id)
+    }
+
+    pub fn entry(&self) -> Option<&OkxEntry<'a>> {
+        self.data.0.as_ref()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -212,6 +378,28 @@ mod tests {
         assert_eq!(message.symbol(), None);
     }
 
+    #[test]
+    fn binance_all_market_frames_are_told_apart_by_name() {
+        let raw = br#"{"stream":"!markPrice@arr","data":[{"e":"markPriceUpdate","E":1,"s":"BTCUSDT","p":"1"},{"e":"markPriceUpdate","E":1,"s":"ETHUSDT","p":"2"}]}"#;
+        assert!(BinanceArrayMessage::matches(raw));
+        assert!(!BinanceArrayMessage::matches(
+            br#"{"stream":"btcusdt@markPrice","data":{"s":"BTCUSDT"}}"#
+        ));
+
+        let message: BinanceArrayMessage<'_> = serde_json::from_slice(raw).unwrap();
+        let symbols: Vec<_> = message
+            .data
+            .iter()
+            .map(|event| {
+                serde_json::from_str::<BinanceSymbol<'_>>(event.get())
+                    .unwrap()
+                    .symbol
+            })
+            .collect();
+        assert_eq!(message.stream, "!markPrice@arr");
+        assert_eq!(symbols, ["BTCUSDT", "ETHUSDT"]);
+    }
+
     #[test]
     fn hyperliquid_routes_without_materializing_market_data() {
         let trade = br#"{"channel":"trades","data":[{"coin":"BTC","side":"B","px":"1","sz":"2"},{"coin":"BTC","side":"A","px":"3","sz":"4"}]}"#;
@@ -221,6 +409,19 @@ mod tests {
         let book = br#"{"channel":"l2Book","data":{"coin":"ETH","time":1,"levels":[[{"px":"1","sz":"2","n":3}],[]]}}"#;
         let book: HyperliquidMessage<'_> = serde_json::from_slice(book).unwrap();
         assert_eq!(book.symbol(), Some("ETH"));
+        assert_eq!(book.time, Some(1));
+    }
+
+    #[test]
+    fn hyperliquid_trades_expose_every_id() {
+        let raw = br#"{"channel":"trades","data":[{"coin":"BTC","side":"B","px":"1","sz":"2","hash":"0x1","time":5,"tid":90},{"coin":"BTC","side":"A","px":"3","sz":"4","hash":"0x2","time":6,"tid":17}]}"#;
+        let message: HyperliquidMessage<'_> = serde_json::from_slice(raw).unwrap();
+
+        assert_eq!(message.symbol(), Some("BTC"));
+        assert_eq!(
+            message.trades,
+            [TradeId { tid: 90, time: 5 }, TradeId { tid: 17, time: 6 }]
+        );
     }
 
     #[test]
@@ -229,6 +430,7 @@ mod tests {
         let message: HyperliquidMessage<'_> = serde_json::from_slice(raw).unwrap();
 
         assert_eq!(message.symbol(), Some("ETH"));
+        assert_eq!(message.time, Some(1));
     }
 
     #[test]
@@ -258,4 +460,34 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn okx_book_exposes_sequence_ids_of_the_first_entry() {
+        let raw = br#"{"arg":{"channel":"books","instId":"BTC-USDT"},"action":"update","data":[{"asks":[["8476.98","415","0","13"]],"bids":[],"ts":"1597026383085","checksum":-1,"prevSeqId":123,"seqId":124}]}"#;
+        let message: OkxMessage<'_> = serde_json::from_slice(raw).unwrap();
+        let entry = message.entry().unwrap();
+
+        assert_eq!(message.channel(), Some("books"));
+        assert_eq!(message.symbol(), Some("BTC-USDT"));
+        assert_eq!(message.action, Some("update"));
+        assert_eq!((entry.prev_seq_id, entry.seq_id), (Some(123), Some(124)));
+    }
+
+    #[test]
+    fn okx_instrument_type_subscription_routes_on_the_entry() {
+        let raw = br#"{"arg":{"channel":"liquidation-orders","instType":"SWAP"},"data":[{"instId":"BTC-USDT-SWAP","details":[{"side":"sell","sz":"1","bkPx":"1","ts":"1"}]}]}"#;
+        let message: OkxMessage<'_> = serde_json::from_slice(raw).unwrap();
+
+        assert_eq!(message.symbol(), Some("BTC-USDT-SWAP"));
+    }
+
+    #[test]
+    fn okx_control_frame_has_no_symbol() {
+        let raw = br#"{"id":"s0","event":"error","code":"60012","msg":"Invalid request"}"#;
+        let message: OkxMessage<'_> = serde_json::from_slice(raw).unwrap();
+
+        assert_eq!(message.event, Some("error"));
+        assert_eq!(message.id, Some("s0"));
+        assert_eq!(message.symbol(), None);
+    }
 }
@@ -0,0 +1,551 @@
+//! Spilling frames to disk while the consumer cannot take them.
+//!
+//! [`ws::deliver`](crate::ws::deliver) hands frames to a bounded queue, and a
+//! queue that stays full — a slow disk, a midnight rotation compressing
+//! yesterday's files — used to cost the frames that arrived meanwhile. With a
+//! spool, they are appended to a file instead: a ring of fixed size, one per
+//! feed, shared by every connection delivering into it. A task replays the
+//! file into the queue, oldest first, as room appears, and every frame that
+//! arrives while it has anything left queues behind it. The stall then costs
+//! latency rather than data, and shedding is left for a spool that is itself
+//! full.
+//!
+//! One spool per feed, rather than per connection, keeps the redundant copies
+//! of a frame as close together as they arrived. With a spool each, one
+//! connection's copies would wait out its replay while another's went straight
+//! to the queue, and the two would reach the consumer too far apart for
+//! [`Dedup`](crate::dedup::Dedup) to recognise them — both would be recorded.
+//!
+//! The file is written and read on a thread of its own, started at the first
+//! spill: a read loop that has to spool is already behind, and must not also
+//! wait on the disk.
+//!
+//! The replay task holds a [`Sender`] of its own, so a shutdown that stops the
+//! connection tasks still drains what they spooled: the feed closes once the
+//! spool is empty, or as soon as the consumer is gone.
+
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    os::unix::fs::FileExt,
+    path::PathBuf,
+    sync::{
+        Arc, Mutex, MutexGuard, OnceLock, PoisonError,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc as std_mpsc,
+    },
+};
+
+use bytes::Bytes;
+use jiff::Timestamp;
+use tokio::sync::{
+    mpsc::{Permit, Sender},
+    oneshot,
+};
+use tracing::{error, info, warn};
+
+use crate::metrics;
+
+/// Size of a connection's share of a spool when the command line does not
+/// say.
+pub const DEFAULT_CAPACITY_MIB: u64 = 256;
+
+/// A frame as it is written to, and read back from, a spool.
+pub trait Spooled: Sized {
+    fn encode(&self, out: &mut Vec<u8>);
+    fn decode(bytes: &[u8]) -> Option<Self>;
+}
+
+/// A websocket frame: its connection, receive time and payload.
+impl Spooled for (usize, Timestamp, Bytes) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let (connection, recv_time, payload) = self;
+        out.extend_from_slice(&(*connection as u64).to_le_bytes());
+        out.extend_from_slice(&recv_time.as_nanosecond().to_le_bytes());
+        out.extend_from_slice(payload);
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let (connection, rest) = bytes.split_first_chunk::<8>()?;
+        let (nanos, payload) = rest.split_first_chunk::<16>()?;
+        let recv_time = Timestamp::from_nanosecond(i128::from_le_bytes(*nanos)).ok()?;
+        Some((
+            u64::from_le_bytes(*connection) as usize,
+            recv_time,
+            Bytes::copy_from_slice(payload),
+        ))
+    }
+}
+
+struct Settings {
+    dir: PathBuf,
+    capacity: u64,
+}
+
+/// Set once by `main`; spooling stays off until it is.
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+/// Numbers the spool files, so two feeds on one exchange never share one.
+static NEXT_FILE: AtomicUsize = AtomicUsize::new(0);
+
+/// Spool overflow to files in `dir`, of `capacity` bytes per connection.
+pub fn enable(dir: PathBuf, capacity: u64) -> Result<(), anyhow::Error> {
+    std::fs::create_dir_all(&dir)?;
+    let _ = SETTINGS.set(Settings { dir, capacity });
+    Ok(())
+}
+
+/// Bytes before each record giving its length.
+const LENGTH_PREFIX: u64 = 4;
+
+/// What [`Spool::append`] did with a frame.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Append {
+    /// Nothing is waiting, and the caller only wanted to queue behind frames
+    /// that were.
+    Idle,
+    /// Written. `first` when the spool was empty, and a replay task has to be
+    /// started for it.
+    Spooled { first: bool },
+    /// No room, or the file cannot be written.
+    Full,
+}
+
+/// Work for a spool's I/O thread, done in the order it was asked for.
+enum Io {
+    /// Write a record, length first, at this position in the ring.
+    Write { at: u64, record: Vec<u8> },
+    /// Read back the record at this position.
+    Read {
+        at: u64,
+        reply: oneshot::Sender<io::Result<Vec<u8>>>,
+    },
+    /// Give the disk space back; the ring is empty.
+    Truncate,
+}
+
+/// One feed's ring of spooled frames.
+pub struct Spool {
+    label: &'static str,
+    path: PathBuf,
+    capacity: u64,
+    spooled_total: metrics::Counter,
+    /// Set by the I/O thread when the file fails; spooling is off for good.
+    broken: Arc<AtomicBool>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// The I/O thread's queue. Started at the first spill, so a feed that
+    /// never falls behind never touches the disk; dropping it ends the thread,
+    /// which removes the file.
+    io: Option<std_mpsc::Sender<Io>>,
+    /// Bytes ever read and ever written; their difference is what the ring
+    /// holds, and each modulo the capacity is where it is.
+    head: u64,
+    tail: u64,
+    frames: u64,
+    /// Frames spooled since the spool was last empty, for the report.
+    spilled: u64,
+}
+
+impl Spool {
+    /// The spool for a feed of `connections` connections of `label`, or
+    /// `None` with spooling off.
+    pub fn for_feed(label: &'static str, connections: usize) -> Option<Arc<Self>> {
+        let settings = SETTINGS.get()?;
+        let file = NEXT_FILE.fetch_add(1, Ordering::Relaxed);
+        Some(Arc::new(Self::new(
+            label,
+            settings.dir.join(format!("{label}-{file}.spool")),
+            settings.capacity.saturating_mul(connections.max(1) as u64),
+        )))
+    }
+
+    fn new(label: &'static str, path: PathBuf, capacity: u64) -> Self {
+        Self {
+            label,
+            path,
+            capacity,
+            spooled_total: metrics::counter(
+                "collector_frames_spooled_total",
+                "Frames written to the overflow spool because the consumer could not keep up.",
+                &[("endpoint", label)],
+            ),
+            broken: Arc::new(AtomicBool::new(false)),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// A spool in the system's temporary directory.
+    #[cfg(test)]
+    pub fn for_test(capacity: u64) -> Arc<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "collector-spool-test-{}-{}",
+            std::process::id(),
+            NEXT_FILE.fetch_add(1, Ordering::Relaxed)
+        ));
+        Arc::new(Self::new("test-spool", path, capacity))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Whether frames are waiting to be replayed.
+    pub fn is_pending(&self) -> bool {
+        self.lock().frames > 0
+    }
+
+    /// Write `record` at the end of the ring; unless `force` is false and the
+    /// ring is empty, in which case the frame may go straight to the queue.
+    ///
+    /// The check and the write are one step, so a frame can never slip into
+    /// the queue between the last spooled frame being taken and its replay.
+    /// The write itself is only handed to the I/O thread.
+    pub fn append(&self, record: &[u8], force: bool) -> Append {
+        let mut state = self.lock();
+        if state.frames == 0 && !force {
+            return Append::Idle;
+        }
+        if self.broken.load(Ordering::Relaxed) {
+            return Append::Full;
+        }
+        let needed = LENGTH_PREFIX + record.len() as u64;
+        if state.tail - state.head + needed > self.capacity {
+            return Append::Full;
+        }
+        let write = Io::Write {
+            at: state.tail,
+            record: record.to_vec(),
+        };
+        if let Err(error) = self.io(&mut state).and_then(|io| {
+            io.send(write)
+                .map_err(|_| io::Error::other("the spool's I/O thread is gone"))
+        }) {
+            error!(
+                endpoint = self.label,
+                path = %self.path.display(),
+                ?error,
+                "couldn't write the overflow spool; shedding instead"
+            );
+            self.broken.store(true, Ordering::Relaxed);
+            return Append::Full;
+        }
+        state.tail += needed;
+        state.frames += 1;
+        state.spilled += 1;
+        self.spooled_total.inc();
+        let first = state.frames == 1;
+        if first {
+            warn!(
+                endpoint = self.label,
+                "writer cannot keep up; spooling frames to disk"
+            );
+        }
+        Append::Spooled { first }
+    }
+
+    /// The I/O thread's queue, starting the thread if this is the first spill.
+    fn io<'a>(&self, state: &'a mut State) -> io::Result<&'a std_mpsc::Sender<Io>> {
+        if state.io.is_none() {
+            let (io_tx, io_rx) = std_mpsc::channel();
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&self.path)?;
+            let (label, path, capacity) = (self.label, self.path.clone(), self.capacity);
+            let broken = Arc::clone(&self.broken);
+            std::thread::Builder::new()
+                .name(format!("spool-{label}"))
+                .spawn(move || run_io(label, file, path, capacity, &broken, io_rx))?;
+            state.io = Some(io_tx);
+        }
+        state
+            .io
+            .as_ref()
+            .ok_or_else(|| io::Error::other("the spool has no I/O thread"))
+    }
+
+    /// The oldest record on the ring, read by the I/O thread. Only the replay
+    /// task takes records off, so it stays the oldest until it is taken.
+    async fn read_oldest(&self) -> io::Result<Vec<u8>> {
+        let (reply, record) = oneshot::channel();
+        {
+            let mut state = self.lock();
+            let read = Io::Read {
+                at: state.head,
+                reply,
+            };
+            self.io(&mut state)?
+                .send(read)
+                .map_err(|_| io::Error::other("the spool's I/O thread is gone"))?;
+        }
+        record
+            .await
+            .map_err(|_| io::Error::other("the spool's I/O thread is gone"))?
+    }
+
+    /// Send the oldest frame through `permit`, and say whether more are left.
+    async fn replay_next<T: Spooled>(&self, permit: Permit<'_, T>) -> bool {
+        let record = self.read_oldest().await;
+        self.replay_one(permit, record)
+    }
+
+    /// Take `record`, the oldest, off the ring and send it through `permit`.
+    ///
+    /// The last frame is sent under the lock, so nothing appended after it can
+    /// reach the queue first.
+    fn replay_one<T: Spooled>(&self, permit: Permit<'_, T>, record: io::Result<Vec<u8>>) -> bool {
+        let mut state = self.lock();
+        let record = record.and_then(|record| {
+            if self.broken.load(Ordering::Relaxed) {
+                return Err(io::Error::other("a write to the spool failed"));
+            }
+            Ok(record)
+        });
+        match record {
+            Ok(record) => {
+                state.head += LENGTH_PREFIX + record.len() as u64;
+                state.frames -= 1;
+                match T::decode(&record) {
+                    Some(frame) => permit.send(frame),
+                    None => warn!(
+                        endpoint = self.label,
+                        "skipping a spooled frame that could not be read back"
+                    ),
+                }
+            }
+            Err(error) => {
+                error!(
+                    endpoint = self.label,
+                    ?error,
+                    frames = state.frames,
+                    "couldn't read the overflow spool; discarding it"
+                );
+                self.clear(&mut state);
+                self.broken.store(true, Ordering::Relaxed);
+                return false;
+            }
+        }
+        if state.frames > 0 {
+            return true;
+        }
+        info!(
+            endpoint = self.label,
+            replayed = state.spilled,
+            "writer caught up; the overflow spool is replayed"
+        );
+        self.clear(&mut state);
+        false
+    }
+
+    /// Forget the ring's contents and give the disk space back.
+    fn clear(&self, state: &mut State) {
+        state.head = 0;
+        state.tail = 0;
+        state.frames = 0;
+        state.spilled = 0;
+        if let Some(io) = state.io.as_ref() {
+            let _ = io.send(Io::Truncate);
+        }
+    }
+}
+
+/// The I/O thread of the spool at `path`: everything asked of it, in order,
+/// until the spool is dropped. The file goes with it.
+fn run_io(
+    label: &'static str,
+    file: File,
+    path: PathBuf,
+    capacity: u64,
+    broken: &AtomicBool,
+    requests: std_mpsc::Receiver<Io>,
+) {
+    for request in requests {
+        match request {
+            Io::Write { at, record } => {
+                let length = (record.len() as u32).to_le_bytes();
+                let written = write_wrapping(&file, capacity, at, &length)
+                    .and_then(|()| write_wrapping(&file, capacity, at + LENGTH_PREFIX, &record));
+                if let Err(error) = written
+                    && !broken.swap(true, Ordering::Relaxed)
+                {
+                    error!(
+                        endpoint = label,
+                        path = %path.display(),
+                        ?error,
+                        "couldn't write the overflow spool; shedding instead"
+                    );
+                }
+            }
+            Io::Read { at, reply } => {
+                let _ = reply.send(read_record(&file, capacity, at));
+            }
+            Io::Truncate => {
+                let _ = file.set_len(0);
+            }
+        }
+    }
+    drop(file);
+    let _ = std::fs::remove_file(&path);
+}
+
+fn write_wrapping(file: &File, capacity: u64, at: u64, bytes: &[u8]) -> io::Result<()> {
+    let offset = at % capacity;
+    let (before, after) = bytes.split_at(bytes.len().min((capacity - offset) as usize));
+    file.write_all_at(before, offset)?;
+    file.write_all_at(after, 0)
+}
+
+fn read_wrapping(file: &File, capacity: u64, at: u64, bytes: &mut [u8]) -> io::Result<()> {
+    let offset = at % capacity;
+    let split = bytes.len().min((capacity - offset) as usize);
+    let (before, after) = bytes.split_at_mut(split);
+    file.read_exact_at(before, offset)?;
+    file.read_exact_at(after, 0)
+}
+
+/// The record at `at`: its length, then that many bytes.
+fn read_record(file: &File, capacity: u64, at: u64) -> io::Result<Vec<u8>> {
+    let mut length = [0; LENGTH_PREFIX as usize];
+    read_wrapping(file, capacity, at, &mut length)?;
+    let length = u64::from(u32::from_le_bytes(length));
+    // Nothing longer fits the ring; a length past it is not one that was
+    // written.
+    if length + LENGTH_PREFIX > capacity {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "a spooled record's length is out of range",
+        ));
+    }
+    let mut record = vec![0; length as usize];
+    read_wrapping(file, capacity, at + LENGTH_PREFIX, &mut record)?;
+    Ok(record)
+}
+
+/// Feed `spool` into the queue `tx` leads to, oldest frame first, until it is
+/// empty or the consumer is gone.
+///
+/// `tx` keeps the queue open meanwhile, so a shutdown drain waits for what is
+/// still spooled; see the module docs.
+pub async fn replay<T: Spooled + Send + 'static>(spool: Arc<Spool>, tx: Sender<T>) {
+    while let Ok(permit) = tx.reserve().await {
+        if !spool.replay_next(permit).await {
+            return;
+        }
+    }
+    let mut state = spool.lock();
+    if state.frames > 0 {
+        warn!(
+            endpoint = spool.label,
+            frames = state.frames,
+            "the consumer is gone; discarding the overflow spool"
+        );
+    }
+    spool.clear(&mut state);
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    fn frame(n: u8) -> (usize, Timestamp, Bytes) {
+        (
+            usize::from(n % 2),
+            Timestamp::from_nanosecond(1_700_000_000_000_000_000 + i128::from(n)).unwrap(),
+            Bytes::from(vec![n; usize::from(n) * 3]),
+        )
+    }
+
+    fn encoded(frame: &(usize, Timestamp, Bytes)) -> Vec<u8> {
+        let mut out = Vec::new();
+        frame.encode(&mut out);
+        out
+    }
+
+    #[test]
+    fn frames_survive_the_round_trip() {
+        let frame = frame(5);
+        assert_eq!(
+            <(usize, Timestamp, Bytes)>::decode(&encoded(&frame)),
+            Some(frame)
+        );
+        assert_eq!(<(usize, Timestamp, Bytes)>::decode(&[0; 10]), None);
+    }
+
+    /// The ring wraps many times over, and still hands the frames back in the
+    /// order they were spooled, refusing what does not fit.
+    #[tokio::test]
+    async fn the_ring_replays_in_order_across_the_wrap() {
+        let spool = Spool::for_test(300);
+        let (tx, mut rx) = mpsc::channel(1);
+
+        assert_eq!(spool.append(&encoded(&frame(0)), false), Append::Idle);
+        assert_eq!(
+            spool.append(&encoded(&frame(0)), true),
+            Append::Spooled { first: true }
+        );
+        // One frame stays in the ring throughout, so it is never reset and
+        // the offsets wrap.
+        for n in 1..40 {
+            assert_eq!(
+                spool.append(&encoded(&frame(n)), false),
+                Append::Spooled { first: false }
+            );
+            assert_eq!(spool.append(&encoded(&frame(80)), true), Append::Full);
+            assert!(spool.replay_next(tx.try_reserve().unwrap()).await);
+            assert_eq!(rx.try_recv().ok(), Some(frame(n - 1)));
+        }
+        assert!(spool.lock().tail > 300);
+
+        assert!(!spool.replay_next(tx.try_reserve().unwrap()).await);
+        assert_eq!(rx.try_recv().ok(), Some(frame(39)));
+        assert!(!spool.is_pending());
+    }
+
+    #[tokio::test]
+    async fn a_closed_queue_discards_the_spool() {
+        let spool = Spool::for_test(1024);
+        let (tx, rx) = mpsc::channel::<(usize, Timestamp, Bytes)>(1);
+        spool.append(&encoded(&frame(1)), true);
+        drop(rx);
+
+        replay(Arc::clone(&spool), tx).await;
+        assert!(!spool.is_pending());
+    }
+
+    /// A shutdown stops the connection tasks, and with them every other
+    /// sender. What they spooled is still drained into the consumer before
+    /// the feed closes.
+    #[tokio::test]
+    async fn a_shutdown_drains_what_is_still_spooled() {
+        let spool = Spool::for_test(1024);
+        let (tx, rx) = mpsc::channel(1);
+        let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+        tx.try_send(frame(0)).unwrap();
+        for n in 1..4 {
+            spool.append(&encoded(&frame(n)), true);
+        }
+        tokio::spawn(replay(Arc::clone(&spool), tx.clone()));
+        let mut tasks = tokio::task::JoinSet::new();
+        tasks.spawn(async move {
+            let _tx = tx;
+            std::future::pending::<()>().await;
+        });
+
+        shutdown_tx.send_replace(true);
+        let mut feed = crate::feed::Feed::new(rx, shutdown_rx);
+        let mut drained = Vec::new();
+        while let Some(frame) = feed.recv(&mut tasks).await {
+            drained.push(frame);
+        }
+
+        assert_eq!(drained, (0..4).map(frame).collect::<Vec<_>>());
+        assert!(!spool.is_pending());
+    }
+}
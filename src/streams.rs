@@ -0,0 +1,272 @@
+//! The streams each venue can record, and the ones it records by default.
+//!
+//! A misspelt stream fails differently on every venue. Binance rejects the
+//! whole combined-stream URL, so one bad name takes every stream of the
+//! connection with it. Bybit and OKX reject that one subscription and keep
+//! the rest streaming, and Hyperliquid answers on its error channel. Either
+//! way the mistake surfaces after connecting, as a feed that is quietly
+//! incomplete. Checking the list against a catalogue first turns all of them
+//! into a startup error that names the stream.
+//!
+//! Catalogue entries are templates in the venue's own syntax, with `$symbol`
+//! where the venue expects one. `{a,b}` stands for either spelling, and an
+//! empty alternative for leaving that part out: `$symbol@depth{,@100ms}` is
+//! both `$symbol@depth` and `$symbol@depth@100ms`.
+
+use anyhow::bail;
+
+pub struct Catalogue {
+    /// Recorded when a venue names no streams.
+    defaults: &'static [&'static str],
+    known: &'static [&'static str],
+    /// Streams that look right but are refused, and why.
+    refused: &'static [(&'static str, &'static str)],
+}
+
+const BINANCE_SPOT: Catalogue = Catalogue {
+    defaults: &["$symbol@trade", "$symbol@bookTicker", "$symbol@depth@100ms"],
+    known: &[
+        "$symbol@{trade,aggTrade,bookTicker,ticker,miniTicker,avgPrice}",
+        "$symbol@depth{,@100ms}",
+        "$symbol@depth{5,10,20}{,@100ms}",
+        "$symbol@kline_{1s,1m,3m,5m,15m,30m,1h,2h,4h,6h,8h,12h,1d,3d,1w,1M}",
+    ],
+    refused: &[],
+};
+
+const BINANCE_USDM: Catalogue = Catalogue {
+    defaults: &[
+        "$symbol@trade",
+        "$symbol@bookTicker",
+        "$symbol@depth@0ms",
+        "$symbol@forceOrder",
+        "$symbol@markPrice@1s",
+    ],
+    known: &[
+        "$symbol@{trade,aggTrade,bookTicker,forceOrder,ticker,miniTicker}",
+        "$symbol@depth{,@0ms,@100ms,@500ms}",
+        "$symbol@depth{5,10,20}{,@100ms,@500ms}",
+        "$symbol@markPrice{,@1s}",
+        "!markPrice@arr{,@1s}",
+        "$symbol@kline_{1m,3m,5m,15m,30m,1h,2h,4h,6h,8h,12h,1d,3d,1w,1M}",
+    ],
+    refused: &[],
+};
+
+const BINANCE_COINM: Catalogue = Catalogue {
+    defaults: &[
+        "$symbol@trade",
+        "$symbol@bookTicker",
+        "$symbol@depth@100ms",
+        "$symbol@forceOrder",
+        "$symbol@markPrice@1s",
+    ],
+    known: &[
+        "$symbol@{trade,aggTrade,bookTicker,forceOrder,ticker,miniTicker}",
+        // `@depth` alone is the 250 ms stream.
+        "$symbol@depth{,@100ms,@500ms}",
+        "$symbol@depth{5,10,20}{,@100ms,@500ms}",
+        "$symbol@markPrice{,@1s}",
+        "$symbol@kline_{1m,3m,5m,15m,30m,1h,2h,4h,6h,8h,12h,1d,3d,1w,1M}",
+    ],
+    refused: &[(
+        "$symbol@depth@0ms",
+        "COIN-M only offers 100ms/250ms/500ms depth; @0ms is USD-M only and gets \
+         the whole connection rejected",
+    )],
+};
+
+const BYBIT_DERIVATIVES: Catalogue = Catalogue {
+    defaults: &[
+        "orderbook.1.$symbol",
+        "orderbook.50.$symbol",
+        "orderbook.200.$symbol",
+        "publicTrade.$symbol",
+        "allLiquidation.$symbol",
+    ],
+    known: &[
+        "orderbook.{1,50,200,1000}.$symbol",
+        "{publicTrade,tickers,allLiquidation}.$symbol",
+        "kline.{1,3,5,15,30,60,120,240,360,720,D,W,M}.$symbol",
+    ],
+    refused: &[],
+};
+
+const BYBIT_SPOT: Catalogue = Catalogue {
+    defaults: &[
+        "orderbook.1.$symbol",
+        "orderbook.50.$symbol",
+        "orderbook.200.$symbol",
+        "publicTrade.$symbol",
+    ],
+    known: &[
+        "orderbook.{1,50,200,1000}.$symbol",
+        "{publicTrade,tickers}.$symbol",
+        "kline.{1,3,5,15,30,60,120,240,360,720,D,W,M}.$symbol",
+    ],
+    refused: &[("allLiquidation.$symbol", "spot has no liquidations")],
+};
+
+const BYBIT_OPTION: Catalogue = Catalogue {
+    // Option trades are published per base coin (`publicTrade.BTC`), so the
+    // symbols are base coins rather than contracts. Books and tickers are per
+    // contract and need the contracts as symbols instead.
+    defaults: &["publicTrade.$symbol"],
+    known: &[
+        "orderbook.{25,100}.$symbol",
+        "{publicTrade,tickers}.$symbol",
+    ],
+    refused: &[],
+};
+
+const OKX: Catalogue = Catalogue {
+    defaults: &["trades", "books", "bbo-tbt", "liquidation-orders"],
+    known: &[
+        "trades",
+        "books",
+        "books5",
+        "bbo-tbt",
+        "tickers",
+        "liquidation-orders",
+        "funding-rate",
+        "open-interest",
+        "mark-price",
+    ],
+    refused: &[
+        ("books-l2-tbt", "requires a logged-in connection"),
+        ("books50-l2-tbt", "requires a logged-in connection"),
+    ],
+};
+
+const HYPERLIQUID: Catalogue = Catalogue {
+    defaults: &["trades", "l2Book", "bbo"],
+    known: &["trades", "l2Book", "bbo", "activeAssetCtx"],
+    refused: &[],
+};
+
+/// The catalogue of `exchange`, or `None` if it is not supported.
+pub fn catalogue(exchange: &str) -> Option<&'static Catalogue> {
+    Some(match exchange {
+        "binance" | "binancespot" => &BINANCE_SPOT,
+        "binancefutures" | "binancefuturesum" => &BINANCE_USDM,
+        "binancefuturescm" => &BINANCE_COINM,
+        "bybit" | "bybitlinear" | "bybitinverse" => &BYBIT_DERIVATIVES,
+        "bybitspot" => &BYBIT_SPOT,
+        "bybitoption" => &BYBIT_OPTION,
+        "okx" => &OKX,
+        "hyperliquid" => &HYPERLIQUID,
+        _ => return None,
+    })
+}
+
+impl Catalogue {
+    /// `streams` once every one is known to the venue, or the defaults if
+    /// none were given.
+    pub fn select(&self, streams: Option<Vec<String>>) -> Result<Vec<String>, anyhow::Error> {
+        let Some(streams) = streams else {
+            return Ok(self
+                .defaults
+                .iter()
+                .map(|stream| stream.to_string())
+                .collect());
+        };
+        if streams.is_empty() {
+            bail!("no streams");
+        }
+        for stream in &streams {
+            if let Some((_, why)) = self.refused.iter().find(|(refused, _)| refused == stream) {
+                bail!("stream {stream:?} is refused: {why}");
+            }
+            if !self
+                .known
+                .iter()
+                .any(|template| expand(template).iter().any(|known| known == stream))
+            {
+                bail!(
+                    "unknown stream {stream:?}; this venue knows {}",
+                    self.known.join(", ")
+                );
+            }
+        }
+        Ok(streams)
+    }
+}
+
+/// Every spelling a catalogue template stands for.
+fn expand(template: &str) -> Vec<String> {
+    let Some((head, rest)) = template.split_once('{') else {
+        return vec![template.to_owned()];
+    };
+    let (alternatives, tail) = rest
+        .split_once('}')
+        .expect("catalogue templates close every brace");
+    let tails = expand(tail);
+    alternatives
+        .split(',')
+        .flat_map(|alternative| {
+            tails
+                .iter()
+                .map(move |tail| format!("{head}{alternative}{tail}"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owned(streams: &[&str]) -> Option<Vec<String>> {
+        Some(streams.iter().map(|stream| stream.to_string()).collect())
+    }
+
+    #[test]
+    fn templates_expand_every_alternative() {
+        assert_eq!(
+            expand("$symbol@depth{5,20}{,@100ms}"),
+            [
+                "$symbol@depth5",
+                "$symbol@depth5@100ms",
+                "$symbol@depth20",
+                "$symbol@depth20@100ms"
+            ]
+        );
+        assert_eq!(expand("trades"), ["trades"]);
+    }
+
+    /// Whatever a venue records by default, it must also accept when asked.
+    #[test]
+    fn every_default_is_in_its_catalogue() {
+        for exchange in [
+            "binance",
+            "binancefutures",
+            "binancefuturescm",
+            "bybit",
+            "bybitspot",
+            "bybitoption",
+            "okx",
+            "hyperliquid",
+        ] {
+            let catalogue = catalogue(exchange).unwrap();
+            let defaults = owned(catalogue.defaults);
+            assert_eq!(
+                catalogue.select(defaults.clone()).unwrap(),
+                defaults.unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn streams_outside_the_catalogue_are_rejected() {
+        let usdm = catalogue("binancefutures").unwrap();
+        assert!(usdm.select(owned(&["$symbol@bookTicker"])).is_ok());
+        assert!(usdm.select(owned(&["bookTicker"])).is_err(), "no $symbol");
+        assert!(usdm.select(owned(&["$symbol@@markPrice@1s"])).is_err());
+        assert!(usdm.select(owned(&[])).is_err());
+
+        let error = catalogue("binancefuturescm")
+            .unwrap()
+            .select(owned(&["$symbol@depth@0ms"]))
+            .unwrap_err();
+        assert!(error.to_string().contains("USD-M only"), "{error}");
+    }
+}
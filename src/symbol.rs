@@ -64,6 +64,22 @@ impl SymbolCache {
         symbol
     }
 
+    /// The symbol for `raw` if the cache already holds it, without interning
+    /// anything new: for frames that carry every symbol on the market, of
+    /// which only the configured ones are wanted.
+    pub fn known(&self, raw: &str) -> Option<Symbol> {
+        self.symbols
+            .get(raw)
+            .or_else(|| self.symbols.get(raw.to_ascii_lowercase().as_str()))
+            .cloned()
+    }
+
+    /// Start over from `symbols`, as when the venue's list is changed while it
+    /// runs: [`known`](Self::known) then answers for the new list alone.
+    pub fn reseed(&mut self, symbols: &[String]) {
+        *self = Self::new(symbols);
+    }
+
     fn remember(&mut self, key: String, symbol: &Symbol) {
         if self.symbols.len() >= MAX_INTERNED {
             if !self.capacity_warned {
@@ -118,6 +118,14 @@ impl Throttler {
         }
         Some(fut.await)
     }
+
+    /// Requests the window would still take now, ignoring any ban.
+    pub async fn spare(&self) -> usize {
+        let cur_ts = Timestamp::now().as_nanosecond() as i64;
+        let mut exec_ts_ = self.exec_ts.lock().await;
+        exec_ts_.retain(|ts| *ts > cur_ts - WINDOW_NANOS);
+        self.rate_limit.saturating_sub(exec_ts_.len())
+    }
 }
 
 #[cfg(test)]
@@ -196,4 +204,16 @@ mod tests {
 
         assert_eq!(Throttler::new(0).execute(async { 1 }).await, None);
     }
+
+    #[tokio::test]
+    async fn spare_counts_what_the_window_has_left() {
+        let throttler = Throttler::new(3);
+        assert_eq!(throttler.spare().await, 3);
+        throttler.execute(async {}).await;
+        throttler.execute(async {}).await;
+        assert_eq!(throttler.spare().await, 1);
+        throttler.execute(async {}).await;
+        throttler.execute(async {}).await;
+        assert_eq!(throttler.spare().await, 0);
+    }
 }
@@ -0,0 +1,601 @@
+//! Symbol lists discovered from the exchange instead of written by hand.
+//!
+//! Each exchange publishes its instruments with a quote asset, a contract type
+//! and a trading status: Binance in `exchangeInfo`, Bybit in
+//! `instruments-info`, Hyperliquid in `meta` and `spotMeta`. A venue with a
+//! `universe` takes every instrument that passes its filters, most traded
+//! first, so a listing is picked up at the next restart, or the next SIGHUP,
+//! without anyone editing the config.
+//!
+//! The volume ranking costs a second, heavier request on Binance and Bybit
+//! (the 24-hour tickers), so it is only made when `top` asks for it.
+//! Hyperliquid always ranks: it answers `meta` and the day's volume in one
+//! request, and its subscription cap may force the list to be cut, which
+//! should drop the quietest coins rather than whichever sort last.
+
+use std::collections::HashMap;
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, de::DeserializeOwned};
+use tracing::{info, warn};
+
+use crate::{config::Venue, hyperliquid, streams};
+
+/// Filters for a venue's discovered symbols. Every comparison ignores case.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Universe {
+    /// Quote asset, such as `USDT`.
+    pub quote: Option<String>,
+    /// Contract type as the exchange names it: `PERPETUAL` on Binance,
+    /// `LinearPerpetual` on Bybit, `perpetual` or `spot` on Hyperliquid.
+    pub contract_type: Option<String>,
+    /// Status as the exchange names it; `trading` if omitted.
+    pub status: Option<String>,
+    /// Only the most traded, by 24-hour quote volume.
+    pub top: Option<usize>,
+}
+
+/// One instrument, reduced to what the filters read.
+#[derive(Debug, PartialEq)]
+struct Instrument {
+    symbol: String,
+    quote: String,
+    contract_type: String,
+    status: String,
+    /// 24-hour volume in the quote asset; 0 where it was not fetched.
+    volume: f64,
+}
+
+/// Where a Binance market publishes its instruments and 24-hour tickers.
+struct BinanceUrls {
+    exchange_info: &'static str,
+    tickers: &'static str,
+}
+
+const BINANCE_SPOT: BinanceUrls = BinanceUrls {
+    exchange_info: "https://api.binance.com/api/v3/exchangeInfo",
+    tickers: "https://api.binance.com/api/v3/ticker/24hr",
+};
+
+const BINANCE_USDM: BinanceUrls = BinanceUrls {
+    exchange_info: "https://fapi.binance.com/fapi/v1/exchangeInfo",
+    tickers: "https://fapi.binance.com/fapi/v1/ticker/24hr",
+};
+
+const BINANCE_COINM: BinanceUrls = BinanceUrls {
+    exchange_info: "https://dapi.binance.com/dapi/v1/exchangeInfo",
+    tickers: "https://dapi.binance.com/dapi/v1/ticker/24hr",
+};
+
+const BYBIT_URL: &str = "https://api.bybit.com/v5/market";
+
+/// The venue's symbols, discovered from the exchange and filtered by its
+/// `universe`.
+pub async fn discover(venue: &Venue, universe: &Universe) -> Result<Vec<String>, anyhow::Error> {
+    let instruments = instruments(&client()?, &venue.exchange, universe.top.is_some()).await?;
+    let limit = if venue.exchange == "hyperliquid" {
+        // The same arithmetic `hyperliquid::run_collection` refuses to start
+        // beyond.
+        let subscriptions = streams::catalogue(&venue.exchange)
+            .map(|catalogue| catalogue.select(venue.streams.clone()))
+            .transpose()?
+            .map_or(1, |streams| streams.len());
+        let per_symbol = subscriptions * usize::from(venue.connections.max(1));
+        Some(hyperliquid::MAX_SUBSCRIPTIONS / per_symbol.max(1))
+    } else {
+        None
+    };
+
+    let available = instruments.len();
+    let matching = filter(instruments, universe);
+    let symbols = match limit {
+        Some(limit) if matching.len() > limit => {
+            warn!(
+                venue = venue.name(),
+                matching = matching.len(),
+                kept = limit,
+                "more symbols match than Hyperliquid's subscription cap allows; keeping the most traded"
+            );
+            matching[..limit].to_vec()
+        }
+        _ => matching,
+    };
+    if symbols.is_empty() {
+        bail!("none of the {available} instruments match the universe filters");
+    }
+    info!(
+        venue = venue.name(),
+        available,
+        symbols = symbols.len(),
+        "discovered the symbol universe"
+    );
+    Ok(symbols)
+}
+
+/// Every instrument `exchange` lists, lowercased, with its status as the
+/// exchange names it; for noticing that a running symbol has stopped trading.
+pub async fn statuses(exchange: &str) -> Result<HashMap<String, String>, anyhow::Error> {
+    Ok(instruments(&client()?, exchange, false)
+        .await?
+        .into_iter()
+        .map(|instrument| (instrument.symbol.to_ascii_lowercase(), instrument.status))
+        .collect())
+}
+
+/// Whether `exchange` publishes an instrument list this module can read.
+pub fn lists_instruments(exchange: &str) -> bool {
+    matches!(
+        exchange,
+        "binance"
+            | "binancespot"
+            | "binancefutures"
+            | "binancefuturesum"
+            | "binancefuturescm"
+            | "bybit"
+            | "bybitlinear"
+            | "bybitinverse"
+            | "bybitspot"
+            | "hyperliquid"
+    )
+}
+
+/// Whether [`statuses`] lists `exchange`'s instruments whatever their status,
+/// so that one missing from it is no longer listed at all. Bybit spot is read
+/// without a status, which answers for the trading pairs alone.
+pub fn lists_every_status(exchange: &str) -> bool {
+    lists_instruments(exchange) && exchange != "bybitspot"
+}
+
+fn client() -> Result<reqwest::Client, anyhow::Error> {
+    Ok(reqwest::Client::builder()
+        .connect_timeout(std::time::Duration::from_secs(10))
+        .timeout(std::time::Duration::from_secs(30))
+        .build()?)
+}
+
+/// `exchange`'s instruments, with their volumes when `ranked`.
+async fn instruments(
+    client: &reqwest::Client,
+    exchange: &str,
+    ranked: bool,
+) -> Result<Vec<Instrument>, anyhow::Error> {
+    match exchange {
+        "binance" | "binancespot" => binance(client, &BINANCE_SPOT, ranked).await,
+        "binancefutures" | "binancefuturesum" => binance(client, &BINANCE_USDM, ranked).await,
+        "binancefuturescm" => binance(client, &BINANCE_COINM, ranked).await,
+        "bybit" | "bybitlinear" => bybit(client, "linear", ranked).await,
+        "bybitinverse" => bybit(client, "inverse", ranked).await,
+        "bybitspot" => bybit(client, "spot", ranked).await,
+        "hyperliquid" => hyperliquid_instruments(client).await,
+        exchange => bail!("symbol discovery is not supported on {exchange}"),
+    }
+}
+
+/// The symbols of the instruments that pass every filter, most traded first.
+fn filter(mut instruments: Vec<Instrument>, universe: &Universe) -> Vec<String> {
+    let matches = |wanted: &Option<String>, value: &str| {
+        wanted
+            .as_deref()
+            .is_none_or(|wanted| wanted.eq_ignore_ascii_case(value))
+    };
+    let status = universe.status.as_deref().unwrap_or("trading");
+    instruments.retain(|instrument| {
+        instrument.status.eq_ignore_ascii_case(status)
+            && matches(&universe.quote, &instrument.quote)
+            && matches(&universe.contract_type, &instrument.contract_type)
+    });
+    // The name breaks ties, so an unranked list comes out the same every run.
+    instruments.sort_by(|a, b| {
+        b.volume
+            .total_cmp(&a.volume)
+            .then_with(|| a.symbol.cmp(&b.symbol))
+    });
+    if let Some(top) = universe.top {
+        instruments.truncate(top);
+    }
+    instruments
+        .into_iter()
+        .map(|instrument| instrument.symbol)
+        .collect()
+}
+
+async fn get<T: DeserializeOwned>(client: &reqwest::Client, url: &str) -> Result<T, anyhow::Error> {
+    let body = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    serde_json::from_slice(&body).with_context(|| format!("unexpected response from {url}"))
+}
+
+async fn post<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    url: &str,
+    request: serde_json::Value,
+) -> Result<T, anyhow::Error> {
+    let body = client
+        .post(url)
+        .json(&request)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    serde_json::from_slice(&body)
+        .with_context(|| format!("unexpected response to {request} from {url}"))
+}
+
+#[derive(Deserialize)]
+struct BinanceExchangeInfo {
+    symbols: Vec<BinanceSymbolInfo>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BinanceSymbolInfo {
+    symbol: String,
+    /// COIN-M calls it `contractStatus`.
+    #[serde(alias = "contractStatus")]
+    status: String,
+    quote_asset: String,
+    /// Absent on spot.
+    contract_type: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BinanceTicker {
+    symbol: String,
+    /// Absent on COIN-M, whose `volume` counts contracts worth a fixed amount
+    /// of USD each.
+    quote_volume: Option<String>,
+    volume: String,
+}
+
+async fn binance(
+    client: &reqwest::Client,
+    urls: &BinanceUrls,
+    ranked: bool,
+) -> Result<Vec<Instrument>, anyhow::Error> {
+    let info = get(client, urls.exchange_info).await?;
+    let tickers = if ranked {
+        get(client, urls.tickers).await?
+    } else {
+        Vec::new()
+    };
+    Ok(binance_instruments(info, tickers))
+}
+
+fn binance_instruments(info: BinanceExchangeInfo, tickers: Vec<BinanceTicker>) -> Vec<Instrument> {
+    let volumes: HashMap<String, f64> = tickers
+        .into_iter()
+        .map(|ticker| {
+            let volume = ticker.quote_volume.as_deref().unwrap_or(&ticker.volume);
+            (ticker.symbol, volume.parse().unwrap_or(0.0))
+        })
+        .collect();
+    info.symbols
+        .into_iter()
+        .map(|symbol| Instrument {
+            volume: volumes.get(&symbol.symbol).copied().unwrap_or(0.0),
+            symbol: symbol.symbol,
+            quote: symbol.quote_asset,
+            contract_type: symbol.contract_type.unwrap_or_else(|| "SPOT".to_owned()),
+            status: symbol.status,
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct BybitResponse<T> {
+    result: BybitList<T>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BybitList<T> {
+    list: Vec<T>,
+    #[serde(default)]
+    next_page_cursor: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BybitInstrument {
+    symbol: String,
+    status: String,
+    quote_coin: String,
+    /// Absent on spot.
+    contract_type: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BybitTicker {
+    symbol: String,
+    turnover24h: String,
+}
+
+/// The statuses Bybit's derivatives are listed under. `instruments-info` only
+/// answers with `Trading` instruments unless asked for another status, so each
+/// is asked for in turn; spot takes no status and is read as it comes.
+fn bybit_statuses(category: &str) -> &'static [&'static str] {
+    match category {
+        "spot" => &[""],
+        _ => &["PreLaunch", "Trading", "Delivering", "Closed"],
+    }
+}
+
+async fn bybit(
+    client: &reqwest::Client,
+    category: &str,
+    ranked: bool,
+) -> Result<Vec<Instrument>, anyhow::Error> {
+    let mut instruments = Vec::new();
+    for status in bybit_statuses(category) {
+        let status = match *status {
+            "" => String::new(),
+            status => format!("&status={status}"),
+        };
+        let mut cursor = String::new();
+        loop {
+            let url = format!(
+                "{BYBIT_URL}/instruments-info?category={category}{status}&limit=1000&cursor={cursor}"
+            );
+            let page: BybitResponse<BybitInstrument> = get(client, &url).await?;
+            instruments.extend(page.result.list);
+            if page.result.next_page_cursor.is_empty() {
+                break;
+            }
+            cursor = page.result.next_page_cursor;
+        }
+    }
+    let tickers = if ranked {
+        let url = format!("{BYBIT_URL}/tickers?category={category}");
+        get::<BybitResponse<BybitTicker>>(client, &url)
+            .await?
+            .result
+            .list
+    } else {
+        Vec::new()
+    };
+    Ok(bybit_instruments(instruments, tickers))
+}
+
+fn bybit_instruments(
+    instruments: Vec<BybitInstrument>,
+    tickers: Vec<BybitTicker>,
+) -> Vec<Instrument> {
+    let volumes: HashMap<String, f64> = tickers
+        .into_iter()
+        .map(|ticker| (ticker.symbol, ticker.turnover24h.parse().unwrap_or(0.0)))
+        .collect();
+    instruments
+        .into_iter()
+        .map(|instrument| Instrument {
+            volume: volumes.get(&instrument.symbol).copied().unwrap_or(0.0),
+            symbol: instrument.symbol,
+            quote: instrument.quote_coin,
+            contract_type: instrument
+                .contract_type
+                .unwrap_or_else(|| "Spot".to_owned()),
+            status: instrument.status,
+        })
+        .collect()
+}
+
+/// `metaAndAssetCtxs`: the perpetuals and, in the same order, their contexts.
+#[derive(Deserialize)]
+struct PerpMeta(PerpUniverse, Vec<DayVolume>);
+
+#[derive(Deserialize)]
+struct PerpUniverse {
+    universe: Vec<Perp>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Perp {
+    name: String,
+    #[serde(default)]
+    is_delisted: bool,
+}
+
+/// `spotMetaAndAssetCtxs`: the pairs and tokens, then each pair's context.
+#[derive(Deserialize)]
+struct SpotMeta(SpotUniverse, Vec<SpotContext>);
+
+#[derive(Deserialize)]
+struct SpotUniverse {
+    universe: Vec<SpotPair>,
+    tokens: Vec<SpotToken>,
+}
+
+#[derive(Deserialize)]
+struct SpotPair {
+    name: String,
+    /// Base and quote, as token indices.
+    tokens: [usize; 2],
+}
+
+#[derive(Deserialize)]
+struct SpotToken {
+    name: String,
+    index: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DayVolume {
+    day_ntl_vlm: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SpotContext {
+    coin: String,
+    day_ntl_vlm: String,
+}
+
+/// `meta` and `spotMeta`, each with the assets' contexts alongside, which is
+/// where the day's volume comes from.
+async fn hyperliquid_instruments(
+    client: &reqwest::Client,
+) -> Result<Vec<Instrument>, anyhow::Error> {
+    let perps = post(
+        client,
+        hyperliquid::INFO_URL,
+        serde_json::json!({ "type": "metaAndAssetCtxs" }),
+    )
+    .await?;
+    let spot = post(
+        client,
+        hyperliquid::INFO_URL,
+        serde_json::json!({ "type": "spotMetaAndAssetCtxs" }),
+    )
+    .await?;
+    Ok(hyperliquid_universe(perps, spot))
+}
+
+fn hyperliquid_universe(perps: PerpMeta, spot: SpotMeta) -> Vec<Instrument> {
+    let PerpMeta(meta, contexts) = perps;
+    // Perpetuals are margined and settled in USDC.
+    let perps = meta
+        .universe
+        .into_iter()
+        .zip(contexts)
+        .map(|(perp, context)| Instrument {
+            symbol: perp.name,
+            quote: "USDC".to_owned(),
+            contract_type: "perpetual".to_owned(),
+            status: if perp.is_delisted {
+                "delisted"
+            } else {
+                "trading"
+            }
+            .to_owned(),
+            volume: context.day_ntl_vlm.parse().unwrap_or(0.0),
+        });
+
+    let SpotMeta(meta, contexts) = spot;
+    let tokens: HashMap<usize, String> = meta
+        .tokens
+        .into_iter()
+        .map(|token| (token.index, token.name))
+        .collect();
+    let volumes: HashMap<String, f64> = contexts
+        .into_iter()
+        .map(|context| (context.coin, context.day_ntl_vlm.parse().unwrap_or(0.0)))
+        .collect();
+    let pairs = meta.universe.into_iter().map(|pair| Instrument {
+        volume: volumes.get(&pair.name).copied().unwrap_or(0.0),
+        quote: tokens.get(&pair.tokens[1]).cloned().unwrap_or_default(),
+        symbol: pair.name,
+        contract_type: "spot".to_owned(),
+        status: "trading".to_owned(),
+    });
+    perps.chain(pairs).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binance_instruments_are_filtered_and_ranked() {
+        let info: BinanceExchangeInfo = serde_json::from_str(
+            r#"{"symbols":[
+                {"symbol":"BTCUSDT","status":"TRADING","quoteAsset":"USDT","contractType":"PERPETUAL"},
+                {"symbol":"ETHUSDT","status":"TRADING","quoteAsset":"USDT","contractType":"PERPETUAL"},
+                {"symbol":"BTCUSDT_260925","status":"TRADING","quoteAsset":"USDT","contractType":"CURRENT_QUARTER"},
+                {"symbol":"LUNAUSDT","status":"SETTLING","quoteAsset":"USDT","contractType":"PERPETUAL"},
+                {"symbol":"BTCUSDC","status":"TRADING","quoteAsset":"USDC","contractType":"PERPETUAL"}
+            ]}"#,
+        )
+        .unwrap();
+        let tickers: Vec<BinanceTicker> = serde_json::from_str(
+            r#"[{"symbol":"BTCUSDT","quoteVolume":"900.5","volume":"1"},
+                {"symbol":"ETHUSDT","quoteVolume":"1200","volume":"1"}]"#,
+        )
+        .unwrap();
+        let universe = Universe {
+            quote: Some("usdt".to_owned()),
+            contract_type: Some("perpetual".to_owned()),
+            ..Universe::default()
+        };
+
+        let instruments = binance_instruments(info, tickers);
+
+        assert_eq!(filter(instruments, &universe), ["ETHUSDT", "BTCUSDT"]);
+    }
+
+    #[test]
+    fn coin_m_status_and_spot_contract_type_are_read() {
+        let info: BinanceExchangeInfo = serde_json::from_str(
+            r#"{"symbols":[
+                {"symbol":"BTCUSD_PERP","contractStatus":"TRADING","quoteAsset":"USD","contractType":"PERPETUAL"},
+                {"symbol":"BTCUSDT","status":"TRADING","quoteAsset":"USDT"}
+            ]}"#,
+        )
+        .unwrap();
+
+        let instruments = binance_instruments(info, Vec::new());
+
+        assert_eq!(instruments[0].status, "TRADING");
+        assert_eq!(instruments[1].contract_type, "SPOT");
+    }
+
+    #[test]
+    fn bybit_top_keeps_the_most_traded() {
+        let page: BybitResponse<BybitInstrument> = serde_json::from_str(
+            r#"{"retCode":0,"result":{"category":"linear","list":[
+                {"symbol":"BTCUSDT","status":"Trading","quoteCoin":"USDT","contractType":"LinearPerpetual"},
+                {"symbol":"ETHUSDT","status":"Trading","quoteCoin":"USDT","contractType":"LinearPerpetual"},
+                {"symbol":"NEWUSDT","status":"PreLaunch","quoteCoin":"USDT","contractType":"LinearPerpetual"},
+                {"symbol":"SOLUSDT","status":"Trading","quoteCoin":"USDT","contractType":"LinearPerpetual"}
+            ],"nextPageCursor":""}}"#,
+        )
+        .unwrap();
+        let tickers: BybitResponse<BybitTicker> = serde_json::from_str(
+            r#"{"retCode":0,"result":{"list":[
+                {"symbol":"BTCUSDT","turnover24h":"5000"},
+                {"symbol":"SOLUSDT","turnover24h":"7000"},
+                {"symbol":"NEWUSDT","turnover24h":"9000"}
+            ]}}"#,
+        )
+        .unwrap();
+        let universe = Universe {
+            top: Some(2),
+            ..Universe::default()
+        };
+
+        let instruments = bybit_instruments(page.result.list, tickers.result.list);
+
+        assert_eq!(filter(instruments, &universe), ["SOLUSDT", "BTCUSDT"]);
+    }
+
+    #[test]
+    fn hyperliquid_spot_pairs_are_quoted_by_token_name() {
+        let perps: PerpMeta = serde_json::from_str(
+            r#"[{"universe":[{"name":"BTC","szDecimals":5},{"name":"OLD","szDecimals":0,"isDelisted":true}]},
+                [{"dayNtlVlm":"100.0","funding":"0"},{"dayNtlVlm":"0.0","funding":"0"}]]"#,
+        )
+        .unwrap();
+        let spot: SpotMeta = serde_json::from_str(
+            r#"[{"universe":[{"name":"PURR/USDC","tokens":[1,0],"index":0},{"name":"@1","tokens":[2,0],"index":1}],
+                 "tokens":[{"name":"USDC","index":0},{"name":"PURR","index":1},{"name":"HFUN","index":2}]},
+                [{"coin":"@1","dayNtlVlm":"250.0"},{"coin":"PURR/USDC","dayNtlVlm":"50.0"}]]"#,
+        )
+        .unwrap();
+
+        let instruments = hyperliquid_universe(perps, spot);
+
+        assert_eq!(
+            filter(instruments, &Universe::default()),
+            ["@1", "BTC", "PURR/USDC"]
+        );
+    }
+}
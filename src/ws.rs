@@ -20,6 +20,13 @@ use tokio_rustls::TlsConnector;
 use tracing::{error, info, warn};
 use url::Url;
 
+use collector::record::Stream;
+
+use crate::{
+    metrics,
+    spool::{self, Append, Spool, Spooled},
+};
+
 type Io = TokioIo<Upgraded>;
 
 /// How long a frame may wait for room in the websocket queue before it is
@@ -65,6 +72,54 @@ where
     }
 }
 
+/// How much a frame is worth keeping when the consumer falls behind; frames
+/// are shed in this order, cheapest loss first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Best bid and offer. The next one supersedes it, so losing one costs
+    /// the least.
+    Quote,
+    /// Trades, liquidations and any other stream: lost for good, but the
+    /// rest of the recording does not depend on them.
+    Trade,
+    /// Order book updates. A lost diff breaks the book until a snapshot is
+    /// refetched through the [`Throttler`](crate::throttler::Throttler), so
+    /// these go last.
+    ///
+    /// Full books are here too. The snapshot that opens an incremental book
+    /// (Bybit `orderbook`, OKX `books`) is what every later diff applies to,
+    /// and losing it costs as much as losing a diff. The full books pushed on
+    /// a timer (Binance `depth20`, OKX `books5`, Hyperliquid `l2Book`) are
+    /// superseded by the next one like a quote, but they carry the same
+    /// [`Stream::DepthSnapshot`] tag, and a quote's price would put the other
+    /// kind at risk.
+    Depth,
+    /// Subscription acks and rejections, never shed.
+    Control,
+}
+
+impl Priority {
+    /// The class of market data tagged `stream`.
+    pub fn of(stream: Stream) -> Self {
+        match stream {
+            Stream::Bbo => Self::Quote,
+            Stream::Depth | Stream::DepthSnapshot => Self::Depth,
+            _ => Self::Trade,
+        }
+    }
+
+    /// Free room the queue must have for a frame of this class to take a
+    /// slot while shedding, so what is left goes to the classes above it.
+    fn headroom(self, max: usize) -> usize {
+        match self {
+            Self::Quote => max.div_ceil(2),
+            Self::Trade => max.div_ceil(4),
+            Self::Depth => 1,
+            Self::Control => 0,
+        }
+    }
+}
+
 /// Rate-limited accounting for frames dropped because the writer cannot keep up.
 ///
 /// Dropping is preferable to stalling: a stalled read loop stops answering
@@ -72,35 +127,77 @@ where
 /// larger than the overflow itself. Binance depth streams additionally detect
 /// the resulting sequence gap and refetch a snapshot. What must never happen is
 /// dropping *silently*.
+///
+/// Nor is every frame equal: while shedding, each [`Priority`] class only
+/// takes a slot if the queue has its headroom free, so quotes go first, then
+/// trades, and depth diffs only once there is no room at all. Shedding ends
+/// when the queue is half empty again, the point at which every class gets
+/// through.
 pub struct Overflow {
     label: &'static str,
+    /// Process-wide totals for this endpoint. `dropped` below resets when the
+    /// consumer catches up; these never do.
+    received_total: metrics::Counter,
+    shed_total: metrics::Counter,
     dropped: u64,
+    /// `dropped`, by the class of the frames: quotes, trades, depth.
+    dropped_by_class: [u64; 3],
     reported: u64,
     last_report: Instant,
     /// Once the consumer is established as too slow, frames are shed without
     /// waiting at all. See [`deliver`].
     shedding: bool,
+    /// Where frames go instead of being shed; see [`crate::spool`].
+    spool: Option<Arc<Spool>>,
 }
 
 impl Overflow {
     pub fn new(label: &'static str) -> Self {
         Self {
             label,
+            received_total: metrics::counter(
+                "collector_frames_received_total",
+                "Websocket text and binary frames read, before deduplication.",
+                &[("endpoint", label)],
+            ),
+            shed_total: metrics::counter(
+                "collector_frames_shed_total",
+                "Frames dropped because the consumer could not keep up.",
+                &[("endpoint", label)],
+            ),
             dropped: 0,
+            dropped_by_class: [0; 3],
             reported: 0,
             last_report: Instant::now(),
             shedding: false,
+            spool: None,
         }
     }
 
-    /// Record one dropped frame.
-    pub fn record_drop(&mut self) {
+    /// Spill to `spool`, the feed's, when the consumer falls behind. Every
+    /// connection delivering into one queue shares its spool; see
+    /// [`crate::spool`].
+    pub fn with_spool(mut self, spool: Option<Arc<Spool>>) -> Self {
+        self.spool = spool;
+        self
+    }
+
+    /// Record one dropped frame of class `priority`.
+    pub fn record_drop(&mut self, priority: Priority) {
+        self.shed_total.inc();
         self.dropped += 1;
+        if let Some(count) = self.dropped_by_class.get_mut(priority as usize) {
+            *count += 1;
+        }
         if self.dropped == 1 || self.last_report.elapsed() >= OVERFLOW_REPORT_INTERVAL {
+            let [quote, trade, depth] = self.dropped_by_class;
             error!(
                 endpoint = self.label,
                 dropped_total = self.dropped,
                 dropped_since_last_report = self.dropped - self.reported,
+                quote,
+                trade,
+                depth,
                 "writer cannot keep up; dropping frames"
             );
             self.reported = self.dropped;
@@ -111,12 +208,17 @@ impl Overflow {
     /// Record one frame that made it through, reporting recovery once.
     pub fn record_sent(&mut self) {
         if self.dropped > 0 {
+            let [quote, trade, depth] = self.dropped_by_class;
             info!(
                 endpoint = self.label,
                 dropped_total = self.dropped,
+                quote,
+                trade,
+                depth,
                 "writer caught up; no longer dropping frames"
             );
             self.dropped = 0;
+            self.dropped_by_class = [0; 3];
             self.reported = 0;
         }
         self.shedding = false;
@@ -130,16 +232,33 @@ impl Overflow {
         self.shedding = true;
     }
 
+    /// Drop a frame of class `priority` the queue cannot take, unless it is
+    /// one that must not be shed.
+    fn shed(&mut self, priority: Priority) -> Delivery {
+        if priority == Priority::Control {
+            return Delivery::Undeliverable;
+        }
+        self.record_drop(priority);
+        Delivery::Dropped
+    }
+
     #[cfg(test)]
     pub fn dropped(&self) -> u64 {
         self.dropped
     }
+
+    #[cfg(test)]
+    fn dropped_of(&self, priority: Priority) -> u64 {
+        self.dropped_by_class[priority as usize]
+    }
 }
 
 /// Outcome of handing a frame to the consumer.
 #[derive(Debug, PartialEq, Eq)]
 pub enum Delivery {
     Sent,
+    /// Written to the spool, to reach the consumer once it catches up.
+    Spooled,
     /// Shed because the consumer is saturated. Counted and reported.
     Dropped,
     /// The consumer is gone; the collector is shutting down.
@@ -155,6 +274,21 @@ pub fn payload_contains(haystack: &[u8], needle: &[u8]) -> bool {
     haystack.len() >= needle.len() && haystack.windows(needle.len()).any(|w| w == needle)
 }
 
+/// The string value of the first `"name":"…"` in `payload`, found without
+/// parsing it.
+///
+/// Only used on the slow path, like [`payload_contains`], to classify a frame
+/// by its stream, topic or channel.
+pub fn string_field<'a>(payload: &'a [u8], name: &str) -> Option<&'a str> {
+    let needle = format!("\"{name}\":\"");
+    let start = payload
+        .windows(needle.len())
+        .position(|window| window == needle.as_bytes())?
+        + needle.len();
+    let len = payload[start..].iter().position(|&byte| byte == b'"')?;
+    std::str::from_utf8(&payload[start..start + len]).ok()
+}
+
 /// Hand a frame to the consumer without ever starving control frames.
 ///
 /// Waiting for queue space also blocks the read loop, so waiting on *every*
@@ -165,35 +299,89 @@ pub fn payload_contains(haystack: &[u8], needle: &[u8]) -> bool {
 /// *first* fills; after that frames are shed without waiting until the consumer
 /// catches up.
 ///
-/// `sheddable` decides whether a given frame may be dropped, and is consulted
-/// only on the slow path, so classification costs nothing while the consumer
-/// keeps up. Subscription acks and rejections must not be shed: losing one
-/// silently leaves topics unsubscribed with nothing left to trigger a retry.
+/// `priority` classifies a frame, and is consulted only on the slow path, so
+/// classification costs nothing while the consumer keeps up. Subscription acks
+/// and rejections must not be shed — losing one silently leaves topics
+/// unsubscribed with nothing left to trigger a retry — so they are
+/// [`Priority::Control`]; the rest are shed cheapest first, as [`Overflow`]
+/// describes.
+///
+/// With a spool, none of that is reached until the spool is full: a full queue
+/// sends the frame to the spool at once, without waiting, and while the spool
+/// holds anything every frame goes behind it, so the consumer still sees them
+/// in the order they arrived. A control frame the full spool cannot take waits
+/// for the queue like any other: it goes ahead of the spooled market data, but
+/// an ack out of order costs nothing and a lost one costs streams.
 pub async fn deliver<T, F>(
     tx: &mpsc::Sender<T>,
     overflow: &mut Overflow,
     value: T,
-    sheddable: F,
+    priority: F,
 ) -> Delivery
 where
-    F: FnOnce(&T) -> bool,
+    T: Spooled + Send + 'static,
+    F: FnOnce(&T) -> Priority,
 {
+    overflow.received_total.inc();
+    let mut priority = Some(priority);
+    let mut class = None;
+    let mut class_of = |value: &T| {
+        *class.get_or_insert_with(|| priority.take().map_or(Priority::Control, |f| f(value)))
+    };
+    let mut record = Vec::new();
+    if let Some(spool) = &overflow.spool
+        && spool.is_pending()
+    {
+        value.encode(&mut record);
+        match spool.append(&record, false) {
+            Append::Idle => {}
+            Append::Spooled { first } => return spooled(tx, spool, first),
+            Append::Full if class_of(&value) == Priority::Control => {
+                return wait_for_room(tx, overflow, value, Priority::Control).await;
+            }
+            // Straight to the queue would put it ahead of older frames.
+            Append::Full => return overflow.shed(class_of(&value)),
+        }
+    }
+    let max = tx.max_capacity();
+    if overflow.is_shedding() && tx.capacity() < class_of(&value).headroom(max) {
+        return overflow.shed(class_of(&value));
+    }
     match tx.try_reserve() {
         Ok(permit) => {
             permit.send(value);
-            overflow.record_sent();
+            if !overflow.is_shedding() || tx.capacity() >= Priority::Quote.headroom(max) {
+                overflow.record_sent();
+            }
             return Delivery::Sent;
         }
         Err(mpsc::error::TrySendError::Closed(())) => return Delivery::Closed,
         Err(mpsc::error::TrySendError::Full(())) => {}
     }
+    if let Some(spool) = &overflow.spool {
+        if record.is_empty() {
+            value.encode(&mut record);
+        }
+        if let Append::Spooled { first } = spool.append(&record, true) {
+            return spooled(tx, spool, first);
+        }
+    }
 
-    let may_shed = sheddable(&value);
-    if may_shed && overflow.is_shedding() {
-        overflow.record_drop();
-        return Delivery::Dropped;
+    let priority = class_of(&value);
+    if priority != Priority::Control && overflow.is_shedding() {
+        return overflow.shed(priority);
     }
+    wait_for_room(tx, overflow, value, priority).await
+}
 
+/// Wait up to [`QUEUE_FULL_GRACE`] for the queue to take a frame of class
+/// `priority`, and shed it if it does not.
+async fn wait_for_room<T>(
+    tx: &mpsc::Sender<T>,
+    overflow: &mut Overflow,
+    value: T,
+    priority: Priority,
+) -> Delivery {
     match timeout(QUEUE_FULL_GRACE, tx.reserve()).await {
         Ok(Ok(permit)) => {
             permit.send(value);
@@ -201,15 +389,27 @@ where
             Delivery::Sent
         }
         Ok(Err(_)) => Delivery::Closed,
-        Err(_) if may_shed => {
-            overflow.begin_shedding();
-            overflow.record_drop();
-            Delivery::Dropped
+        Err(_) => {
+            if priority != Priority::Control {
+                overflow.begin_shedding();
+            }
+            overflow.shed(priority)
         }
-        Err(_) => Delivery::Undeliverable,
     }
 }
 
+/// Start replaying `spool` into `tx` if this frame is the first it holds.
+fn spooled<T: Spooled + Send + 'static>(
+    tx: &mpsc::Sender<T>,
+    spool: &Arc<Spool>,
+    first: bool,
+) -> Delivery {
+    if first {
+        tokio::spawn(spool::replay(Arc::clone(spool), tx.clone()));
+    }
+    Delivery::Spooled
+}
+
 /// A frame handed to the writer task.
 pub enum Outgoing {
     Text(Vec<u8>),
@@ -549,8 +749,8 @@ mod tests {
     #[test]
     fn overflow_reports_the_first_drop_and_resets_after_recovery() {
         let mut overflow = Overflow::new("test");
-        overflow.record_drop();
-        overflow.record_drop();
+        overflow.record_drop(Priority::Quote);
+        overflow.record_drop(Priority::Depth);
         assert_eq!(overflow.dropped(), 2);
         overflow.record_sent();
         assert_eq!(overflow.dropped(), 0);
@@ -566,7 +766,7 @@ mod tests {
 
         let before = tokio::time::Instant::now();
         assert_eq!(
-            deliver(&tx, &mut overflow, 1, |_| true).await,
+            deliver(&tx, &mut overflow, 1, |_| Priority::Trade).await,
             Delivery::Dropped
         );
         let after_first = tokio::time::Instant::now();
@@ -578,7 +778,7 @@ mod tests {
         // Every subsequent frame is shed immediately.
         for value in 2..100 {
             assert_eq!(
-                deliver(&tx, &mut overflow, value, |_| true).await,
+                deliver(&tx, &mut overflow, value, |_| Priority::Trade).await,
                 Delivery::Dropped
             );
         }
@@ -590,6 +790,23 @@ mod tests {
         assert_eq!(overflow.dropped(), 99);
     }
 
+    /// The exported totals belong to the endpoint, not the session: a
+    /// reconnect builds a fresh `Overflow`, and the counts must carry on.
+    #[tokio::test(start_paused = true)]
+    async fn exported_totals_survive_a_new_session() {
+        let (tx, _rx) = mpsc::channel::<u32>(1);
+        tx.try_reserve().unwrap().send(0);
+
+        let mut first = Overflow::new("test-exported");
+        deliver(&tx, &mut first, 1, |_| Priority::Trade).await;
+        let mut second = Overflow::new("test-exported");
+        deliver(&tx, &mut second, 2, |_| Priority::Trade).await;
+
+        assert_eq!(second.received_total.get(), 2);
+        assert_eq!(second.shed_total.get(), 2);
+        assert_eq!(second.dropped(), 1, "the log accounting is per session");
+    }
+
     /// A brief burst is absorbed losslessly rather than shed.
     #[tokio::test(start_paused = true)]
     async fn a_short_burst_is_absorbed_without_dropping() {
@@ -604,7 +821,7 @@ mod tests {
 
         let mut overflow = Overflow::new("test");
         assert_eq!(
-            deliver(&tx, &mut overflow, 1, |_| true).await,
+            deliver(&tx, &mut overflow, 1, |_| Priority::Trade).await,
             Delivery::Sent
         );
         assert_eq!(overflow.dropped(), 0);
@@ -620,18 +837,210 @@ mod tests {
 
         // Get into the shedding state with a data frame.
         assert_eq!(
-            deliver(&tx, &mut overflow, 1, |_| true).await,
+            deliver(&tx, &mut overflow, 1, |_| Priority::Trade).await,
             Delivery::Dropped
         );
         assert!(overflow.is_shedding());
 
         // A non-sheddable frame reports undeliverable instead of vanishing.
         assert_eq!(
-            deliver(&tx, &mut overflow, 2, |_| false).await,
+            deliver(&tx, &mut overflow, 2, |_| Priority::Control).await,
             Delivery::Undeliverable
         );
     }
 
+    /// Under backpressure the queue's last slots go to the frames that cost
+    /// the most to lose.
+    #[tokio::test(start_paused = true)]
+    async fn quotes_are_shed_before_trades_and_trades_before_depth() {
+        let (tx, mut rx) = mpsc::channel::<u32>(8);
+        for value in 0..8 {
+            tx.try_send(value).unwrap();
+        }
+        let mut overflow = Overflow::new("test");
+        let mut send =
+            async |value, priority| deliver(&tx, &mut overflow, value, |_| priority).await;
+
+        // The first frame waits out the grace, and shedding starts.
+        assert_eq!(send(8, Priority::Depth).await, Delivery::Dropped);
+        for _ in 0..3 {
+            rx.recv().await;
+        }
+        // Three free slots: too few for quotes, which need four.
+        assert_eq!(send(9, Priority::Quote).await, Delivery::Dropped);
+        assert_eq!(send(10, Priority::Trade).await, Delivery::Sent);
+        assert_eq!(send(11, Priority::Trade).await, Delivery::Sent);
+        // One slot left, and it is kept for depth.
+        assert_eq!(send(12, Priority::Trade).await, Delivery::Dropped);
+        assert_eq!(send(13, Priority::Depth).await, Delivery::Sent);
+        assert_eq!(send(14, Priority::Depth).await, Delivery::Dropped);
+        // Control frames are never shed.
+        assert_eq!(send(15, Priority::Control).await, Delivery::Undeliverable);
+
+        assert_eq!(overflow.dropped_of(Priority::Quote), 1);
+        assert_eq!(overflow.dropped_of(Priority::Trade), 1);
+        assert_eq!(overflow.dropped_of(Priority::Depth), 2);
+
+        // Half empty again: shedding ends, and quotes get through.
+        for _ in 0..5 {
+            rx.recv().await;
+        }
+        let mut send =
+            async |value, priority| deliver(&tx, &mut overflow, value, |_| priority).await;
+        assert_eq!(send(16, Priority::Quote).await, Delivery::Sent);
+        assert_eq!(overflow.dropped(), 0);
+        assert!(!overflow.is_shedding());
+    }
+
+    #[test]
+    fn string_fields_are_found_without_parsing() {
+        let payload = br#"{"topic":"orderbook.50.BTCUSDT","type":"delta","data":{"s":"BTCUSDT"}}"#;
+
+        assert_eq!(string_field(payload, "topic"), Some("orderbook.50.BTCUSDT"));
+        assert_eq!(string_field(payload, "type"), Some("delta"));
+        assert_eq!(string_field(payload, "data"), None);
+    }
+
+    #[test]
+    fn streams_are_shed_cheapest_first() {
+        let classes = [
+            Stream::Bbo,
+            Stream::Trade,
+            Stream::Liquidation,
+            Stream::Depth,
+            Stream::DepthSnapshot,
+        ]
+        .map(Priority::of);
+
+        assert_eq!(
+            classes,
+            [
+                Priority::Quote,
+                Priority::Trade,
+                Priority::Trade,
+                Priority::Depth,
+                Priority::Depth,
+            ]
+        );
+        assert!(classes.is_sorted());
+        assert!(Priority::Depth < Priority::Control);
+    }
+
+    impl Spooled for u32 {
+        fn encode(&self, out: &mut Vec<u8>) {
+            out.extend_from_slice(&self.to_le_bytes());
+        }
+
+        fn decode(bytes: &[u8]) -> Option<Self> {
+            Some(u32::from_le_bytes(bytes.try_into().ok()?))
+        }
+    }
+
+    /// With a spool, a stalled consumer costs latency and no frames: nothing
+    /// waits, nothing is shed, and everything arrives in order once it
+    /// catches up.
+    #[tokio::test(start_paused = true)]
+    async fn a_spool_absorbs_a_stall_and_replays_in_order() {
+        let (tx, mut rx) = mpsc::channel::<u32>(1);
+        let mut overflow = Overflow::new("test").with_spool(Some(Spool::for_test(1024)));
+
+        let before = tokio::time::Instant::now();
+        assert_eq!(
+            deliver(&tx, &mut overflow, 0, |_| Priority::Trade).await,
+            Delivery::Sent
+        );
+        for value in 1..50 {
+            assert_eq!(
+                deliver(&tx, &mut overflow, value, |_| Priority::Trade).await,
+                Delivery::Spooled
+            );
+        }
+        assert_eq!(
+            tokio::time::Instant::now(),
+            before,
+            "spooling must not wait"
+        );
+
+        for value in 0..25 {
+            assert_eq!(rx.recv().await, Some(value));
+        }
+        // Frames arriving mid-replay go behind the ones still spooled.
+        let late = deliver(&tx, &mut overflow, 50, |_| Priority::Trade).await;
+        assert_ne!(late, Delivery::Dropped);
+        for value in 25..=50 {
+            assert_eq!(rx.recv().await, Some(value));
+        }
+        assert_eq!(overflow.dropped(), 0);
+    }
+
+    /// The connections of a feed share its spool, so once one spools, the
+    /// others' frames queue behind it. The consumer sees every copy in the
+    /// order it arrived, and redundant copies stay close enough together for
+    /// the dedup window.
+    #[tokio::test]
+    async fn connections_sharing_a_spool_stay_in_arrival_order() {
+        let (tx, mut rx) = mpsc::channel::<u32>(1);
+        let spool = Spool::for_test(1024);
+        let mut overflows = [
+            Overflow::new("test").with_spool(Some(Arc::clone(&spool))),
+            Overflow::new("test").with_spool(Some(spool)),
+        ];
+
+        assert_eq!(
+            deliver(&tx, &mut overflows[0], 0, |_| Priority::Trade).await,
+            Delivery::Sent
+        );
+        for value in 1..20 {
+            let overflow = &mut overflows[value as usize % 2];
+            assert_eq!(
+                deliver(&tx, overflow, value, |_| Priority::Trade).await,
+                Delivery::Spooled
+            );
+        }
+
+        for value in 0..20 {
+            assert_eq!(rx.recv().await, Some(value));
+        }
+    }
+
+    /// A control frame the full spool cannot take still gets the grace period
+    /// to reach the queue, rather than failing at once.
+    #[tokio::test]
+    async fn a_control_frame_waits_for_room_when_the_spool_is_full() {
+        let (tx, mut rx) = mpsc::channel::<u32>(1);
+        // Room for two records of eight bytes.
+        let mut overflow = Overflow::new("test").with_spool(Some(Spool::for_test(16)));
+        assert_eq!(
+            deliver(&tx, &mut overflow, 0, |_| Priority::Trade).await,
+            Delivery::Sent
+        );
+        for value in 1..3 {
+            assert_eq!(
+                deliver(&tx, &mut overflow, value, |_| Priority::Trade).await,
+                Delivery::Spooled
+            );
+        }
+        assert_eq!(
+            deliver(&tx, &mut overflow, 3, |_| Priority::Trade).await,
+            Delivery::Dropped
+        );
+
+        let consumer = tokio::spawn(async move {
+            let mut received = Vec::new();
+            while let Some(value) = rx.recv().await {
+                received.push(value);
+            }
+            received
+        });
+        assert_eq!(
+            deliver(&tx, &mut overflow, 4, |_| Priority::Control).await,
+            Delivery::Sent
+        );
+        drop(tx);
+        let received = consumer.await.unwrap();
+        assert!(received.contains(&4), "{received:?}");
+    }
+
     #[test]
     fn payload_classification_separates_control_from_market_data() {
         let market = br#"{"topic":"orderbook.1.BTCUSDT","type":"delta","data":{}}"#;